@@ -9,7 +9,7 @@ limits:
 
 {{- define "redis-sidecar" }}
 - name: redis-sidecar
-  image: redis:4
+  image: "{{ if .image }}{{ .image }}{{ if .version }}:{{ .version }}{{ end }}{{ else }}redis:4{{ end }}"
   imagePullPolicy: IfNotPresent
   resources:
 {{- if .resources }}
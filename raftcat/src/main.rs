@@ -9,7 +9,7 @@ use std::{
 
 use chrono::Local;
 use reqwest::Url;
-use shipcat_definitions::Manifest;
+use shipcat_definitions::{pricing::Cost, Manifest};
 use std::env;
 
 pub use raftcat::*;
@@ -50,6 +50,35 @@ async fn get_resource_usage(c: Data<State>, req: HttpRequest) -> Result<HttpResp
         Ok(HttpResponse::NotFound().finish())
     }
 }
+#[derive(Serialize, Default)]
+struct CostSummary {
+    services: BTreeMap<String, Cost>,
+    squads: BTreeMap<String, Cost>,
+    tribes: BTreeMap<String, Cost>,
+}
+async fn get_costs(c: Data<State>, _req: HttpRequest) -> Result<HttpResponse> {
+    let cfg = c.get_config().await?;
+    let region = c.get_region().await?;
+    let pricing = cfg.pricing_for(&region);
+    let mut summary = CostSummary::default();
+    for (name, mf) in c.get_manifests().await? {
+        let cost = match mf.compute_resource_totals().and_then(|t| pricing.monthly_cost(&t)) {
+            Ok(cost) => cost,
+            Err(e) => {
+                warn!("Failed to estimate cost of {}: {}", name, e);
+                continue;
+            }
+        };
+        if let Some(md) = &mf.metadata {
+            *summary.squads.entry(md.team.clone()).or_default() += cost.clone();
+            if let Some(tribe) = &md.tribe {
+                *summary.tribes.entry(tribe.clone()).or_default() += cost.clone();
+            }
+        }
+        summary.services.insert(name, cost);
+    }
+    Ok(HttpResponse::Ok().json(summary))
+}
 async fn get_manifests_for_team(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
     let name = req.match_info().get("name").unwrap();
     let cfg = c.get_config().await?;
@@ -211,9 +240,13 @@ async fn get_service(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
 
         // stats
         if let Ok(_usage) = mf.compute_resource_totals() {
+            let pricing = cfg.pricing_for(&region);
+            if let Ok(cost) = pricing.monthly_cost(&_usage) {
+                ctx.insert("cost", &cost);
+                ctx.insert("pricing", &pricing);
+            }
             let usagen = _usage.normalise();
             ctx.insert("usage", &serde_json::to_string_pretty(&usagen)?);
-            ctx.insert("cost", &usagen.daily_cost());
             ctx.insert("rollouts", &mf.estimate_rollout_iterations());
         }
         if let Some(ru) = mf.rollingUpdate {
@@ -345,6 +378,7 @@ async fn main() -> io::Result<()> {
             )
             .service(web::resource("/raftcat/manifests/{name}").route(web::get().to(get_single_manifest)))
             .service(web::resource("/raftcat/manifests").route(web::get().to(get_all_manifests)))
+            .service(web::resource("/raftcat/costs").route(web::get().to(get_costs)))
             .service(web::resource("/raftcat/services/{name}").route(web::get().to(get_service)))
             .service(web::resource("/raftcat/teams/{name}").route(web::get().to(get_manifests_for_team)))
             .service(web::resource("/raftcat/teams").route(web::get().to(get_teams)))
@@ -4,7 +4,9 @@ use tokio::fs;
 use crate::{
     diff, helm,
     kubeapi::ShipKube,
-    kubectl, track,
+    kubectl, populate,
+    registry::{self, RegistryClient},
+    strimzi, track,
     webhooks::{self, UpgradeState},
 };
 use serde_json::json;
@@ -82,6 +84,8 @@ impl UpgradeInfo {
 /// It is also entirely responsible for sending webhooks on errors / successes.
 /// As such, it's entirely responsible for not propagating random errors here with `?`
 /// Every error cases is something that might need to be notified.
+///
+/// `mfs` are the `region_manifests`, loaded here when not given, so reconciles can load them once.
 pub async fn apply(
     svc: String,
     force: bool,
@@ -89,9 +93,12 @@ pub async fn apply(
     conf: &Config,
     wait: bool,
     passed_version: Option<String>,
+    mfs: Option<&[Manifest]>,
 ) -> Result<Option<UpgradeInfo>> {
     match region.reconciliationMode {
-        ReconciliationMode::CrdOwned => apply_kubectl(&svc, force, region, conf, wait, passed_version).await,
+        ReconciliationMode::CrdOwned => {
+            apply_kubectl(&svc, force, region, conf, wait, passed_version, mfs).await
+        }
     }
 }
 
@@ -131,6 +138,7 @@ async fn apply_kubectl(
     conf: &Config,
     wait: bool,
     passed_version: Option<String>,
+    mfs: Option<&[Manifest]>,
 ) -> Result<Option<UpgradeInfo>> {
     if let Err(e) = webhooks::ensure_requirements(&region) {
         warn!("Could not ensure webhook requirements: {}", e);
@@ -204,6 +212,12 @@ async fn apply_kubectl(
 
     // Complete and apply the CRD
     let mfcrd = mfbase.version(actual_version.clone());
+    // Refuse versions that were never pushed before recording them in the CRD
+    // The client caches what it saw for populating the manifest below
+    let images = RegistryClient::from_env()?;
+    if region.registry.is_some() {
+        registry::verify(&mfcrd, &images).await?;
+    }
     let crd_changed = s.apply(mfcrd.clone()).await?;
     // Cheap reconcile ends here if !changed && !force
     if crd_changed {
@@ -248,6 +262,27 @@ async fn apply_kubectl(
         }
     };
 
+    // NetworkPolicies, istio and strimzi objects depend on the dependencies of other services
+    let populated: Result<()> = async {
+        let loaded;
+        let mfs = match mfs {
+            Some(mfs) => mfs,
+            None => {
+                loaded = populate::region_manifests(conf, region).await?;
+                &loaded
+            }
+        };
+        populate::populate_region_resources(&mut mf, mfs, conf, region, &images).await?;
+        Ok(())
+    }
+    .await;
+    if let Err(e) = populated {
+        webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
+        s.update_generate_false("ResolveFailure", e.description().to_string())
+            .await?;
+        return Err(e);
+    }
+
     // Create completed kubernetes yaml (via shipcat values | helm template)
     let tfile = format!("{}.kube.gen.yml", svc);
     let tpth = Path::new(".").join(tfile.clone());
@@ -306,6 +341,9 @@ async fn apply_kubectl(
         }
         Ok(_) => {
             let _ = s.update_apply_true(ureason.to_string()).await;
+            if let Err(e) = strimzi::prune(&mf, &region).await {
+                warn!("Failed to prune kafka topics of {}: {}", ui.name, e);
+            }
             if !wait {
                 info!("successfully applied {} (without waiting)", ui.name);
             } else {
@@ -1,11 +1,15 @@
 use futures::stream::{self, StreamExt};
-use shipcat_definitions::{BaseManifest, Config, Region, ShipcatConfig};
+use shipcat_definitions::{math::ResourceTotals, Config, NodePool, Region, ShipcatConfig};
 use shipcat_filebacked::SimpleManifest;
+use std::collections::BTreeMap;
 
-use super::{kubectl, Error, ErrorKind, Result};
+use super::{kubectl, Error, ErrorKind, Manifest, Result};
 use crate::{
     apply, diff, helm,
     kubeapi::ShipKube,
+    populate,
+    registry::RegistryClient,
+    top,
     webhooks::{self, UpgradeState},
 };
 
@@ -13,7 +17,7 @@ struct DiffResult {
     name: String,
     diff: Option<String>,
 }
-async fn diff_summary(svc: String, conf: &Config, reg: &Region) -> Result<DiffResult> {
+async fn diff_summary(svc: String, mfs: &[Manifest], conf: &Config, reg: &Region) -> Result<DiffResult> {
     let mut mf = shipcat_filebacked::load_manifest(&svc, &conf, &reg)
         .await?
         .complete(&reg)
@@ -23,6 +27,7 @@ async fn diff_summary(svc: String, conf: &Config, reg: &Region) -> Result<DiffRe
     let crd = s.get().await?;
     mf.version = mf.version.or(crd.spec.version);
     mf.uid = crd.metadata.uid;
+    populate::populate_region_resources(&mut mf, mfs, conf, reg, &RegistryClient::from_env()?).await?;
     info!("diffing {}", mf.name);
     let d = if let Some(kdiffunobfusc) = diff::template_vs_kubectl(&mf).await? {
         let kubediff = diff::obfuscate_secrets(
@@ -46,9 +51,10 @@ async fn diff_summary(svc: String, conf: &Config, reg: &Region) -> Result<DiffRe
 pub async fn mass_diff(conf: &Config, reg: &Region) -> Result<()> {
     let svcs = shipcat_filebacked::available(conf, reg).await?;
     assert!(conf.has_secrets());
+    let mfs = populate::region_manifests(conf, reg).await?;
 
     let mut buffered = stream::iter(svcs)
-        .map(move |mf| diff_summary(mf.base.name, &conf, &reg))
+        .map(|mf| diff_summary(mf.base.name, &mfs, &conf, &reg))
         .buffer_unordered(10);
 
     let mut errs = vec![];
@@ -87,13 +93,21 @@ pub async fn mass_diff(conf: &Config, reg: &Region) -> Result<()> {
     Ok(())
 }
 
-async fn check_summary(svc: String, skipped: &[String], conf: &Config, reg: &Region) -> Result<String> {
+async fn check_summary(
+    svc: String,
+    skipped: &[String],
+    mfs: &[Manifest],
+    conf: &Config,
+    reg: &Region,
+) -> Result<String> {
     let mut mf = shipcat_filebacked::load_manifest(&svc, &conf, &reg)
         .await?
         .stub(&reg)
         .await?;
-    mf.version = mf.version.or(Some("latest".to_string()));
     mf.uid = Some("FAKE-GUID".to_string());
+    populate::populate_region_resources(&mut mf, mfs, conf, reg, &RegistryClient::from_env()?).await?;
+    // placeholder version after the registry lookups, which need a real one
+    mf.version = mf.version.or(Some("latest".to_string()));
 
     info!("verifying template for {}", mf.name);
     let tpl = helm::template(&mf, None).await?;
@@ -106,9 +120,10 @@ async fn check_summary(svc: String, skipped: &[String], conf: &Config, reg: &Reg
 /// Helper that shells out to helm template in parallel.
 pub async fn mass_template_verify(conf: &Config, reg: &Region, skipped: &[String]) -> Result<()> {
     let svcs = shipcat_filebacked::available(conf, reg).await?;
+    let mfs = populate::region_manifests(conf, reg).await?;
 
     let mut buffered = stream::iter(svcs)
-        .map(move |mf| check_summary(mf.base.name, &skipped, &conf, &reg))
+        .map(|mf| check_summary(mf.base.name, &skipped, &mfs, &conf, &reg))
         .buffer_unordered(100);
 
     let (mut errs, mut passed): (Vec<Error>, Vec<_>) = (vec![], vec![]);
@@ -213,10 +228,19 @@ async fn crd_reconcile(
 
     let conf = config_sec.clone();
     let reg = region_sec.clone();
+    let mfs = populate::region_manifests(&conf, &reg).await?;
     let mut buffered = stream::iter(svcs)
         .map(|mf| {
             debug!("Running CRD reconcile for {:?}", mf.base.name);
-            apply::apply(mf.base.name, force, &reg, &conf, wait_for_rollout, None)
+            apply::apply(
+                mf.base.name,
+                force,
+                &reg,
+                &conf,
+                wait_for_rollout,
+                None,
+                Some(&mfs),
+            )
         })
         .buffer_unordered(n_workers);
 
@@ -251,122 +275,241 @@ async fn crd_reconcile(
     Ok(())
 }
 
-/// Apply all vault policies in a region
-///
-/// Generates and writes policies direct to vault using their github team name as auth mappers.
-/// Equivalent to:
-///
-/// ```pseudo
-/// for team in config.teams:
-///   shipcat get vaultpolicy {team.name} | vault policy write {team.admins} -
-///   vault write auth/github/map/teams/{team.admins} value={team.admins}
-/// ```
-///
-/// using vault setup for the vault specified in the `Region`.
-/// If one vault is reused for all regions, this can be done once.
-///
-/// Requires a `vault login` outside of this command as a user who
-/// is sufficiently elevated to write general policies.
-pub async fn mass_vault(conf: &Config, reg: &Region, n_workers: usize) -> Result<()> {
-    let svcs = shipcat_filebacked::all(conf).await?;
-    vault_reconcile(svcs, conf, reg, n_workers).await
+/// Requested cores and bytes of memory placed on a group of node pools
+#[derive(Default, Clone, Debug)]
+struct Demand {
+    cpu: f64,
+    memory: f64,
 }
 
-async fn vault_reconcile(
-    mfs: Vec<BaseManifest>,
-    conf: &Config,
-    region: &Region,
-    n_workers: usize,
-) -> Result<()> {
-    let n_jobs = conf.owners.squads.len();
-    info!(
-        "Starting {} parallel vault jobs with {} workers",
-        n_jobs, n_workers
-    );
+/// Node pools sharing a taint, and the demand of services scheduled onto them
+#[derive(Default, Debug)]
+struct PoolGroup {
+    pools: Vec<String>,
+    nodes: (u32, u32),
+    min_capacity: Demand,
+    max_capacity: Demand,
+    requests: Demand,
+    autoscaling: Demand,
+    surge: Demand,
+}
 
-    // then parallel apply the remaining ones
-    let reg = region.clone();
+impl PoolGroup {
+    fn total(&self) -> Demand {
+        Demand {
+            cpu: self.requests.cpu + self.autoscaling.cpu + self.surge.cpu,
+            memory: self.requests.memory + self.autoscaling.memory + self.surge.memory,
+        }
+    }
 
-    let mut buffered = stream::iter(conf.owners.clone().squads)
-        .map(|(name, squad)| {
-            let mfs = mfs.clone();
-            debug!("Running vault reconcile for {}", name);
-            vault_reconcile_worker(mfs, name, squad.github.admins, &reg)
-        })
-        .buffer_unordered(n_workers);
+    fn overcommitted(&self) -> bool {
+        let total = self.total();
+        total.cpu > self.max_capacity.cpu || total.memory > self.max_capacity.memory
+    }
 
-    let mut errs = vec![];
-    while let Some(r) = buffered.next().await {
-        if let Err(e) = r {
-            warn!("{}", e);
-            errs.push(e);
-        }
+    fn needs_scale_up(&self) -> bool {
+        self.requests.cpu > self.min_capacity.cpu || self.requests.memory > self.min_capacity.memory
     }
+}
 
-    // propagate first non-ignorable error if exists
-    if let Some(e) = errs.into_iter().next() {
-        // no errors ignoreable atm
-        return Err(e);
+/// Group node pools by taint and assign the demand of every service to them
+///
+/// Services go to the pools whose taint they tolerate, or to the untainted pools.
+/// Autoscaling ceilings and the rolling update surge of a mass reconcile are tracked separately.
+fn assign_demand(
+    pools: &[NodePool],
+    totals: Vec<(Manifest, ResourceTotals)>,
+) -> Result<BTreeMap<Option<String>, PoolGroup>> {
+    let mut groups: BTreeMap<Option<String>, PoolGroup> = BTreeMap::new();
+    for np in pools {
+        let (min, max) = (np.capacity(np.minCount)?, np.capacity(np.maxCount)?);
+        let g = groups.entry(np.taint.clone()).or_default();
+        g.pools.push(np.name.clone());
+        g.nodes.0 += np.minCount;
+        g.nodes.1 += np.maxCount;
+        g.min_capacity.cpu += min.cpu;
+        g.min_capacity.memory += min.memory;
+        g.max_capacity.cpu += max.cpu;
+        g.max_capacity.memory += max.memory;
     }
-    Ok(())
+    for (mf, res) in totals {
+        let taint = mf
+            .tolerations
+            .iter()
+            .filter_map(|t| t.key.clone())
+            .find(|k| groups.contains_key(&Some(k.clone())));
+        let g = match groups.get_mut(&taint) {
+            Some(g) => g,
+            None => bail!("No untainted node pool can schedule {}", mf.name),
+        };
+        let surge = mf.rollout_surge();
+        g.requests.cpu += res.base.requests.cpu;
+        g.requests.memory += res.base.requests.memory;
+        g.autoscaling.cpu += res.extra.requests.cpu;
+        g.autoscaling.memory += res.extra.requests.memory;
+        g.surge.cpu += res.base.requests.cpu * surge;
+        g.surge.memory += res.base.requests.memory * surge;
+    }
+    Ok(groups)
 }
 
-async fn vault_reconcile_worker(
-    svcs: Vec<BaseManifest>,
-    team: String,
-    admins_opt: Option<String>,
-    reg: &Region,
-) -> Result<()> {
-    use std::{fs::File, io::Write, path::Path};
-    if admins_opt.is_none() {
-        debug!("'{}' does not have a github admins team - ignoring", team);
-        return Ok(()); // nothing to do
+/// Check that the requests of a region fit within the node pools of its cluster
+///
+/// Compares requests, autoscaling ceilings, and the surge from rolling updates
+/// happening at the same time (as in a mass reconcile) against the node pools.
+pub async fn capacity(conf: &Config, reg: &Region) -> Result<()> {
+    let cluster = match conf.find_owning_cluster(reg) {
+        Some(c) => c,
+        None => bail!("Could not find the cluster serving {}", reg.name),
     };
-    let admins = admins_opt.unwrap();
+    if cluster.nodePools.is_empty() {
+        bail!(
+            "Cluster {} has no nodePools defined in shipcat.conf",
+            cluster.name
+        );
+    }
+    for r in cluster.regions.iter().filter(|r| *r != &reg.name) {
+        warn!("{} also serves {} which is not accounted for", cluster.name, r);
+    }
+    let totals = top::calculate_manifest_requests(conf, reg)
+        .await?
+        .into_iter()
+        .map(|(mf, res, _)| (mf, res))
+        .collect();
+    let groups = assign_demand(&cluster.nodePools, totals)?;
 
-    let policy = reg
-        .vault
-        .make_policy(svcs, &team, reg.environment.clone())
-        .await?;
-    debug!("Vault policy: {}", policy);
-    // Write policy to a file named "{admins}-policy.hcl"
-    let pth = Path::new(".").join(format!("{}-policy.hcl", admins));
-    info!("Writing vault policy for {} to {}", admins, pth.display());
-    let mut f = File::create(&pth)?;
-    writeln!(f, "{}", policy)?;
-    // Write a vault policy with the name equal to the admin team:
-    use tokio::process::Command;
-    // vault write policy < file
-    {
-        info!("Applying vault policy for {} in {}", admins, reg.name);
-        let write_args = vec![
-            "policy".into(),
-            "write".into(),
-            admins.clone(),
-            format!("{}-policy.hcl", admins),
-        ];
-        debug!("vault {}", write_args.join(" "));
-        let s = Command::new("vault").args(&write_args).status().await?;
-        if !s.success() {
-            bail!("Subprocess failure from vault: {}", s.code().unwrap_or(1001))
-        }
+    let gib = 1024.0 * 1024.0 * 1024.0;
+    println!(
+        "{0:<40} {1:<8} {2:<8} {3:>10} {4:>12} {5:>10} {6:>12} {7:>12}  {8}",
+        "NODE POOLS",
+        "NODES",
+        "RESOURCE",
+        "REQUESTS",
+        "AUTOSCALING",
+        "SURGE",
+        "MIN NODES",
+        "MAX NODES",
+        "STATUS"
+    );
+    let mut overcommitted = vec![];
+    for g in groups.values() {
+        let status = if g.overcommitted() {
+            overcommitted.push(g.pools.join(","));
+            "overcommitted"
+        } else if g.needs_scale_up() {
+            "needs scale-up"
+        } else {
+            "ok"
+        };
+        let name = g.pools.join(",");
+        let nodes = format!("{}-{}", g.nodes.0, g.nodes.1);
+        println!(
+            "{0:<40} {1:<8} {2:<8} {3:>10.1} {4:>12.1} {5:>10.1} {6:>12.1} {7:>12.1}  {8}",
+            name,
+            nodes,
+            "cpu",
+            g.requests.cpu,
+            g.autoscaling.cpu,
+            g.surge.cpu,
+            g.min_capacity.cpu,
+            g.max_capacity.cpu,
+            status
+        );
+        println!(
+            "{0:<40} {1:<8} {2:<8} {3:>10.1} {4:>12.1} {5:>10.1} {6:>12.1} {7:>12.1}",
+            "",
+            "",
+            "memory",
+            g.requests.memory / gib,
+            g.autoscaling.memory / gib,
+            g.surge.memory / gib,
+            g.min_capacity.memory / gib,
+            g.max_capacity.memory / gib,
+        );
     }
-    // vault write auth -> team
-    {
-        info!(
-            "Associating vault policy for {} with github team {} in {}",
-            team, admins, reg.name
+    if !overcommitted.is_empty() {
+        bail!(
+            "Node pools in {} would be overcommitted: {}",
+            cluster.name,
+            overcommitted.join(" ")
         );
-        let assoc_args = vec![
-            "write".into(),
-            format!("auth/github/map/teams/{}", admins),
-            format!("value={}", admins),
-        ];
-        debug!("vault {}", assoc_args.join(" "));
-        let s = Command::new("vault").args(&assoc_args).status().await?;
-        if !s.success() {
-            bail!("Subprocess failure from vault: {}", s.code().unwrap_or(1001))
-        }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::assign_demand;
+    use crate::Manifest;
+    use shipcat_definitions::NodePool;
+
+    fn pool(name: &str, count: (u32, u32), taint: Option<&str>) -> NodePool {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "minCount": count.0,
+            "maxCount": count.1,
+            "allocatable": { "cpu": "4", "memory": "16Gi" },
+            "taint": taint,
+        }))
+        .unwrap()
+    }
+
+    fn service(name: &str, replicas: u32, toleration: Option<&str>) -> Manifest {
+        let mut mf = Manifest::test(name);
+        mf.replicaCount = Some(replicas);
+        mf.resources = Some(
+            serde_json::from_value(serde_json::json!({
+                "requests": { "cpu": "1", "memory": "1Gi" },
+                "limits": { "cpu": "2", "memory": "2Gi" },
+            }))
+            .unwrap(),
+        );
+        if let Some(key) = toleration {
+            mf.tolerations = vec![serde_json::from_value(serde_json::json!({
+                "key": key,
+                "operator": "Exists",
+            }))
+            .unwrap()];
+        }
+        mf
+    }
+
+    #[test]
+    fn capacity_assigns_demand_by_taint() {
+        let pools = vec![
+            pool("general", (1, 2), None),
+            pool("general-spot", (0, 2), None),
+            pool("batch", (1, 1), Some("batch")),
+        ];
+        let mfs = vec![service("web", 5, None), service("etl", 4, Some("batch"))];
+        let totals = mfs
+            .into_iter()
+            .map(|mf| {
+                let res = mf.compute_resource_totals().unwrap();
+                (mf, res)
+            })
+            .collect();
+        let groups = assign_demand(&pools, totals).unwrap();
+
+        let general = &groups[&None];
+        assert_eq!(general.pools, vec!["general", "general-spot"]);
+        assert_eq!(general.max_capacity.cpu, 16.0);
+        assert_eq!(general.requests.cpu, 5.0);
+        assert_eq!(general.surge.cpu, 2.0); // 25% of 5 replicas rounded up
+        assert!(general.needs_scale_up());
+        assert!(!general.overcommitted());
+
+        // 4 cores requested + 1 surging on a single 4 core node
+        let batch = &groups[&Some("batch".to_string())];
+        assert_eq!(batch.requests.cpu, 4.0);
+        assert!(batch.overcommitted());
+    }
+
+    #[test]
+    fn capacity_needs_untainted_pool() {
+        let pools = vec![pool("batch", (1, 1), Some("batch"))];
+        let mf = service("web", 1, None);
+        let res = mf.compute_resource_totals().unwrap();
+        assert!(assign_demand(&pools, vec![(mf, res)]).is_err());
+    }
+}
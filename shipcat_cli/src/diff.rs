@@ -170,7 +170,7 @@ pub async fn template_vs_kubectl(mf: &Manifest) -> Result<Option<String>> {
 
 // Compare using diff(1)
 // difference libraries all seemed to be lacking somewhat
-fn shell_diff(before: &str, after: &str, before_name: &str, after_name: &str) -> Result<bool> {
+pub(crate) fn shell_diff(before: &str, after: &str, before_name: &str, after_name: &str) -> Result<bool> {
     let beforefilename = format!("{}.shipcat.gen.yml", before_name);
     let beforepth = Path::new(".").join(&beforefilename);
     debug!("Writing before to {}", beforepth.display());
@@ -1,7 +1,19 @@
 use super::{Config, Region};
-use std::collections::BTreeMap;
+use petgraph::{
+    graph::{DiGraph, NodeIndex},
+    visit::EdgeRef,
+    Direction,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    str::FromStr,
+};
 
-use super::{structs::security::DataHandling, Result};
+use super::{
+    populate,
+    structs::security::{format_period, parse_period, DataHandling, InformationClassification, KeyRotator},
+    Error, Result,
+};
 
 /// GdprOutput across manifests
 #[derive(Serialize)]
@@ -38,3 +50,675 @@ pub async fn show(svc: Option<String>, conf: &Config, region: &Region) -> Result
     println!("{}", out);
     Ok(())
 }
+
+/// What the data-flow report needs from a manifest
+#[derive(Clone, Debug, Default)]
+pub struct ServiceData {
+    pub data: Option<DataHandling>,
+    /// Names of the services this service depends on
+    pub dependencies: Vec<String>,
+    /// Kafka topics this service produces to
+    pub produces: Vec<String>,
+    /// Kafka topics this service consumes from
+    pub consumes: Vec<String>,
+}
+
+/// An edge in the data-flow graph
+#[derive(Clone, Debug)]
+pub enum FlowEdge {
+    /// A field processed from a `DataProcess.source`
+    Field(String),
+    /// A kafka topic from a producer to a consumer
+    Topic(String),
+    /// From a dependency back to its dependent, which may not carry any data
+    Dependency,
+}
+
+impl FlowEdge {
+    /// Whether the edge is declared to carry data
+    fn carries_data(&self) -> bool {
+        match self {
+            FlowEdge::Field(_) | FlowEdge::Topic(_) => true,
+            FlowEdge::Dependency => false,
+        }
+    }
+}
+
+/// Graph of services with the data flowing between them
+pub type FlowGraph = DiGraph<String, FlowEdge>;
+
+/// Services storing and processing a field
+#[derive(Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldUsage {
+    pub stored_by: BTreeSet<String>,
+    pub processed_by: BTreeSet<String>,
+}
+
+/// Classified data reaching a service, directly or transitively
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ClassifiedFlow {
+    pub origin: String,
+    pub classification: InformationClassification,
+    pub service: String,
+    /// Services the data passes through, from origin to service
+    pub path: Vec<String>,
+}
+
+/// A store holding classified data without encryption
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UnencryptedStore {
+    pub service: String,
+    pub backend: String,
+    pub classification: InformationClassification,
+    /// Unencrypted fields, empty when the store declares no fields
+    pub fields: Vec<String>,
+}
+
+/// A service declaring a lower classification than the data it receives
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Underclassified {
+    pub service: String,
+    pub highest_processed: Option<InformationClassification>,
+    pub receives: InformationClassification,
+}
+
+/// Data-flow report across a region
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DataFlowReport {
+    pub fields: BTreeMap<String, FieldUsage>,
+    /// Classified data reaching services through processed fields and kafka topics
+    pub classified_flows: Vec<ClassifiedFlow>,
+    /// Classified data that could only reach services through plain dependencies
+    pub advisory_flows: Vec<ClassifiedFlow>,
+    pub unencrypted_stores: Vec<UnencryptedStore>,
+    pub underclassified: Vec<Underclassified>,
+    #[serde(skip)]
+    pub graph: FlowGraph,
+    #[serde(skip)]
+    classifications: BTreeMap<String, InformationClassification>,
+    /// Service, field, backend, classification and encryption of every stored field
+    #[serde(skip)]
+    stored: Vec<(String, String, String, Option<InformationClassification>, bool)>,
+}
+
+fn highest(
+    a: Option<InformationClassification>,
+    b: Option<InformationClassification>,
+) -> Option<InformationClassification> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn highest_processed(dh: &DataHandling) -> Option<InformationClassification> {
+    dh.informationClassification
+        .as_ref()
+        .map(|ic| ic.highestProcessed.clone())
+}
+
+/// Shortest paths from `origin` to every service it reaches, optionally only along data edges
+fn reachable(graph: &FlowGraph, origin: NodeIndex, data_only: bool) -> Vec<(NodeIndex, Vec<String>)> {
+    let mut res = vec![];
+    let mut seen = BTreeSet::new();
+    seen.insert(origin);
+    let mut queue = VecDeque::new();
+    queue.push_back((origin, vec![graph[origin].clone()]));
+    while let Some((idx, path)) = queue.pop_front() {
+        for e in graph.edges_directed(idx, Direction::Outgoing) {
+            if (data_only && !e.weight().carries_data()) || !seen.insert(e.target()) {
+                continue;
+            }
+            let mut path = path.clone();
+            path.push(graph[e.target()].clone());
+            res.push((e.target(), path.clone()));
+            queue.push_back((e.target(), path));
+        }
+    }
+    res
+}
+
+/// Build the data-flow report from every service in a region
+///
+/// Stores without an `informationClassification` are assumed to hold data at the
+/// `highestProcessed` level of their service.
+/// Classifications only propagate along processed fields and kafka topics,
+/// flows through other dependencies are reported as advisory.
+pub fn analyse(services: &BTreeMap<String, ServiceData>) -> DataFlowReport {
+    let mut graph = FlowGraph::new();
+    let mut nodes: BTreeMap<String, NodeIndex> = BTreeMap::new();
+    let mut node = |graph: &mut FlowGraph, name: &str| {
+        *nodes
+            .entry(name.to_string())
+            .or_insert_with(|| graph.add_node(name.to_string()))
+    };
+
+    let mut fields: BTreeMap<String, FieldUsage> = BTreeMap::new();
+    let mut unencrypted_stores = vec![];
+    let mut stored = vec![];
+    let mut own: BTreeMap<NodeIndex, InformationClassification> = BTreeMap::new();
+    for (name, sd) in services {
+        let idx = node(&mut graph, name);
+        for dep in &sd.dependencies {
+            let depidx = node(&mut graph, dep);
+            graph.update_edge(depidx, idx, FlowEdge::Dependency);
+        }
+        let dh = match &sd.data {
+            Some(dh) => dh,
+            None => continue,
+        };
+        let mut level = None;
+        for s in &dh.stores {
+            let class = s
+                .informationClassification
+                .clone()
+                .or_else(|| highest_processed(dh));
+            for f in &s.fields {
+                fields
+                    .entry(f.name.clone())
+                    .or_default()
+                    .stored_by
+                    .insert(name.clone());
+                let encrypted = f.encrypted == Some(true);
+                stored.push((
+                    name.clone(),
+                    f.name.clone(),
+                    s.backend.clone(),
+                    class.clone(),
+                    encrypted,
+                ));
+            }
+            if let Some(c) = class.clone().filter(|c| c.rank() > 0) {
+                let open = s
+                    .fields
+                    .iter()
+                    .filter(|f| f.encrypted != Some(true))
+                    .map(|f| f.name.clone())
+                    .collect::<Vec<_>>();
+                if !open.is_empty() || (s.fields.is_empty() && s.encrypted != Some(true)) {
+                    unencrypted_stores.push(UnencryptedStore {
+                        service: name.clone(),
+                        backend: s.backend.clone(),
+                        classification: c,
+                        fields: open,
+                    });
+                }
+            }
+            level = highest(level, class);
+        }
+        if let Some(l) = level {
+            own.insert(idx, l);
+        }
+        for p in &dh.processes {
+            fields
+                .entry(p.field.clone())
+                .or_default()
+                .processed_by
+                .insert(name.clone());
+            let srcidx = node(&mut graph, &p.source);
+            graph.add_edge(srcidx, idx, FlowEdge::Field(p.field.clone()));
+        }
+    }
+    for (producer, sd) in services {
+        for topic in &sd.produces {
+            for (consumer, other) in services {
+                if consumer != producer && other.consumes.contains(topic) {
+                    graph.add_edge(nodes[producer], nodes[consumer], FlowEdge::Topic(topic.clone()));
+                }
+            }
+        }
+    }
+
+    // Propagate classifications along the flows until nothing changes
+    let mut effective = own.clone();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for e in graph.edge_references().filter(|e| e.weight().carries_data()) {
+            if let Some(c) = effective.get(&e.source()).cloned() {
+                let current = effective.get(&e.target()).map(|t| t.rank());
+                if current.filter(|r| *r >= c.rank()).is_none() {
+                    effective.insert(e.target(), c);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    let mut classified_flows = vec![];
+    let mut advisory_flows = vec![];
+    for (origin, class) in &own {
+        if class.rank() < InformationClassification::ConfidentialPatientData.rank() {
+            continue;
+        }
+        let flow = |(idx, path): (NodeIndex, Vec<String>)| ClassifiedFlow {
+            origin: graph[*origin].clone(),
+            classification: class.clone(),
+            service: graph[idx].clone(),
+            path,
+        };
+        let data = reachable(&graph, *origin, true);
+        let reached = data.iter().map(|(idx, _)| *idx).collect::<BTreeSet<_>>();
+        advisory_flows.extend(
+            reachable(&graph, *origin, false)
+                .into_iter()
+                .filter(|(idx, _)| !reached.contains(idx))
+                .map(flow),
+        );
+        classified_flows.extend(data.into_iter().map(flow));
+    }
+
+    let mut underclassified = vec![];
+    for (name, sd) in services {
+        let idx = nodes[name];
+        let receives = graph
+            .edges_directed(idx, Direction::Incoming)
+            .filter(|e| e.weight().carries_data())
+            .filter_map(|e| effective.get(&e.source()).cloned())
+            .fold(None, |acc, c| highest(acc, Some(c)));
+        let receives = match receives {
+            Some(r) if r.rank() > 0 => r,
+            _ => continue,
+        };
+        let declared = sd.data.as_ref().and_then(highest_processed);
+        if declared
+            .as_ref()
+            .filter(|d| d.rank() >= receives.rank())
+            .is_none()
+        {
+            underclassified.push(Underclassified {
+                service: name.clone(),
+                highest_processed: declared,
+                receives,
+            });
+        }
+    }
+
+    let classifications = effective
+        .into_iter()
+        .map(|(idx, c)| (graph[idx].clone(), c))
+        .collect();
+    DataFlowReport {
+        fields,
+        classified_flows,
+        advisory_flows,
+        unencrypted_stores,
+        underclassified,
+        graph,
+        classifications,
+        stored,
+    }
+}
+
+/// Output formats for a `DataFlowReport`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportFormat {
+    Yaml,
+    /// One row per store, process and finding
+    Csv,
+    /// Graphviz data-flow diagram
+    Dot,
+}
+
+impl FromStr for ReportFormat {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "yaml" => Ok(Self::Yaml),
+            "csv" => Ok(Self::Csv),
+            "dot" => Ok(Self::Dot),
+            _ => bail!("Report format must be yaml, csv or dot"),
+        }
+    }
+}
+
+fn csv_row(cols: &[&str]) -> String {
+    cols.iter()
+        .map(|c| {
+            if c.contains(',') || c.contains('"') {
+                format!("\"{}\"", c.replace('"', "\"\""))
+            } else {
+                c.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl DataFlowReport {
+    fn classification(&self, service: &str) -> String {
+        self.classifications
+            .get(service)
+            .map(|c| c.to_string())
+            .unwrap_or_default()
+    }
+
+    /// Render the report in a given output format
+    pub fn render(&self, format: ReportFormat) -> Result<String> {
+        let out = match format {
+            ReportFormat::Yaml => serde_yaml::to_string(self)?,
+            ReportFormat::Csv => {
+                let mut lines = vec![csv_row(&[
+                    "kind",
+                    "service",
+                    "field",
+                    "peer",
+                    "classification",
+                    "detail",
+                ])];
+                for (svc, field, backend, class, encrypted) in &self.stored {
+                    let class = class.as_ref().map(|c| c.to_string()).unwrap_or_default();
+                    let detail = if *encrypted { "encrypted" } else { "unencrypted" };
+                    lines.push(csv_row(&["store", svc, field, backend, &class, detail]));
+                }
+                for e in self.graph.edge_references() {
+                    if let FlowEdge::Field(field) = e.weight() {
+                        let (src, dst) = (&self.graph[e.source()], &self.graph[e.target()]);
+                        lines.push(csv_row(&[
+                            "process",
+                            dst,
+                            field,
+                            src,
+                            &self.classification(src),
+                            "",
+                        ]));
+                    }
+                }
+                for f in &self.classified_flows {
+                    let path = f.path.join(" > ");
+                    let class = f.classification.to_string();
+                    lines.push(csv_row(&["flow", &f.service, "", &f.origin, &class, &path]));
+                }
+                for f in &self.advisory_flows {
+                    let path = f.path.join(" > ");
+                    let class = f.classification.to_string();
+                    lines.push(csv_row(&["advisory", &f.service, "", &f.origin, &class, &path]));
+                }
+                for s in &self.unencrypted_stores {
+                    let class = s.classification.to_string();
+                    if s.fields.is_empty() {
+                        lines.push(csv_row(&["unencrypted", &s.service, "", &s.backend, &class, ""]));
+                    }
+                    for f in &s.fields {
+                        lines.push(csv_row(&["unencrypted", &s.service, f, &s.backend, &class, ""]));
+                    }
+                }
+                for u in &self.underclassified {
+                    let declared = u
+                        .highest_processed
+                        .as_ref()
+                        .map(|c| c.to_string())
+                        .unwrap_or_else(|| "none".into());
+                    let detail = format!("highestProcessed={}", declared);
+                    let class = u.receives.to_string();
+                    lines.push(csv_row(&["underclassified", &u.service, "", "", &class, &detail]));
+                }
+                lines.join("\n")
+            }
+            ReportFormat::Dot => {
+                let sensitive = InformationClassification::ConfidentialPatientData.rank();
+                let mut lines = vec!["digraph {".to_string()];
+                for n in self.graph.raw_nodes().iter().map(|n| &n.weight) {
+                    let (label, colour) = match self.classifications.get(n) {
+                        Some(c) if c.rank() >= sensitive => (format!("{}\\n{}", n, c), " color=red"),
+                        Some(c) => (format!("{}\\n{}", n, c), ""),
+                        None => (n.clone(), ""),
+                    };
+                    lines.push(format!("    \"{}\" [label=\"{}\"{}]", n, label, colour));
+                }
+                for e in self.graph.edge_references() {
+                    let attrs = match e.weight() {
+                        FlowEdge::Field(f) => format!("label=\"{}\"", f),
+                        FlowEdge::Topic(t) => format!("label=\"{}\" style=bold", t),
+                        FlowEdge::Dependency => "style=dashed".into(),
+                    };
+                    lines.push(format!(
+                        "    \"{}\" -> \"{}\" [{}]",
+                        self.graph[e.source()],
+                        self.graph[e.target()],
+                        attrs
+                    ));
+                }
+                lines.push("}".into());
+                lines.join("\n")
+            }
+        };
+        Ok(out)
+    }
+}
+
+/// Build the data-flow report for every service in a region
+pub async fn flows(conf: &Config, region: &Region) -> Result<DataFlowReport> {
+    let mfs = populate::region_manifests(conf, region).await?;
+    let mut services: BTreeMap<String, ServiceData> = BTreeMap::new();
+    for mf in &mfs {
+        let sd = services.entry(mf.name.clone()).or_default();
+        sd.data = mf.dataHandling.clone();
+        sd.dependencies = mf.dependencies.iter().map(|d| d.name.clone()).collect();
+        // eventStreams are declared once, by the owner of the topic
+        for es in &mf.eventStreams {
+            for p in &es.producers {
+                services
+                    .entry(p.clone())
+                    .or_default()
+                    .produces
+                    .push(es.name.clone());
+            }
+            for c in &es.consumers {
+                services
+                    .entry(c.clone())
+                    .or_default()
+                    .consumes
+                    .push(es.name.clone());
+            }
+        }
+    }
+    // streams may name services that are not enabled in the region
+    let enabled = mfs.iter().map(|mf| mf.name.as_str()).collect::<BTreeSet<_>>();
+    services.retain(|name, _| enabled.contains(name.as_str()));
+    Ok(analyse(&services))
+}
+
+/// Effective retention of a stored field
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionEntry {
+    pub service: String,
+    pub backend: String,
+    /// Field name, unset for stores without declared fields
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    pub classification: Option<InformationClassification>,
+    /// Normalised retention period, unset when kept indefinitely
+    pub retention_period: Option<String>,
+    /// Maximum retention of the classification in the region
+    pub max_retention: Option<String>,
+    pub key_rotator: Option<KeyRotator>,
+}
+
+/// Effective retention of the stores of a service
+///
+/// Store level values are cascaded to the fields before reporting.
+pub fn retention_entries(
+    service: &str,
+    dh: &DataHandling,
+    limits: &BTreeMap<InformationClassification, String>,
+) -> Result<Vec<RetentionEntry>> {
+    let mut entries = vec![];
+    for s in &dh.stores {
+        let classification = s.classification(dh);
+        let max_retention = match classification.as_ref().and_then(|c| limits.get(c)) {
+            Some(l) => Some(format_period(parse_period(l)?)),
+            None => None,
+        };
+        let mut entry =
+            |field: Option<String>, retention: Option<&String>, rotator: Option<&String>| -> Result<()> {
+                entries.push(RetentionEntry {
+                    service: service.to_string(),
+                    backend: s.backend.clone(),
+                    field,
+                    classification: classification.clone(),
+                    retention_period: retention.map(|r| parse_period(r)).transpose()?.map(format_period),
+                    max_retention: max_retention.clone(),
+                    key_rotator: rotator.map(|k| k.parse()).transpose()?,
+                });
+                Ok(())
+            };
+        if s.fields.is_empty() {
+            entry(None, s.retentionPeriod.as_ref(), s.keyRotator.as_ref())?;
+        }
+        for f in &s.fields {
+            entry(
+                Some(f.name.clone()),
+                f.retentionPeriod.as_ref(),
+                f.keyRotator.as_ref(),
+            )?;
+        }
+    }
+    Ok(entries)
+}
+
+/// Retention of every store in a region
+pub async fn retention(conf: &Config, region: &Region) -> Result<Vec<RetentionEntry>> {
+    let mut entries = vec![];
+    for s in shipcat_filebacked::available(conf, region).await? {
+        let mf = shipcat_filebacked::load_manifest(&s.base.name, conf, region).await?;
+        if let Some(dh) = &mf.dataHandling {
+            entries.extend(retention_entries(&mf.name, dh, &region.maxRetention)?);
+        }
+    }
+    Ok(entries)
+}
+
+/// Render retention entries as yaml or csv
+pub fn render_retention(entries: &[RetentionEntry], format: ReportFormat) -> Result<String> {
+    let out = match format {
+        ReportFormat::Yaml => serde_yaml::to_string(entries)?,
+        ReportFormat::Csv => {
+            let mut lines = vec![csv_row(&[
+                "service",
+                "backend",
+                "field",
+                "classification",
+                "retentionPeriod",
+                "maxRetention",
+                "keyRotator",
+            ])];
+            for e in entries {
+                let class = e
+                    .classification
+                    .as_ref()
+                    .map(|c| c.to_string())
+                    .unwrap_or_default();
+                let rotator = e.key_rotator.as_ref().map(|k| k.to_string()).unwrap_or_default();
+                lines.push(csv_row(&[
+                    &e.service,
+                    &e.backend,
+                    e.field.as_deref().unwrap_or_default(),
+                    &class,
+                    e.retention_period.as_deref().unwrap_or_default(),
+                    e.max_retention.as_deref().unwrap_or_default(),
+                    &rotator,
+                ]));
+            }
+            lines.join("\n")
+        }
+        ReportFormat::Dot => bail!("Retention can only be shown as yaml or csv"),
+    };
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{analyse, ReportFormat, ServiceData};
+    use std::collections::BTreeMap;
+
+    fn service(data: &str, deps: &[&str]) -> ServiceData {
+        let mut dh: shipcat_definitions::structs::security::DataHandling =
+            serde_yaml::from_str(data).unwrap();
+        dh.implicits();
+        ServiceData {
+            data: Some(dh),
+            dependencies: deps.iter().map(|d| d.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn gdpr_data_flows() {
+        let mut services = BTreeMap::new();
+        services.insert(
+            "records".to_string(),
+            service(
+                "
+informationClassification: { highestProcessed: confidentialPatientData }
+stores:
+- backend: MySQL
+  encrypted: true
+  fields: [{ name: Diagnosis }, { name: Notes, encrypted: false }]",
+                &[],
+            ),
+        );
+        services.insert(
+            "triage".to_string(),
+            service(
+                "
+informationClassification: { highestProcessed: protectedInternal }
+processes: [{ field: Diagnosis, source: records }]",
+                &[],
+            ),
+        );
+        services.get_mut("triage").unwrap().produces = vec!["diagnoses".into()];
+        services.insert("web".to_string(), ServiceData {
+            dependencies: vec!["triage".into()],
+            ..Default::default()
+        });
+        services.insert("alerts".to_string(), ServiceData {
+            consumes: vec!["diagnoses".into()],
+            ..Default::default()
+        });
+
+        let report = analyse(&services);
+        assert_eq!(report.fields["Diagnosis"].stored_by.len(), 1);
+        assert!(report.fields["Diagnosis"].processed_by.contains("triage"));
+
+        let flows = report
+            .classified_flows
+            .iter()
+            .map(|f| f.path.join(">"))
+            .collect::<Vec<_>>();
+        assert_eq!(flows, vec!["records>triage", "records>triage>alerts"]);
+        // web only depends on triage, which declares no data flowing to it
+        let advisory = report
+            .advisory_flows
+            .iter()
+            .map(|f| f.path.join(">"))
+            .collect::<Vec<_>>();
+        assert_eq!(advisory, vec!["records>triage>web"]);
+
+        assert_eq!(report.unencrypted_stores.len(), 1);
+        assert_eq!(report.unencrypted_stores[0].fields, vec!["Notes".to_string()]);
+
+        let under = report
+            .underclassified
+            .iter()
+            .map(|u| u.service.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(under, vec!["alerts", "triage"]);
+
+        let csv = report.render(ReportFormat::Csv).unwrap();
+        assert!(csv.contains("process,triage,Diagnosis,records,confidentialPatientData,"));
+        assert!(csv.contains("underclassified,alerts,,,confidentialPatientData,highestProcessed=none"));
+        assert!(csv.contains("advisory,web,,records,confidentialPatientData,records > triage > web"));
+        let dot = report.render(ReportFormat::Dot).unwrap();
+        assert!(dot.contains("\"records\" -> \"triage\" [label=\"Diagnosis\"]"));
+        assert!(dot.contains("\"triage\" -> \"web\" [style=dashed]"));
+        assert!(dot.contains("\"triage\" -> \"alerts\" [label=\"diagnoses\" style=bold]"));
+    }
+}
@@ -2,7 +2,7 @@ use super::{Config, Region, Result};
 use semver::Version;
 use shipcat_definitions::Environment;
 /// This file contains the `shipcat get` subcommand
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 // ----------------------------------------------------------------------------
 // Simple reducers
@@ -102,10 +102,7 @@ pub async fn vaultpolicy(conf: &Config, region: &Region, team_name: &str) -> Res
     } else {
         bail!("Squad '{}' does not exist in teams.yml", team_name)
     };
-    let output = region
-        .vault
-        .make_policy(mfs, &team, region.environment.clone())
-        .await?;
+    let output = crate::vaultpolicy::render(region, &mfs, &[team]).await?;
     println!("{}", output);
     Ok(output)
 }
@@ -254,7 +251,12 @@ pub async fn eventstreams(conf: &Config, reg: &Region) -> Result<()> {
     for svc in shipcat_filebacked::available(conf, reg).await? {
         let mf = shipcat_filebacked::load_manifest(&svc.base.name, &conf, &reg).await?;
         for k in mf.eventStreams {
-            eventstreams.insert(k.name.clone(), k);
+            if let Some(prev) = eventstreams.insert(k.name.clone(), k) {
+                warn!(
+                    "eventStream {} is declared more than once, see shipcat verify",
+                    prev.name
+                );
+            }
         }
     }
 
@@ -384,7 +386,12 @@ pub async fn kafkatopics(conf: &Config, reg: &Region) -> Result<()> {
                 replicas: topic.config.get("replicas").map(String::from).unwrap_or_default(),
                 config: topic.config,
             };
-            kafkaTopics.insert(topic.name, params);
+            if kafkaTopics.insert(topic.name.clone(), params).is_some() {
+                warn!(
+                    "kafka topic {} is declared more than once, see shipcat verify",
+                    topic.name
+                );
+            }
         }
         // get kafka topics from KafkaResources struct
         if let Some(kr) = mf.kafkaResources {
@@ -396,7 +403,12 @@ pub async fn kafkatopics(conf: &Config, reg: &Region) -> Result<()> {
                     replicas: topic.replicas.to_string(),
                     config: topic.config,
                 };
-                kafkaTopics.insert(topic.name, params);
+                if kafkaTopics.insert(topic.name.clone(), params).is_some() {
+                    warn!(
+                        "kafka topic {} is declared more than once, see shipcat verify",
+                        topic.name
+                    );
+                }
             }
         }
     }
@@ -405,3 +417,83 @@ pub async fn kafkatopics(conf: &Config, reg: &Region) -> Result<()> {
     println!("{}", serde_json::to_string_pretty(&output)?);
     Ok(())
 }
+
+// get rbac
+use shipcat_definitions::structs::{rbac::RbacRisk, Rbac};
+
+#[derive(Default, Serialize)]
+struct RbacReport {
+    region: String,
+    services: BTreeMap<String, ServiceRbac>,
+    /// resource -> verb -> services
+    permissions: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+}
+
+#[derive(Serialize)]
+struct ServiceRbac {
+    rules: Vec<Rbac>,
+    risks: Vec<RbacRisk>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    disallowed: Vec<RbacRisk>,
+}
+
+/// Resources a rule applies to in `resource.group/name` notation
+fn rbac_resources(rule: &Rbac) -> Vec<String> {
+    let mut res = vec![];
+    for g in &rule.apiGroups {
+        for r in &rule.resources {
+            let qualified = if g.is_empty() {
+                r.clone()
+            } else {
+                format!("{}.{}", r, g)
+            };
+            if rule.resourceNames.is_empty() {
+                res.push(qualified);
+            } else {
+                res.extend(rule.resourceNames.iter().map(|n| format!("{}/{}", qualified, n)));
+            }
+        }
+    }
+    res
+}
+
+pub async fn rbac(conf: &Config, reg: &Region) -> Result<()> {
+    let mut output = RbacReport {
+        region: reg.name.clone(),
+        ..RbacReport::default()
+    };
+    for svc in shipcat_filebacked::available(conf, reg).await? {
+        let mf = shipcat_filebacked::load_manifest(&svc.base.name, conf, reg).await?;
+        if mf.rbac.is_empty() {
+            continue;
+        }
+        for rule in &mf.rbac {
+            for resource in rbac_resources(rule) {
+                let verbs = output.permissions.entry(resource).or_default();
+                for v in &rule.verbs {
+                    let svcs = verbs.entry(v.clone()).or_default();
+                    if !svcs.contains(&mf.name) {
+                        svcs.push(mf.name.clone());
+                    }
+                }
+            }
+        }
+        let risks = mf
+            .rbac
+            .iter()
+            .flat_map(|r| r.risks(&mf.name))
+            .collect::<BTreeSet<_>>();
+        let disallowed = reg
+            .rbacPolicy
+            .as_ref()
+            .map(|rp| rp.disallowed(&mf.name, &mf.rbac))
+            .unwrap_or_default();
+        output.services.insert(mf.name.clone(), ServiceRbac {
+            rules: mf.rbac,
+            risks: risks.into_iter().collect(),
+            disallowed,
+        });
+    }
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
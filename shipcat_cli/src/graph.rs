@@ -1,24 +1,35 @@
 use petgraph::{
+    algo::tarjan_scc,
     dot,
     graph::{DiGraph, NodeIndex},
+    visit::EdgeRef,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Debug},
+    str::FromStr,
 };
-use std::fmt::{self, Debug};
 
 use super::{
+    networkpolicy::dependency_ports,
+    populate,
     structs::{Dependency, DependencyProtocol},
-    Config, Manifest, Region, Result,
+    Config, Error, Manifest, Region, Result,
 };
 
 /// The node type in `CatGraph` representing a `Manifest`
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ManifestNode {
     pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
     // pub image: String,
 }
 impl ManifestNode {
     fn new(mf: &Manifest) -> Self {
         ManifestNode {
             name: mf.name.clone(),
+            team: mf.metadata.as_ref().map(|md| md.team.clone()),
             /* image would be nice, but requires env override atm - should be global
              * image: format!("{}", mf.image.clone().unwrap()), */
         }
@@ -102,8 +113,199 @@ fn recurse_manifest(
     Ok(())
 }
 
+/// Output formats for a `CatGraph`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GraphFormat {
+    /// Serialized petgraph structure
+    Yaml,
+    /// Graphviz
+    Dot,
+    /// Mermaid flowchart
+    Mermaid,
+    /// Adjacency map from service to its dependencies
+    Json,
+    /// GraphML xml
+    GraphMl,
+}
+
+impl FromStr for GraphFormat {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "yaml" => Ok(Self::Yaml),
+            "dot" => Ok(Self::Dot),
+            "mermaid" => Ok(Self::Mermaid),
+            "json" => Ok(Self::Json),
+            "graphml" => Ok(Self::GraphMl),
+            _ => bail!("Graph format must be yaml, dot, mermaid, json or graphml"),
+        }
+    }
+}
+
+/// Entry in the json adjacency output
+#[derive(Serialize)]
+struct AdjacentDep {
+    name: String,
+    protocol: DependencyProtocol,
+    api: String,
+}
+
+/// Mermaid node ids cannot contain dashes
+fn mermaid_id(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Render a graph in a given output format
+pub fn render(graph: &CatGraph, format: GraphFormat) -> Result<String> {
+    let out = match format {
+        GraphFormat::Yaml => serde_yaml::to_string(graph)?,
+        GraphFormat::Dot => format!("{:?}", dot::Dot::with_config(graph, &[dot::Config::EdgeNoLabel])),
+        GraphFormat::Mermaid => {
+            let mut lines = vec!["graph LR".to_string()];
+            for n in graph.raw_nodes().iter().map(|n| &n.weight) {
+                lines.push(format!("    {}[\"{}\"]", mermaid_id(&n.name), n.name));
+            }
+            for e in graph.edge_references() {
+                lines.push(format!(
+                    "    {} -->|{}| {}",
+                    mermaid_id(&graph[e.source()].name),
+                    e.weight().protocol,
+                    mermaid_id(&graph[e.target()].name)
+                ));
+            }
+            lines.join("\n")
+        }
+        GraphFormat::Json => {
+            let mut adjacency: BTreeMap<String, Vec<AdjacentDep>> = BTreeMap::new();
+            for idx in graph.node_indices() {
+                let deps = graph
+                    .edges(idx)
+                    .map(|e| AdjacentDep {
+                        name: graph[e.target()].name.clone(),
+                        protocol: e.weight().protocol.clone(),
+                        api: e.weight().api.clone(),
+                    })
+                    .collect();
+                adjacency.insert(graph[idx].name.clone(), deps);
+            }
+            serde_json::to_string_pretty(&adjacency)?
+        }
+        GraphFormat::GraphMl => {
+            let mut lines = vec![
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string(),
+                r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#.to_string(),
+                r#"  <key id="team" for="node" attr.name="team" attr.type="string"/>"#.to_string(),
+                r#"  <key id="protocol" for="edge" attr.name="protocol" attr.type="string"/>"#.to_string(),
+                r#"  <key id="api" for="edge" attr.name="api" attr.type="string"/>"#.to_string(),
+                r#"  <graph id="dependencies" edgedefault="directed">"#.to_string(),
+            ];
+            for n in graph.raw_nodes().iter().map(|n| &n.weight) {
+                lines.push(format!(r#"    <node id="{}">"#, xml_escape(&n.name)));
+                if let Some(team) = &n.team {
+                    lines.push(format!(r#"      <data key="team">{}</data>"#, xml_escape(team)));
+                }
+                lines.push("    </node>".into());
+            }
+            for e in graph.edge_references() {
+                lines.push(format!(
+                    r#"    <edge source="{}" target="{}">"#,
+                    xml_escape(&graph[e.source()].name),
+                    xml_escape(&graph[e.target()].name)
+                ));
+                lines.push(format!(
+                    r#"      <data key="protocol">{}</data>"#,
+                    e.weight().protocol
+                ));
+                lines.push(format!(
+                    r#"      <data key="api">{}</data>"#,
+                    xml_escape(&e.weight().api)
+                ));
+                lines.push("    </edge>".into());
+            }
+            lines.push("  </graph>".into());
+            lines.push("</graphml>".into());
+            lines.join("\n")
+        }
+    };
+    Ok(out)
+}
+
+/// Restrictions on which parts of a `CatGraph` to show
+#[derive(Default, Clone, Debug)]
+pub struct GraphFilter {
+    /// Only show dependencies from or to services owned by this team
+    pub team: Option<String>,
+    /// Only show dependencies using this protocol
+    pub protocol: Option<DependencyProtocol>,
+}
+
+impl GraphFilter {
+    fn is_empty(&self) -> bool {
+        self.team.is_none() && self.protocol.is_none()
+    }
+
+    fn owned(&self, node: &ManifestNode) -> bool {
+        self.team.is_none() || node.team == self.team
+    }
+
+    /// Reduce a graph to the matching edges and the nodes they touch
+    ///
+    /// Services owned by a filtered team are kept even without matching edges.
+    pub fn apply(&self, graph: &CatGraph) -> CatGraph {
+        if self.is_empty() {
+            return graph.clone();
+        }
+        let mut nodes = BTreeSet::new();
+        let mut edges = BTreeSet::new();
+        for e in graph.edge_references() {
+            let (src, dst) = (e.source(), e.target());
+            let protocol_ok = self.protocol.as_ref().map_or(true, |p| p == &e.weight().protocol);
+            if protocol_ok && (self.owned(&graph[src]) || self.owned(&graph[dst])) {
+                edges.insert(e.id());
+                nodes.insert(src);
+                nodes.insert(dst);
+            }
+        }
+        if self.team.is_some() {
+            nodes.extend(graph.node_indices().filter(|i| self.owned(&graph[*i])));
+        }
+        graph.filter_map(
+            |idx, n| {
+                if nodes.contains(&idx) {
+                    Some(n.clone())
+                } else {
+                    None
+                }
+            },
+            |idx, e| {
+                if edges.contains(&idx) {
+                    Some(e.clone())
+                } else {
+                    None
+                }
+            },
+        )
+    }
+}
+
 /// Generate dependency graph from an entry point via recursion
-pub async fn generate(service: &str, conf: &Config, reg: &Region, dot: bool) -> Result<CatGraph> {
+pub async fn generate(
+    service: &str,
+    conf: &Config,
+    reg: &Region,
+    format: GraphFormat,
+    filter: &GraphFilter,
+) -> Result<CatGraph> {
     let base = shipcat_filebacked::load_manifest(service, conf, reg).await?;
 
     let mut graph: CatGraph = DiGraph::<_, _>::new();
@@ -112,12 +314,8 @@ pub async fn generate(service: &str, conf: &Config, reg: &Region, dot: bool) ->
 
     recurse_manifest(baseidx, &base, conf, reg, &mut graph)?;
 
-    let out = if dot {
-        format!("{:?}", dot::Dot::with_config(&graph, &[dot::Config::EdgeNoLabel]))
-    } else {
-        serde_yaml::to_string(&graph)?
-    };
-    println!("{}", out);
+    let graph = filter.apply(&graph);
+    println!("{}", render(&graph, format)?);
     Ok(graph)
 }
 
@@ -127,7 +325,12 @@ pub async fn generate(service: &str, conf: &Config, reg: &Region, dot: bool) ->
 /// one or more services as we could also show grahps reaching into the ecosystem.
 ///
 /// But it would require: TODO: optionally filter edges around node(s)
-pub async fn full(dot: bool, conf: &Config, reg: &Region) -> Result<CatGraph> {
+pub async fn full(
+    format: GraphFormat,
+    filter: &GraphFilter,
+    conf: &Config,
+    reg: &Region,
+) -> Result<CatGraph> {
     let mut graph: CatGraph = DiGraph::<_, _>::new();
     for svc in shipcat_filebacked::available(conf, reg).await? {
         debug!("Scanning service {:?}", svc);
@@ -150,25 +353,196 @@ pub async fn full(dot: bool, conf: &Config, reg: &Region) -> Result<CatGraph> {
         }
     }
 
-    let out = if dot {
-        format!("{:?}", dot::Dot::with_config(&graph, &[dot::Config::EdgeNoLabel]))
-    } else {
-        serde_yaml::to_string(&graph)?
-    };
-    println!("{}", out);
+    let graph = filter.apply(&graph);
+    println!("{}", render(&graph, format)?);
     Ok(graph)
 }
 
+/// Find the services among `mfs` depending on a service, along with their dependency
+pub fn callers(service: &str, mfs: &[Manifest]) -> Vec<(String, Dependency)> {
+    mfs.iter()
+        .filter_map(|mf| {
+            mf.dependencies
+                .iter()
+                .find(|d| d.name == service)
+                .map(|dep| (mf.name.clone(), dep.clone()))
+        })
+        .collect()
+}
+
+/// Find the services in a region depending on a service, along with their dependency
+pub async fn reverse_dependencies(
+    service: &str,
+    conf: &Config,
+    reg: &Region,
+) -> Result<Vec<(String, Dependency)>> {
+    let mfs = populate::region_manifests(conf, reg).await?;
+    Ok(callers(service, &mfs))
+}
+
 /// Generate first level reverse dependencies for a service
 pub async fn reverse(service: &str, conf: &Config, reg: &Region) -> Result<Vec<String>> {
-    let mut res = vec![];
-    for svc in shipcat_filebacked::available(conf, reg).await? {
-        let mf = shipcat_filebacked::load_manifest(&svc.base.name, conf, reg).await?;
-        if mf.dependencies.into_iter().any(|d| d.name == service) {
-            res.push(svc.base.name)
-        }
-    }
+    let res = reverse_dependencies(service, conf, reg)
+        .await?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>();
     let out = serde_yaml::to_string(&res)?;
     println!("{}", out);
     Ok(res)
 }
+
+/// Whether a service exposes a port usable by a dependency of a given protocol
+fn exposes_port(mf: &Manifest, protocol: &DependencyProtocol) -> bool {
+    match protocol {
+        DependencyProtocol::Http | DependencyProtocol::Grpc => !dependency_ports(mf, protocol).is_empty(),
+        _ => true,
+    }
+}
+
+/// Whether an eventStream of `owner` lists `member` as a producer or consumer
+fn streams_with(owner: &Manifest, member: &str) -> bool {
+    owner.eventStreams.iter().any(|es| {
+        es.producers
+            .iter()
+            .chain(es.consumers.iter())
+            .any(|s| s == member)
+    })
+}
+
+/// Find dependency problems across a set of manifests enabled in one region
+///
+/// Checks that dependencies are enabled in the region, that they expose a port
+/// for http and grpc dependencies, that kafka dependencies share an eventStream,
+/// and that there are no dependency cycles.
+pub fn check_dependencies(mfs: &[Manifest]) -> Vec<String> {
+    let by_name: BTreeMap<&str, &Manifest> = mfs.iter().map(|mf| (mf.name.as_str(), mf)).collect();
+    let mut errs = vec![];
+
+    let mut graph: CatGraph = DiGraph::<_, _>::new();
+    let indices: BTreeMap<&str, NodeIndex> = mfs
+        .iter()
+        .map(|mf| (mf.name.as_str(), graph.add_node(ManifestNode::new(mf))))
+        .collect();
+
+    for mf in mfs {
+        for dep in &mf.dependencies {
+            let depmf = match by_name.get(dep.name.as_str()) {
+                Some(depmf) => depmf,
+                None => {
+                    errs.push(format!(
+                        "{} depends on {} which is not enabled in this region",
+                        mf.name, dep.name
+                    ));
+                    continue;
+                }
+            };
+            graph.update_edge(
+                indices[mf.name.as_str()],
+                indices[dep.name.as_str()],
+                DepEdge::new(dep),
+            );
+            if !exposes_port(depmf, &dep.protocol) {
+                errs.push(format!(
+                    "{} has a {} dependency on {} which exposes no {} port",
+                    mf.name, dep.protocol, dep.name, dep.protocol
+                ));
+            }
+            if dep.protocol == DependencyProtocol::Kafka
+                && !streams_with(depmf, &mf.name)
+                && !streams_with(mf, &dep.name)
+            {
+                errs.push(format!(
+                    "{} has a kafka dependency on {} without an eventStream shared between them",
+                    mf.name, dep.name
+                ));
+            }
+        }
+    }
+
+    for scc in tarjan_scc(&graph) {
+        let cyclic = scc.len() > 1 || graph.find_edge(scc[0], scc[0]).is_some();
+        if cyclic {
+            let mut names = scc.iter().map(|i| graph[*i].name.clone()).collect::<Vec<_>>();
+            names.sort();
+            errs.push(format!("Dependency cycle between {}", names.join(", ")));
+        }
+    }
+    errs
+}
+
+/// Validate the dependency graph of all services in a region
+pub async fn verify(conf: &Config, reg: &Region) -> Result<()> {
+    let mfs = populate::region_manifests(conf, reg).await?;
+    let errs = check_dependencies(&mfs);
+    if !errs.is_empty() {
+        for e in &errs {
+            error!("{}", e);
+        }
+        bail!("Invalid dependency graph in {}: {} errors", reg.name, errs.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_dependencies;
+    use crate::{
+        structs::{Dependency, DependencyProtocol, EventStream, Port},
+        Manifest,
+    };
+
+    fn service(name: &str, deps: &[(&str, DependencyProtocol)]) -> Manifest {
+        let mut mf = Manifest::default();
+        mf.name = name.into();
+        mf.dependencies = deps
+            .iter()
+            .map(|(n, protocol)| Dependency {
+                name: (*n).into(),
+                protocol: protocol.clone(),
+                ..Default::default()
+            })
+            .collect();
+        mf
+    }
+
+    #[test]
+    fn graph_dependency_checks() {
+        let mut api = service("api", &[
+            ("store", DependencyProtocol::Http),
+            ("rpc", DependencyProtocol::Grpc),
+            ("events", DependencyProtocol::Kafka),
+            ("missing", DependencyProtocol::Http),
+        ]);
+        api.httpPort = Some(8000);
+        let store = service("store", &[]);
+        let mut rpc = service("rpc", &[]);
+        rpc.ports = vec![Port {
+            name: "grpc".into(),
+            port: 9000,
+            ..Default::default()
+        }];
+        let events = service("events", &[]);
+
+        let errs = check_dependencies(&[api.clone(), store.clone(), rpc.clone(), events.clone()]);
+        assert_eq!(errs, vec![
+            "api has a http dependency on store which exposes no http port",
+            "api has a kafka dependency on events without an eventStream shared between them",
+            "api depends on missing which is not enabled in this region",
+        ]);
+
+        // fix the ports and share an eventStream; then introduce a cycle
+        let mut store = store;
+        store.httpPort = Some(3000);
+        store.dependencies = service("store", &[("api", DependencyProtocol::Http)]).dependencies;
+        let mut events = events;
+        events.eventStreams = vec![EventStream {
+            name: "topic".into(),
+            producers: vec!["api".into()],
+            ..Default::default()
+        }];
+        api.dependencies.pop();
+        let errs = check_dependencies(&[api, store, rpc, events]);
+        assert_eq!(errs, vec!["Dependency cycle between api, store"]);
+    }
+}
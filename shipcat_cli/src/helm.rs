@@ -10,7 +10,7 @@ use tokio::{
 };
 
 use super::Result;
-use shipcat_definitions::{Manifest, ReconciliationMode, Region};
+use shipcat_definitions::{podsecurity, Manifest, ReconciliationMode, Region};
 
 pub fn hexists() -> Result<()> {
     if which::which("helm").is_err() {
@@ -164,13 +164,15 @@ pub fn template_check(mf: &Manifest, reg: &Region, skipped: &[String], tpl: &str
             .unwrap_or_else(|| format!("unset metadata.name from {}", kind));
 
         let tiller_ok = check_no_tiller_refs(&kind, &obj)?;
+        let security_ok = check_pod_security(mf, reg, &kind, name, to)?;
         let ok = match reg.reconciliationMode {
             ReconciliationMode::CrdOwned => {
                 let owner_ok = check_owner_refs(mf, &kind, &obj)?;
                 let labels_ok = check_labels(mf, &kind, skipped, &obj)?;
                 labels_ok && owner_ok
             }
-        } && tiller_ok;
+        } && tiller_ok
+            && security_ok;
         if !ok {
             invalids.push(format!("{} {{ {} }}", kind, name));
         }
@@ -281,6 +283,26 @@ fn check_owner_refs(mf: &Manifest, kind: &str, obj: &KubeObject) -> Result<bool>
     Ok(success)
 }
 
+/// Pod specs in the chart output must meet the region's pod security level
+///
+/// Exemptions declared in the manifest are not reported.
+fn check_pod_security(mf: &Manifest, reg: &Region, kind: &str, name: &str, tpl: &str) -> Result<bool> {
+    let level = match reg.podSecurity {
+        Some(l) => l,
+        None => return Ok(true),
+    };
+    let obj: serde_json::Value = serde_yaml::from_str(tpl)?;
+    let spec = match podsecurity::pod_spec(kind, &obj) {
+        Some(s) => s,
+        None => return Ok(true),
+    };
+    let violations = mf.pod_security_violations(level, podsecurity::evaluate(name, spec));
+    for v in &violations {
+        warn!("{}: violates {} pod security: {}", kind, level, v);
+    }
+    Ok(violations.is_empty())
+}
+
 // charts should not reference tiller
 fn check_no_tiller_refs(kind: &str, obj: &KubeObject) -> Result<bool> {
     let mut success = true;
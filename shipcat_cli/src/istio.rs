@@ -0,0 +1,205 @@
+use serde_json::{json, Value};
+use shipcat_definitions::region::IstioConfig;
+
+use super::{
+    structs::{Dependency, DependencyProtocol, IstioResources, TrafficPolicy},
+    Manifest, Region,
+};
+
+/// DestinationRule spec with the connection settings and version subsets of a service
+fn destination_rule(name: &str, tp: &TrafficPolicy) -> Value {
+    let mut spec = json!({ "host": name });
+    let mut policy = json!({});
+    if let Some(cp) = &tp.connectionPool {
+        policy["connectionPool"] = json!(cp);
+    }
+    if let Some(od) = &tp.outlierDetection {
+        policy["outlierDetection"] = json!(od);
+    }
+    if policy != json!({}) {
+        spec["trafficPolicy"] = policy;
+    }
+    if !tp.subsets.is_empty() {
+        spec["subsets"] = tp
+            .subsets
+            .iter()
+            .map(|s| json!({ "name": s.name, "labels": { "version": s.version } }))
+            .collect();
+    }
+    spec
+}
+
+/// Add the timeout and retries of a traffic policy to a http route
+fn with_policy(mut route: Value, tp: Option<&TrafficPolicy>) -> Value {
+    if let Some(tp) = tp {
+        if let Some(timeout) = &tp.timeout {
+            route["timeout"] = json!(timeout);
+        }
+        if let Some(retries) = &tp.retries {
+            route["retries"] = json!(retries);
+        }
+    }
+    route
+}
+
+/// VirtualService spec redirecting `destinationRules` identifiers and splitting traffic by subset
+fn virtual_service(mf: &Manifest, cfg: &IstioConfig) -> Value {
+    let tp = mf.trafficPolicy.as_ref();
+    let mut http = vec![];
+    for dr in mf.destinationRules.iter().flatten() {
+        let mut headers = serde_json::Map::new();
+        headers.insert(cfg.identifierHeader.clone(), json!({ "exact": dr.identifier }));
+        let route = json!({
+            "match": [{ "headers": headers }],
+            "route": [{ "destination": { "host": dr.host } }],
+        });
+        http.push(with_policy(route, tp));
+    }
+    let subsets = tp.map(|tp| tp.subsets.clone()).unwrap_or_default();
+    let destinations: Vec<Value> = if subsets.is_empty() {
+        vec![json!({ "destination": { "host": mf.name } })]
+    } else {
+        subsets
+            .iter()
+            .map(|s| json!({ "destination": { "host": mf.name, "subset": s.name }, "weight": s.weight }))
+            .collect()
+    };
+    http.push(with_policy(json!({ "route": destinations }), tp));
+    json!({ "hosts": [mf.name], "http": http })
+}
+
+/// AuthorizationPolicy spec allowing the service accounts of the services depending on a service
+///
+/// `callers` pairs the ServiceAccount of each dependent service with its dependency.
+/// Returns nothing when there is nobody to allow, as an empty ALLOW policy denies all traffic.
+fn authorization_policy(mf: &Manifest, callers: &[(String, Dependency)], cfg: &IstioConfig) -> Option<Value> {
+    let mut principals = callers
+        .iter()
+        .filter(|(_, dep)| {
+            dep.protocol == DependencyProtocol::Http || dep.protocol == DependencyProtocol::Grpc
+        })
+        .map(|(sa, _)| format!("{}/ns/{}/sa/{}", cfg.trustDomain, mf.namespace, sa))
+        .collect::<Vec<_>>();
+    principals.extend(cfg.extraPrincipals.iter().cloned());
+    principals.sort();
+    principals.dedup();
+    if principals.is_empty() {
+        return None;
+    }
+    Some(json!({
+        "selector": { "matchLabels": { "app": mf.name } },
+        "action": "ALLOW",
+        "rules": [{ "from": [{ "source": { "principals": principals } }] }],
+    }))
+}
+
+/// Build the istio objects of a service
+pub fn build(mf: &Manifest, callers: &[(String, Dependency)], cfg: &IstioConfig) -> IstioResources {
+    let mut res = IstioResources::default();
+    if let Some(tp) = &mf.trafficPolicy {
+        res.destinationRules
+            .insert(mf.name.clone(), destination_rule(&mf.name, tp));
+    }
+    if mf.trafficPolicy.is_some() || mf.destinationRules.iter().flatten().next().is_some() {
+        res.virtualServices
+            .insert(mf.name.clone(), virtual_service(mf, cfg));
+    }
+    if let Some(ap) = authorization_policy(mf, callers, cfg) {
+        res.authorizationPolicies.insert(mf.name.clone(), ap);
+    }
+    res
+}
+
+/// Inject the istio objects of a service for the helm chart
+///
+/// Does nothing in regions without `istio` configured.
+pub fn populate(mf: &mut Manifest, mfs: &[Manifest], reg: &Region) {
+    if let Some(cfg) = &reg.istio {
+        // principals are ServiceAccounts, which need not be named after their service
+        let callers = mfs
+            .iter()
+            .filter_map(|c| {
+                let dep = c.dependencies.iter().find(|d| d.name == mf.name)?;
+                Some((c.service_account().to_string(), dep.clone()))
+            })
+            .collect::<Vec<_>>();
+        mf.istio = Some(build(mf, &callers, cfg));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::build;
+    use crate::{
+        structs::{Dependency, DependencyProtocol, DestinationRule},
+        Manifest,
+    };
+    use serde_json::json;
+    use shipcat_definitions::region::IstioConfig;
+
+    #[test]
+    fn istio_resources_from_traffic_policy() {
+        let mut mf = Manifest::test("api");
+        mf.trafficPolicy = Some(
+            serde_yaml::from_str(
+                "
+timeout: 10s
+retries: { attempts: 3 }
+outlierDetection: { consecutiveErrors: 5 }
+subsets:
+- { name: stable, version: 1.2.3, weight: 90 }
+- { name: canary, version: 1.3.0, weight: 10 }",
+            )
+            .unwrap(),
+        );
+        mf.destinationRules = Some(vec![DestinationRule {
+            identifier: "USA".into(),
+            host: "api-us".into(),
+        }]);
+        let cfg = IstioConfig {
+            identifierHeader: "x-region".into(),
+            trustDomain: "cluster.local".into(),
+            extraPrincipals: vec![],
+        };
+        let callers = vec![
+            ("web".to_string(), Dependency {
+                name: "api".into(),
+                ..Default::default()
+            }),
+            ("consumer".to_string(), Dependency {
+                name: "api".into(),
+                protocol: DependencyProtocol::Kafka,
+                ..Default::default()
+            }),
+        ];
+        let res = build(&mf, &callers, &cfg);
+
+        assert_eq!(
+            res.destinationRules["api"],
+            json!({
+                "host": "api",
+                "trafficPolicy": { "outlierDetection": { "consecutiveErrors": 5 } },
+                "subsets": [
+                    { "name": "stable", "labels": { "version": "1.2.3" } },
+                    { "name": "canary", "labels": { "version": "1.3.0" } },
+                ],
+            })
+        );
+        let http = &res.virtualServices["api"]["http"];
+        assert_eq!(http[0]["match"][0]["headers"]["x-region"]["exact"], "USA");
+        assert_eq!(http[0]["route"][0]["destination"]["host"], "api-us");
+        assert_eq!(http[1]["route"][1]["destination"]["subset"], "canary");
+        assert_eq!(http[1]["route"][1]["weight"], 10);
+        assert_eq!(http[1]["timeout"], "10s");
+        assert_eq!(http[1]["retries"]["attempts"], 3);
+        assert_eq!(
+            res.authorizationPolicies["api"]["rules"][0]["from"][0]["source"]["principals"],
+            json!(["cluster.local/ns/apps/sa/web"])
+        );
+
+        // nothing to generate without a policy or callers
+        let res = build(&Manifest::test("api"), &[], &cfg);
+        assert!(res.virtualServices.is_empty());
+        assert!(res.authorizationPolicies.is_empty());
+    }
+}
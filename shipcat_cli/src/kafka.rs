@@ -0,0 +1,230 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{
+    structs::kafkaresources::{KafkaUserOperation, KafkaUserPatternType, KafkaUserResourceType},
+    Manifest,
+};
+
+/// One declaration of a topic, from an eventStream or from kafkaResources
+struct TopicDecl<'a> {
+    service: &'a str,
+    team: Option<&'a str>,
+    partitions: Option<i32>,
+    replicas: Option<i32>,
+    config: &'a BTreeMap<String, String>,
+}
+
+fn team(mf: &Manifest) -> Option<&str> {
+    mf.metadata.as_ref().map(|md| md.team.as_str())
+}
+
+fn config_int(config: &BTreeMap<String, String>, key: &str) -> Option<i32> {
+    config.get(key).and_then(|v| v.parse().ok())
+}
+
+/// Validate the kafka topics and users of all services in a region
+///
+/// Checks that every topic and user is declared once, that eventStream producers and
+/// consumers are services enabled in the region, that repeated topic settings agree,
+/// and that no kafka user is granted `All` or a prefix covering other teams' topics.
+/// `services` is every service name, enabled or not, to tell typos from disabled services.
+pub fn check_kafka(mfs: &[Manifest], services: &[String]) -> Vec<String> {
+    let enabled = mfs.iter().map(|mf| mf.name.as_str()).collect::<BTreeSet<_>>();
+    let mut errs = vec![];
+
+    let mut topics: BTreeMap<&str, Vec<TopicDecl<'_>>> = BTreeMap::new();
+    for mf in mfs {
+        for es in &mf.eventStreams {
+            topics.entry(&es.name).or_default().push(TopicDecl {
+                service: &mf.name,
+                team: team(mf),
+                partitions: config_int(&es.config, "partitions"),
+                replicas: config_int(&es.config, "replicas"),
+                config: &es.config,
+            });
+            for svc in es.producers.iter().chain(es.consumers.iter()) {
+                if enabled.contains(svc.as_str()) {
+                    continue;
+                }
+                let reason = if services.contains(svc) {
+                    "is not enabled in this region"
+                } else {
+                    "does not exist"
+                };
+                errs.push(format!(
+                    "{} eventStream {} references {} which {}",
+                    mf.name, es.name, svc, reason
+                ));
+            }
+        }
+        if let Some(kr) = &mf.kafkaResources {
+            for t in &kr.topics {
+                topics.entry(&t.name).or_default().push(TopicDecl {
+                    service: &mf.name,
+                    team: team(mf),
+                    partitions: Some(t.partitions),
+                    replicas: Some(t.replicas),
+                    config: &t.config,
+                });
+            }
+        }
+    }
+
+    for (name, decls) in &topics {
+        if decls.len() > 1 {
+            let owners = decls.iter().map(|d| d.service).collect::<Vec<_>>();
+            errs.push(format!("topic {} is declared by {}", name, owners.join(", ")));
+            let partitions = decls.iter().filter_map(|d| d.partitions).collect::<BTreeSet<_>>();
+            if partitions.len() > 1 {
+                errs.push(format!(
+                    "topic {} has conflicting partitions {:?}",
+                    name, partitions
+                ));
+            }
+            let replicas = decls.iter().filter_map(|d| d.replicas).collect::<BTreeSet<_>>();
+            if replicas.len() > 1 {
+                errs.push(format!("topic {} has conflicting replicas {:?}", name, replicas));
+            }
+        }
+        for d in decls {
+            if let (Some(replicas), Some(min)) = (d.replicas, config_int(d.config, "min.insync.replicas")) {
+                if min > replicas {
+                    errs.push(format!(
+                        "{} topic {} needs {} in-sync replicas but only has {}",
+                        d.service, name, min, replicas
+                    ));
+                }
+            }
+        }
+    }
+
+    let mut users: BTreeMap<&str, &str> = BTreeMap::new();
+    for mf in mfs {
+        let kr = match &mf.kafkaResources {
+            Some(kr) => kr,
+            None => continue,
+        };
+        for user in &kr.users {
+            if let Some(other) = users.insert(&user.name, &mf.name) {
+                errs.push(format!(
+                    "kafka user {} is declared by {}, {}",
+                    user.name, other, mf.name
+                ));
+            }
+            for acl in &user.acls {
+                if matches!(acl.operation, KafkaUserOperation::All) {
+                    errs.push(format!(
+                        "{} kafka user {} is granted All on {}",
+                        mf.name, user.name, acl.resource_name
+                    ));
+                }
+                let prefix = matches!(acl.pattern_type, Some(KafkaUserPatternType::Prefix));
+                let on_topics = matches!(acl.resource_type, Some(KafkaUserResourceType::Topic));
+                if !(prefix && on_topics) {
+                    continue;
+                }
+                let foreign = topics
+                    .iter()
+                    .filter(|(t, _)| t.starts_with(acl.resource_name.as_str()))
+                    .filter(|(_, decls)| decls.iter().any(|d| d.team != team(mf)))
+                    .map(|(t, _)| *t)
+                    .collect::<Vec<_>>();
+                if !foreign.is_empty() {
+                    errs.push(format!(
+                        "{} kafka user {} has prefix acl {} covering other teams' topics: {}",
+                        mf.name,
+                        user.name,
+                        acl.resource_name,
+                        foreign.join(", ")
+                    ));
+                }
+            }
+        }
+    }
+    errs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_kafka;
+    use crate::{
+        structs::{
+            kafkaresources::{AclDefinition, KafkaResources, KafkaTopics, KafkaUserOperation, KafkaUsers},
+            EventStream,
+        },
+        Manifest,
+    };
+
+    fn service(name: &str, team: &str) -> Manifest {
+        let mut mf = Manifest::default();
+        mf.name = name.into();
+        mf.metadata = Some(
+            serde_yaml::from_str(&format!("repo: https://github.com/x/{}\nteam: {}", name, team)).unwrap(),
+        );
+        mf
+    }
+
+    fn topic(name: &str, partitions: i32, replicas: i32) -> KafkaTopics {
+        KafkaTopics {
+            name: name.into(),
+            partitions,
+            replicas,
+            config: Default::default(),
+        }
+    }
+
+    #[test]
+    fn kafka_region_checks() {
+        let mut orders = service("orders", "shop");
+        orders.eventStreams = vec![EventStream {
+            name: "orders".into(),
+            producers: vec!["orders".into()],
+            consumers: vec!["billing".into(), "old-billing".into(), "typo".into()],
+            ..Default::default()
+        }];
+        let mut payments = service("payments", "money");
+        let mut user = KafkaUsers {
+            name: "payments".into(),
+            acls: vec![],
+        };
+        user.acls.push(
+            serde_yaml::from_str(
+                "resourceName: ord\nresourceType: topic\npatternType: prefix\noperation: Read",
+            )
+            .unwrap(),
+        );
+        user.acls.push(AclDefinition {
+            resource_name: "payments".into(),
+            resource_type: None,
+            pattern_type: None,
+            operation: KafkaUserOperation::All,
+            host: "*".into(),
+        });
+        let mut payments_topic = topic("payments", 3, 2);
+        payments_topic
+            .config
+            .insert("min.insync.replicas".into(), "3".into());
+        payments.kafkaResources = Some(KafkaResources {
+            topics: vec![topic("orders", 6, 3), payments_topic],
+            users: vec![user],
+            ..Default::default()
+        });
+        let billing = service("billing", "money");
+
+        let all = vec![
+            "orders".into(),
+            "payments".into(),
+            "billing".into(),
+            "old-billing".into(),
+        ];
+        let errs = check_kafka(&[orders, payments, billing], &all);
+        assert_eq!(errs, vec![
+            "orders eventStream orders references old-billing which is not enabled in this region",
+            "orders eventStream orders references typo which does not exist",
+            "topic orders is declared by orders, payments",
+            "payments topic payments needs 3 in-sync replicas but only has 2",
+            "payments kafka user payments has prefix acl ord covering other teams' topics: orders",
+            "payments kafka user payments is granted All on payments",
+        ]);
+    }
+}
@@ -5,6 +5,7 @@ use std::{
 
 use super::{
     structs::{
+        kongdeck::DeckConfig,
         kongfig::{kongfig_apis, kongfig_consumers, Api, Certificate, Consumer, Plugin, Upstream},
         Kong,
     },
@@ -44,6 +45,12 @@ impl KongfigOutput {
     }
 }
 
+/// Kong declarative config as consumed by decK
+pub fn deck_output(data: KongOutput, region: &Region) -> DeckConfig {
+    let apis = kongfig_apis(data.apis, data.kong.clone(), region);
+    DeckConfig::new(apis, kongfig_consumers(data.kong))
+}
+
 /// KongOutput in CRD form
 #[derive(Serialize)]
 struct KongCrdOutput {
@@ -97,17 +104,115 @@ pub async fn generate_kong_output(conf: &Config, region: &Region) -> Result<Kong
     }
 }
 
+/// Whether `prefix` matches `path` on whole segments, so `/api` covers `/api/v2` but not `/api-v2`
+fn segment_prefix(prefix: &str, path: &str) -> bool {
+    path.starts_with(prefix)
+        && (path.len() == prefix.len() || prefix.ends_with('/') || path[prefix.len()..].starts_with('/'))
+}
+
+/// Find apis in a region that kong cannot route between reliably
+///
+/// Apis overlap when they share a host, or when either leaves hosts unrestricted,
+/// as kong matches an api without hosts on every host.
+/// Overlapping apis must not claim identical uris or uris prefixing on whole path segments,
+/// and apis sharing a host must agree on being internal and publicly accessible.
+pub fn route_conflicts(apis: &BTreeMap<String, Kong>) -> Vec<String> {
+    let mut errs = vec![];
+    let list = apis.values().collect::<Vec<_>>();
+    for (i, a) in list.iter().enumerate() {
+        for b in &list[i + 1..] {
+            let shared = a
+                .hosts
+                .iter()
+                .filter(|h| b.hosts.contains(h))
+                .cloned()
+                .collect::<Vec<_>>();
+            let on = match (a.hosts.is_empty(), b.hosts.is_empty()) {
+                (true, true) => Some("any host".to_string()),
+                (true, false) => Some(b.hosts.join(", ")),
+                (false, true) => Some(a.hosts.join(", ")),
+                (false, false) if !shared.is_empty() => Some(shared.join(", ")),
+                _ => None,
+            };
+            if let Some(on) = on {
+                match (&a.uris, &b.uris) {
+                    (None, None) => errs.push(format!(
+                        "{} and {} both claim every path on {}",
+                        a.name, b.name, on
+                    )),
+                    (Some(x), Some(y)) if x == y => errs.push(format!(
+                        "{} and {} both claim uri {} on {}",
+                        a.name, b.name, x, on
+                    )),
+                    (Some(x), Some(y)) if segment_prefix(x, y) || segment_prefix(y, x) => {
+                        let (short, long) = if x.len() < y.len() { (a, b) } else { (b, a) };
+                        errs.push(format!(
+                            "{} uri {} shadows {} uri {} on {}",
+                            short.name,
+                            short.uris.as_ref().unwrap(),
+                            long.name,
+                            long.uris.as_ref().unwrap(),
+                            on
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+            if !shared.is_empty()
+                && (a.internal != b.internal || a.publiclyAccessible != b.publiclyAccessible)
+            {
+                errs.push(format!(
+                    "{} and {} share {} but disagree on internal or publiclyAccessible",
+                    a.name,
+                    b.name,
+                    shared.join(", ")
+                ));
+            }
+        }
+    }
+    errs
+}
+
+fn check_routes(data: &KongOutput, region: &Region) -> Result<()> {
+    let errs = route_conflicts(&data.apis);
+    if !errs.is_empty() {
+        for e in &errs {
+            error!("{}", e);
+        }
+        bail!(
+            "Conflicting kong routes in {}: {} errors",
+            region.name,
+            errs.len()
+        );
+    }
+    Ok(())
+}
+
+/// Generate kong apis for a region and check they do not conflict
+pub async fn checked_kong_output(conf: &Config, region: &Region) -> Result<KongOutput> {
+    let data = generate_kong_output(conf, region).await?;
+    check_routes(&data, region)?;
+    Ok(data)
+}
+
+/// Validate the kong routes of all services in a region
+pub async fn verify(conf: &Config, region: &Region) -> Result<()> {
+    checked_kong_output(conf, region).await.map(|_| ())
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum KongOutputMode {
     /// Kongfig CRD - TODO:
     Crd,
     /// Kongfig raw yaml
     Kongfig,
+    /// Kong declarative config (decK)
+    Deck,
 }
 
 /// Generate Kong config from a filled in global config
 pub async fn output(conf: &Config, region: &Region, mode: KongOutputMode) -> Result<()> {
-    let data = generate_kong_output(conf, &region).await?;
+    let data = checked_kong_output(conf, &region).await?;
     let output = match mode {
         KongOutputMode::Crd => {
             let res = KongCrdOutput::new(&region.name, data);
@@ -117,6 +222,10 @@ pub async fn output(conf: &Config, region: &Region, mode: KongOutputMode) -> Res
             let res = KongfigOutput::new(data, region);
             serde_yaml::to_string(&res)?
         }
+        KongOutputMode::Deck => {
+            let res = deck_output(data, region);
+            serde_yaml::to_string(&res)?
+        }
     };
     let _ = io::stdout().write(format!("{}\n", output).as_bytes());
     Ok(())
@@ -131,3 +240,46 @@ pub fn config_url(region: &Region) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::route_conflicts;
+    use crate::structs::Kong;
+    use std::collections::BTreeMap;
+
+    fn api(name: &str, uris: Option<&str>, hosts: &[&str]) -> Kong {
+        Kong {
+            name: name.into(),
+            uris: uris.map(String::from),
+            hosts: hosts.iter().map(|h| h.to_string()).collect(),
+            ..Kong::default()
+        }
+    }
+
+    #[test]
+    fn kong_route_conflicts() {
+        let mut apis = BTreeMap::new();
+        for a in vec![
+            api("ask", Some("/ai-auth"), &["ask.example.com"]),
+            api("ask-v2", Some("/ai-auth"), &["ask.example.com", "v2.example.com"]),
+            api("storage", Some("/storage"), &[]),
+            api("storage-admin", Some("/storage/admin"), &[]),
+            api("storage-v2", Some("/storage-v2"), &[]),
+            api("other", Some("/storage/admin"), &["other.example.com"]),
+            api("web", None, &["web.example.com"]),
+            api("web-legacy", None, &["web.example.com"]),
+        ] {
+            apis.insert(a.name.clone(), a);
+        }
+        apis.get_mut("web-legacy").unwrap().internal = true;
+
+        assert_eq!(route_conflicts(&apis), vec![
+            "ask and ask-v2 both claim uri /ai-auth on ask.example.com",
+            "storage uri /storage shadows other uri /storage/admin on other.example.com",
+            "other and storage-admin both claim uri /storage/admin on other.example.com",
+            "storage uri /storage shadows storage-admin uri /storage/admin on any host",
+            "web and web-legacy both claim every path on web.example.com",
+            "web and web-legacy share web.example.com but disagree on internal or publiclyAccessible",
+        ]);
+    }
+}
@@ -0,0 +1,648 @@
+use std::fmt;
+
+use reqwest::{Method, Url};
+use serde_json::{json, Value};
+
+use super::{
+    kong::{checked_kong_output, deck_output},
+    reconcile::{print_changes, print_or_apply, Auth, Body, JsonClient},
+    structs::kongdeck::{DeckConfig, SHIPCAT_TAG},
+    Config, Region, Result,
+};
+
+/// Kong entities shipcat manages, in the order they must be created
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum KongEntity {
+    Service,
+    Route,
+    Plugin,
+    Consumer,
+    Jwt,
+}
+
+impl KongEntity {
+    /// Admin API collection listing every object of this type
+    fn collection(self) -> &'static str {
+        match self {
+            KongEntity::Service => "services",
+            KongEntity::Route => "routes",
+            KongEntity::Plugin => "plugins",
+            KongEntity::Consumer => "consumers",
+            KongEntity::Jwt => "jwts",
+        }
+    }
+}
+
+impl fmt::Display for KongEntity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            KongEntity::Service => "service",
+            KongEntity::Route => "route",
+            KongEntity::Plugin => "plugin",
+            KongEntity::Consumer => "consumer",
+            KongEntity::Jwt => "jwt",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// A kong object identified by its natural key rather than its database id
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct KongObject {
+    pub entity: KongEntity,
+    /// Name for services and routes, username for consumers,
+    /// `service/plugin` for plugins and `username/key` for jwt secrets
+    pub key: String,
+    /// Service a route or plugin belongs to, or the consumer of a jwt secret
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    /// Admin API payload
+    pub body: Value,
+}
+
+/// An object as found in the admin API
+#[derive(Clone, Debug)]
+pub struct LiveObject {
+    pub id: String,
+    pub object: KongObject,
+    /// Whether the object carries the shipcat tag
+    pub owned: bool,
+}
+
+/// A change needed to bring kong in line with the generated config
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum KongChange {
+    Create {
+        object: KongObject,
+    },
+    Update {
+        id: String,
+        object: KongObject,
+        /// Top level fields that drifted
+        fields: Vec<String>,
+    },
+    Delete {
+        id: String,
+        object: KongObject,
+    },
+}
+
+impl fmt::Display for KongChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KongChange::Create { object } => write!(f, "+ {} {}", object.entity, object.key),
+            KongChange::Update { object, fields, .. } => {
+                write!(f, "~ {} {} ({})", object.entity, object.key, fields.join(", "))
+            }
+            KongChange::Delete { object, .. } => write!(f, "- {} {}", object.entity, object.key),
+        }
+    }
+}
+
+/// Empty lists are sent as null so that updates clear them
+fn list(xs: &[String]) -> Value {
+    if xs.is_empty() {
+        Value::Null
+    } else {
+        json!(xs)
+    }
+}
+
+/// Flatten the generated declarative config into admin API objects
+pub fn desired(cfg: &DeckConfig) -> Result<Vec<KongObject>> {
+    let mut objects = vec![];
+    for svc in &cfg.services {
+        // the admin api accepts `url` but only ever returns its parts
+        let url = Url::parse(&svc.url)?;
+        let path = if url.path() == "/" {
+            Value::Null
+        } else {
+            url.path().into()
+        };
+        objects.push(KongObject {
+            entity: KongEntity::Service,
+            key: svc.name.clone(),
+            parent: None,
+            body: json!({
+                "name": svc.name,
+                "protocol": url.scheme(),
+                "host": url.host_str(),
+                "port": url.port_or_known_default(),
+                "path": path,
+                "retries": svc.retries,
+                "connect_timeout": svc.connect_timeout,
+                "read_timeout": svc.read_timeout,
+                "write_timeout": svc.write_timeout,
+                "tags": svc.tags,
+            }),
+        });
+        for r in &svc.routes {
+            objects.push(KongObject {
+                entity: KongEntity::Route,
+                key: r.name.clone(),
+                parent: Some(svc.name.clone()),
+                body: json!({
+                    "name": r.name,
+                    "hosts": list(&r.hosts),
+                    "paths": list(&r.paths),
+                    "methods": list(&r.methods),
+                    "protocols": r.protocols,
+                    "strip_path": r.strip_path,
+                    "preserve_host": r.preserve_host,
+                    "tags": r.tags,
+                }),
+            });
+        }
+        for p in &svc.plugins {
+            objects.push(KongObject {
+                entity: KongEntity::Plugin,
+                key: format!("{}/{}", svc.name, p.name),
+                parent: Some(svc.name.clone()),
+                body: json!({
+                    "name": p.name,
+                    "enabled": p.enabled,
+                    "config": p.config,
+                    "tags": p.tags,
+                }),
+            });
+        }
+    }
+    for c in &cfg.consumers {
+        objects.push(KongObject {
+            entity: KongEntity::Consumer,
+            key: c.username.clone(),
+            parent: None,
+            body: json!({
+                "username": c.username,
+                "tags": c.tags,
+            }),
+        });
+        for j in &c.jwt_secrets {
+            objects.push(KongObject {
+                entity: KongEntity::Jwt,
+                key: format!("{}/{}", c.username, j.key),
+                parent: Some(c.username.clone()),
+                body: json!({
+                    "key": j.key,
+                    "algorithm": j.algorithm,
+                    "rsa_public_key": j.rsa_public_key,
+                    "tags": c.tags,
+                }),
+            });
+        }
+    }
+    objects.sort_by_key(|o| o.entity);
+    Ok(objects)
+}
+
+fn is_empty(v: &Value) -> bool {
+    match v {
+        Value::Null => true,
+        Value::Array(xs) => xs.is_empty(),
+        _ => false,
+    }
+}
+
+/// Whether everything we set in `desired` is reflected in `live`
+///
+/// Kong fills in defaults for anything left out, so only the fields
+/// we generate are compared, and null is equivalent to an empty list.
+fn subset(desired: &Value, live: &Value) -> bool {
+    match (desired, live) {
+        (d, l) if is_empty(d) || is_empty(l) => is_empty(d) && is_empty(l),
+        (Value::Object(d), Value::Object(l)) => {
+            d.iter().all(|(k, v)| subset(v, l.get(k).unwrap_or(&Value::Null)))
+        }
+        (Value::Array(d), Value::Array(l)) => {
+            d.len() == l.len() && d.iter().zip(l).all(|(d, l)| subset(d, l))
+        }
+        (Value::Number(d), Value::Number(l)) => d.as_f64() == l.as_f64(),
+        (d, l) => d == l,
+    }
+}
+
+/// Top level fields of `desired` that differ from `live`
+fn drifted(desired: &KongObject, live: &KongObject) -> Vec<String> {
+    let mut fields = vec![];
+    if let Value::Object(d) = &desired.body {
+        for (k, v) in d {
+            if !subset(v, live.body.get(k).unwrap_or(&Value::Null)) {
+                fields.push(k.clone());
+            }
+        }
+    }
+    if desired.parent != live.parent {
+        fields.push("service".into());
+    }
+    fields
+}
+
+/// Compute the changes needed to turn `live` into `desired`
+///
+/// Creates and updates come first in dependency order, followed by deletes in
+/// reverse order. Only objects tagged as owned by shipcat are ever deleted.
+pub fn diff(desired: &[KongObject], live: &[LiveObject]) -> Vec<KongChange> {
+    let mut changes = vec![];
+    for obj in desired {
+        match live
+            .iter()
+            .find(|l| l.object.entity == obj.entity && l.object.key == obj.key)
+        {
+            None => changes.push(KongChange::Create { object: obj.clone() }),
+            Some(l) => {
+                let fields = drifted(obj, &l.object);
+                if !fields.is_empty() {
+                    changes.push(KongChange::Update {
+                        id: l.id.clone(),
+                        object: obj.clone(),
+                        fields,
+                    });
+                }
+            }
+        }
+    }
+    let mut deletes = live
+        .iter()
+        .filter(|l| l.owned)
+        .filter(|l| {
+            !desired
+                .iter()
+                .any(|o| o.entity == l.object.entity && o.key == l.object.key)
+        })
+        .collect::<Vec<_>>();
+    deletes.sort_by_key(|l| std::cmp::Reverse(l.object.entity));
+    for l in deletes {
+        changes.push(KongChange::Delete {
+            id: l.id.clone(),
+            object: l.object.clone(),
+        });
+    }
+    changes
+}
+
+// ----------------------------------------------------------------------------------
+// admin api
+// ----------------------------------------------------------------------------------
+
+/// The admin API url from `KongConfig.config_url`, which may lack a scheme
+pub fn admin_url(region: &Region) -> Result<Url> {
+    let kong = match &region.kong {
+        Some(k) => k,
+        None => bail!("No kong specified in {} region", region.name),
+    };
+    let mut url = kong.config_url.clone();
+    if !url.contains("://") {
+        url = format!("https://{}", url);
+    }
+    if !url.ends_with('/') {
+        url.push('/');
+    }
+    Ok(Url::parse(&url)?)
+}
+
+#[derive(Deserialize)]
+struct Page {
+    data: Vec<Value>,
+    next: Option<String>,
+}
+
+/// Fetch every object in a paginated collection
+async fn list_all(admin: &JsonClient, collection: &str) -> Result<Vec<Value>> {
+    let mut res = vec![];
+    let mut next = Some(collection.to_string());
+    while let Some(path) = next {
+        let page: Page = serde_json::from_value(admin.send(Method::GET, &path, Body::Empty).await?)?;
+        res.extend(page.data);
+        // kong returns the next page relative to the admin root
+        next = page.next.map(|n| n.trim_start_matches('/').to_string());
+    }
+    Ok(res)
+}
+
+fn str_field(v: &Value, path: &[&str]) -> Option<String> {
+    let mut cur = v;
+    for p in path {
+        cur = cur.get(p)?;
+    }
+    cur.as_str().map(String::from)
+}
+
+fn live_object(entity: KongEntity, key: String, parent: Option<String>, body: Value) -> Result<LiveObject> {
+    let id = match str_field(&body, &["id"]) {
+        Some(id) => id,
+        None => bail!("{} {} from the kong admin api has no id", entity, key),
+    };
+    let owned = body["tags"]
+        .as_array()
+        .map(|ts| ts.iter().any(|t| t == SHIPCAT_TAG))
+        .unwrap_or(false);
+    Ok(LiveObject {
+        id,
+        owned,
+        object: KongObject {
+            entity,
+            key,
+            parent,
+            body,
+        },
+    })
+}
+
+/// Fetch the services, routes, plugins, consumers and jwt secrets kong knows about
+///
+/// Plugins scoped to routes or consumers are never generated by shipcat and are ignored.
+pub async fn fetch(admin: &JsonClient) -> Result<Vec<LiveObject>> {
+    let mut live = vec![];
+    let services = list_all(admin, KongEntity::Service.collection()).await?;
+    let consumers = list_all(admin, KongEntity::Consumer.collection()).await?;
+    let name_of = |xs: &[Value], field: &str, id: Option<String>| -> Option<String> {
+        let id = id?;
+        xs.iter()
+            .find(|x| x["id"] == id.as_str())
+            .and_then(|x| str_field(x, &[field]))
+            .or(Some(id))
+    };
+
+    for s in &services {
+        let key = str_field(s, &["name"])
+            .or_else(|| str_field(s, &["id"]))
+            .unwrap_or_default();
+        live.push(live_object(KongEntity::Service, key, None, s.clone())?);
+    }
+    for r in list_all(admin, KongEntity::Route.collection()).await? {
+        let key = str_field(&r, &["name"])
+            .or_else(|| str_field(&r, &["id"]))
+            .unwrap_or_default();
+        let parent = name_of(&services, "name", str_field(&r, &["service", "id"]));
+        live.push(live_object(KongEntity::Route, key, parent, r)?);
+    }
+    for p in list_all(admin, KongEntity::Plugin.collection()).await? {
+        if !p["route"].is_null() || !p["consumer"].is_null() {
+            continue;
+        }
+        let name = str_field(&p, &["name"]).unwrap_or_default();
+        let parent = name_of(&services, "name", str_field(&p, &["service", "id"]));
+        let key = match &parent {
+            Some(svc) => format!("{}/{}", svc, name),
+            None => name,
+        };
+        live.push(live_object(KongEntity::Plugin, key, parent, p)?);
+    }
+    for c in &consumers {
+        let key = str_field(c, &["username"])
+            .or_else(|| str_field(c, &["id"]))
+            .unwrap_or_default();
+        live.push(live_object(KongEntity::Consumer, key, None, c.clone())?);
+    }
+    for j in list_all(admin, KongEntity::Jwt.collection()).await? {
+        let parent = name_of(&consumers, "username", str_field(&j, &["consumer", "id"]));
+        let key = format!(
+            "{}/{}",
+            parent.clone().unwrap_or_default(),
+            str_field(&j, &["key"]).unwrap_or_default()
+        );
+        live.push(live_object(KongEntity::Jwt, key, parent, j)?);
+    }
+    Ok(live)
+}
+
+/// Admin API path for creating an object, nested under its parent where needed
+fn create_path(obj: &KongObject) -> String {
+    match (obj.entity, &obj.parent) {
+        (KongEntity::Route, Some(p)) => format!("services/{}/routes", p),
+        (KongEntity::Plugin, Some(p)) => format!("services/{}/plugins", p),
+        (KongEntity::Jwt, Some(p)) => format!("consumers/{}/jwt", p),
+        (e, _) => e.collection().to_string(),
+    }
+}
+
+/// Admin API path for an existing object
+fn object_path(obj: &KongObject, id: &str) -> String {
+    match (obj.entity, &obj.parent) {
+        (KongEntity::Jwt, Some(p)) => format!("consumers/{}/jwt/{}", p, id),
+        (e, _) => format!("{}/{}", e.collection(), id),
+    }
+}
+
+/// Apply changes to the admin API in order
+pub async fn apply(admin: &JsonClient, changes: &[KongChange]) -> Result<()> {
+    for c in changes {
+        info!("{}", c);
+        match c {
+            KongChange::Create { object } => {
+                let body = Body::Json(object.body.clone());
+                admin.send(Method::POST, &create_path(object), body).await?;
+            }
+            KongChange::Update { id, object, fields } => {
+                let mut body = object.body.clone();
+                if let (true, Some(svc)) = (fields.iter().any(|f| f == "service"), &object.parent) {
+                    // routes moved between services need the new service id
+                    let path = format!("services/{}", svc);
+                    let live = admin.send(Method::GET, &path, Body::Empty).await?;
+                    body["service"] = json!({ "id": live["id"] });
+                }
+                admin
+                    .send(Method::PATCH, &object_path(object, id), Body::Json(body))
+                    .await?;
+            }
+            KongChange::Delete { id, object } => {
+                admin
+                    .send(Method::DELETE, &object_path(object, id), Body::Empty)
+                    .await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Changes needed to bring the region's kong in line with its manifests
+pub async fn changes(conf: &Config, region: &Region, admin: &JsonClient) -> Result<Vec<KongChange>> {
+    let data = checked_kong_output(conf, region).await?;
+    let wanted = desired(&deck_output(data, region))?;
+    let live = fetch(admin).await?;
+    Ok(diff(&wanted, &live))
+}
+
+/// Print the drift between the generated config and the admin API
+pub async fn diff_region(conf: &Config, region: &Region) -> Result<()> {
+    let admin = JsonClient::new(admin_url(region)?, Auth::None);
+    let changes = changes(conf, region, &admin).await?;
+    print_changes(&format!("kong in {}", region.name), &changes);
+    Ok(())
+}
+
+/// Reconcile the admin API with the generated config
+pub async fn sync_region(conf: &Config, region: &Region, dry_run: bool) -> Result<()> {
+    let admin = JsonClient::new(admin_url(region)?, Auth::None);
+    let changes = changes(conf, region, &admin).await?;
+    let what = format!("kong in {}", region.name);
+    print_or_apply(&what, &changes, dry_run, apply(&admin, &changes)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, desired, diff, fetch, live_object, KongChange, KongEntity, LiveObject};
+    use crate::{
+        reconcile::{Auth, JsonClient},
+        structs::kongdeck::{DeckConfig, DeckConsumer, DeckPlugin, DeckRoute, DeckService, SHIPCAT_TAG},
+        Result,
+    };
+    use reqwest::Url;
+    use serde_json::json;
+
+    fn config() -> DeckConfig {
+        let tags = vec![SHIPCAT_TAG.to_string()];
+        DeckConfig {
+            _format_version: "1.1".into(),
+            services: vec![DeckService {
+                name: "fake-ask".into(),
+                url: "http://fake-ask.dev.svc.cluster.local".into(),
+                retries: 0,
+                connect_timeout: 30000,
+                read_timeout: 30000,
+                write_timeout: 30000,
+                tags: tags.clone(),
+                routes: vec![DeckRoute {
+                    name: "fake-ask".into(),
+                    hosts: vec![],
+                    paths: vec!["/ai-auth".into()],
+                    methods: vec![],
+                    protocols: vec!["http".into(), "https".into()],
+                    strip_path: false,
+                    preserve_host: true,
+                    tags: tags.clone(),
+                }],
+                plugins: vec![DeckPlugin {
+                    name: "correlation-id".into(),
+                    enabled: true,
+                    config: json!({ "header_name": "babylon-request-id" }),
+                    tags: tags.clone(),
+                }],
+            }],
+            consumers: vec![DeckConsumer {
+                username: "anonymous".into(),
+                jwt_secrets: vec![],
+                tags,
+            }],
+        }
+    }
+
+    fn live(entity: KongEntity, key: &str, parent: Option<&str>, body: serde_json::Value) -> LiveObject {
+        live_object(entity, key.into(), parent.map(String::from), body).unwrap()
+    }
+
+    #[test]
+    fn kong_diff_ownership_and_drift() -> Result<()> {
+        let wanted = desired(&config())?;
+        let state = vec![
+            // in sync, with server side defaults and nulls
+            live(
+                KongEntity::Service,
+                "fake-ask",
+                None,
+                json!({
+                    "id": "s1", "name": "fake-ask", "protocol": "http", "host": "fake-ask.dev.svc.cluster.local",
+                    "port": 80, "path": null, "retries": 0, "connect_timeout": 30000, "read_timeout": 30000,
+                    "write_timeout": 30000, "tags": [SHIPCAT_TAG],
+                }),
+            ),
+            // drifted route
+            live(
+                KongEntity::Route,
+                "fake-ask",
+                Some("fake-ask"),
+                json!({
+                    "id": "r1", "name": "fake-ask", "hosts": null, "paths": ["/ai-auth"], "methods": null,
+                    "protocols": ["http", "https"], "strip_path": true, "preserve_host": true,
+                    "regex_priority": 0, "tags": [SHIPCAT_TAG],
+                }),
+            ),
+            // owned and no longer generated
+            live(
+                KongEntity::Service,
+                "old",
+                None,
+                json!({ "id": "s2", "name": "old", "tags": [SHIPCAT_TAG] }),
+            ),
+            // not owned by shipcat
+            live(
+                KongEntity::Service,
+                "manual",
+                None,
+                json!({ "id": "s3", "name": "manual", "tags": null }),
+            ),
+            live(
+                KongEntity::Plugin,
+                "prometheus",
+                None,
+                json!({ "id": "p1", "name": "prometheus" }),
+            ),
+        ];
+        let changes = diff(&wanted, &state);
+        let summary = changes.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            "~ route fake-ask (strip_path)",
+            "+ plugin fake-ask/correlation-id",
+            "+ consumer anonymous",
+            "- service old",
+        ]);
+        match &changes[0] {
+            KongChange::Update { id, .. } => assert_eq!(id, "r1"),
+            c => panic!("unexpected change {}", c),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn kong_sync_against_mock_admin() -> Result<()> {
+        let page = |data: serde_json::Value| json!({ "data": data, "next": null }).to_string();
+        let services = mockito::mock("GET", "/services")
+            .with_body(page(json!([
+                { "id": "s2", "name": "old", "tags": [SHIPCAT_TAG] },
+                { "id": "s3", "name": "manual" },
+            ])))
+            .create();
+        let empty = ["/routes", "/plugins", "/consumers", "/jwts"]
+            .iter()
+            .map(|p| mockito::mock("GET", *p).with_body(page(json!([]))).create())
+            .collect::<Vec<_>>();
+        let create_service = mockito::mock("POST", "/services")
+            .match_body(mockito::Matcher::PartialJson(json!({ "name": "fake-ask" })))
+            .with_status(201)
+            .with_body("{}")
+            .create();
+        let create_route = mockito::mock("POST", "/services/fake-ask/routes")
+            .with_status(201)
+            .with_body("{}")
+            .create();
+        let create_plugin = mockito::mock("POST", "/services/fake-ask/plugins")
+            .with_status(201)
+            .with_body("{}")
+            .create();
+        let create_consumer = mockito::mock("POST", "/consumers")
+            .with_status(201)
+            .with_body("{}")
+            .create();
+        let delete_old = mockito::mock("DELETE", "/services/s2").with_status(204).create();
+        let delete_manual = mockito::mock("DELETE", "/services/s3").expect(0).create();
+
+        let admin = JsonClient::new(Url::parse(&format!("{}/", mockito::server_url()))?, Auth::None);
+        let changes = diff(&desired(&config())?, &fetch(&admin).await?);
+        assert_eq!(changes.len(), 5);
+        apply(&admin, &changes).await?;
+
+        services.assert();
+        for m in empty {
+            m.assert();
+        }
+        create_service.assert();
+        create_route.assert();
+        create_plugin.assert();
+        create_consumer.assert();
+        delete_old.assert();
+        delete_manual.assert();
+        Ok(())
+    }
+}
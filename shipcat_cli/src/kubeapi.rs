@@ -15,7 +15,7 @@ use shipcat_definitions::{
 /// Client creator
 ///
 /// TODO: embed inside shipcat::apply when needed for other things
-async fn make_client() -> Result<APIClient> {
+pub(crate) async fn make_client() -> Result<APIClient> {
     let config = if let Ok(cfg) = kube::config::incluster_config() {
         cfg
     } else {
@@ -127,12 +127,45 @@ pub mod helm;
 /// A small CLI kong config generator interface
 pub mod kong;
 
+/// JSON api client and dry-run printing shared by the api syncs
+pub mod reconcile;
+
+/// Kong admin API diffing and syncing
+pub mod kongsync;
+
 /// A small CLI Statuscake config generator interface
 pub mod statuscake;
 
+/// New Relic alert policy diffing and syncing
+pub mod newrelic;
+
+/// Sentry project and slack alert rule syncing
+pub mod sentry;
+
 /// A graph generator for manifests using `petgraph`
 pub mod graph;
 
+/// NetworkPolicies derived from the dependency graph
+pub mod networkpolicy;
+
+/// Region-wide kafka topic and ACL validation
+pub mod kafka;
+
+/// Strimzi kafka topics and users derived from eventStreams and kafkaResources
+pub mod strimzi;
+
+/// PrometheusRule generation from prometheusAlerts
+pub mod prometheusrule;
+
+/// Docker registry image checks, digests and sizes
+pub mod registry;
+
+/// Istio traffic objects derived from traffic policies and the dependency graph
+pub mod istio;
+
+/// Region wide resources injected into manifests before templating
+pub mod populate;
+
 /// Various simple reducers
 pub mod get;
 
@@ -140,9 +173,18 @@ pub mod get;
 pub mod top;
 pub use top::{OutputFormat, ResourceOrder};
 
+/// Actual resource usage from the metrics api or prometheus
+pub mod usage;
+
 /// Diffing module for values
 pub mod diff;
 
+/// Vault policy generation and reconciliation for squads
+pub mod vaultpolicy;
+
+/// Vault secret audits
+pub mod secret;
+
 /// Git stuff
 pub mod git;
 
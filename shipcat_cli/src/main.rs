@@ -106,6 +106,9 @@ fn build_cli() -> App<'static, 'static> {
               .about("Validate the shipcat manifest"))
 
         .subcommand(SubCommand::with_name("verify")
+            .arg(Arg::with_name("strict-dependencies")
+                .long("strict-dependencies")
+                .help("Fail on dependency graph problems rather than warning"))
             .about("Verify all manifests of a region"))
 
         .subcommand(SubCommand::with_name("secret")
@@ -126,11 +129,77 @@ fn build_cli() -> App<'static, 'static> {
                     .multiple(true)
                     .help("Regions to validate all enabled services for"))
                 .about("Verify existence of secrets for entire regions"))
+            .subcommand(SubCommand::with_name("audit")
+                // not named region, which is taken by the global -r
+                .arg(Arg::with_name("audit-region")
+                    .value_name("region")
+                    .required(true)
+                    .help("Region to audit secrets in"))
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .takes_value(true)
+                    .default_value("yaml")
+                    .possible_values(&["yaml", "markdown"])
+                    .help("Output format of the audit"))
+                .about("Find missing, unused and shared secrets in a region"))
+            .subcommand(SubCommand::with_name("diff")
+                .arg(Arg::with_name("service")
+                    .required(true)
+                    .help("Service whose secrets to compare"))
+                .arg(Arg::with_name("from")
+                    .long("from")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Region to compare from"))
+                .arg(Arg::with_name("to")
+                    .long("to")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Region to compare against"))
+                .about("Show secrets of a service missing or differing between regions"))
+            .subcommand(SubCommand::with_name("copy")
+                .arg(Arg::with_name("service")
+                    .required(true)
+                    .help("Service whose secrets to copy"))
+                .arg(Arg::with_name("from")
+                    .long("from")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Region to copy from"))
+                .arg(Arg::with_name("to")
+                    .long("to")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Region to copy to"))
+                .arg(Arg::with_name("keys")
+                    .long("keys")
+                    .takes_value(true)
+                    .help("Keys to copy (comma separated) - defaults to all missing keys"))
+                .arg(Arg::with_name("overwrite")
+                    .long("overwrite")
+                    .help("Overwrite selected keys with a different value in the target region"))
+                .about("Copy secrets of a service between regions"))
             .about("Secret interaction"))
 
         .subcommand(SubCommand::with_name("gdpr")
               .arg(Arg::with_name("service")
+                .conflicts_with("flows")
                 .help("Service names to show"))
+              .arg(Arg::with_name("flows")
+                .long("flows")
+                .help("Report data flows across the dependency graph"))
+              .arg(Arg::with_name("retention")
+                .long("retention")
+                .conflicts_with_all(&["service", "flows"])
+                .help("List the effective retention and key rotation of every store"))
+              .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .default_value("yaml")
+                .possible_values(&["yaml", "csv", "dot"])
+                .help("Output format of the data-flow or retention report"))
               .about("Reduce data handling structs"))
 
         .subcommand(SubCommand::with_name("get")
@@ -150,6 +219,8 @@ fn build_cli() -> App<'static, 'static> {
                 .help("Reduce kafkaUser info"))
               .subcommand(SubCommand::with_name("kafkatopics")
                 .help("Reduce KafkaTopic info"))
+              .subcommand(SubCommand::with_name("rbac")
+                .help("Reduce rbac rules and their privilege risks"))
               .subcommand(SubCommand::with_name("codeowners")
                 .help("Generate CODEOWNERS syntax for manifests based on team ownership"))
               .subcommand(SubCommand::with_name("vault-policy")
@@ -169,28 +240,100 @@ fn build_cli() -> App<'static, 'static> {
             .arg(Arg::with_name("crd")
                 .long("crd")
                 .help("Produce an experimental custom resource values for this kubernetes region"))
+            .arg(Arg::with_name("deck")
+                .long("deck")
+                .conflicts_with("crd")
+                .help("Produce Kong declarative config for decK"))
             .subcommand(SubCommand::with_name("config-url")
-                .help("Generate Kong config URL")))
+                .help("Generate Kong config URL"))
+            .subcommand(SubCommand::with_name("diff")
+                .about("Show drift between generated config and the kong admin api"))
+            .subcommand(SubCommand::with_name("sync")
+                .about("Apply generated config to the kong admin api")
+                .arg(Arg::with_name("dry-run")
+                    .long("dry-run")
+                    .help("Only print the changes that would be made"))))
         // Statuscake helper
         .subcommand(SubCommand::with_name("statuscake")
-            .about("Generate Statuscake config"))
+            .about("Generate Statuscake config")
+            .subcommand(SubCommand::with_name("sync")
+                .about("Reconcile StatusCake uptime and ssl checks through its API")
+                .arg(Arg::with_name("dry-run")
+                    .long("dry-run")
+                    .help("Only print the changes that would be made"))))
+        // Sentry projects and alert routing
+        .subcommand(SubCommand::with_name("sentry")
+            .about("Reconcile Sentry projects and slack alert rules with the region")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("sync")
+                .about("Create projects and alert rules through the Sentry API")
+                .arg(Arg::with_name("dry-run")
+                    .long("dry-run")
+                    .help("Only print the changes that would be made"))))
+        // New Relic alert policies
+        .subcommand(SubCommand::with_name("newrelic")
+            .about("Reconcile New Relic alert policies with the region")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("diff")
+                .about("Show changes needed to bring New Relic in line with the region"))
+            .subcommand(SubCommand::with_name("sync")
+                .about("Apply alert policies and conditions through the New Relic API")
+                .arg(Arg::with_name("dry-run")
+                    .long("dry-run")
+                    .help("Only print the changes that would be made"))))
         // dependency graphing
         .subcommand(SubCommand::with_name("graph")
               .arg(Arg::with_name("service")
                 .help("Service name to graph around"))
               .arg(Arg::with_name("dot")
                 .long("dot")
+                .conflicts_with("output")
                 .help("Generate dot output for graphviz"))
+              .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .default_value("yaml")
+                .possible_values(&["yaml", "dot", "mermaid", "json", "graphml"])
+                .help("Output format of the graph"))
+              .arg(Arg::with_name("team")
+                .long("team")
+                .takes_value(true)
+                .help("Only show dependencies from or to services owned by a team"))
+              .arg(Arg::with_name("protocol")
+                .long("protocol")
+                .takes_value(true)
+                .possible_values(&["http", "grpc", "kafka", "amqp", "sqs"])
+                .help("Only show dependencies using a protocol"))
               .arg(Arg::with_name("reverse")
                 .long("reverse")
                 .help("Generate reverse dependencies for a service"))
+              .arg(Arg::with_name("check")
+                .long("check")
+                .conflicts_with_all(&["service", "reverse"])
+                .help("Validate the dependency graph of the region"))
               .about("Graph the dependencies of a service"))
+        // network policies
+        .subcommand(SubCommand::with_name("networkpolicy")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Inspect NetworkPolicies derived from dependencies")
+            .subcommand(SubCommand::with_name("audit")
+                .about("List observed traffic sources that no dependency declares")
+                .arg(Arg::with_name("service")
+                    .help("Only audit traffic to this service"))
+                .arg(Arg::with_name("prometheus")
+                    .long("prometheus")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Prometheus url with istio metrics"))))
         // cluster admin operations
         .subcommand(SubCommand::with_name("cluster")
             .setting(AppSettings::SubcommandRequiredElseHelp)
             .about("Perform cluster level recovery / reconcilation commands")
             .subcommand(SubCommand::with_name("diff")
                 .about("Diff all services against the a region"))
+            .subcommand(SubCommand::with_name("capacity")
+                .about("Check that the requests of a region fit within its cluster's node pools"))
             .subcommand(SubCommand::with_name("check")
                 .arg(Arg::with_name("skip-kinds")
                     .long("skip-kinds")
@@ -214,6 +357,12 @@ fn build_cli() -> App<'static, 'static> {
                     .takes_value(true)
                     .help("Number of worker threads used"))
                 .subcommand(SubCommand::with_name("reconcile")
+                    .arg(Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Show the policy and github team changes without writing them"))
+                    .arg(Arg::with_name("prune")
+                        .long("prune")
+                        .help("Remove shipcat managed policies that are no longer generated"))
                     .about("Reconcile vault policies with manifest state"))))
         // all the listers (hidden from cli output)
         .subcommand(SubCommand::with_name("list-regions")
@@ -384,7 +533,7 @@ fn build_cli() -> App<'static, 'static> {
                 .help("Remove the old tsh state file to force a login")))
 
         .subcommand(SubCommand::with_name("top")
-            .about("Show top requests from manifests on disk")
+            .about("Show top requests and monthly cost estimates from manifests on disk")
             .arg(Arg::with_name("upper")
                 .short("u")
                 .long("upper-bounds")
@@ -392,13 +541,22 @@ fn build_cli() -> App<'static, 'static> {
             .arg(Arg::with_name("output")
                 .takes_value(true)
                 .default_value("table")
-                .possible_values(&["table", "yaml"])
+                .possible_values(&["table", "yaml", "csv"])
                 .long("output")
                 .short("o")
-                .help("Output format to print. Yaml contains machine parseable numbers."))
+                .help("Output format to print. Yaml and csv contain machine parseable numbers."))
             .arg(Arg::with_name("world")
                 .long("world")
                 .help("Show resource requests across all regions"))
+            .arg(Arg::with_name("usage")
+                .long("usage")
+                .conflicts_with_all(&["world", "upper"])
+                .help("Compare requests against actual usage from the metrics api and suggest rightsizing"))
+            .arg(Arg::with_name("prometheus")
+                .long("prometheus")
+                .takes_value(true)
+                .requires("usage")
+                .help("Prometheus url to query for usage instead of the metrics api"))
             .arg(Arg::with_name("squads")
                 .long("squads")
                 .conflicts_with("tribes")
@@ -409,7 +567,7 @@ fn build_cli() -> App<'static, 'static> {
                 .help("Aggregate services by tribe ownership"))
             .arg(Arg::with_name("sort")
                 .takes_value(true)
-                .possible_values(&["cpu", "memory"])
+                .possible_values(&["cpu", "memory", "cost"])
                 .default_value("cpu")
                 .long("sort")
                 .short("s")
@@ -601,10 +759,34 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         if let Some(_) = a.subcommand_matches("kafkatopics") {
             return shipcat::get::kafkatopics(&conf, &region).await;
         }
+        if let Some(_) = a.subcommand_matches("rbac") {
+            return shipcat::get::rbac(&conf, &region).await;
+        }
     } else if let Some(a) = args.subcommand_matches("top") {
         let sort = top::ResourceOrder::from_str(a.value_of("sort").unwrap())?;
         let fmt = top::OutputFormat::from_str(a.value_of("output").unwrap())?;
         let ub = a.is_present("upper");
+        if a.is_present("usage") {
+            let (conf, region) = resolve_config(a, ConfigState::Base).await?;
+            let source = if let Some(prom) = a.value_of("prometheus") {
+                usage::UsageSource::Prometheus(url::Url::parse(prom)?)
+            } else {
+                usage::UsageSource::MetricsApi
+            };
+            return if a.is_present("squads") {
+                shipcat::top::region_squad_rightsizing(sort, fmt, &conf, &region, source)
+                    .await
+                    .map(void)
+            } else if a.is_present("tribes") {
+                shipcat::top::region_tribe_rightsizing(sort, fmt, &conf, &region, source)
+                    .await
+                    .map(void)
+            } else {
+                shipcat::top::region_rightsizing(sort, fmt, &conf, &region, source)
+                    .await
+                    .map(void)
+            };
+        }
         return if a.is_present("world") {
             let rawconf = Config::read().await?;
             if a.is_present("squads") {
@@ -676,6 +858,32 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
                 shipcat::validate::secret_presence_full(&rawconf, regions).await
             };
         }
+        if let Some(b) = a.subcommand_matches("audit") {
+            let region = rawconf.get_region(b.value_of("audit-region").unwrap())?;
+            let fmt = shipcat::secret::AuditFormat::from_str(b.value_of("output").unwrap())?;
+            return shipcat::secret::audit_region(&rawconf, &region, fmt).await.map(void);
+        }
+        if let Some(b) = a.subcommand_matches("diff") {
+            let svc = b.value_of("service").unwrap();
+            let from = rawconf.get_region(b.value_of("from").unwrap())?;
+            let to = rawconf.get_region(b.value_of("to").unwrap())?;
+            return shipcat::secret::diff_regions(&rawconf, svc, &from, &to).await.map(void);
+        }
+        if let Some(b) = a.subcommand_matches("copy") {
+            let svc = b.value_of("service").unwrap();
+            let from = rawconf.get_region(b.value_of("from").unwrap())?;
+            let to = rawconf.get_region(b.value_of("to").unwrap())?;
+            let keys = b.value_of("keys").map(|ks| {
+                ks.split(',')
+                    .filter(|k| !k.is_empty())
+                    .map(String::from)
+                    .collect()
+            });
+            let overwrite = b.is_present("overwrite");
+            return shipcat::secret::copy_regions(&rawconf, svc, &from, &to, keys, overwrite)
+                .await
+                .map(void);
+        }
     }
     // ------------------------------------------------------------------------------
     // important dev commands below - they resolve kube context as a fallback
@@ -685,17 +893,41 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
         return shipcat::status::show(&svc, &conf, &region).await;
     } else if let Some(a) = args.subcommand_matches("graph") {
-        let dot = a.is_present("dot");
+        use shipcat::{graph, structs::DependencyProtocol};
+        let format = if a.is_present("dot") {
+            graph::GraphFormat::Dot
+        } else {
+            graph::GraphFormat::from_str(a.value_of("output").unwrap())?
+        };
+        let filter = graph::GraphFilter {
+            team: a.value_of("team").map(String::from),
+            protocol: a
+                .value_of("protocol")
+                .map(DependencyProtocol::from_str)
+                .transpose()?,
+        };
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
-        return if let Some(svc) = a.value_of("service") {
+        return if a.is_present("check") {
+            graph::verify(&conf, &region).await
+        } else if let Some(svc) = a.value_of("service") {
             if a.is_present("reverse") {
-                shipcat::graph::reverse(svc, &conf, &region).await.map(void)
+                graph::reverse(svc, &conf, &region).await.map(void)
             } else {
-                shipcat::graph::generate(svc, &conf, &region, dot).await.map(void)
+                graph::generate(svc, &conf, &region, format, &filter)
+                    .await
+                    .map(void)
             }
         } else {
-            shipcat::graph::full(dot, &conf, &region).await.map(void)
+            graph::full(format, &filter, &conf, &region).await.map(void)
         };
+    } else if let Some(a) = args.subcommand_matches("networkpolicy") {
+        if let Some(b) = a.subcommand_matches("audit") {
+            let (conf, region) = resolve_config(args, ConfigState::Base).await?;
+            let prom = url::Url::parse(b.value_of("prometheus").unwrap())?;
+            return shipcat::networkpolicy::audit(b.value_of("service"), &prom, &conf, &region)
+                .await
+                .map(void);
+        }
     } else if let Some(a) = args.subcommand_matches("validate") {
         let services = a
             .values_of("services")
@@ -713,7 +945,7 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
     } else if let Some(a) = args.subcommand_matches("verify") {
         return if a.value_of("region").is_some() {
             let (conf, region) = resolve_config(a, ConfigState::Base).await?;
-            shipcat::validate::regional_manifests(&conf, &region).await
+            shipcat::validate::regional_manifests(&conf, &region, a.is_present("strict-dependencies")).await
         } else {
             shipcat::validate::all_manifests().await
         };
@@ -727,7 +959,7 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         };
         let (conf, region) = resolve_config(a, ss).await?;
 
-        let mf = if a.is_present("secrets") {
+        let mut mf = if a.is_present("secrets") {
             shipcat_filebacked::load_manifest(&svc, &conf, &region)
                 .await?
                 .complete(&region)
@@ -738,6 +970,9 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
                 .stub(&region)
                 .await?
         };
+        let mfs = shipcat::populate::region_manifests(&conf, &region).await?;
+        let images = shipcat::registry::RegistryClient::from_env()?;
+        shipcat::populate::populate_region_resources(&mut mf, &mfs, &conf, &region, &images).await?;
         mf.print()?;
         return Ok(());
     } else if let Some(a) = args.subcommand_matches("template") {
@@ -771,6 +1006,12 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         } else {
             // ensure valid chart
             mf.uid = Some("FAKE-GUID".to_string());
+        }
+        let mfs = shipcat::populate::region_manifests(&conf, &region).await?;
+        let images = shipcat::registry::RegistryClient::from_env()?;
+        shipcat::populate::populate_region_resources(&mut mf, &mfs, &conf, &region, &images).await?;
+        if !a.is_present("current") {
+            // placeholder version after the registry lookups, which need a real one
             mf.version = mf.version.or(Some("latest".to_string()));
         }
         let tpl = shipcat::helm::template(&mf, None).await?;
@@ -852,6 +1093,12 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
             } else {
                 // ensure valid chart
                 mf.uid = Some("FAKE-GUID".to_string());
+            }
+            let mfs = shipcat::populate::region_manifests(&conf, &region).await?;
+            let images = shipcat::registry::RegistryClient::from_env()?;
+            shipcat::populate::populate_region_resources(&mut mf, &mfs, &conf, &region, &images).await?;
+            if a.is_present("mock") {
+                // placeholder version after the registry lookups, which need a real one
                 mf.version = mf.version.or(Some("latest".to_string()));
             }
             let diff = shipcat::diff::template_vs_kubectl(&mf).await?;
@@ -873,9 +1120,15 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
         return if let Some(_b) = a.subcommand_matches("config-url") {
             shipcat::kong::config_url(&region)
+        } else if let Some(_b) = a.subcommand_matches("diff") {
+            shipcat::kongsync::diff_region(&conf, &region).await
+        } else if let Some(b) = a.subcommand_matches("sync") {
+            shipcat::kongsync::sync_region(&conf, &region, b.is_present("dry-run")).await
         } else {
             let mode = if a.is_present("crd") {
                 kong::KongOutputMode::Crd
+            } else if a.is_present("deck") {
+                kong::KongOutputMode::Deck
             } else {
                 kong::KongOutputMode::Kongfig
             };
@@ -883,7 +1136,25 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         };
     } else if let Some(a) = args.subcommand_matches("statuscake") {
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
-        return shipcat::statuscake::output(&conf, &region).await;
+        return if let Some(b) = a.subcommand_matches("sync") {
+            let client = shipcat::statuscake::StatuscakeClient::from_env()?;
+            shipcat::statuscake::sync(&conf, &region, &client, b.is_present("dry-run")).await
+        } else {
+            shipcat::statuscake::output(&conf, &region).await
+        };
+    } else if let Some(a) = args.subcommand_matches("sentry") {
+        let (conf, region) = resolve_config(a, ConfigState::Base).await?;
+        if let Some(b) = a.subcommand_matches("sync") {
+            let client = shipcat::sentry::SentryClient::from_env(&region)?;
+            return shipcat::sentry::sync(&conf, &region, &client, b.is_present("dry-run")).await;
+        }
+    } else if let Some(a) = args.subcommand_matches("newrelic") {
+        let (conf, region) = resolve_config(a, ConfigState::Base).await?;
+        let client = shipcat::newrelic::NewrelicClient::from_env()?;
+        if let Some(b) = a.subcommand_matches("sync") {
+            return shipcat::newrelic::sync(&conf, &region, &client, b.is_present("dry-run")).await;
+        }
+        return shipcat::newrelic::diff_region(&conf, &region, &client).await;
     }
     // ------------------------------------------------------------------------------
     // everything below needs a kube context!
@@ -895,7 +1166,7 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         let force = a.is_present("force");
         let ver = a.value_of("tag").map(String::from); // needed for some subcommands
         assert!(conf.has_secrets()); // sanity on cluster disruptive commands
-        return shipcat::apply::apply(svc, force, &region, &conf, wait, ver)
+        return shipcat::apply::apply(svc, force, &region, &conf, wait, ver, None)
             .await
             .map(void);
     } else if let Some(a) = args.subcommand_matches("restart") {
@@ -929,6 +1200,10 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
             let (conf, region) = resolve_config(args, ConfigState::Filtered).await?;
             return shipcat::cluster::mass_diff(&conf, &region).await;
         }
+        if let Some(_b) = a.subcommand_matches("capacity") {
+            let (conf, region) = resolve_config(args, ConfigState::Base).await?;
+            return shipcat::cluster::capacity(&conf, &region).await;
+        }
         if let Some(b) = a.subcommand_matches("check") {
             let (conf, region) = resolve_config(args, ConfigState::Base).await?;
             let skipped = b
@@ -944,8 +1219,10 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         if let Some(b) = a.subcommand_matches("vault-policy") {
             let (conf, region) = resolve_config(args, ConfigState::Base).await?;
             let jobs = b.value_of("num-jobs").unwrap_or("8").parse().unwrap();
-            if let Some(_) = b.subcommand_matches("reconcile") {
-                return shipcat::cluster::mass_vault(&conf, &region, jobs).await;
+            if let Some(c) = b.subcommand_matches("reconcile") {
+                let dry_run = c.is_present("dry-run");
+                let prune = c.is_present("prune");
+                return shipcat::vaultpolicy::reconcile(&conf, &region, jobs, dry_run, prune).await;
             }
         }
     }
@@ -1000,6 +1277,18 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         return shipcat::slack::send_dumb(msg).await;
     } else if let Some(a) = args.subcommand_matches("gdpr") {
         let (conf, region) = resolve_config(args, ConfigState::Base).await?;
+        if a.is_present("retention") {
+            let format = a.value_of("output").unwrap().parse()?;
+            let entries = shipcat::gdpr::retention(&conf, &region).await?;
+            println!("{}", shipcat::gdpr::render_retention(&entries, format)?);
+            return Ok(());
+        }
+        if a.is_present("flows") {
+            let format = a.value_of("output").unwrap().parse()?;
+            let report = shipcat::gdpr::flows(&conf, &region).await?;
+            println!("{}", report.render(format)?);
+            return Ok(());
+        }
         let svc = a.value_of("service").map(String::from);
         return shipcat::gdpr::show(svc, &conf, &region).await;
     }
@@ -0,0 +1,287 @@
+use shipcat_definitions::region::NetworkPolicyConfig;
+use std::collections::{BTreeMap, BTreeSet};
+use url::Url;
+
+use super::{
+    graph, populate,
+    structs::{
+        networkpolicy::{LabelSelector, NetworkPolicyPeer, NetworkPolicyPort, NetworkPolicyRule},
+        Dependency, DependencyProtocol, NetworkPolicy,
+    },
+    usage::prometheus_vector,
+    Config, Manifest, Region, Result,
+};
+
+/// Ports of a service that a dependency using a protocol connects to
+///
+/// Http dependencies use the `httpPort` and ports named `http*`,
+/// grpc dependencies use ports named `grpc*`.
+/// Other protocols go through a broker and never connect to the service.
+pub fn dependency_ports(mf: &Manifest, protocol: &DependencyProtocol) -> Vec<NetworkPolicyPort> {
+    let prefix = match protocol {
+        DependencyProtocol::Http => "http",
+        DependencyProtocol::Grpc => "grpc",
+        _ => return vec![],
+    };
+    let mut ports = vec![];
+    if protocol == &DependencyProtocol::Http {
+        if let Some(port) = mf.httpPort {
+            ports.push(NetworkPolicyPort {
+                port,
+                ..Default::default()
+            });
+        }
+    }
+    for p in mf.ports.iter().filter(|p| p.name.starts_with(prefix)) {
+        if !ports.iter().any(|np| np.port == p.port) {
+            ports.push(NetworkPolicyPort {
+                port: p.port,
+                protocol: p.protocol.clone(),
+            });
+        }
+    }
+    ports
+}
+
+/// Build the ingress rules of a service from the services depending on it
+///
+/// Callers get one rule per protocol, limited to the ports of that protocol.
+/// Kong can reach the http ports of services with `kongApis`,
+/// and the extra sources of the region can reach every service.
+pub fn build(mf: &Manifest, callers: &[(String, Dependency)], cfg: &NetworkPolicyConfig) -> NetworkPolicy {
+    let mut ingress = vec![];
+    for protocol in &[DependencyProtocol::Http, DependencyProtocol::Grpc] {
+        let ports = dependency_ports(mf, protocol);
+        let mut from = callers
+            .iter()
+            .filter(|(_, dep)| &dep.protocol == protocol)
+            .map(|(name, _)| NetworkPolicyPeer {
+                podSelector: Some(LabelSelector::app(name)),
+                namespaceSelector: None,
+            })
+            .collect::<Vec<_>>();
+        from.dedup();
+        if !from.is_empty() && !ports.is_empty() {
+            ingress.push(NetworkPolicyRule { from, ports });
+        }
+    }
+    if let Some(kong) = &cfg.kong {
+        if !mf.kongApis.is_empty() {
+            let ports = if kong.ports.is_empty() {
+                dependency_ports(mf, &DependencyProtocol::Http)
+            } else {
+                kong.ports.clone()
+            };
+            ingress.push(NetworkPolicyRule {
+                from: vec![kong.peer()],
+                ports,
+            });
+        }
+    }
+    for src in &cfg.extraSources {
+        ingress.push(NetworkPolicyRule {
+            from: vec![src.peer()],
+            ports: src.ports.clone(),
+        });
+    }
+    NetworkPolicy { ingress }
+}
+
+/// Inject the NetworkPolicy of a service for the helm chart
+///
+/// Does nothing in regions without `networkPolicies` configured.
+pub fn populate(mf: &mut Manifest, mfs: &[Manifest], reg: &Region) {
+    if let Some(cfg) = &reg.networkPolicies {
+        let callers = graph::callers(&mf.name, mfs);
+        mf.networkPolicy = Some(build(mf, &callers, cfg));
+    }
+}
+
+/// Names of the sources a service allows traffic from
+fn declared_sources(
+    mf: &Manifest,
+    callers: &[(String, Dependency)],
+    cfg: &NetworkPolicyConfig,
+) -> BTreeSet<String> {
+    let mut names = callers
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect::<BTreeSet<_>>();
+    if let Some(kong) = &cfg.kong {
+        if !mf.kongApis.is_empty() {
+            names.insert(kong.name.clone());
+        }
+    }
+    names.extend(cfg.extraSources.iter().map(|src| src.name.clone()));
+    names
+}
+
+/// Traffic observed between two apps that no dependency declares
+#[derive(Serialize, Debug, PartialEq)]
+pub struct UndeclaredTraffic {
+    pub source: String,
+    pub destination: String,
+}
+
+/// Compare observed (source, destination) pairs against the declared sources of each service
+fn undeclared(
+    observed: &BTreeSet<(String, String)>,
+    declared: &BTreeMap<String, BTreeSet<String>>,
+) -> Vec<UndeclaredTraffic> {
+    observed
+        .iter()
+        .filter(|(src, dst)| src != "unknown" && src != dst)
+        .filter(|(src, dst)| matches!(declared.get(dst), Some(allowed) if !allowed.contains(src)))
+        .map(|(src, dst)| UndeclaredTraffic {
+            source: src.clone(),
+            destination: dst.clone(),
+        })
+        .collect()
+}
+
+/// List traffic sources observed by istio that the NetworkPolicies would block
+///
+/// Meant to be run before enabling `networkPolicies` in a region.
+/// Uses the istio request and tcp metrics from the last week.
+pub async fn audit(
+    service: Option<&str>,
+    prometheus: &Url,
+    conf: &Config,
+    reg: &Region,
+) -> Result<Vec<UndeclaredTraffic>> {
+    let cfg = reg.networkPolicies.clone().unwrap_or_default();
+    let mut declared = BTreeMap::new();
+    let mfs = populate::region_manifests(conf, reg).await?;
+    for mf in &mfs {
+        if matches!(service, Some(s) if s != mf.name) {
+            continue;
+        }
+        let callers = graph::callers(&mf.name, &mfs);
+        declared.insert(mf.name.clone(), declared_sources(mf, &callers, &cfg));
+    }
+
+    let mut observed = BTreeSet::new();
+    for metric in &["istio_requests_total", "istio_tcp_connections_opened_total"] {
+        let query = format!(
+            "sum by (source_app, destination_app) (increase({}{{reporter=\"destination\",destination_service_namespace=\"{}\"}}[7d])) > 0",
+            metric, reg.namespace
+        );
+        for (labels, _) in prometheus_vector(prometheus, &query).await? {
+            if let (Some(src), Some(dst)) = (labels.get("source_app"), labels.get("destination_app")) {
+                observed.insert((src.clone(), dst.clone()));
+            }
+        }
+    }
+
+    let res = undeclared(&observed, &declared);
+    if res.is_empty() {
+        info!("No undeclared traffic observed in {}", reg.name);
+    } else {
+        println!("{0:<40} {1:<40}", "SOURCE", "DESTINATION");
+        for t in &res {
+            println!("{0:<40} {1:<40}", t.source, t.destination);
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build, declared_sources, undeclared, UndeclaredTraffic};
+    use crate::{
+        structs::{Dependency, DependencyProtocol, Kong, Port},
+        Manifest,
+    };
+    use shipcat_definitions::region::NetworkPolicyConfig;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    fn caller(name: &str, protocol: DependencyProtocol) -> (String, Dependency) {
+        let dep = Dependency {
+            name: "api".into(),
+            protocol,
+            ..Default::default()
+        };
+        (name.into(), dep)
+    }
+
+    fn config() -> NetworkPolicyConfig {
+        serde_yaml::from_str(
+            "
+kong:
+  name: kong
+  namespaceSelector: { matchLabels: { name: kong } }
+extraSources:
+- name: prometheus
+  namespaceSelector: { matchLabels: { name: monitoring } }
+  ports:
+  - port: 9102",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn networkpolicy_from_dependencies() {
+        let mut mf = Manifest::test("api");
+        mf.httpPort = Some(8000);
+        mf.ports = vec![Port {
+            name: "grpc".into(),
+            port: 9000,
+            ..Default::default()
+        }];
+        mf.kongApis = vec![Kong::default()];
+        let callers = vec![
+            caller("web", DependencyProtocol::Http),
+            caller("worker", DependencyProtocol::Grpc),
+            caller("consumer", DependencyProtocol::Kafka),
+        ];
+        let cfg = config();
+        assert!(cfg.verify().is_ok());
+
+        let np = build(&mf, &callers, &cfg);
+        let yaml = serde_yaml::to_value(&np).unwrap();
+        let expected: serde_yaml::Value = serde_yaml::from_str(
+            "
+ingress:
+- from: [{ podSelector: { matchLabels: { app: web } } }]
+  ports: [{ port: 8000, protocol: TCP }]
+- from: [{ podSelector: { matchLabels: { app: worker } } }]
+  ports: [{ port: 9000, protocol: TCP }]
+- from: [{ namespaceSelector: { matchLabels: { name: kong } } }]
+  ports: [{ port: 8000, protocol: TCP }]
+- from: [{ namespaceSelector: { matchLabels: { name: monitoring } } }]
+  ports: [{ port: 9102, protocol: TCP }]",
+        )
+        .unwrap();
+        assert_eq!(yaml, expected);
+
+        // no kong rule without kongApis
+        mf.kongApis = vec![];
+        assert_eq!(build(&mf, &callers, &cfg).ingress.len(), 3);
+    }
+
+    #[test]
+    fn networkpolicy_audit_undeclared() {
+        let mut mf = Manifest::test("api");
+        mf.kongApis = vec![Kong::default()];
+        let callers = vec![caller("web", DependencyProtocol::Http)];
+        let mut declared = BTreeMap::new();
+        declared.insert("api".to_string(), declared_sources(&mf, &callers, &config()));
+
+        let observed = vec![
+            ("web", "api"),
+            ("kong", "api"),
+            ("prometheus", "api"),
+            ("unknown", "api"),
+            ("api", "api"),
+            ("batch", "api"),
+            ("batch", "elsewhere"),
+        ]
+        .into_iter()
+        .map(|(s, d)| (s.to_string(), d.to_string()))
+        .collect::<BTreeSet<_>>();
+        assert_eq!(undeclared(&observed, &declared), vec![UndeclaredTraffic {
+            source: "batch".into(),
+            destination: "api".into(),
+        }]);
+    }
+}
@@ -0,0 +1,611 @@
+use std::{collections::BTreeMap, env, fmt};
+
+use reqwest::{Method, Url};
+use serde_json::{json, Value};
+use shipcat_definitions::structs::newrelic::{NewrelicCondition, NewrelicIncidentPreference};
+
+use super::{
+    reconcile::{print_changes, print_or_apply, Auth, Body, JsonClient},
+    Config, Region, Result,
+};
+
+/// An alert policy for one service in a region
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Policy {
+    pub name: String,
+    pub incident_preference: NewrelicIncidentPreference,
+    /// Name of the APM application the conditions apply to
+    #[serde(skip)]
+    pub application: String,
+    pub conditions: Vec<NewrelicCondition>,
+}
+
+/// Name of the policy, and of the APM application, of a service in a region
+pub fn policy_name(service: &str, region: &str) -> String {
+    format!("{}@{}", service, region)
+}
+
+/// The alert policies a region should have
+///
+/// One policy per service with `newrelic` alerts, named `service@region`.
+pub async fn desired(conf: &Config, region: &Region) -> Result<Vec<Policy>> {
+    let mut policies = vec![];
+    for svc in shipcat_filebacked::available(conf, region).await? {
+        let mf = shipcat_filebacked::load_manifest(&svc.base.name, conf, region).await?;
+        let nr = match mf.newrelic {
+            Some(nr) => nr,
+            None => continue,
+        };
+        let mut conditions = vec![];
+        for a in nr.alerts.values() {
+            conditions.push(a.condition()?);
+        }
+        let name = policy_name(&mf.name, &region.name);
+        policies.push(Policy {
+            application: name.clone(),
+            name,
+            incident_preference: nr.incident_preference,
+            conditions,
+        });
+    }
+    Ok(policies)
+}
+
+/// A policy as found in New Relic
+#[derive(Clone, Debug)]
+pub struct LivePolicy {
+    pub id: String,
+    pub name: String,
+    pub incident_preference: NewrelicIncidentPreference,
+    pub conditions: Vec<LiveCondition>,
+    /// Whether shipcat may delete the policy and its conditions
+    pub owned: bool,
+}
+
+/// A condition as found in a New Relic policy
+#[derive(Clone, Debug)]
+pub struct LiveCondition {
+    pub id: String,
+    pub name: String,
+    /// The condition, unless it has a shape shipcat does not generate
+    pub condition: Option<NewrelicCondition>,
+}
+
+/// A change needed to bring New Relic in line with the region
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum NewrelicChange {
+    CreatePolicy {
+        policy: String,
+        incident_preference: NewrelicIncidentPreference,
+    },
+    UpdatePolicy {
+        id: String,
+        policy: String,
+        incident_preference: NewrelicIncidentPreference,
+    },
+    DeletePolicy {
+        id: String,
+        policy: String,
+    },
+    CreateCondition {
+        policy: String,
+        /// Id of the policy, unless it is created in the same sync
+        #[serde(skip_serializing_if = "Option::is_none")]
+        policy_id: Option<String>,
+        condition: NewrelicCondition,
+    },
+    UpdateCondition {
+        id: String,
+        policy: String,
+        condition: NewrelicCondition,
+        fields: Vec<String>,
+    },
+    DeleteCondition {
+        id: String,
+        policy: String,
+        condition: String,
+    },
+}
+
+impl fmt::Display for NewrelicChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NewrelicChange::CreatePolicy { policy, .. } => write!(f, "+ policy {}", policy),
+            NewrelicChange::UpdatePolicy { policy, .. } => {
+                write!(f, "~ policy {} (incident_preference)", policy)
+            }
+            NewrelicChange::DeletePolicy { policy, .. } => write!(f, "- policy {}", policy),
+            NewrelicChange::CreateCondition {
+                policy, condition, ..
+            } => {
+                write!(f, "+ condition {}/{}", policy, condition.name)
+            }
+            NewrelicChange::UpdateCondition {
+                policy,
+                condition,
+                fields,
+                ..
+            } => write!(
+                f,
+                "~ condition {}/{} ({})",
+                policy,
+                condition.name,
+                fields.join(", ")
+            ),
+            NewrelicChange::DeleteCondition {
+                policy, condition, ..
+            } => {
+                write!(f, "- condition {}/{}", policy, condition)
+            }
+        }
+    }
+}
+
+/// Fields of a live condition that differ from the desired one
+fn drifted(desired: &NewrelicCondition, live: &NewrelicCondition) -> Vec<String> {
+    let mut fields = vec![];
+    if desired.kind != live.kind {
+        fields.push("type".into());
+    }
+    if desired.enabled != live.enabled {
+        fields.push("enabled".into());
+    }
+    let mut entities = live.entities.clone();
+    entities.sort();
+    if desired.entities != entities {
+        fields.push("entities".into());
+    }
+    if desired.metric != live.metric {
+        fields.push("metric".into());
+    }
+    if desired.condition_scope != live.condition_scope {
+        fields.push("condition_scope".into());
+    }
+    if desired.terms != live.terms {
+        fields.push("terms".into());
+    }
+    fields
+}
+
+/// Compute the changes needed to turn `live` into `desired`
+///
+/// Only owned policies are ever deleted.
+/// Conditions not in a desired policy are deleted from it, unless shipcat could not parse them.
+/// Unparsable conditions with the name of a desired condition are overwritten.
+pub fn diff(desired: &[Policy], live: &[LivePolicy]) -> Vec<NewrelicChange> {
+    let mut changes = vec![];
+    for p in desired {
+        let lp = live.iter().find(|l| l.name == p.name);
+        match lp {
+            None => changes.push(NewrelicChange::CreatePolicy {
+                policy: p.name.clone(),
+                incident_preference: p.incident_preference.clone(),
+            }),
+            Some(l) if l.incident_preference != p.incident_preference => {
+                changes.push(NewrelicChange::UpdatePolicy {
+                    id: l.id.clone(),
+                    policy: p.name.clone(),
+                    incident_preference: p.incident_preference.clone(),
+                })
+            }
+            _ => {}
+        }
+        let live_conditions = lp.map(|l| l.conditions.as_slice()).unwrap_or_default();
+        for c in &p.conditions {
+            match live_conditions.iter().find(|lc| lc.name == c.name) {
+                None => changes.push(NewrelicChange::CreateCondition {
+                    policy: p.name.clone(),
+                    policy_id: lp.map(|l| l.id.clone()),
+                    condition: c.clone(),
+                }),
+                Some(lc) => {
+                    let fields = match &lc.condition {
+                        Some(live) => drifted(c, live),
+                        None => vec!["unparsed".into()],
+                    };
+                    if !fields.is_empty() {
+                        changes.push(NewrelicChange::UpdateCondition {
+                            id: lc.id.clone(),
+                            policy: p.name.clone(),
+                            condition: c.clone(),
+                            fields,
+                        });
+                    }
+                }
+            }
+        }
+        for lc in live_conditions.iter().filter(|lc| lc.condition.is_some()) {
+            if !p.conditions.iter().any(|c| c.name == lc.name) {
+                changes.push(NewrelicChange::DeleteCondition {
+                    id: lc.id.clone(),
+                    policy: p.name.clone(),
+                    condition: lc.name.clone(),
+                });
+            }
+        }
+    }
+    for l in live.iter().filter(|l| l.owned) {
+        if !desired.iter().any(|p| p.name == l.name) {
+            changes.push(NewrelicChange::DeletePolicy {
+                id: l.id.clone(),
+                policy: l.name.clone(),
+            });
+        }
+    }
+    changes
+}
+
+/// Authenticated client for the New Relic REST API (v2)
+pub struct NewrelicClient {
+    api: JsonClient,
+}
+
+#[derive(Deserialize)]
+struct PolicyResponse {
+    id: Value,
+    name: String,
+    incident_preference: NewrelicIncidentPreference,
+}
+
+/// New Relic returns numeric ids, but accepts them as strings in urls and bodies
+fn id_of(v: &Value) -> Result<String> {
+    match v {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        _ => bail!("newrelic object without an id: {}", v),
+    }
+}
+
+impl NewrelicClient {
+    /// Client against `url`, e.g. https://api.newrelic.com/v2/
+    pub fn new(url: Url, key: String) -> Self {
+        NewrelicClient {
+            api: JsonClient::new(url, Auth::Header("X-Api-Key", key)),
+        }
+    }
+
+    /// Client from `NEWRELIC_API_KEY` and an optional `NEWRELIC_API_URL`
+    pub fn from_env() -> Result<Self> {
+        let key = match env::var("NEWRELIC_API_KEY") {
+            Ok(k) => k,
+            Err(_) => bail!("NEWRELIC_API_KEY must be set to sync newrelic"),
+        };
+        let url = env::var("NEWRELIC_API_URL").unwrap_or_else(|_| "https://api.newrelic.com/v2/".into());
+        Ok(NewrelicClient::new(Url::parse(&url)?, key))
+    }
+
+    async fn send(&self, method: Method, path: &str, body: Option<Value>) -> Result<Value> {
+        let body = body.map(Body::Json).unwrap_or(Body::Empty);
+        self.api.send(method, path, body).await
+    }
+
+    /// Id of the APM application with exactly this name
+    pub async fn application_id(&self, name: &str) -> Result<Option<String>> {
+        let mut url = Url::parse("http://x/applications.json")?;
+        url.query_pairs_mut().append_pair("filter[name]", name);
+        let path = format!("applications.json?{}", url.query().unwrap_or_default());
+        let res = self.send(Method::GET, &path, None).await?;
+        let apps = res["applications"].as_array().cloned().unwrap_or_default();
+        match apps.iter().find(|a| a["name"] == name) {
+            Some(a) => Ok(Some(id_of(&a["id"])?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn policies(&self) -> Result<Vec<PolicyResponse>> {
+        let mut res = vec![];
+        let mut page = 1;
+        loop {
+            let body = self
+                .send(Method::GET, &format!("alerts_policies.json?page={}", page), None)
+                .await?;
+            let policies: Vec<PolicyResponse> = serde_json::from_value(body["policies"].clone())?;
+            if policies.is_empty() {
+                break;
+            }
+            res.extend(policies);
+            page += 1;
+        }
+        Ok(res)
+    }
+
+    /// Fetch policies and their conditions, marking those of this region as owned
+    ///
+    /// Conditions are only fetched for `wanted` and owned policies.
+    pub async fn fetch(&self, region: &str, wanted: &[Policy]) -> Result<Vec<LivePolicy>> {
+        let suffix = format!("@{}", region);
+        let mut live = vec![];
+        for p in self.policies().await? {
+            let owned = p.name.ends_with(&suffix);
+            if !owned && !wanted.iter().any(|w| w.name == p.name) {
+                continue;
+            }
+            let id = id_of(&p.id)?;
+            let body = self
+                .send(
+                    Method::GET,
+                    &format!("alerts_conditions.json?policy_id={}", id),
+                    None,
+                )
+                .await?;
+            let mut conditions = vec![];
+            for c in body["conditions"].as_array().cloned().unwrap_or_default() {
+                let id = id_of(&c["id"])?;
+                let name = c["name"].as_str().unwrap_or_default().to_string();
+                let condition = match serde_json::from_value(c) {
+                    Ok(c) => Some(c),
+                    Err(e) => {
+                        warn!("Cannot parse newrelic condition {}/{}: {}", p.name, name, e);
+                        None
+                    }
+                };
+                conditions.push(LiveCondition { id, name, condition });
+            }
+            live.push(LivePolicy {
+                id,
+                name: p.name,
+                incident_preference: p.incident_preference,
+                conditions,
+                owned,
+            });
+        }
+        Ok(live)
+    }
+
+    /// Apply changes in order
+    ///
+    /// Conditions of policies created earlier in `changes` use the ids of the new policies.
+    pub async fn apply(&self, changes: &[NewrelicChange]) -> Result<()> {
+        let mut ids = BTreeMap::new();
+        for c in changes {
+            info!("{}", c);
+            match c {
+                NewrelicChange::CreatePolicy {
+                    policy,
+                    incident_preference,
+                } => {
+                    let body =
+                        json!({ "policy": { "name": policy, "incident_preference": incident_preference } });
+                    let res = self
+                        .send(Method::POST, "alerts_policies.json", Some(body))
+                        .await?;
+                    ids.insert(policy.clone(), id_of(&res["policy"]["id"])?);
+                }
+                NewrelicChange::UpdatePolicy {
+                    id,
+                    policy,
+                    incident_preference,
+                } => {
+                    let body =
+                        json!({ "policy": { "name": policy, "incident_preference": incident_preference } });
+                    let path = format!("alerts_policies/{}.json", id);
+                    self.send(Method::PUT, &path, Some(body)).await?;
+                }
+                NewrelicChange::DeletePolicy { id, .. } => {
+                    let path = format!("alerts_policies/{}.json", id);
+                    self.send(Method::DELETE, &path, None).await?;
+                }
+                NewrelicChange::CreateCondition {
+                    policy,
+                    policy_id,
+                    condition,
+                } => {
+                    let id = match policy_id.as_ref().or_else(|| ids.get(policy)) {
+                        Some(id) => id,
+                        None => bail!("newrelic policy {} does not exist", policy),
+                    };
+                    let path = format!("alerts_conditions/policies/{}.json", id);
+                    let body = json!({ "condition": condition });
+                    self.send(Method::POST, &path, Some(body)).await?;
+                }
+                NewrelicChange::UpdateCondition { id, condition, .. } => {
+                    let path = format!("alerts_conditions/{}.json", id);
+                    let body = json!({ "condition": condition });
+                    self.send(Method::PUT, &path, Some(body)).await?;
+                }
+                NewrelicChange::DeleteCondition { id, .. } => {
+                    let path = format!("alerts_conditions/{}.json", id);
+                    self.send(Method::DELETE, &path, None).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Point the conditions of each policy at its APM application
+///
+/// Policies of services that have not reported to New Relic yet are skipped,
+/// and returned by name alongside the policies to sync.
+async fn with_entities(client: &NewrelicClient, policies: Vec<Policy>) -> Result<(Vec<Policy>, Vec<String>)> {
+    let mut res = vec![];
+    let mut skipped = vec![];
+    for mut p in policies {
+        match client.application_id(&p.application).await? {
+            Some(id) => {
+                for c in &mut p.conditions {
+                    c.entities = vec![id.clone()];
+                }
+                res.push(p);
+            }
+            None => {
+                warn!(
+                    "Skipping newrelic policy {}: no application named {}",
+                    p.name, p.application
+                );
+                skipped.push(p.name);
+            }
+        }
+    }
+    Ok((res, skipped))
+}
+
+/// Changes needed to reconcile New Relic with the region
+pub async fn changes(conf: &Config, region: &Region, client: &NewrelicClient) -> Result<Vec<NewrelicChange>> {
+    let (wanted, skipped) = with_entities(client, desired(conf, region).await?).await?;
+    let mut live = client.fetch(&region.name, &wanted).await?;
+    // skipped policies are still wanted, so they must not be pruned
+    for l in live.iter_mut().filter(|l| skipped.contains(&l.name)) {
+        l.owned = false;
+    }
+    Ok(diff(&wanted, &live))
+}
+
+/// Print the drift between the region and New Relic
+pub async fn diff_region(conf: &Config, region: &Region, client: &NewrelicClient) -> Result<()> {
+    let changes = changes(conf, region, client).await?;
+    print_changes(&format!("newrelic for {}", region.name), &changes);
+    Ok(())
+}
+
+/// Reconcile New Relic alert policies with the region
+///
+/// With `dry_run` the changes are only printed.
+pub async fn sync(conf: &Config, region: &Region, client: &NewrelicClient, dry_run: bool) -> Result<()> {
+    let changes = changes(conf, region, client).await?;
+    let what = format!("newrelic for {}", region.name);
+    print_or_apply(&what, &changes, dry_run, client.apply(&changes)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, LiveCondition, LivePolicy, NewrelicClient, Policy};
+    use crate::Result;
+    use reqwest::Url;
+    use serde_json::json;
+    use shipcat_definitions::structs::newrelic::{
+        NewrelicAlert, NewrelicCondition, NewrelicIncidentPreference,
+    };
+
+    fn condition(name: &str, threshold: &str) -> NewrelicCondition {
+        let mut c = NewrelicAlert {
+            name: name.into(),
+            template: "apdex".into(),
+            params: vec![("threshold".to_string(), threshold.to_string())]
+                .into_iter()
+                .collect(),
+        }
+        .condition()
+        .unwrap();
+        c.entities = vec!["42".into()];
+        c
+    }
+
+    fn live(id: &str, c: NewrelicCondition) -> LiveCondition {
+        LiveCondition {
+            id: id.into(),
+            name: c.name.clone(),
+            condition: Some(c),
+        }
+    }
+
+    fn policy(name: &str, conditions: Vec<NewrelicCondition>) -> Policy {
+        Policy {
+            name: name.into(),
+            application: name.into(),
+            incident_preference: NewrelicIncidentPreference::PerPolicy,
+            conditions,
+        }
+    }
+
+    #[test]
+    fn newrelic_diff_ownership() {
+        let wanted = vec![
+            policy("ask@dev-uk", vec![
+                condition("slow", "0.7"),
+                condition("slower", "0.5"),
+            ]),
+            policy("storage@dev-uk", vec![condition("slow", "0.7")]),
+        ];
+        let live = vec![
+            LivePolicy {
+                id: "1".into(),
+                name: "ask@dev-uk".into(),
+                incident_preference: NewrelicIncidentPreference::PerCondition,
+                conditions: vec![
+                    live("10", condition("slow", "0.8")),
+                    live("11", condition("manual", "0.1")),
+                    LiveCondition {
+                        id: "12".into(),
+                        name: "nrql".into(),
+                        condition: None,
+                    },
+                ],
+                owned: true,
+            },
+            LivePolicy {
+                id: "2".into(),
+                name: "gone@dev-uk".into(),
+                incident_preference: NewrelicIncidentPreference::PerPolicy,
+                conditions: vec![],
+                owned: true,
+            },
+        ];
+        let summary = diff(&wanted, &live)
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            "~ policy ask@dev-uk (incident_preference)",
+            "~ condition ask@dev-uk/slow (terms)",
+            "+ condition ask@dev-uk/slower",
+            "- condition ask@dev-uk/manual",
+            "+ policy storage@dev-uk",
+            "+ condition storage@dev-uk/slow",
+            "- policy gone@dev-uk",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn newrelic_sync_against_stub() -> Result<()> {
+        let app = mockito::mock("GET", "/applications.json?filter%5Bname%5D=ask%40dev-uk")
+            .match_header("x-api-key", "key")
+            .with_body(json!({ "applications": [{ "id": 42, "name": "ask@dev-uk" }] }).to_string())
+            .create();
+        let policies = mockito::mock("GET", "/alerts_policies.json?page=1")
+            .with_body(
+                json!({ "policies": [
+                    { "id": 1, "name": "ask@dev-uk", "incident_preference": "PER_POLICY" },
+                    { "id": 2, "name": "ask@prod-uk", "incident_preference": "PER_POLICY" },
+                ]})
+                .to_string(),
+            )
+            .create();
+        let done = mockito::mock("GET", "/alerts_policies.json?page=2")
+            .with_body(json!({ "policies": [] }).to_string())
+            .create();
+        let mut live = condition("slow", "0.8");
+        let mut live_json = json!(live);
+        live_json["id"] = json!(10);
+        live.entities = vec![];
+        let conditions = mockito::mock("GET", "/alerts_conditions.json?policy_id=1")
+            .with_body(json!({ "conditions": [live_json] }).to_string())
+            .create();
+        let update = mockito::mock("PUT", "/alerts_conditions/10.json")
+            .match_body(mockito::Matcher::Regex("\"threshold\":\"0.7\"".into()))
+            .create();
+        let other_region = mockito::mock("GET", "/alerts_conditions.json?policy_id=2")
+            .expect(0)
+            .create();
+
+        let url = Url::parse(&format!("{}/", mockito::server_url()))?;
+        let client = NewrelicClient::new(url, "key".into());
+        let mut wanted = policy("ask@dev-uk", vec![condition("slow", "0.7")]);
+        wanted.conditions[0].entities = vec![];
+        let (wanted, skipped) = super::with_entities(&client, vec![wanted]).await?;
+        assert!(skipped.is_empty());
+        let found = client.fetch("dev-uk", &wanted).await?;
+        let changes = diff(&wanted, &found);
+        assert_eq!(changes.len(), 1);
+        client.apply(&changes).await?;
+
+        app.assert();
+        policies.assert();
+        done.assert();
+        conditions.assert();
+        update.assert();
+        other_region.assert();
+        Ok(())
+    }
+}
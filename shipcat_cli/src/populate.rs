@@ -0,0 +1,66 @@
+use serde_json::{json, Value};
+
+use super::{Config, Manifest, Region, Result};
+use crate::{
+    istio, networkpolicy, prometheusrule,
+    registry::{self, RegistryClient},
+    strimzi,
+};
+
+/// Metadata for an object generated for a service outside of its chart
+///
+/// `labels` gain the `app.kubernetes.io` labels the chart sets on its own objects.
+/// Owner references are only possible once the ShipcatManifest exists, i.e. when `uid` is known.
+pub fn metadata(name: &str, mf: &Manifest, mut labels: Value) -> Value {
+    labels["app.kubernetes.io/name"] = json!(mf.name);
+    labels["app.kubernetes.io/managed-by"] = json!("shipcat");
+    if let Some(v) = &mf.version {
+        labels["app.kubernetes.io/version"] = json!(v);
+    }
+    let mut md = json!({
+        "name": name,
+        "namespace": mf.namespace,
+        "labels": labels,
+    });
+    if let Some(uid) = &mf.uid {
+        md["ownerReferences"] = json!([{
+            "apiVersion": "babylontech.co.uk/v1",
+            "kind": "ShipcatManifest",
+            "name": mf.name,
+            "uid": uid,
+            "controller": false,
+        }]);
+    }
+    md
+}
+
+/// Load every manifest enabled in a region (without secrets)
+///
+/// Load these once and pass them to `populate_region_resources` for every service.
+pub async fn region_manifests(conf: &Config, reg: &Region) -> Result<Vec<Manifest>> {
+    let mut mfs = vec![];
+    for svc in shipcat_filebacked::available(conf, reg).await? {
+        mfs.push(shipcat_filebacked::load_manifest(&svc.base.name, conf, reg).await?);
+    }
+    Ok(mfs)
+}
+
+/// Inject the objects generated from the rest of the region before templating
+///
+/// NetworkPolicies, istio and strimzi objects depend on the dependencies of other services,
+/// and PrometheusRules on squad routing. `mfs` are the `region_manifests`.
+/// Image sizes and digests come from the `images` registry in regions with a `registry` config.
+pub async fn populate_region_resources(
+    mf: &mut Manifest,
+    mfs: &[Manifest],
+    conf: &Config,
+    reg: &Region,
+    images: &RegistryClient,
+) -> Result<()> {
+    networkpolicy::populate(mf, mfs, reg);
+    istio::populate(mf, mfs, reg);
+    strimzi::populate(mf, mfs, reg);
+    prometheusrule::populate(mf, conf, reg);
+    registry::populate(mf, reg, images).await?;
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use serde_json::{json, Value};
+use shipcat_definitions::teams::Owners;
+
+use super::{populate, structs::metadata::Metadata, Config, Manifest, Region};
+
+/// Slack channel alertmanager should route a service's alerts to
+///
+/// The service's own notifications channel wins over the squad's alerts or notifications channel.
+fn slack_channel(md: &Metadata, owners: &Owners) -> Option<String> {
+    if let Some(chan) = &md.notifications {
+        return Some(chan.to_string());
+    }
+    let slack = &owners.squads.get(&md.team)?.slack;
+    slack
+        .alerts
+        .as_ref()
+        .or(slack.notifications.as_ref())
+        .map(|c| c.to_string())
+}
+
+/// Link to the runbook on the default branch of the service's repository
+fn runbook_url(md: &Metadata) -> Option<String> {
+    let runbook = md.runbook.as_ref()?;
+    if runbook.starts_with("http") {
+        Some(runbook.clone())
+    } else if md.repo.contains("/tree/") {
+        Some(format!("{}/{}", md.repo.trim_end_matches('/'), runbook))
+    } else {
+        let branch = md.defaultBranch.as_deref().unwrap_or("master");
+        Some(format!(
+            "{}/blob/{}/{}",
+            md.repo.trim_end_matches('/'),
+            branch,
+            runbook
+        ))
+    }
+}
+
+/// Build the PrometheusRule of a service
+///
+/// Every alert is labelled with its severity and, when known, the squad, tribe and
+/// slack channel of the owners for alertmanager routing.
+/// `grafana` is the services dashboard link of the region.
+pub fn build(mf: &Manifest, owners: &Owners, grafana: Option<String>) -> Option<Value> {
+    if mf.prometheusAlerts.is_empty() {
+        return None;
+    }
+    let md = mf.metadata.as_ref();
+    let mut routing = json!({});
+    if let Some(md) = md {
+        if let Some(squad) = &md.squad {
+            routing["squad"] = json!(squad);
+        }
+        if let Some(tribe) = &md.tribe {
+            routing["tribe"] = json!(tribe);
+        }
+        if let Some(chan) = slack_channel(md, owners) {
+            routing["slack_channel"] = json!(chan);
+        }
+    }
+    let mut links = json!({});
+    if let Some(url) = md.and_then(runbook_url) {
+        links["runbook_url"] = json!(url);
+    }
+    if let Some(url) = grafana {
+        links["dashboard"] = json!(url);
+    }
+
+    let rules = mf
+        .prometheusAlerts
+        .iter()
+        .map(|pa| {
+            let mut labels = routing.clone();
+            labels["severity"] = json!(pa.severity);
+            let mut annotations = links.clone();
+            annotations["summary"] = json!(pa.summary);
+            annotations["description"] = json!(pa.description);
+            json!({
+                "alert": pa.name,
+                "expr": pa.expr,
+                "for": pa.min_duration,
+                "labels": labels,
+                "annotations": annotations,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut labels = json!(mf.labels);
+    labels["app"] = json!(mf.name);
+    let metadata = populate::metadata(&format!("{}-alerts", mf.name), mf, labels);
+    Some(json!({
+        "apiVersion": "monitoring.coreos.com/v1",
+        "kind": "PrometheusRule",
+        "metadata": metadata,
+        "spec": {
+            "groups": [{ "name": format!("{}.alerts", mf.name), "rules": rules }],
+        },
+    }))
+}
+
+/// Inject the PrometheusRule of a service for the helm chart
+pub fn populate(mf: &mut Manifest, conf: &Config, reg: &Region) {
+    mf.prometheusRule = build(mf, &conf.owners, reg.grafana_url(&mf.name));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::build;
+    use crate::Manifest;
+    use serde_json::json;
+    use shipcat_definitions::teams::Owners;
+
+    #[test]
+    fn prometheus_rule_routing() {
+        let mut mf = Manifest::test("orders");
+        assert!(build(&mf, &Owners::default(), None).is_none());
+
+        mf.version = Some("1.0.0".into());
+        mf.uid = Some("1234".into());
+        mf.metadata = Some(
+            serde_yaml::from_str(
+                "
+repo: https://github.com/babylonhealth/orders
+team: shop
+runbook: RUNBOOK.md",
+            )
+            .unwrap(),
+        );
+        mf.metadata.as_mut().unwrap().squad = Some("shop".into());
+        mf.prometheusAlerts = vec![serde_yaml::from_str(
+            "
+name: OrdersDown
+summary: Orders is down
+description: No orders pods are ready
+expr: 'kube_deployment_status_replicas_available{deployment=\"orders\"} == 0'
+min_duration: 5m
+severity: error",
+        )
+        .unwrap()];
+        let owners: Owners = serde_yaml::from_str(
+            "
+people: {}
+tribes: {}
+squads:
+  shop:
+    name: shop
+    members: []
+    github: { team: shop }
+    slack: { support: CSUPPORT, alerts: CALERTS }",
+        )
+        .unwrap();
+
+        let rule = build(&mf, &owners, Some("https://grafana/d/x".into())).unwrap();
+        assert_eq!(rule["metadata"]["name"], "orders-alerts");
+        assert_eq!(rule["metadata"]["labels"]["app.kubernetes.io/version"], "1.0.0");
+        assert_eq!(rule["metadata"]["ownerReferences"][0]["uid"], "1234");
+        assert_eq!(
+            rule["spec"]["groups"][0]["rules"][0],
+            json!({
+                "alert": "OrdersDown",
+                "expr": "kube_deployment_status_replicas_available{deployment=\"orders\"} == 0",
+                "for": "5m",
+                "labels": {
+                    "squad": "shop",
+                    "slack_channel": "CALERTS",
+                    "severity": "error",
+                },
+                "annotations": {
+                    "runbook_url": "https://github.com/babylonhealth/orders/blob/master/RUNBOOK.md",
+                    "dashboard": "https://grafana/d/x",
+                    "summary": "Orders is down",
+                    "description": "No orders pods are ready",
+                },
+            })
+        );
+
+        // runbooks link to the default branch of the repository
+        mf.metadata.as_mut().unwrap().defaultBranch = Some("main".into());
+        let rule = build(&mf, &owners, None).unwrap();
+        assert_eq!(
+            rule["spec"]["groups"][0]["rules"][0]["annotations"]["runbook_url"],
+            "https://github.com/babylonhealth/orders/blob/main/RUNBOOK.md"
+        );
+    }
+}
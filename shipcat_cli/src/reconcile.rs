@@ -0,0 +1,157 @@
+use std::{fmt::Display, future::Future};
+
+use reqwest::{header::LINK, Method, RequestBuilder, Response, Url};
+use serde_json::Value;
+
+use super::{ErrorKind, Result, ResultExt};
+
+/// How requests to an external API authenticate
+#[derive(Clone, Debug)]
+pub enum Auth {
+    None,
+    /// `Authorization: Bearer <token>`
+    Bearer(String),
+    /// A custom header carrying an api key
+    Header(&'static str, String),
+}
+
+/// Body of a request to an external API
+pub enum Body {
+    Empty,
+    Json(Value),
+    Form(Vec<(String, String)>),
+}
+
+/// Client for a JSON API rooted at a base url
+pub struct JsonClient {
+    url: Url,
+    auth: Auth,
+}
+
+impl JsonClient {
+    /// Client against `url`, which should end in a slash so paths join below it
+    pub fn new(url: Url, auth: Auth) -> Self {
+        JsonClient { url, auth }
+    }
+
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        let req = reqwest::Client::new().request(method, url);
+        match &self.auth {
+            Auth::None => req,
+            Auth::Bearer(token) => req.bearer_auth(token),
+            Auth::Header(name, key) => req.header(*name, key.as_str()),
+        }
+    }
+
+    async fn execute(&self, method: Method, path: &str, body: Body) -> Result<Response> {
+        let url = self.url.join(path)?;
+        debug!("{} {}", method, url);
+        let req = match body {
+            Body::Empty => self.request(method, url.clone()),
+            Body::Json(b) => self.request(method, url.clone()).json(&b),
+            Body::Form(f) => self.request(method, url.clone()).form(&f),
+        };
+        req.send()
+            .await
+            .chain_err(|| ErrorKind::Url(url.clone()))?
+            .error_for_status()
+            .chain_err(|| ErrorKind::Url(url.clone()))
+    }
+
+    /// Send a request to `path` below the base url
+    ///
+    /// `path` may also be an absolute url, like a link to the next page.
+    /// Fails on error statuses. Empty responses are returned as `Value::Null`.
+    pub async fn send(&self, method: Method, path: &str, body: Body) -> Result<Value> {
+        parse(self.execute(method, path, body).await?).await
+    }
+
+    /// Fetch every page of a list, following `rel="next"` links in the `Link` header
+    ///
+    /// Links marked `results="false"`, as sentry marks its last page, are not followed.
+    pub async fn list(&self, path: &str) -> Result<Vec<Value>> {
+        let mut res = vec![];
+        let mut next = Some(path.to_string());
+        while let Some(path) = next {
+            let response = self.execute(Method::GET, &path, Body::Empty).await?;
+            next = response
+                .headers()
+                .get(LINK)
+                .and_then(|l| l.to_str().ok())
+                .and_then(next_link);
+            match parse(response).await? {
+                Value::Array(xs) => res.extend(xs),
+                v => bail!("expected a list from {}, got {}", path, v),
+            }
+        }
+        Ok(res)
+    }
+}
+
+async fn parse(response: Response) -> Result<Value> {
+    let text = response.text().await?;
+    if text.is_empty() {
+        return Ok(Value::Null);
+    }
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// The next page url in a `Link` header
+fn next_link(header: &str) -> Option<String> {
+    header.split(',').find_map(|link| {
+        let mut parts = link.split(';').map(str::trim);
+        let url = parts.next()?.trim_start_matches('<').trim_end_matches('>');
+        let params = parts.collect::<Vec<_>>();
+        if params.contains(&"rel=\"next\"") && !params.contains(&"results=\"false\"") {
+            Some(url.to_string())
+        } else {
+            None
+        }
+    })
+}
+
+/// Print a list of changes, noting when `what` is already up to date
+pub fn print_changes<C: Display>(what: &str, changes: &[C]) {
+    if changes.is_empty() {
+        info!("{} is up to date", what);
+    }
+    for c in changes {
+        println!("{}", c);
+    }
+}
+
+/// Print the changes with `dry_run`, otherwise run `apply`
+///
+/// `apply` is only polled when not in `dry_run`, so it can be built up front.
+pub async fn print_or_apply<C, F>(what: &str, changes: &[C], dry_run: bool, apply: F) -> Result<()>
+where
+    C: Display,
+    F: Future<Output = Result<()>>,
+{
+    if dry_run {
+        print_changes(what, changes);
+        return Ok(());
+    }
+    apply.await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::next_link;
+
+    #[test]
+    fn link_header_pagination() {
+        let page = "<https://sentry.io/api/0/organizations/o/projects/?&cursor=100:0:1>; rel=\"previous\"; results=\"false\"; cursor=\"100:0:1\", <https://sentry.io/api/0/organizations/o/projects/?&cursor=100:1:0>; rel=\"next\"; results=\"true\"; cursor=\"100:1:0\"";
+        assert_eq!(
+            next_link(page).unwrap(),
+            "https://sentry.io/api/0/organizations/o/projects/?&cursor=100:1:0"
+        );
+        let last =
+            "<https://sentry.io/x/?&cursor=100:2:0>; rel=\"next\"; results=\"false\"; cursor=\"100:2:0\"";
+        assert_eq!(next_link(last), None);
+        assert_eq!(
+            next_link("<https://api/x?page=2>; rel=\"next\"").unwrap(),
+            "https://api/x?page=2"
+        );
+    }
+}
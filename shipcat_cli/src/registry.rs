@@ -0,0 +1,467 @@
+use std::{collections::BTreeMap, env, fmt, sync::Mutex};
+
+use reqwest::{header, Method, StatusCode, Url};
+use serde_json::Value;
+use shipcat_definitions::{structs::Container, Manifest, Region};
+
+use super::{ErrorKind, Result, ResultExt};
+
+const MANIFEST_TYPES: &str = "application/vnd.docker.distribution.manifest.v2+json, \
+                              application/vnd.docker.distribution.manifest.list.v2+json, \
+                              application/vnd.oci.image.manifest.v1+json, \
+                              application/vnd.oci.image.index.v1+json";
+
+/// A parsed docker image reference
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageRef {
+    /// Registry host, e.g. quay.io or registry-1.docker.io
+    pub registry: String,
+    /// Repository within the registry, e.g. library/nginx
+    pub repository: String,
+    /// Tag or digest
+    pub reference: String,
+}
+
+impl ImageRef {
+    /// Parse an image name with an optional separate tag
+    ///
+    /// Follows the docker conventions: the first path component is a registry
+    /// only if it looks like a host, and single component images live in `library/`.
+    pub fn parse(image: &str, version: Option<&str>) -> Self {
+        let (name, inline) = if let Some(i) = image.find('@') {
+            (&image[..i], Some(&image[i + 1..]))
+        } else {
+            match image.rfind(':') {
+                Some(i) if !image[i..].contains('/') => (&image[..i], Some(&image[i + 1..])),
+                _ => (image, None),
+            }
+        };
+        let reference = version.or(inline).unwrap_or("latest").to_string();
+        let mut parts = name.splitn(2, '/');
+        let first = parts.next().unwrap_or_default();
+        let (registry, repository) = match parts.next() {
+            Some(rest) if first.contains('.') || first.contains(':') || first == "localhost" => {
+                (first.to_string(), rest.to_string())
+            }
+            Some(_) => ("registry-1.docker.io".to_string(), name.to_string()),
+            None => ("registry-1.docker.io".to_string(), format!("library/{}", name)),
+        };
+        ImageRef {
+            registry,
+            repository,
+            reference,
+        }
+    }
+}
+
+impl fmt::Display for ImageRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}:{}", self.registry, self.repository, self.reference)
+    }
+}
+
+/// What the registry knows about a tag
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageInfo {
+    /// Content digest of the manifest the tag points to
+    pub digest: String,
+    /// Sum of the compressed layer sizes in bytes
+    pub size: u64,
+}
+
+/// Client for the Docker Registry HTTP API v2
+///
+/// Handles anonymous and basic-auth bearer token flows.
+/// Lookups are cached, so verifying and populating a manifest only inspects each image once.
+pub struct RegistryClient {
+    /// Registry to use for every image instead of the one in its name
+    url: Option<Url>,
+    credentials: Option<(String, String)>,
+    inspected: Mutex<BTreeMap<String, Option<ImageInfo>>>,
+}
+
+/// Parse the parameters of a `WWW-Authenticate: Bearer ...` challenge
+fn bearer_challenge(header: &str) -> Option<Vec<(String, String)>> {
+    let params = header.strip_prefix("Bearer ")?;
+    let mut res = vec![];
+    let mut rest = params.trim();
+    while !rest.is_empty() {
+        let eq = rest.find('=')?;
+        let key = rest[..eq].trim().to_string();
+        let after = &rest[eq + 1..];
+        let (value, next) = if let Some(quoted) = after.strip_prefix('"') {
+            let end = quoted.find('"')?;
+            (&quoted[..end], &quoted[end + 1..])
+        } else {
+            let end = after.find(',').unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+        res.push((key, value.to_string()));
+        rest = next.trim_start_matches(',').trim();
+    }
+    Some(res)
+}
+
+impl RegistryClient {
+    /// Client talking to the registries named in images, or always to `url`
+    pub fn new(url: Option<Url>, credentials: Option<(String, String)>) -> Self {
+        RegistryClient {
+            url,
+            credentials,
+            inspected: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Client with optional `REGISTRY_USERNAME`/`REGISTRY_PASSWORD` credentials
+    ///
+    /// `REGISTRY_URL` redirects every lookup to one registry, e.g. a local mirror.
+    pub fn from_env() -> Result<Self> {
+        let url = match env::var("REGISTRY_URL") {
+            Ok(u) => Some(Url::parse(&u)?),
+            Err(_) => None,
+        };
+        let credentials = match (env::var("REGISTRY_USERNAME"), env::var("REGISTRY_PASSWORD")) {
+            (Ok(u), Ok(p)) => Some((u, p)),
+            _ => None,
+        };
+        Ok(RegistryClient::new(url, credentials))
+    }
+
+    fn manifest_url(&self, image: &ImageRef, reference: &str) -> Result<Url> {
+        let base = match &self.url {
+            Some(u) => u.clone(),
+            None => Url::parse(&format!("https://{}/", image.registry))?,
+        };
+        let path = format!("v2/{}/manifests/{}", image.repository, reference);
+        Ok(base.join(&path)?)
+    }
+
+    /// Fetch a bearer token for the scope demanded by a challenge
+    async fn token(&self, challenge: &str) -> Result<String> {
+        let params = match bearer_challenge(challenge) {
+            Some(p) => p,
+            None => bail!("Unsupported registry auth challenge: {}", challenge),
+        };
+        let realm = match params.iter().find(|(k, _)| k == "realm") {
+            Some((_, r)) => r.clone(),
+            None => bail!("Registry auth challenge without a realm: {}", challenge),
+        };
+        let mut url = Url::parse(&realm)?;
+        for (k, v) in params.iter().filter(|(k, _)| k == "service" || k == "scope") {
+            url.query_pairs_mut().append_pair(k, v);
+        }
+        let mut req = reqwest::Client::new().get(url.clone());
+        if let Some((user, pass)) = &self.credentials {
+            req = req.basic_auth(user, Some(pass));
+        }
+        let body: Value = req
+            .send()
+            .await
+            .chain_err(|| ErrorKind::Url(url.clone()))?
+            .error_for_status()
+            .chain_err(|| ErrorKind::Url(url.clone()))?
+            .json()
+            .await?;
+        match body["token"].as_str().or_else(|| body["access_token"].as_str()) {
+            Some(t) => Ok(t.to_string()),
+            None => bail!("No token in registry auth response from {}", url),
+        }
+    }
+
+    /// GET a manifest, authenticating once if challenged
+    ///
+    /// Returns the digest header and the body, or `None` if the reference does not exist.
+    async fn get_manifest(
+        &self,
+        image: &ImageRef,
+        reference: &str,
+    ) -> Result<Option<(Option<String>, Value)>> {
+        let url = self.manifest_url(image, reference)?;
+        let mut token: Option<String> = None;
+        loop {
+            debug!("GET {}", url);
+            let mut req = reqwest::Client::new()
+                .request(Method::GET, url.clone())
+                .header(header::ACCEPT, MANIFEST_TYPES);
+            if let Some(t) = &token {
+                req = req.bearer_auth(t);
+            }
+            let res = req.send().await.chain_err(|| ErrorKind::Url(url.clone()))?;
+            if res.status() == StatusCode::UNAUTHORIZED && token.is_none() {
+                let challenge = res
+                    .headers()
+                    .get(header::WWW_AUTHENTICATE)
+                    .and_then(|h| h.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string();
+                token = Some(self.token(&challenge).await?);
+                continue;
+            }
+            if res.status() == StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            let res = res.error_for_status().chain_err(|| ErrorKind::Url(url.clone()))?;
+            let digest = res
+                .headers()
+                .get("docker-content-digest")
+                .and_then(|h| h.to_str().ok())
+                .map(String::from);
+            return Ok(Some((digest, res.json().await?)));
+        }
+    }
+
+    /// Look up a tag, returning `None` if it was never pushed
+    ///
+    /// Multi-arch images are sized by their linux/amd64 manifest.
+    pub async fn inspect(&self, image: &ImageRef) -> Result<Option<ImageInfo>> {
+        let key = image.to_string();
+        if let Some(info) = self.inspected.lock().unwrap().get(&key) {
+            return Ok(info.clone());
+        }
+        let info = self.fetch_info(image).await?;
+        self.inspected.lock().unwrap().insert(key, info.clone());
+        Ok(info)
+    }
+
+    async fn fetch_info(&self, image: &ImageRef) -> Result<Option<ImageInfo>> {
+        let (digest, body) = match self.get_manifest(image, &image.reference).await? {
+            Some(m) => m,
+            None => return Ok(None),
+        };
+        let digest = match digest {
+            Some(d) => d,
+            None => bail!("Registry returned no digest for {}", image),
+        };
+        let layers = if let Some(list) = body["manifests"].as_array() {
+            let platform = list
+                .iter()
+                .find(|m| m["platform"]["os"] == "linux" && m["platform"]["architecture"] == "amd64");
+            let child = match platform
+                .or_else(|| list.first())
+                .and_then(|m| m["digest"].as_str())
+            {
+                Some(d) => d.to_string(),
+                None => bail!("Empty manifest list for {}", image),
+            };
+            match self.get_manifest(image, &child).await? {
+                Some((_, m)) => m["layers"].clone(),
+                None => bail!("Manifest {} of {} is missing", child, image),
+            }
+        } else {
+            body["layers"].clone()
+        };
+        let size = layers
+            .as_array()
+            .map(|ls| ls.iter().filter_map(|l| l["size"].as_u64()).sum())
+            .unwrap_or_default();
+        Ok(Some(ImageInfo { digest, size }))
+    }
+}
+
+/// Containers with an explicit image, i.e. not relying on a chart template
+fn explicit(containers: &[Container]) -> impl Iterator<Item = &Container> {
+    containers.iter().filter(|c| c.image.is_some())
+}
+
+/// Every image a manifest will run
+///
+/// Workers and cron jobs run the main image unless they set their own.
+pub fn images(mf: &Manifest) -> Vec<ImageRef> {
+    let mut res = vec![];
+    if let Some(image) = &mf.image {
+        res.push(ImageRef::parse(image, mf.version.as_deref()));
+    }
+    let containers = explicit(&mf.sidecars)
+        .chain(explicit(&mf.initContainers))
+        .chain(
+            mf.workers
+                .iter()
+                .map(|w| &w.container)
+                .filter(|c| c.image.is_some()),
+        )
+        .chain(
+            mf.cronJobs
+                .iter()
+                .map(|j| &j.container)
+                .filter(|c| c.image.is_some()),
+        );
+    for c in containers {
+        let image = c.image.as_ref().expect("explicit image");
+        res.push(ImageRef::parse(image, c.version.as_deref()));
+    }
+    res
+}
+
+/// Ensure every image of a manifest has been pushed
+pub async fn verify(mf: &Manifest, client: &RegistryClient) -> Result<()> {
+    for image in images(mf) {
+        if client.inspect(&image).await?.is_none() {
+            bail!("Image {} of {} does not exist in the registry", image, mf.name);
+        }
+    }
+    Ok(())
+}
+
+/// Fill in `imageSize` and, if enabled, pinned digests from the registry
+///
+/// The size is the compressed size of the main image in MB.
+/// Does nothing in regions without a `registry` config.
+/// The main image is only looked up once the manifest has a version.
+pub async fn populate(mf: &mut Manifest, region: &Region, client: &RegistryClient) -> Result<()> {
+    let conf = match &region.registry {
+        Some(c) => c,
+        None => return Ok(()),
+    };
+    if mf.version.is_none() {
+        warn!("No version of {} to look up in the registry", mf.name);
+    } else if let Some(image) = &mf.image {
+        let r = ImageRef::parse(image, mf.version.as_deref());
+        let info = match client.inspect(&r).await? {
+            Some(i) => i,
+            None => bail!("Image {} of {} does not exist in the registry", r, mf.name),
+        };
+        mf.imageSize = Some((info.size as f64 / 1_000_000.0).ceil() as u32);
+        if conf.resolveDigests {
+            mf.imageDigest = Some(info.digest);
+        }
+    }
+    if conf.resolveDigests {
+        // other containers with their own image get the digest in the image
+        for c in mf.initContainers.iter_mut().chain(mf.sidecars.iter_mut()) {
+            pin(c, client).await?;
+        }
+        for w in &mut mf.workers {
+            pin(&mut w.container, client).await?;
+        }
+        for j in &mut mf.cronJobs {
+            pin(&mut j.container, client).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Pin an explicit container image to its digest
+///
+/// The version the digest was resolved from is dropped so charts do not append it as a tag.
+async fn pin(c: &mut Container, client: &RegistryClient) -> Result<()> {
+    let image = match &c.image {
+        Some(i) if !i.contains('@') => i.clone(),
+        _ => return Ok(()),
+    };
+    let r = ImageRef::parse(&image, c.version.as_deref());
+    if let Some(info) = client.inspect(&r).await? {
+        c.image = Some(format!("{}@{}", image, info.digest));
+        c.version = None;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bearer_challenge, images, ImageRef, RegistryClient};
+    use crate::Result;
+    use reqwest::Url;
+    use serde_json::json;
+    use shipcat_definitions::{structs::CronJob, Manifest};
+
+    #[test]
+    fn image_references() {
+        let r = ImageRef::parse("nginx", None);
+        assert_eq!(r.to_string(), "registry-1.docker.io/library/nginx:latest");
+        let r = ImageRef::parse("gophernet/netcat:1.0", None);
+        assert_eq!(r.to_string(), "registry-1.docker.io/gophernet/netcat:1.0");
+        let r = ImageRef::parse("quay.io:80/babylon/ask", Some("1.2.3"));
+        assert_eq!(r.registry, "quay.io:80");
+        assert_eq!(r.repository, "babylon/ask");
+        assert_eq!(r.reference, "1.2.3");
+        let r = ImageRef::parse("localhost/ask@sha256:abc", None);
+        assert_eq!(r.to_string(), "localhost/ask:sha256:abc");
+    }
+
+    #[test]
+    fn images_of_workers_and_cron_jobs() -> Result<()> {
+        let mut mf = Manifest::default();
+        mf.name = "ask".into();
+        mf.image = Some("quay.io/babylon/ask".into());
+        mf.version = Some("1.0.0".into());
+        mf.workers = vec![
+            serde_json::from_value(json!({ "name": "poller", "replicaCount": 1 }))?,
+            serde_json::from_value(json!({
+                "name": "exporter",
+                "replicaCount": 1,
+                "image": "quay.io/babylon/exporter",
+                "version": "0.2.0",
+            }))?,
+        ];
+        let mut job = CronJob::default();
+        job.container.image = Some("busybox:1.31".into());
+        mf.cronJobs = vec![job];
+        let found = images(&mf).iter().map(|i| i.to_string()).collect::<Vec<_>>();
+        assert_eq!(found, vec![
+            "quay.io/babylon/ask:1.0.0",
+            "quay.io/babylon/exporter:0.2.0",
+            "registry-1.docker.io/library/busybox:1.31",
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn registry_challenges() {
+        let c = r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/nginx:pull""#;
+        let params = bearer_challenge(c).unwrap();
+        assert_eq!(params[0], ("realm".into(), "https://auth.docker.io/token".into()));
+        assert_eq!(
+            params[2],
+            ("scope".into(), "repository:library/nginx:pull".into())
+        );
+        assert!(bearer_challenge("Basic realm=x").is_none());
+    }
+
+    #[tokio::test]
+    async fn registry_inspect_against_stub() -> Result<()> {
+        let server = mockito::server_url();
+        let challenge = format!(
+            "Bearer realm=\"{}/token\",service=\"stub\",scope=\"repository:babylon/ask:pull\"",
+            server
+        );
+        let anonymous = mockito::mock("GET", "/v2/babylon/ask/manifests/1.0.0")
+            .match_header("authorization", mockito::Matcher::Missing)
+            .with_status(401)
+            .with_header("www-authenticate", &challenge)
+            .create();
+        let token = mockito::mock(
+            "GET",
+            "/token?service=stub&scope=repository%3Ababylon%2Fask%3Apull",
+        )
+        .match_header("authorization", "Basic dXNlcjpwYXNz")
+        .with_body(json!({ "token": "t0k" }).to_string())
+        .create();
+        let manifest = mockito::mock("GET", "/v2/babylon/ask/manifests/1.0.0")
+            .match_header("authorization", "Bearer t0k")
+            .with_header("docker-content-digest", "sha256:abc")
+            .with_body(json!({ "layers": [{ "size": 1_500_000 }, { "size": 600_000 }] }).to_string())
+            .create();
+        let missing = mockito::mock("GET", "/v2/babylon/ask/manifests/9.9.9")
+            .with_status(404)
+            .create();
+
+        let url = Url::parse(&format!("{}/", server))?;
+        let client = RegistryClient::new(Some(url), Some(("user".into(), "pass".into())));
+        let info = client
+            .inspect(&ImageRef::parse("quay.io/babylon/ask", Some("1.0.0")))
+            .await?
+            .unwrap();
+        assert_eq!(info.digest, "sha256:abc");
+        assert_eq!(info.size, 2_100_000);
+        let none = client
+            .inspect(&ImageRef::parse("quay.io/babylon/ask", Some("9.9.9")))
+            .await?;
+        assert!(none.is_none());
+
+        anonymous.assert();
+        token.assert();
+        manifest.assert();
+        missing.assert();
+        Ok(())
+    }
+}
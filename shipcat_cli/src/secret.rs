@@ -0,0 +1,459 @@
+use futures::stream::{self, StreamExt};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+};
+
+use super::{Config, Error, Manifest, Region, Result};
+use ring::{hmac, rand::SystemRandom};
+use shipcat_definitions::Vault;
+
+/// Keys per vault folder under the region folder
+pub type VaultContents = BTreeMap<String, BTreeSet<String>>;
+
+/// A vault key referenced by manifests that does not exist
+#[derive(Serialize, Debug, PartialEq)]
+pub struct MissingSecret {
+    pub path: String,
+    /// Referencing services and their containers
+    pub services: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// A vault folder read by several services via `vault.name`
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SharedFolder {
+    pub path: String,
+    pub services: BTreeSet<String>,
+}
+
+/// Cross reference of manifest secret references and vault contents for a region
+#[derive(Serialize, Debug, Default)]
+pub struct SecretAudit {
+    pub region: String,
+    /// Keys referenced by manifests, but missing from vault
+    pub missing: Vec<MissingSecret>,
+    /// Keys in the folder of a service that no manifest references
+    pub unused: Vec<String>,
+    /// Folders shared between services
+    pub shared: Vec<SharedFolder>,
+    /// Folders not read by any service in the region
+    pub orphanedFolders: Vec<String>,
+}
+
+/// Audit manifests against the contents of a vault region folder
+pub fn audit(region: &str, root: &str, mfs: &[Manifest], live: &VaultContents) -> SecretAudit {
+    // folder -> services reading it
+    let mut readers: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    // folder -> key -> service -> containers
+    let mut refs: BTreeMap<String, BTreeMap<String, BTreeMap<String, BTreeSet<String>>>> = BTreeMap::new();
+    for mf in mfs {
+        let folder = mf.vault_name();
+        readers.entry(folder.clone()).or_default().insert(mf.name.clone());
+        let keys = refs.entry(folder).or_default();
+        for (k, containers) in mf.vault_references() {
+            keys.entry(k).or_default().insert(mf.name.clone(), containers);
+        }
+    }
+
+    let mut res = SecretAudit {
+        region: region.to_string(),
+        ..SecretAudit::default()
+    };
+    let empty = BTreeSet::new();
+    for (folder, keys) in &refs {
+        let found = live.get(folder).unwrap_or(&empty);
+        for (k, services) in keys {
+            if !found.contains(k) {
+                res.missing.push(MissingSecret {
+                    path: format!("{}/{}/{}", root, folder, k),
+                    services: services.clone(),
+                });
+            }
+        }
+    }
+    for (folder, found) in live {
+        match refs.get(folder) {
+            Some(keys) => {
+                for k in found.iter().filter(|k| !keys.contains_key(*k)) {
+                    res.unused.push(format!("{}/{}/{}", root, folder, k));
+                }
+            }
+            None => res.orphanedFolders.push(format!("{}/{}", root, folder)),
+        }
+    }
+    for (folder, services) in readers {
+        if services.len() > 1 {
+            res.shared.push(SharedFolder {
+                path: format!("{}/{}", root, folder),
+                services,
+            });
+        }
+    }
+    res
+}
+
+/// Fetch the keys of every folder under the region folder
+pub async fn fetch(vault: &Vault, region: &Region) -> Result<VaultContents> {
+    let root = &region.vault.folder;
+    let folders = vault.list_folders(root).await?;
+    let mut buffered = stream::iter(folders)
+        .map(|f| async move {
+            let keys = vault.list(&format!("{}/{}", root, f)).await?;
+            Ok::<_, Error>((f, keys.into_iter().collect::<BTreeSet<_>>()))
+        })
+        .buffer_unordered(8);
+    let mut res = VaultContents::new();
+    while let Some(r) = buffered.next().await {
+        let (f, keys) = r?;
+        res.insert(f, keys);
+    }
+    Ok(res)
+}
+
+/// Output formats for secret audits
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AuditFormat {
+    Yaml,
+    /// A markdown checklist
+    Markdown,
+}
+
+impl FromStr for AuditFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "yaml" => Ok(AuditFormat::Yaml),
+            "markdown" => Ok(AuditFormat::Markdown),
+            _ => bail!("Unknown audit format {}", s),
+        }
+    }
+}
+
+fn render_markdown(a: &SecretAudit) -> String {
+    let mut out = vec![format!("# Secret audit for {}", a.region)];
+    out.push("\n## Missing (referenced by manifests, absent from vault)".into());
+    for m in &a.missing {
+        let users = m
+            .services
+            .iter()
+            .map(|(svc, cs)| format!("{}: {}", svc, cs.iter().cloned().collect::<Vec<_>>().join(", ")))
+            .collect::<Vec<_>>();
+        out.push(format!("- [ ] {} ({})", m.path, users.join("; ")));
+    }
+    out.push("\n## Unused (in vault, referenced by no manifest)".into());
+    out.extend(a.unused.iter().map(|u| format!("- [ ] {}", u)));
+    out.push("\n## Shared (read by several services via vault.name)".into());
+    for s in &a.shared {
+        let services = s.services.iter().cloned().collect::<Vec<_>>();
+        out.push(format!("- [ ] {}: {}", s.path, services.join(", ")));
+    }
+    out.push("\n## Orphaned folders (read by no service in the region)".into());
+    out.extend(a.orphanedFolders.iter().map(|f| format!("- [ ] {}", f)));
+    out.join("\n")
+}
+
+/// Audit secret usage in a region against vault
+///
+/// Requires a vault token able to list the region folder.
+pub async fn audit_region(conf: &Config, region: &Region, fmt: AuditFormat) -> Result<SecretAudit> {
+    let mut mfs = vec![];
+    for svc in shipcat_filebacked::available(conf, region).await? {
+        mfs.push(shipcat_filebacked::load_manifest(&svc.base.name, conf, region).await?);
+    }
+    let vault = Vault::regional(&region.vault)?;
+    let live = fetch(&vault, region).await?;
+    let res = audit(&region.name, &region.vault.folder, &mfs, &live);
+    match fmt {
+        AuditFormat::Yaml => println!("{}", serde_yaml::to_string(&res)?),
+        AuditFormat::Markdown => println!("{}", render_markdown(&res)),
+    }
+    if !res.missing.is_empty() {
+        warn!("{} secrets are missing in {}", res.missing.len(), region.name);
+    }
+    Ok(res)
+}
+
+/// Random key for fingerprints, generated once per run and never stored
+///
+/// Fingerprints are only comparable within a run, and cannot be brute forced afterwards.
+fn fingerprint_key() -> Result<hmac::Key> {
+    match hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new()) {
+        Ok(k) => Ok(k),
+        Err(_) => bail!("Failed to generate a fingerprint key"),
+    }
+}
+
+/// Short keyed hash of a secret value, safe to print
+fn fingerprint(key: &hmac::Key, value: &str) -> String {
+    let tag = hmac::sign(key, value.as_bytes());
+    tag.as_ref()
+        .iter()
+        .take(8)
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Hashes of a key whose value differs between regions
+#[derive(Serialize, Debug, PartialEq)]
+pub struct HashPair {
+    pub from: String,
+    pub to: String,
+}
+
+/// Comparison of the vault folder of a service in two regions
+///
+/// Values are never included, only their fingerprints.
+#[derive(Serialize, Debug, Default)]
+pub struct SecretDiff {
+    pub service: String,
+    pub from: String,
+    pub to: String,
+    /// Keys only in the source region
+    pub missing: Vec<String>,
+    /// Keys only in the target region
+    pub extra: Vec<String>,
+    /// Keys present in both regions with different values
+    pub differing: BTreeMap<String, HashPair>,
+    /// Keys present in both regions with equal values
+    pub matching: Vec<String>,
+}
+
+/// Compare secret values of two folders
+///
+/// Differing values are fingerprinted with `key`, so use the same key for both sides.
+pub fn compare(
+    from: &BTreeMap<String, String>,
+    to: &BTreeMap<String, String>,
+    key: &hmac::Key,
+) -> SecretDiff {
+    let mut res = SecretDiff::default();
+    for (k, v) in from {
+        match to.get(k) {
+            None => res.missing.push(k.clone()),
+            Some(w) if w == v => res.matching.push(k.clone()),
+            Some(w) => {
+                res.differing.insert(k.clone(), HashPair {
+                    from: fingerprint(key, v),
+                    to: fingerprint(key, w),
+                });
+            }
+        }
+    }
+    res.extra = to.keys().filter(|k| !from.contains_key(*k)).cloned().collect();
+    res
+}
+
+/// Select the keys to copy given a comparison
+///
+/// Keys equal in both regions are skipped. Keys with a different value in the
+/// target region are only selected with `overwrite`.
+pub fn plan_copy(diff: &SecretDiff, keys: &[String], overwrite: bool) -> Result<Vec<String>> {
+    let mut res = vec![];
+    for k in keys {
+        if diff.missing.contains(k) {
+            res.push(k.clone());
+        } else if diff.differing.contains_key(k) {
+            if !overwrite {
+                bail!(
+                    "{} differs in {} - refusing to overwrite without --overwrite",
+                    k,
+                    diff.to
+                );
+            }
+            res.push(k.clone());
+        } else if diff.matching.contains(k) {
+            debug!("{} already matches in {}", k, diff.to);
+        } else {
+            bail!("{} does not exist for {} in {}", k, diff.service, diff.from);
+        }
+    }
+    Ok(res)
+}
+
+/// Read every key in the vault folder of a service
+///
+/// A folder that does not exist yet is empty.
+async fn read_folder(vault: &Vault, root: &str, name: &str) -> Result<BTreeMap<String, String>> {
+    let mut res = BTreeMap::new();
+    if !vault.list_folders(root).await?.iter().any(|f| f == name) {
+        return Ok(res);
+    }
+    let folder = format!("{}/{}", root, name);
+    for k in vault.list(&folder).await? {
+        let value = vault.read(&format!("{}/{}", folder, k)).await?;
+        res.insert(k, value);
+    }
+    Ok(res)
+}
+
+/// The vault folders of a service in two regions, with clients for both
+struct Folders {
+    src: Vault,
+    dst: Vault,
+    from: String,
+    to: String,
+}
+
+async fn compare_regions(
+    conf: &Config,
+    svc: &str,
+    from: &Region,
+    to: &Region,
+) -> Result<(SecretDiff, Folders)> {
+    // vault.name is not region specific
+    let name = shipcat_filebacked::load_manifest(svc, conf, from)
+        .await?
+        .vault_name();
+    let src = Vault::regional(&from.vault)?;
+    let dst = Vault::regional(&to.vault)?;
+    let mut res = compare(
+        &read_folder(&src, &from.vault.folder, &name).await?,
+        &read_folder(&dst, &to.vault.folder, &name).await?,
+        &fingerprint_key()?,
+    );
+    res.service = svc.into();
+    res.from = from.name.clone();
+    res.to = to.name.clone();
+    let folders = Folders {
+        src,
+        dst,
+        from: format!("{}/{}", from.vault.folder, name),
+        to: format!("{}/{}", to.vault.folder, name),
+    };
+    Ok((res, folders))
+}
+
+/// Show which secrets of a service are missing or differ between two regions
+///
+/// Requires vault tokens able to read the service folder in both regions.
+pub async fn diff_regions(conf: &Config, svc: &str, from: &Region, to: &Region) -> Result<SecretDiff> {
+    let (res, _) = compare_regions(conf, svc, from, to).await?;
+    println!("{}", serde_yaml::to_string(&res)?);
+    Ok(res)
+}
+
+/// Copy selected secrets of a service from one region to another
+///
+/// Without explicit keys, every key missing in the target region is copied.
+/// Requires a vault token able to write to the service folder of the target region.
+pub async fn copy_regions(
+    conf: &Config,
+    svc: &str,
+    from: &Region,
+    to: &Region,
+    keys: Option<Vec<String>>,
+    overwrite: bool,
+) -> Result<Vec<String>> {
+    let (d, folders) = compare_regions(conf, svc, from, to).await?;
+    let selected = keys.unwrap_or_else(|| d.missing.clone());
+    let plan = plan_copy(&d, &selected, overwrite)?;
+    if plan.is_empty() {
+        info!("No secrets to copy for {} from {} to {}", svc, from.name, to.name);
+        return Ok(plan);
+    }
+    for k in &plan {
+        let value = folders.src.read(&format!("{}/{}", folders.from, k)).await?;
+        folders
+            .dst
+            .write(&format!("{}/{}", folders.to, k), &value)
+            .await?;
+        info!("Copied {} to {}/{}", k, folders.to, k);
+    }
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{audit, compare, fingerprint_key, plan_copy, render_markdown, VaultContents};
+    use crate::Manifest;
+    use shipcat_definitions::structs::{Container, EnvVars, VaultOpts};
+    use std::collections::BTreeMap;
+
+    fn env(keys: &[&str]) -> EnvVars {
+        EnvVars::new(
+            keys.iter()
+                .map(|k| (k.to_string(), "IN_VAULT".to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn secret_audit() {
+        let mut ask = Manifest::test("fake-ask");
+        ask.env = env(&["DB_URL"]);
+        ask.sidecars = vec![Container {
+            name: "redis".into(),
+            env: env(&["REDIS_PASS", "DB_URL"]),
+            ..Container::default()
+        }];
+        ask.secretFiles.insert("cert".into(), "IN_VAULT".into());
+        let mut worker = Manifest::test("fake-worker");
+        worker.vault = Some(VaultOpts {
+            name: "fake-ask".into(),
+        });
+        worker.initContainers = vec![Container {
+            name: "migrate".into(),
+            env: env(&["MIGRATE_TOKEN"]),
+            ..Container::default()
+        }];
+
+        let mut live = VaultContents::new();
+        live.insert(
+            "fake-ask".into(),
+            vec!["DB_URL", "REDIS_PASS", "cert", "OLD_KEY"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+        live.insert("removed-svc".into(), vec!["X".to_string()].into_iter().collect());
+
+        let res = audit("dev-uk", "dev-uk", &[ask, worker], &live);
+        assert_eq!(res.missing.len(), 1);
+        assert_eq!(res.missing[0].path, "dev-uk/fake-ask/MIGRATE_TOKEN");
+        assert_eq!(res.missing[0].services["fake-worker"].len(), 1);
+        assert_eq!(res.unused, vec!["dev-uk/fake-ask/OLD_KEY"]);
+        assert_eq!(res.shared.len(), 1);
+        assert_eq!(res.shared[0].services.len(), 2);
+        assert_eq!(res.orphanedFolders, vec!["dev-uk/removed-svc"]);
+
+        let md = render_markdown(&res);
+        assert!(md.contains("- [ ] dev-uk/fake-ask/MIGRATE_TOKEN (fake-worker: migrate)"));
+        assert!(md.contains("- [ ] dev-uk/fake-ask: fake-ask, fake-worker"));
+    }
+
+    #[test]
+    fn secret_diff_and_copy() {
+        let kv = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let from = kv(&[("DB_URL", "pg://a"), ("API_KEY", "s3cret"), ("SAME", "x")]);
+        let to = kv(&[("API_KEY", "other"), ("SAME", "x"), ("LOCAL", "y")]);
+        let key = fingerprint_key().unwrap();
+        let mut d = compare(&from, &to, &key);
+        d.to = "dev-ie".into();
+        assert_eq!(d.missing, vec!["DB_URL"]);
+        assert_eq!(d.extra, vec!["LOCAL"]);
+        assert_eq!(d.matching, vec!["SAME"]);
+        let pair = &d.differing["API_KEY"];
+        assert_eq!(pair.from.len(), 16);
+        assert_ne!(pair.from, pair.to);
+        // fingerprints are stable within a run, but not across runs
+        assert_eq!(compare(&from, &to, &key).differing["API_KEY"], *pair);
+        let other = compare(&from, &to, &fingerprint_key().unwrap());
+        assert_ne!(other.differing["API_KEY"], *pair);
+        let out = serde_yaml::to_string(&d).unwrap();
+        assert!(!out.contains("s3cret") && !out.contains("pg://a"));
+
+        let keys = |ks: &[&str]| ks.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+        assert_eq!(plan_copy(&d, &keys(&["DB_URL", "SAME"]), false).unwrap(), vec![
+            "DB_URL"
+        ]);
+        assert!(plan_copy(&d, &keys(&["API_KEY"]), false).is_err());
+        assert_eq!(plan_copy(&d, &keys(&["API_KEY"]), true).unwrap(), vec!["API_KEY"]);
+        assert!(plan_copy(&d, &keys(&["LOCAL"]), true).is_err());
+    }
+}
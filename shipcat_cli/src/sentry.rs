@@ -0,0 +1,500 @@
+use std::{env, fmt};
+
+use reqwest::{Method, Url};
+use serde_json::{json, Value};
+
+use super::{
+    reconcile::{print_or_apply, Auth, Body, JsonClient},
+    Config, Region, Result,
+};
+
+/// Name of the alert rules shipcat manages in every project, one per environment
+pub const RULE_NAME: &str = "shipcat slack alerts";
+
+/// Name of the rule of an environment, so regions sharing an organisation keep their own
+pub fn rule_name(environment: &str) -> String {
+    format!("{} ({})", RULE_NAME, environment)
+}
+
+const SLACK_ACTION: &str = "sentry.integrations.slack.notify_action.SlackNotifyServiceAction";
+const FIRST_SEEN: &str = "sentry.rules.conditions.first_seen_event.FirstSeenEventCondition";
+const REGRESSION: &str = "sentry.rules.conditions.regression_event.RegressionEventCondition";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RuleCondition {
+    pub id: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RuleAction {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<String>,
+}
+
+/// The parts of a Sentry issue alert rule shipcat manages
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Rule {
+    pub name: String,
+    /// Only issues from this environment trigger the rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+    pub action_match: String,
+    /// Minutes between notifications for the same issue
+    pub frequency: u32,
+    pub conditions: Vec<RuleCondition>,
+    pub actions: Vec<RuleAction>,
+}
+
+/// A project and its alert rule as a region wants them
+#[derive(Clone, Debug, PartialEq)]
+pub struct Project {
+    pub slug: String,
+    /// Team the project is created in, the squad owning the service
+    pub team: String,
+    pub rule: Rule,
+}
+
+/// Alert rule notifying `channel` of new and regressed issues in an environment
+///
+/// `channel` is a slack channel id, or a #name that sentry resolves to an id itself.
+/// A silent service keeps its rule without the slack action,
+/// so it can be unmuted without recreating anything.
+pub fn rule(workspace: &str, channel: &str, silent: bool, environment: &str) -> Rule {
+    let actions = if silent {
+        vec![]
+    } else {
+        vec![RuleAction {
+            id: SLACK_ACTION.into(),
+            workspace: Some(workspace.into()),
+            channel: Some(channel.into()),
+            channel_id: if channel.starts_with('#') {
+                None
+            } else {
+                Some(channel.into())
+            },
+        }]
+    };
+    Rule {
+        name: rule_name(environment),
+        environment: Some(environment.into()),
+        action_match: "any".into(),
+        frequency: 30,
+        conditions: vec![
+            RuleCondition {
+                id: FIRST_SEEN.into(),
+            },
+            RuleCondition {
+                id: REGRESSION.into(),
+            },
+        ],
+        actions,
+    }
+}
+
+/// The projects a region should have in Sentry
+pub async fn desired(conf: &Config, region: &Region) -> Result<Vec<Project>> {
+    let workspace = match region.sentry.as_ref().and_then(|s| s.slack_workspace.clone()) {
+        Some(ws) => ws,
+        None => bail!(
+            "sentry.slack_workspace must be set for {} to sync sentry",
+            region.name
+        ),
+    };
+    let environment = region.environment.to_string();
+    let mut projects = vec![];
+    for svc in shipcat_filebacked::available(conf, region).await? {
+        let mf = shipcat_filebacked::load_manifest(&svc.base.name, conf, region).await?;
+        if let Some(s) = mf.sentry {
+            let team = match &mf.metadata {
+                Some(md) => md.team.clone(),
+                None => bail!("{} needs metadata.team to own its sentry project", mf.name),
+            };
+            projects.push(Project {
+                slug: mf.name.clone(),
+                team,
+                rule: rule(&workspace, &s.slack, s.silent, &environment),
+            });
+        }
+    }
+    Ok(projects)
+}
+
+/// A project as found in the organisation
+#[derive(Clone, Debug)]
+pub struct LiveProject {
+    pub slug: String,
+    /// Id and contents of the shipcat rule of the environment, if the project has one
+    pub rule: Option<(String, Rule)>,
+}
+
+/// A change needed to bring Sentry in line with the region
+#[derive(Clone, Debug, PartialEq)]
+pub enum SentryChange {
+    CreateProject {
+        project: String,
+        team: String,
+    },
+    CreateRule {
+        project: String,
+        rule: Rule,
+    },
+    UpdateRule {
+        project: String,
+        id: String,
+        rule: Rule,
+        fields: Vec<String>,
+    },
+    DeleteRule {
+        project: String,
+        id: String,
+        name: String,
+    },
+}
+
+impl fmt::Display for SentryChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SentryChange::CreateProject { project, team } => {
+                write!(f, "+ project {} (team {})", project, team)
+            }
+            SentryChange::CreateRule { project, rule } => write!(f, "+ rule {}/{}", project, rule.name),
+            SentryChange::UpdateRule {
+                project,
+                rule,
+                fields,
+                ..
+            } => write!(f, "~ rule {}/{} ({})", project, rule.name, fields.join(", ")),
+            SentryChange::DeleteRule { project, name, .. } => write!(f, "- rule {}/{}", project, name),
+        }
+    }
+}
+
+/// Whether live actions match, ignoring the channel ids sentry resolved for #names
+fn same_actions(desired: &[RuleAction], live: &[RuleAction]) -> bool {
+    desired.len() == live.len()
+        && desired.iter().zip(live).all(|(d, l)| {
+            d.id == l.id
+                && d.workspace == l.workspace
+                && d.channel == l.channel
+                && (d.channel_id.is_none() || d.channel_id == l.channel_id)
+        })
+}
+
+fn drifted(desired: &Rule, live: &Rule) -> Vec<String> {
+    let mut fields = vec![];
+    if desired.environment != live.environment {
+        fields.push("environment".into());
+    }
+    if desired.action_match != live.action_match {
+        fields.push("actionMatch".into());
+    }
+    if desired.frequency != live.frequency {
+        fields.push("frequency".into());
+    }
+    if desired.conditions != live.conditions {
+        fields.push("conditions".into());
+    }
+    if !same_actions(&desired.actions, &live.actions) {
+        fields.push("actions".into());
+    }
+    fields
+}
+
+/// Compute the changes needed to turn `live` into `desired`
+///
+/// Projects are never deleted, only the shipcat rule of services without a `sentry` block.
+/// `live` only carries the rules of the region's environment, so other regions' rules are kept.
+pub fn diff(desired: &[Project], live: &[LiveProject]) -> Vec<SentryChange> {
+    let mut changes = vec![];
+    for p in desired {
+        let lp = live.iter().find(|l| l.slug == p.slug);
+        if lp.is_none() {
+            changes.push(SentryChange::CreateProject {
+                project: p.slug.clone(),
+                team: p.team.clone(),
+            });
+        }
+        match lp.and_then(|l| l.rule.as_ref()) {
+            None => changes.push(SentryChange::CreateRule {
+                project: p.slug.clone(),
+                rule: p.rule.clone(),
+            }),
+            Some((id, r)) => {
+                let fields = drifted(&p.rule, r);
+                if !fields.is_empty() {
+                    changes.push(SentryChange::UpdateRule {
+                        project: p.slug.clone(),
+                        id: id.clone(),
+                        rule: p.rule.clone(),
+                        fields,
+                    });
+                }
+            }
+        }
+    }
+    for l in live {
+        if let Some((id, r)) = &l.rule {
+            if !desired.iter().any(|p| p.slug == l.slug) {
+                changes.push(SentryChange::DeleteRule {
+                    project: l.slug.clone(),
+                    id: id.clone(),
+                    name: r.name.clone(),
+                });
+            }
+        }
+    }
+    changes
+}
+
+/// Authenticated client for the Sentry web API of one organisation
+pub struct SentryClient {
+    api: JsonClient,
+    organisation: String,
+}
+
+impl SentryClient {
+    /// Client against a sentry installation, e.g. https://sentry.io/
+    pub fn new(url: Url, token: String, organisation: String) -> Self {
+        SentryClient {
+            api: JsonClient::new(url, Auth::Bearer(token)),
+            organisation,
+        }
+    }
+
+    /// Client for the region from `SENTRY_TOKEN`
+    ///
+    /// `SENTRY_API_URL` overrides the url in the sentry config of the region.
+    pub fn from_env(region: &Region) -> Result<Self> {
+        let conf = match &region.sentry {
+            Some(s) => s,
+            None => bail!("No sentry config for {}", region.name),
+        };
+        let token = match env::var("SENTRY_TOKEN") {
+            Ok(t) => t,
+            Err(_) => bail!("SENTRY_TOKEN must be set to sync sentry"),
+        };
+        let url = env::var("SENTRY_API_URL").unwrap_or_else(|_| conf.url.clone());
+        let url = Url::parse(&format!("{}/", url.trim_end_matches('/')))?;
+        Ok(SentryClient::new(url, token, conf.organisation.clone()))
+    }
+
+    fn team_projects(&self, team: &str) -> String {
+        format!("api/0/teams/{}/{}/projects/", self.organisation, team)
+    }
+
+    fn rules(&self, project: &str) -> String {
+        format!("api/0/projects/{}/{}/rules/", self.organisation, project)
+    }
+
+    /// Projects of the organisation along with their shipcat rule for `environment`
+    ///
+    /// Every team is searched, as services can move between squads.
+    pub async fn fetch(&self, environment: &str) -> Result<Vec<LiveProject>> {
+        let path = format!("api/0/organizations/{}/projects/", self.organisation);
+        let mut res = vec![];
+        for p in self.api.list(&path).await? {
+            let slug = match p["slug"].as_str() {
+                Some(s) => s.to_string(),
+                None => bail!("sentry project without a slug: {}", p),
+            };
+            let rule = environment_rule(self.api.list(&self.rules(&slug)).await?, environment)?;
+            res.push(LiveProject { slug, rule });
+        }
+        Ok(res)
+    }
+
+    /// Apply changes in order
+    pub async fn apply(&self, changes: &[SentryChange]) -> Result<()> {
+        for c in changes {
+            info!("{}", c);
+            match c {
+                SentryChange::CreateProject { project, team } => {
+                    let body = json!({ "name": project, "slug": project });
+                    self.api
+                        .send(Method::POST, &self.team_projects(team), Body::Json(body))
+                        .await?;
+                }
+                SentryChange::CreateRule { project, rule } => {
+                    self.api
+                        .send(Method::POST, &self.rules(project), Body::Json(json!(rule)))
+                        .await?;
+                }
+                SentryChange::UpdateRule {
+                    project, id, rule, ..
+                } => {
+                    let path = format!("{}{}/", self.rules(project), id);
+                    self.api.send(Method::PUT, &path, Body::Json(json!(rule))).await?;
+                }
+                SentryChange::DeleteRule { project, id, .. } => {
+                    let path = format!("{}{}/", self.rules(project), id);
+                    self.api.send(Method::DELETE, &path, Body::Empty).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The id and contents of the shipcat rule of an environment among a project's rules
+fn environment_rule(rules: Vec<Value>, environment: &str) -> Result<Option<(String, Rule)>> {
+    let name = rule_name(environment);
+    match rules.into_iter().find(|r| r["name"] == name.as_str()) {
+        Some(r) => {
+            let id = match &r["id"] {
+                Value::String(s) => s.clone(),
+                v => v.to_string(),
+            };
+            Ok(Some((id, serde_json::from_value(r)?)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Reconcile Sentry projects and slack alert rules with the region
+///
+/// Missing projects are created in the team of the squad owning the service.
+/// With `dry_run` the changes are only printed.
+pub async fn sync(conf: &Config, region: &Region, client: &SentryClient, dry_run: bool) -> Result<()> {
+    let wanted = desired(conf, region).await?;
+    let environment = region.environment.to_string();
+    let changes = diff(&wanted, &client.fetch(&environment).await?);
+    let what = format!("sentry for {}", region.name);
+    print_or_apply(&what, &changes, dry_run, client.apply(&changes)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, drifted, environment_rule, rule, LiveProject, Project, SentryChange, SentryClient};
+    use crate::Result;
+    use reqwest::Url;
+    use serde_json::json;
+
+    #[test]
+    fn sentry_diff_silent_and_removed() {
+        let wanted = vec![
+            Project {
+                slug: "ask".into(),
+                team: "ai".into(),
+                rule: rule("123", "CASK", true, "dev"),
+            },
+            Project {
+                slug: "orders".into(),
+                team: "shop".into(),
+                rule: rule("123", "#orders", false, "dev"),
+            },
+        ];
+        let live = vec![
+            LiveProject {
+                slug: "ask".into(),
+                rule: Some(("1".into(), rule("123", "CASK", false, "dev"))),
+            },
+            LiveProject {
+                slug: "gone".into(),
+                rule: Some(("2".into(), rule("123", "CGONE", false, "dev"))),
+            },
+            LiveProject {
+                slug: "manual".into(),
+                rule: None,
+            },
+        ];
+        let changes = diff(&wanted, &live);
+        let summary = changes.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            "~ rule ask/shipcat slack alerts (dev) (actions)",
+            "+ project orders (team shop)",
+            "+ rule orders/shipcat slack alerts (dev)",
+            "- rule gone/shipcat slack alerts (dev)",
+        ]);
+        match &changes[0] {
+            SentryChange::UpdateRule { rule, .. } => assert!(rule.actions.is_empty()),
+            c => panic!("unexpected change {}", c),
+        }
+    }
+
+    #[test]
+    fn sentry_rules_per_environment() -> Result<()> {
+        let with_id = |id: &str, channel: &str, env: &str| {
+            let mut r = json!(rule("123", channel, false, env));
+            r["id"] = json!(id);
+            r
+        };
+        let dev = rule("123", "CASK", false, "dev");
+        assert_eq!(dev.name, "shipcat slack alerts (dev)");
+        assert_eq!(dev.environment.as_deref(), Some("dev"));
+        // the prod region shares the organisation and has its own rules
+        let gone = vec![with_id("2", "CGONE", "prod"), with_id("3", "CGONE", "dev")];
+        let prod_only = vec![with_id("4", "CPROD", "prod")];
+        let live = vec![
+            LiveProject {
+                slug: "gone".into(),
+                rule: environment_rule(gone, "dev")?,
+            },
+            LiveProject {
+                slug: "prod-only".into(),
+                rule: environment_rule(prod_only, "dev")?,
+            },
+        ];
+        let changes = diff(&[], &live);
+        assert_eq!(changes, vec![SentryChange::DeleteRule {
+            project: "gone".into(),
+            id: "3".into(),
+            name: "shipcat slack alerts (dev)".into(),
+        }]);
+        Ok(())
+    }
+
+    #[test]
+    fn sentry_channel_ids() {
+        let by_id = rule("123", "CORDERS", false, "dev");
+        assert_eq!(by_id.actions[0].channel_id.as_deref(), Some("CORDERS"));
+
+        // sentry fills in the id of a #name, which is not drift
+        let by_name = rule("123", "#orders", false, "dev");
+        assert_eq!(by_name.actions[0].channel_id, None);
+        let mut resolved = by_name.clone();
+        resolved.actions[0].channel_id = Some("CORDERS".into());
+        assert!(drifted(&by_name, &resolved).is_empty());
+        assert_eq!(drifted(&by_id, &rule("123", "COTHER", false, "dev")), vec![
+            "actions"
+        ]);
+    }
+
+    #[tokio::test]
+    async fn sentry_sync_against_stub() -> Result<()> {
+        let projects = mockito::mock("GET", "/api/0/organizations/sentry/projects/")
+            .match_header("authorization", "Bearer token")
+            .with_body(json!([{ "slug": "ask", "name": "ask" }]).to_string())
+            .create();
+        let mut live = json!(rule("123", "COLD", false, "dev"));
+        live["id"] = json!("7");
+        let rules = mockito::mock("GET", "/api/0/projects/sentry/ask/rules/")
+            .with_body(json!([live]).to_string())
+            .create();
+        let update = mockito::mock("PUT", "/api/0/projects/sentry/ask/rules/7/")
+            .match_body(mockito::Matcher::Regex("\"channel_id\":\"CNEW\"".into()))
+            .create();
+
+        let url = Url::parse(&format!("{}/", mockito::server_url()))?;
+        let client = SentryClient::new(url, "token".into(), "sentry".into());
+        let wanted = vec![Project {
+            slug: "ask".into(),
+            team: "ai".into(),
+            rule: rule("123", "CNEW", false, "dev"),
+        }];
+        let found = client.fetch("dev").await?;
+        let changes = diff(&wanted, &found);
+        assert_eq!(changes.len(), 1);
+        client.apply(&changes).await?;
+
+        projects.assert();
+        rules.assert();
+        update.assert();
+        Ok(())
+    }
+}
@@ -1,5 +1,12 @@
-use super::{Config, Region, Result};
-use shipcat_definitions::{structs::Kong, BaseManifest};
+use std::{env, fmt};
+
+use reqwest::{Method, Url};
+
+use super::{
+    reconcile::{print_or_apply, Auth, Body, JsonClient},
+    Config, Region, Result,
+};
+use shipcat_definitions::{structs::Kong, BaseManifest, Environment};
 
 /// One Statuscake object
 #[derive(Serialize)]
@@ -116,3 +123,601 @@ pub async fn output(conf: &Config, region: &Region) -> Result<()> {
 
     Ok(())
 }
+
+// ----------------------------------------------------------------------------------
+// StatusCake API reconciliation
+// ----------------------------------------------------------------------------------
+
+/// Tag marking uptime tests as managed by shipcat
+pub const SHIPCAT_TAG: &str = "shipcat";
+
+/// Seconds between ssl certificate checks
+const SSL_CHECK_RATE: u32 = 86400;
+
+/// An uptime test as sent to and read from the StatusCake API
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct UptimeCheck {
+    pub name: String,
+    pub website_url: String,
+    pub check_rate: u32,
+    #[serde(default)]
+    pub contact_groups: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// An ssl certificate expiry check
+///
+/// The StatusCake API does not tag ssl checks, so they are identified by url.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct SslCheck {
+    pub website_url: String,
+    pub check_rate: u32,
+    #[serde(default)]
+    pub contact_groups: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Check {
+    Uptime(UptimeCheck),
+    Ssl(SslCheck),
+}
+
+impl Check {
+    fn kind(&self) -> &'static str {
+        match self {
+            Check::Uptime(_) => "uptime",
+            Check::Ssl(_) => "ssl",
+        }
+    }
+
+    /// Name of uptime tests, url of ssl checks
+    pub fn key(&self) -> &str {
+        match self {
+            Check::Uptime(u) => &u.name,
+            Check::Ssl(s) => &s.website_url,
+        }
+    }
+
+    /// Form parameters for creating or updating the check
+    fn form(&self) -> Vec<(String, String)> {
+        let mut form = vec![];
+        let groups = match self {
+            Check::Uptime(u) => {
+                form.push(("name".into(), u.name.clone()));
+                form.push(("test_type".into(), "HTTP".into()));
+                form.push(("website_url".into(), u.website_url.clone()));
+                form.push(("check_rate".into(), u.check_rate.to_string()));
+                for t in &u.tags {
+                    form.push(("tags[]".into(), t.clone()));
+                }
+                &u.contact_groups
+            }
+            Check::Ssl(s) => {
+                form.push(("website_url".into(), s.website_url.clone()));
+                form.push(("check_rate".into(), s.check_rate.to_string()));
+                for days in &["7", "14", "30"] {
+                    form.push(("alert_at[]".into(), days.to_string()));
+                }
+                form.push(("alert_expiry".into(), "true".into()));
+                &s.contact_groups
+            }
+        };
+        for g in groups {
+            form.push(("contact_groups[]".into(), g.clone()));
+        }
+        form
+    }
+
+    /// Fields that differ from a live check of the same kind
+    fn drifted(&self, live: &Check) -> Vec<String> {
+        let mut fields = vec![];
+        match (self, live) {
+            (Check::Uptime(d), Check::Uptime(l)) => {
+                if d.website_url != l.website_url {
+                    fields.push("website_url".into());
+                }
+                if d.check_rate != l.check_rate {
+                    fields.push("check_rate".into());
+                }
+                if d.contact_groups != l.contact_groups {
+                    fields.push("contact_groups".into());
+                }
+                if d.tags != l.tags {
+                    fields.push("tags".into());
+                }
+            }
+            (Check::Ssl(d), Check::Ssl(l)) => {
+                if d.check_rate != l.check_rate {
+                    fields.push("check_rate".into());
+                }
+                if d.contact_groups != l.contact_groups {
+                    fields.push("contact_groups".into());
+                }
+            }
+            _ => fields.push("type".into()),
+        }
+        fields
+    }
+}
+
+/// A check as found in the StatusCake API
+#[derive(Clone, Debug)]
+pub struct LiveCheck {
+    pub id: String,
+    pub check: Check,
+    /// Whether shipcat may delete the check
+    pub owned: bool,
+}
+
+/// A change needed to bring StatusCake in line with the region
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum StatuscakeChange {
+    Create {
+        check: Check,
+    },
+    Update {
+        id: String,
+        check: Check,
+        fields: Vec<String>,
+    },
+    Delete {
+        id: String,
+        check: Check,
+    },
+}
+
+impl fmt::Display for StatuscakeChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatuscakeChange::Create { check } => write!(f, "+ {} {}", check.kind(), check.key()),
+            StatuscakeChange::Update { check, fields, .. } => {
+                write!(f, "~ {} {} ({})", check.kind(), check.key(), fields.join(", "))
+            }
+            StatuscakeChange::Delete { check, .. } => write!(f, "- {} {}", check.kind(), check.key()),
+        }
+    }
+}
+
+fn sorted(mut xs: Vec<String>) -> Vec<String> {
+    xs.sort();
+    xs.dedup();
+    xs
+}
+
+fn check_rate(region: &Region) -> u32 {
+    let configured = region.statuscake.as_ref().and_then(|sc| sc.check_rate);
+    configured.unwrap_or(match region.environment {
+        Environment::Prod => 60,
+        _ => 300,
+    })
+}
+
+/// Contact groups for the region, plus those of the owning squad and tribe
+fn contact_groups(
+    conf: &Config,
+    region: &Region,
+    squad: Option<&String>,
+    tribe: Option<&String>,
+) -> Vec<String> {
+    let mut groups = vec![];
+    if let Some(g) = region.statuscake.as_ref().and_then(|sc| sc.contact_group.clone()) {
+        groups.push(g);
+    }
+    if let Some(g) = squad
+        .and_then(|s| conf.owners.squads.get(s))
+        .and_then(|s| s.statuscake.clone())
+    {
+        groups.push(g);
+    }
+    if let Some(g) = tribe
+        .and_then(|t| conf.owners.tribes.get(t))
+        .and_then(|t| t.statuscake.clone())
+    {
+        groups.push(g);
+    }
+    sorted(groups)
+}
+
+/// The uptime and ssl checks a region should have
+///
+/// Every main kong api gets an uptime test, including `extra_apis`,
+/// and every kong host gets an ssl expiry check.
+pub async fn desired(conf: &Config, region: &Region) -> Result<Vec<Check>> {
+    let external_svc = match region.base_urls.get("external_services") {
+        Some(s) => s.to_string(),
+        None => bail!(
+            "base_url.external_services is not defined for region {}",
+            region.name
+        ),
+    };
+    let rate = check_rate(region);
+    let mut base_tags = vec![
+        SHIPCAT_TAG.to_string(),
+        region.name.clone(),
+        region.environment.to_string(),
+    ];
+    if let Some(extra) = region.statuscake.as_ref().and_then(|sc| sc.extra_tags.clone()) {
+        base_tags.push(extra);
+    }
+
+    // (name, kong api, squad, tribe)
+    let mut apis = vec![];
+    for mf in shipcat_filebacked::available(conf, region).await? {
+        let md = &mf.base.metadata;
+        for k in &mf.kong_apis {
+            if k.name == mf.base.name {
+                apis.push((k.clone(), md.squad.clone(), md.tribe.clone()));
+            }
+        }
+    }
+    if let Some(kong) = &region.kong {
+        for api in kong.extra_apis.values() {
+            apis.push((api.clone(), None, None));
+        }
+    }
+
+    let mut checks = vec![];
+    let mut hosts = vec![];
+    for (k, squad, tribe) in apis {
+        let website_url = if let Some(host) = k.hosts.first() {
+            format!("https://{}/health", host)
+        } else if let Some(uris) = &k.uris {
+            format!("{}/status/{}/health", external_svc, uris.trim_start_matches('/'))
+        } else {
+            continue;
+        };
+        let mut tags = base_tags.clone();
+        let name = match (&squad, &tribe) {
+            (Some(s), Some(t)) => {
+                tags.push(format!("squad={}", s));
+                tags.push(format!("tribe={}", t));
+                format!("{} {} healthcheck squad={},tribe={}", region.name, k.name, s, t)
+            }
+            _ => format!("{} {} healthcheck", region.name, k.name),
+        };
+        let groups = contact_groups(conf, region, squad.as_ref(), tribe.as_ref());
+        for h in &k.hosts {
+            if !hosts.iter().any(|(x, _)| x == h) {
+                hosts.push((h.clone(), groups.clone()));
+            }
+        }
+        checks.push(Check::Uptime(UptimeCheck {
+            name,
+            website_url,
+            check_rate: rate,
+            contact_groups: groups,
+            tags: sorted(tags),
+        }));
+    }
+    for (h, groups) in hosts {
+        checks.push(Check::Ssl(SslCheck {
+            website_url: format!("https://{}", h),
+            check_rate: SSL_CHECK_RATE,
+            contact_groups: groups,
+        }));
+    }
+    Ok(checks)
+}
+
+/// Compute the changes needed to turn `live` into `desired`
+///
+/// Only checks marked as owned are ever deleted.
+pub fn diff(desired: &[Check], live: &[LiveCheck]) -> Vec<StatuscakeChange> {
+    let mut changes = vec![];
+    for c in desired {
+        match live
+            .iter()
+            .find(|l| l.check.kind() == c.kind() && l.check.key() == c.key())
+        {
+            None => changes.push(StatuscakeChange::Create { check: c.clone() }),
+            Some(l) => {
+                let fields = c.drifted(&l.check);
+                if !fields.is_empty() {
+                    changes.push(StatuscakeChange::Update {
+                        id: l.id.clone(),
+                        check: c.clone(),
+                        fields,
+                    });
+                }
+            }
+        }
+    }
+    for l in live.iter().filter(|l| l.owned) {
+        if !desired
+            .iter()
+            .any(|c| c.kind() == l.check.kind() && c.key() == l.check.key())
+        {
+            changes.push(StatuscakeChange::Delete {
+                id: l.id.clone(),
+                check: l.check.clone(),
+            });
+        }
+    }
+    changes
+}
+
+/// Authenticated client for the StatusCake API
+pub struct StatuscakeClient {
+    api: JsonClient,
+}
+
+#[derive(Deserialize)]
+struct Page {
+    data: Vec<serde_json::Value>,
+    #[serde(default)]
+    metadata: Option<PageMetadata>,
+}
+
+#[derive(Deserialize)]
+struct PageMetadata {
+    page: u32,
+    page_count: u32,
+}
+
+impl StatuscakeClient {
+    /// Client against `url`, e.g. https://api.statuscake.com/v1/
+    pub fn new(url: Url, token: String) -> Self {
+        StatuscakeClient {
+            api: JsonClient::new(url, Auth::Bearer(token)),
+        }
+    }
+
+    /// Client from `STATUSCAKE_API_KEY` and an optional `STATUSCAKE_API_URL`
+    pub fn from_env() -> Result<Self> {
+        let token = match env::var("STATUSCAKE_API_KEY") {
+            Ok(t) => t,
+            Err(_) => bail!("STATUSCAKE_API_KEY must be set to sync statuscake"),
+        };
+        let url = env::var("STATUSCAKE_API_URL").unwrap_or_else(|_| "https://api.statuscake.com/v1/".into());
+        Ok(StatuscakeClient::new(Url::parse(&url)?, token))
+    }
+
+    async fn list(&self, path: &str) -> Result<Vec<serde_json::Value>> {
+        let mut res = vec![];
+        let mut page = 1;
+        loop {
+            let body = self
+                .api
+                .send(Method::GET, &format!("{}?page={}", path, page), Body::Empty)
+                .await?;
+            let p: Page = serde_json::from_value(body)?;
+            res.extend(p.data);
+            match p.metadata {
+                Some(m) if m.page < m.page_count => page += 1,
+                _ => break,
+            }
+        }
+        Ok(res)
+    }
+
+    /// Fetch uptime and ssl checks, marking those this region may delete
+    ///
+    /// Uptime tests are owned when tagged with both the shipcat tag and the region.
+    /// Ssl checks are owned when their host is under the region's kong `base_url`.
+    pub async fn fetch(&self, region: &str, base_url: Option<&str>) -> Result<Vec<LiveCheck>> {
+        let mut live = vec![];
+        for v in self.list("uptime").await? {
+            let id = id_of(&v)?;
+            let mut u: UptimeCheck = serde_json::from_value(v)?;
+            u.contact_groups = sorted(u.contact_groups);
+            u.tags = sorted(u.tags);
+            let owned = u.tags.iter().any(|t| t == SHIPCAT_TAG) && u.tags.iter().any(|t| t == region);
+            live.push(LiveCheck {
+                id,
+                check: Check::Uptime(u),
+                owned,
+            });
+        }
+        for v in self.list("ssl").await? {
+            live.push(live_ssl(v, base_url)?);
+        }
+        Ok(live)
+    }
+
+    /// Apply changes in order
+    pub async fn apply(&self, changes: &[StatuscakeChange]) -> Result<()> {
+        for c in changes {
+            info!("{}", c);
+            match c {
+                StatuscakeChange::Create { check } => {
+                    let form = Body::Form(check.form());
+                    self.api.send(Method::POST, check.kind(), form).await?;
+                }
+                StatuscakeChange::Update { id, check, .. } => {
+                    let path = format!("{}/{}", check.kind(), id);
+                    self.api
+                        .send(Method::PUT, &path, Body::Form(check.form()))
+                        .await?;
+                }
+                StatuscakeChange::Delete { id, check } => {
+                    let path = format!("{}/{}", check.kind(), id);
+                    self.api.send(Method::DELETE, &path, Body::Empty).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An ssl check from the api, owned when its host is under `base_url`
+fn live_ssl(v: serde_json::Value, base_url: Option<&str>) -> Result<LiveCheck> {
+    let id = id_of(&v)?;
+    let mut s: SslCheck = serde_json::from_value(v)?;
+    s.contact_groups = sorted(s.contact_groups);
+    let owned = base_url.map(|b| under_domain(&s.website_url, b)).unwrap_or(false);
+    Ok(LiveCheck {
+        id,
+        check: Check::Ssl(s),
+        owned,
+    })
+}
+
+/// Whether the host of `url` is `domain` or one of its subdomains
+///
+/// Kong base urls start with a dot, which is ignored.
+fn under_domain(url: &str, domain: &str) -> bool {
+    let domain = domain.trim_start_matches('.');
+    match Url::parse(url) {
+        Ok(u) => u
+            .host_str()
+            .map(|h| h == domain || h.ends_with(&format!(".{}", domain)))
+            .unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+/// StatusCake returns ids as strings, but older tests may have numeric ids
+fn id_of(v: &serde_json::Value) -> Result<String> {
+    match &v["id"] {
+        serde_json::Value::String(s) => Ok(s.clone()),
+        serde_json::Value::Number(n) => Ok(n.to_string()),
+        _ => bail!("statuscake check without an id: {}", v),
+    }
+}
+
+/// Reconcile StatusCake with the region, or print the diff with `dry_run`
+pub async fn sync(conf: &Config, region: &Region, client: &StatuscakeClient, dry_run: bool) -> Result<()> {
+    let wanted = desired(conf, region).await?;
+    let base_url = region.kong.as_ref().map(|k| k.base_url.as_str());
+    let changes = diff(&wanted, &client.fetch(&region.name, base_url).await?);
+    let what = format!("statuscake for {}", region.name);
+    print_or_apply(&what, &changes, dry_run, client.apply(&changes)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, live_ssl, under_domain, Check, LiveCheck, SslCheck, StatuscakeClient, UptimeCheck};
+    use crate::Result;
+    use reqwest::Url;
+    use serde_json::json;
+
+    fn uptime(name: &str, rate: u32, region: &str) -> UptimeCheck {
+        UptimeCheck {
+            name: name.into(),
+            website_url: format!("https://{}.example.com/health", name),
+            check_rate: rate,
+            contact_groups: vec!["1000".into()],
+            tags: vec![region.into(), "shipcat".into()],
+        }
+    }
+
+    #[test]
+    fn statuscake_diff_ownership() {
+        let wanted = vec![
+            Check::Uptime(uptime("ask", 60, "dev-uk")),
+            Check::Uptime(uptime("storage", 60, "dev-uk")),
+        ];
+        let live = vec![
+            LiveCheck {
+                id: "1".into(),
+                check: Check::Uptime(uptime("ask", 300, "dev-uk")),
+                owned: true,
+            },
+            LiveCheck {
+                id: "2".into(),
+                check: Check::Uptime(uptime("gone", 60, "dev-uk")),
+                owned: true,
+            },
+            LiveCheck {
+                id: "3".into(),
+                check: Check::Uptime(uptime("manual", 60, "dev-uk")),
+                owned: false,
+            },
+        ];
+        let summary = diff(&wanted, &live)
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            "~ uptime ask (check_rate)",
+            "+ uptime storage",
+            "- uptime gone",
+        ]);
+    }
+
+    #[test]
+    fn statuscake_ssl_ownership_on_domain_boundary() {
+        assert!(under_domain("https://dev.example.com", "dev.example.com"));
+        assert!(under_domain(
+            "https://api.dev.example.com/health",
+            "dev.example.com"
+        ));
+        assert!(!under_domain("https://evildev.example.com", "dev.example.com"));
+        assert!(!under_domain("not a url", "dev.example.com"));
+        // kong base urls carry a leading dot
+        assert!(under_domain("https://dev.example.com", ".dev.example.com"));
+        assert!(under_domain("https://ask.dev.example.com", ".dev.example.com"));
+        assert!(!under_domain("https://evildev.example.com", ".dev.example.com"));
+    }
+
+    #[test]
+    fn statuscake_deletes_owned_ssl_under_kong_base_url() -> Result<()> {
+        let base_url = Some(".dev.example.com");
+        let live = vec![
+            live_ssl(
+                json!({ "id": 7, "website_url": "https://old.dev.example.com", "check_rate": 86400 }),
+                base_url,
+            )?,
+            live_ssl(
+                json!({ "id": "8", "website_url": "https://old.prod.example.com", "check_rate": 86400 }),
+                base_url,
+            )?,
+        ];
+        assert!(live[0].owned && !live[1].owned);
+        let summary = diff(&[], &live).iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(summary, vec!["- ssl https://old.dev.example.com"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn statuscake_sync_against_stub() -> Result<()> {
+        let page = |data: serde_json::Value| {
+            json!({ "data": data, "metadata": { "page": 1, "page_count": 1 } }).to_string()
+        };
+        let list_uptime = mockito::mock("GET", "/uptime?page=1")
+            .match_header("authorization", "Bearer token")
+            .with_body(page(json!([
+                { "id": "1", "name": "ask", "website_url": "https://ask.example.com/health",
+                  "check_rate": 300, "contact_groups": ["1000"], "tags": ["shipcat", "dev-uk"] },
+                { "id": "2", "name": "other", "website_url": "https://other.example.com/health",
+                  "check_rate": 60, "tags": ["shipcat", "prod-uk"] },
+            ])))
+            .create();
+        let list_ssl = mockito::mock("GET", "/ssl?page=1")
+            .with_body(page(json!([
+                { "id": "7", "website_url": "https://old.example.com", "check_rate": 86400 },
+            ])))
+            .create();
+        let update = mockito::mock("PUT", "/uptime/1")
+            .match_body(mockito::Matcher::Regex("check_rate=60".into()))
+            .create();
+        let create_ssl = mockito::mock("POST", "/ssl").with_status(201).create();
+        let delete_other = mockito::mock("DELETE", "/uptime/2").expect(0).create();
+        let delete_ssl = mockito::mock("DELETE", "/ssl/7").create();
+
+        let url = Url::parse(&format!("{}/", mockito::server_url()))?;
+        let client = StatuscakeClient::new(url, "token".into());
+        let wanted = vec![
+            Check::Uptime(uptime("ask", 60, "dev-uk")),
+            Check::Ssl(SslCheck {
+                website_url: "https://ask.example.com".into(),
+                check_rate: 86400,
+                contact_groups: vec![],
+            }),
+        ];
+        let changes = diff(&wanted, &client.fetch("dev-uk", Some(".example.com")).await?);
+        assert_eq!(changes.len(), 3);
+        client.apply(&changes).await?;
+
+        list_uptime.assert();
+        list_ssl.assert();
+        update.assert();
+        create_ssl.assert();
+        delete_other.assert();
+        delete_ssl.assert();
+        Ok(())
+    }
+}
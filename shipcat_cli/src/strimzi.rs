@@ -0,0 +1,326 @@
+use std::collections::BTreeMap;
+
+use kube::api::{CustomResource, DeleteParams, ListParams, Object, ObjectList};
+use serde_json::{json, Value};
+
+use super::{
+    kubeapi::make_client,
+    populate,
+    structs::{
+        kafkaresources::{AclDefinition, DEFAULT_PARTITIONS, DEFAULT_REPLICAS},
+        EventStream, StrimziResources,
+    },
+    ErrorKind, Manifest, Region, Result,
+};
+
+const API_VERSION: &str = "kafka.strimzi.io/v1beta1";
+
+/// Kubernetes safe name for a kafka topic
+///
+/// The topic operator keeps the real name in `spec.topicName`.
+pub fn resource_name(topic: &str) -> String {
+    topic
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '-' | '.' => c,
+            _ => '-',
+        })
+        .collect()
+}
+
+/// Metadata shared by all strimzi objects of a service
+fn metadata(name: &str, mf: &Manifest, cluster: &str) -> Value {
+    populate::metadata(name, mf, json!({ "strimzi.io/cluster": cluster }))
+}
+
+fn topic(
+    mf: &Manifest,
+    cluster: &str,
+    name: &str,
+    partitions: i32,
+    replicas: i32,
+    config: &BTreeMap<String, String>,
+) -> Value {
+    json!({
+        "apiVersion": API_VERSION,
+        "kind": "KafkaTopic",
+        "metadata": metadata(&resource_name(name), mf, cluster),
+        "spec": {
+            "topicName": name,
+            "partitions": partitions,
+            "replicas": replicas,
+            "config": config,
+        },
+    })
+}
+
+/// Topic for an eventStream, lifting partitions and replicas out of its config
+fn event_stream_topic(mf: &Manifest, cluster: &str, es: &EventStream) -> Value {
+    let mut config = es.config.clone();
+    let mut take =
+        |key: &str, default: i32| config.remove(key).and_then(|v| v.parse().ok()).unwrap_or(default);
+    let partitions = take("partitions", DEFAULT_PARTITIONS);
+    let replicas = take("replicas", DEFAULT_REPLICAS);
+    topic(mf, cluster, &es.name, partitions, replicas, &config)
+}
+
+fn acl(a: &AclDefinition) -> Value {
+    let mut resource = json!({
+        "type": a.resource_type.as_ref().map(|t| json!(t)).unwrap_or_else(|| json!("topic")),
+        "name": a.resource_name,
+        "patternType": a.pattern_type.as_ref().map(|p| json!(p)).unwrap_or_else(|| json!("literal")),
+    });
+    if resource["type"] == "cluster" {
+        resource.as_object_mut().unwrap().remove("name");
+    }
+    json!({
+        "resource": resource,
+        "operation": a.operation,
+        "host": a.host,
+    })
+}
+
+fn user(mf: &Manifest, cluster: &str, name: &str, acls: Vec<Value>) -> Value {
+    json!({
+        "apiVersion": API_VERSION,
+        "kind": "KafkaUser",
+        "metadata": metadata(name, mf, cluster),
+        "spec": {
+            "authentication": { "type": "tls" },
+            "authorization": { "type": "simple", "acls": acls },
+        },
+    })
+}
+
+fn topic_acl(topic: &str, operation: &str) -> Value {
+    json!({
+        "resource": { "type": "topic", "name": topic, "patternType": "literal" },
+        "operation": operation,
+        "host": "*",
+    })
+}
+
+/// Build the strimzi objects of a service
+///
+/// `streams` are the eventStreams of the region that the service produces to or consumes from.
+/// Topics come from the service's own `eventStreams` and `kafkaResources`.
+/// Users come from `kafkaResources`, plus a user named after the service with access to
+/// the `streams` it produces to or consumes from, and to consumer groups prefixed by its name.
+pub fn build(mf: &Manifest, streams: &[EventStream], cluster: &str) -> StrimziResources {
+    let mut res = StrimziResources::default();
+    for es in &mf.eventStreams {
+        res.kafkaTopics.push(event_stream_topic(mf, cluster, es));
+    }
+    let mut users: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+    if let Some(kr) = &mf.kafkaResources {
+        for t in &kr.topics {
+            res.kafkaTopics
+                .push(topic(mf, cluster, &t.name, t.partitions, t.replicas, &t.config));
+        }
+        for u in &kr.users {
+            users
+                .entry(u.name.clone())
+                .or_default()
+                .extend(u.acls.iter().map(acl));
+        }
+    }
+
+    let mut stream_acls = vec![];
+    for es in streams {
+        if es.producers.contains(&mf.name) {
+            stream_acls.push(topic_acl(&es.name, "Write"));
+        }
+        if es.consumers.contains(&mf.name) {
+            stream_acls.push(topic_acl(&es.name, "Read"));
+        }
+        stream_acls.push(topic_acl(&es.name, "Describe"));
+    }
+    if streams.iter().any(|es| es.consumers.contains(&mf.name)) {
+        stream_acls.push(json!({
+            "resource": { "type": "group", "name": mf.name, "patternType": "prefix" },
+            "operation": "Read",
+            "host": "*",
+        }));
+    }
+    if !stream_acls.is_empty() {
+        users.entry(mf.name.clone()).or_default().extend(stream_acls);
+    }
+    for (name, acls) in users {
+        res.kafkaUsers.push(user(mf, cluster, &name, acls));
+    }
+    res
+}
+
+/// EventStreams among `mfs` a service produces to or consumes from
+fn streams_for(service: &str, mfs: &[Manifest]) -> Vec<EventStream> {
+    mfs.iter()
+        .flat_map(|mf| mf.eventStreams.iter())
+        .filter(|es| {
+            es.producers
+                .iter()
+                .chain(es.consumers.iter())
+                .any(|s| s == service)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Inject the strimzi objects of a service for the helm chart
+///
+/// Does nothing in regions without a `kafka.strimziCluster`.
+pub fn populate(mf: &mut Manifest, mfs: &[Manifest], reg: &Region) {
+    if let Some(cluster) = &reg.kafka.strimziCluster {
+        let streams = streams_for(&mf.name, mfs);
+        let res = build(mf, &streams, cluster);
+        if !res.is_empty() {
+            mf.strimzi = Some(res);
+        }
+    }
+}
+
+/// Names of existing KafkaTopic resources of a service that it no longer declares
+pub fn stale_topics(mf: &Manifest, existing: &[String]) -> Vec<String> {
+    let wanted = mf
+        .strimzi
+        .iter()
+        .flat_map(|s| s.kafkaTopics.iter())
+        .filter_map(|t| t["metadata"]["name"].as_str())
+        .collect::<Vec<_>>();
+    existing
+        .iter()
+        .filter(|n| !wanted.contains(&n.as_str()))
+        .cloned()
+        .collect()
+}
+
+/// Delete KafkaTopic resources for topics removed from a manifest
+///
+/// Only runs for services that opt in with `kafkaResources.pruneTopics`.
+pub async fn prune(mf: &Manifest, reg: &Region) -> Result<()> {
+    let opted_in = mf
+        .kafkaResources
+        .as_ref()
+        .map(|kr| kr.prune_topics)
+        .unwrap_or(false);
+    if reg.kafka.strimziCluster.is_none() || !opted_in {
+        return Ok(());
+    }
+    let client = make_client().await?;
+    let (group, version) = API_VERSION.split_at(API_VERSION.find('/').unwrap());
+    let topics = CustomResource::kind("KafkaTopic")
+        .group(group)
+        .version(version.trim_start_matches('/'))
+        .within(&mf.namespace)
+        .into_resource();
+    let lp = ListParams {
+        label_selector: Some(format!("app.kubernetes.io/name={}", mf.name)),
+        ..ListParams::default()
+    };
+    let req = topics.list(&lp).map_err(ErrorKind::KubeError)?;
+    let existing = client
+        .request::<ObjectList<Object<Value, Value>>>(req)
+        .await
+        .map_err(ErrorKind::KubeError)?
+        .items
+        .into_iter()
+        .filter_map(|t| t.metadata.name)
+        .collect::<Vec<_>>();
+    for name in stale_topics(mf, &existing) {
+        info!("Deleting KafkaTopic {} removed from {}", name, mf.name);
+        let req = topics
+            .delete(&name, &DeleteParams::default())
+            .map_err(ErrorKind::KubeError)?;
+        client
+            .request_status::<Value>(req)
+            .await
+            .map_err(ErrorKind::KubeError)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build, resource_name, stale_topics};
+    use crate::{structs::EventStream, Manifest};
+    use serde_json::json;
+
+    #[test]
+    fn strimzi_resources() {
+        let mut mf = Manifest::test("orders");
+        mf.uid = Some("1234".into());
+        mf.version = Some("1.0.0".into());
+        mf.eventStreams = vec![EventStream {
+            name: "Order_Events".into(),
+            producers: vec!["orders".into()],
+            config: vec![("partitions".to_string(), "6".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        }];
+        mf.kafkaResources = Some(
+            serde_yaml::from_str(
+                "
+topics:
+- { name: orders-audit, partitions: 1, replicas: 2 }
+users:
+- name: orders-admin
+  acls:
+  - { resourceName: orders-audit, resourceType: topic, patternType: literal, operation: Write }",
+            )
+            .unwrap(),
+        );
+        let payments = EventStream {
+            name: "payments".into(),
+            consumers: vec!["orders".into()],
+            ..Default::default()
+        };
+        let res = build(&mf, &[mf.eventStreams[0].clone(), payments], "main");
+
+        let events = &res.kafkaTopics[0];
+        assert_eq!(events["metadata"]["name"], "order-events");
+        assert_eq!(events["metadata"]["labels"]["strimzi.io/cluster"], "main");
+        assert_eq!(events["metadata"]["labels"]["app.kubernetes.io/version"], "1.0.0");
+        assert_eq!(
+            events["metadata"]["ownerReferences"][0]["kind"],
+            "ShipcatManifest"
+        );
+        assert_eq!(events["metadata"]["ownerReferences"][0]["uid"], "1234");
+        assert_eq!(
+            events["spec"],
+            json!({
+                "topicName": "Order_Events",
+                "partitions": 6,
+                "replicas": 3,
+                "config": {},
+            })
+        );
+        assert_eq!(res.kafkaTopics[1]["spec"]["replicas"], 2);
+
+        let users = res
+            .kafkaUsers
+            .iter()
+            .map(|u| u["metadata"]["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(users, vec!["orders", "orders-admin"]);
+        let acls = &res.kafkaUsers[0]["spec"]["authorization"]["acls"];
+        assert_eq!(acls[0]["operation"], "Write");
+        assert_eq!(acls[2]["resource"]["name"], "payments");
+        assert_eq!(acls[2]["operation"], "Read");
+        assert_eq!(acls[4]["resource"]["type"], "group");
+        assert_eq!(
+            res.kafkaUsers[1]["spec"]["authorization"]["acls"][0],
+            json!({
+                "resource": { "type": "topic", "name": "orders-audit", "patternType": "literal" },
+                "operation": "Write",
+                "host": "*",
+            })
+        );
+
+        let mut pruned = mf.clone();
+        pruned.strimzi = Some(res);
+        let existing = vec!["order-events".to_string(), "orders-old".to_string()];
+        assert_eq!(stale_topics(&pruned, &existing), vec!["orders-old"]);
+        assert_eq!(resource_name("a_B.c"), "a-b.c");
+    }
+}
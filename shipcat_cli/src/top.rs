@@ -1,7 +1,15 @@
-use super::{Config, Error, Manifest, Region, Result};
+use super::{
+    usage::{self, ResourceUsage, UsageSource},
+    Config, Error, Manifest, Region, Result,
+};
 use futures::stream::{self, StreamExt};
-use shipcat_definitions::{math::ResourceTotals, BaseManifest};
-use std::{collections::BTreeMap, str::FromStr};
+use shipcat_definitions::{
+    math::ResourceTotals,
+    pricing::Cost,
+    structs::{ResourceRequirements, Resources},
+    BaseManifest,
+};
+use std::{collections::BTreeMap, ops::AddAssign, str::FromStr};
 
 use generic_array::{typenum::U4, GenericArray};
 use size_format::{PointSeparated, PrefixType, SizeFormatter, SizeFormatterBinary};
@@ -24,6 +32,7 @@ impl PrefixType for Millicores {
 pub enum ResourceOrder {
     Cpu,
     Memory,
+    Cost,
 }
 
 impl FromStr for ResourceOrder {
@@ -33,21 +42,26 @@ impl FromStr for ResourceOrder {
         match input {
             "cpu" => Ok(ResourceOrder::Cpu),
             "memory" => Ok(ResourceOrder::Memory),
-            _ => bail!("Resource type must be cpu or memory"),
+            "cost" => Ok(ResourceOrder::Cost),
+            _ => bail!("Resource type must be cpu, memory or cost"),
         }
     }
 }
 
-async fn load_mf_req(svc: String, conf: &Config, reg: &Region) -> Result<(Manifest, ResourceTotals)> {
+async fn load_mf_req(svc: String, conf: &Config, reg: &Region) -> Result<(Manifest, ResourceTotals, Cost)> {
     let mf = shipcat_filebacked::load_manifest(&svc, &conf, &reg)
         .await?
         .stub(&reg)
         .await?;
     let res = mf.compute_resource_totals()?;
-    Ok((mf, res))
+    let cost = conf.pricing_for(reg).monthly_cost(&res)?;
+    Ok((mf, res, cost))
 }
 
-async fn calculate_manifest_requests(conf: &Config, reg: &Region) -> Result<Vec<(Manifest, ResourceTotals)>> {
+pub(crate) async fn calculate_manifest_requests(
+    conf: &Config,
+    reg: &Region,
+) -> Result<Vec<(Manifest, ResourceTotals, Cost)>> {
     let available = shipcat_filebacked::available(conf, &reg).await?;
     let mut buffered = stream::iter(available)
         .map(move |mf| load_mf_req(mf.base.name, conf, reg))
@@ -59,8 +73,12 @@ async fn calculate_manifest_requests(conf: &Config, reg: &Region) -> Result<Vec<
     Ok(mfs)
 }
 
-async fn load_mf_req_world(base: BaseManifest, conf: &Config) -> Result<Option<(Manifest, ResourceTotals)>> {
+async fn load_mf_req_world(
+    base: BaseManifest,
+    conf: &Config,
+) -> Result<Option<(Manifest, ResourceTotals, Cost)>> {
     let mut res = ResourceTotals::default();
+    let mut cost = Cost::default();
     let mut first_mf = None;
     debug!("{} looping over {:?}", base.name, base.regions);
     for r in &base.regions {
@@ -71,25 +89,26 @@ async fn load_mf_req_world(base: BaseManifest, conf: &Config) -> Result<Option<(
                 .stub(&reg)
                 .await?;
             if !mf.disabled && !mf.external {
-                let ResourceTotals { base: rb, extra: se } = mf.compute_resource_totals()?;
+                let totals = mf.compute_resource_totals()?;
                 debug!(
                     "{} in {}: adding reqs: {} {}",
-                    mf.name, r, rb.requests.cpu, rb.requests.memory
+                    mf.name, r, totals.base.requests.cpu, totals.base.requests.memory
                 );
-                res.base += rb.clone();
-                res.extra += se.clone();
+                // regions can be served by clusters with different pricing
+                cost += conf.pricing_for(&reg).monthly_cost(&totals)?;
+                res += totals;
                 first_mf = Some(mf);
             }
         }
     }
     if let Some(mf) = first_mf {
-        Ok(Some((mf, res)))
+        Ok(Some((mf, res, cost)))
     } else {
         Ok(None)
     }
 }
 
-async fn calculate_manifest_requests_world(conf: &Config) -> Result<Vec<(Manifest, ResourceTotals)>> {
+async fn calculate_manifest_requests_world(conf: &Config) -> Result<Vec<(Manifest, ResourceTotals, Cost)>> {
     let all = shipcat_filebacked::all(conf).await?;
     let mut buffered = stream::iter(all)
         .map(|mf| load_mf_req_world(mf, conf))
@@ -108,13 +127,14 @@ async fn calculate_manifest_requests_world(conf: &Config) -> Result<Vec<(Manifes
 /// This presents an analytical solution to aggregate resource requests.
 /// It does NOT talk to kubernetes.
 ///
-/// It works out ResourceTotals based on Manifest properties analytically.
+/// It works out ResourceTotals based on Manifest properties analytically,
+/// and estimates monthly costs from the pricing model of each serving cluster.
 pub async fn world_requests(
     order: ResourceOrder,
     ub: bool,
     fmt: OutputFormat,
     conf: &Config,
-) -> Result<Vec<(Manifest, ResourceTotals)>> {
+) -> Result<Vec<(Manifest, ResourceTotals, Cost)>> {
     let mfs = calculate_manifest_requests_world(conf).await?;
     let mfs = sort_and_print_resources(mfs, order, fmt, ub)?;
     Ok(mfs)
@@ -125,14 +145,15 @@ pub async fn world_requests(
 /// This presents an analytical solution to aggregate resource requests in a region.
 /// It does NOT talk to kubernetes.
 ///
-/// It works out ResourceTotals based on Manifest properties analytically.
+/// It works out ResourceTotals based on Manifest properties analytically,
+/// and estimates monthly costs from the pricing model of the serving cluster.
 pub async fn region_requests(
     order: ResourceOrder,
     ub: bool,
     fmt: OutputFormat,
     conf: &Config,
     reg: &Region,
-) -> Result<Vec<(Manifest, ResourceTotals)>> {
+) -> Result<Vec<(Manifest, ResourceTotals, Cost)>> {
     let mfs = calculate_manifest_requests(conf, reg).await?;
     let mfs = sort_and_print_resources(mfs, order, fmt, ub)?;
     Ok(mfs)
@@ -144,6 +165,8 @@ pub enum OutputFormat {
     Table,
     /// Yaml output with raw numbers in milli-cores and Bytes
     Yaml,
+    /// Csv output with the same raw numbers as yaml
+    Csv,
 }
 
 impl FromStr for OutputFormat {
@@ -153,7 +176,8 @@ impl FromStr for OutputFormat {
         match input {
             "table" => Ok(Self::Table),
             "yaml" => Ok(Self::Yaml),
-            _ => bail!("Resource type must be table or yaml"),
+            "csv" => Ok(Self::Csv),
+            _ => bail!("Output format must be table, yaml or csv"),
         }
     }
 }
@@ -163,40 +187,51 @@ impl Default for OutputFormat {
     }
 }
 
+/// Value to sort by (descending) for a set of totals
+fn sort_key(r: &ResourceTotals, c: &Cost, order: &ResourceOrder, upper_bounds: bool) -> f64 {
+    match (order, upper_bounds) {
+        (ResourceOrder::Cpu, true) => r.base.requests.cpu + r.extra.requests.cpu,
+        (ResourceOrder::Cpu, false) => r.base.requests.cpu,
+        (ResourceOrder::Memory, true) => r.base.requests.memory + r.extra.requests.memory,
+        (ResourceOrder::Memory, false) => r.base.requests.memory,
+        (ResourceOrder::Cost, true) => c.upper,
+        (ResourceOrder::Cost, false) => c.lower,
+    }
+}
+
+/// Convert totals to millicores, Bytes and dollars per month
+fn printable_numbers(r: &ResourceTotals, c: &Cost, upper_bounds: bool) -> (u64, u64, f64) {
+    if upper_bounds {
+        let ub_cpu = (1000.0 * (r.base.requests.cpu + r.extra.requests.cpu)) as u64;
+        let ub_memory = (r.base.requests.memory + r.extra.requests.memory) as u64;
+        (ub_cpu, ub_memory, c.upper)
+    } else {
+        let lb_cpu = (1000.0 * r.base.requests.cpu) as u64;
+        let lb_memory = r.base.requests.memory as u64;
+        (lb_cpu, lb_memory, c.lower)
+    }
+}
+
+/// Quote a csv field if necessary
+fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
 fn sort_and_print_resources(
-    mut mfs: Vec<(Manifest, ResourceTotals)>,
+    mut mfs: Vec<(Manifest, ResourceTotals, Cost)>,
     order: ResourceOrder,
     formatting: OutputFormat,
     upper_bounds: bool,
-) -> Result<Vec<(Manifest, ResourceTotals)>> {
-    match order {
-        ResourceOrder::Cpu => {
-            mfs.sort_by(|(_, r1), (_, r2)| {
-                if upper_bounds {
-                    (r2.base.requests.cpu + r2.extra.requests.cpu)
-                        .partial_cmp(&(r1.base.requests.cpu + r1.extra.requests.cpu))
-                        .unwrap()
-                } else {
-                    r2.base.requests.cpu.partial_cmp(&r1.base.requests.cpu).unwrap()
-                }
-            });
-        }
-        ResourceOrder::Memory => {
-            mfs.sort_by(|(_, r1), (_, r2)| {
-                if upper_bounds {
-                    (r2.base.requests.memory + r2.extra.requests.memory)
-                        .partial_cmp(&(r1.base.requests.memory + r1.extra.requests.memory))
-                        .unwrap()
-                } else {
-                    r2.base
-                        .requests
-                        .memory
-                        .partial_cmp(&r1.base.requests.memory)
-                        .unwrap()
-                }
-            });
-        }
-    }
+) -> Result<Vec<(Manifest, ResourceTotals, Cost)>> {
+    mfs.sort_by(|(_, r1, c1), (_, r2, c2)| {
+        sort_key(r2, c2, &order, upper_bounds)
+            .partial_cmp(&sort_key(r1, c1, &order, upper_bounds))
+            .unwrap()
+    });
     // Convert the sorted data into a printable structure.
     #[derive(Serialize)]
     struct YamlOutput {
@@ -205,23 +240,16 @@ fn sort_and_print_resources(
         tribe: Option<String>,
         cpu: u64,
         memory: u64,
+        cost: f64,
     }
     let output = mfs
         .iter()
-        .map(|(mf, r)| {
-            // Convert to Millicores and Bytes
-            let (cpu, memory) = if upper_bounds {
-                let ub_cpu = (1000.0 * (r.base.requests.cpu + r.extra.requests.cpu)) as u64;
-                let ub_memory = (r.base.requests.memory + r.extra.requests.memory) as u64;
-                (ub_cpu, ub_memory)
-            } else {
-                let lb_cpu = (1000.0 * r.base.requests.cpu) as u64;
-                let lb_memory = r.base.requests.memory as u64;
-                (lb_cpu, lb_memory)
-            };
+        .map(|(mf, r, c)| {
+            let (cpu, memory, cost) = printable_numbers(r, c, upper_bounds);
             YamlOutput {
                 memory,
                 cpu,
+                cost,
                 name: mf.name.clone(),
                 squad: mf.metadata.as_ref().unwrap().team.clone(),
                 tribe: mf.metadata.as_ref().unwrap().tribe.clone(),
@@ -232,18 +260,19 @@ fn sort_and_print_resources(
     match formatting {
         OutputFormat::Table => {
             println!(
-                "{0:<50} {1:<8} {2:<8} {3:40} {4:40}",
-                "SERVICE", "CPU", "MEMORY", "SQUAD", "TRIBE"
+                "{0:<50} {1:<8} {2:<8} {3:<10} {4:40} {5:40}",
+                "SERVICE", "CPU", "MEMORY", "COST", "SQUAD", "TRIBE"
             );
             output.into_iter().for_each(|o| {
                 println!(
-                    "{0:<50} {1:width$} {2:width$} {3:<40} {4:<40}",
+                    "{0:<50} {1:width$} {2:width$} {3:<10} {4:<40} {5:<40}",
                     o.name,
                     format!(
                         "{:.0}",
                         SizeFormatter::<u64, Millicores, PointSeparated>::new(o.cpu)
                     ),
                     format!("{:.0}", SizeFormatterBinary::new(o.memory)),
+                    format!("${:.0}", o.cost),
                     o.squad,
                     o.tribe.unwrap_or("".to_string()),
                     width = 8,
@@ -253,50 +282,68 @@ fn sort_and_print_resources(
         OutputFormat::Yaml => {
             println!("{}", serde_yaml::to_string(&output)?);
         }
+        OutputFormat::Csv => {
+            println!("service,squad,tribe,cpu,memory,cost");
+            for o in output {
+                println!(
+                    "{},{},{},{},{},{:.2}",
+                    csv_field(&o.name),
+                    csv_field(&o.squad),
+                    csv_field(&o.tribe.unwrap_or_default()),
+                    o.cpu,
+                    o.memory,
+                    o.cost
+                );
+            }
+        }
     }
     Ok(mfs)
 }
 
-fn fold_manifests_by_squad(reqs: Vec<(Manifest, ResourceTotals)>) -> Result<Vec<(String, ResourceTotals)>> {
-    let team_requests: Vec<(String, ResourceTotals)> = reqs
+fn fold_manifests_by_squad(
+    reqs: Vec<(Manifest, ResourceTotals, Cost)>,
+) -> Result<Vec<(String, ResourceTotals, Cost)>> {
+    let team_requests: Vec<(String, ResourceTotals, Cost)> = reqs
         .into_iter()
-        .fold(BTreeMap::<String, ResourceTotals>::new(), |mut acc, (mf, res)| {
-            acc.entry(mf.metadata.as_ref().unwrap().squad.clone().unwrap())
-                .and_modify(|e| {
-                    let ResourceTotals { base: rb, extra: se } = &res;
-                    e.base += rb.clone();
-                    e.extra += se.clone();
-                })
-                .or_insert(res);
-            acc
-        })
+        .fold(
+            BTreeMap::<String, (ResourceTotals, Cost)>::new(),
+            |mut acc, (mf, res, cost)| {
+                let e = acc
+                    .entry(mf.metadata.as_ref().unwrap().squad.clone().unwrap())
+                    .or_default();
+                e.0 += res;
+                e.1 += cost;
+                acc
+            },
+        )
         .into_iter()
-        .map(|(t, res)| (t, res)) // btreemap -> vector
+        .map(|(t, (res, cost))| (t, res, cost)) // btreemap -> vector
         .collect();
     Ok(team_requests)
 }
 
-fn fold_manifests_by_tribe(reqs: Vec<(Manifest, ResourceTotals)>) -> Result<Vec<(String, ResourceTotals)>> {
-    let team_requests: Vec<(String, ResourceTotals)> = reqs
+fn fold_manifests_by_tribe(
+    reqs: Vec<(Manifest, ResourceTotals, Cost)>,
+) -> Result<Vec<(String, ResourceTotals, Cost)>> {
+    let team_requests: Vec<(String, ResourceTotals, Cost)> = reqs
         .into_iter()
-        .fold(BTreeMap::<String, ResourceTotals>::new(), |mut acc, (mf, res)| {
-            let md = mf.metadata.as_ref().unwrap();
-            if let Some(tribe) = &md.tribe {
-                acc.entry(tribe.to_string())
-                    .and_modify(|e| {
-                        let ResourceTotals { base: rb, extra: se } = &res;
-                        e.base += rb.clone();
-                        e.extra += se.clone();
-                    })
-                    .or_insert(res);
-            } else {
-                // Can happen if ewok orphaned_squads is not set to hard error
-                warn!("Could not find a matching tribe for {}", mf.name);
-            }
-            acc
-        })
+        .fold(
+            BTreeMap::<String, (ResourceTotals, Cost)>::new(),
+            |mut acc, (mf, res, cost)| {
+                let md = mf.metadata.as_ref().unwrap();
+                if let Some(tribe) = &md.tribe {
+                    let e = acc.entry(tribe.to_string()).or_default();
+                    e.0 += res;
+                    e.1 += cost;
+                } else {
+                    // Can happen if ewok orphaned_squads is not set to hard error
+                    warn!("Could not find a matching tribe for {}", mf.name);
+                }
+                acc
+            },
+        )
         .into_iter()
-        .map(|(t, res)| (t, res)) // btreemap -> vector
+        .map(|(t, (res, cost))| (t, res, cost)) // btreemap -> vector
         .collect();
     Ok(team_requests)
 }
@@ -310,7 +357,7 @@ pub async fn region_squad_requests(
     fmt: OutputFormat,
     conf: &Config,
     reg: &Region,
-) -> Result<Vec<(String, ResourceTotals)>> {
+) -> Result<Vec<(String, ResourceTotals, Cost)>> {
     let mfs = calculate_manifest_requests(conf, reg).await?;
     let team_requests = fold_manifests_by_squad(mfs)?;
     let sorted = sort_and_print_team_resources(team_requests, "squad", order, fmt, ub)?;
@@ -327,7 +374,7 @@ pub async fn region_tribe_requests(
     fmt: OutputFormat,
     conf: &Config,
     reg: &Region,
-) -> Result<Vec<(String, ResourceTotals)>> {
+) -> Result<Vec<(String, ResourceTotals, Cost)>> {
     let mfs = calculate_manifest_requests(conf, reg).await?;
     let team_requests = fold_manifests_by_tribe(mfs)?;
     let sorted = sort_and_print_team_resources(team_requests, "tribe", order, fmt, ub)?;
@@ -342,7 +389,7 @@ pub async fn world_squad_requests(
     ub: bool,
     fmt: OutputFormat,
     conf: &Config,
-) -> Result<Vec<(String, ResourceTotals)>> {
+) -> Result<Vec<(String, ResourceTotals, Cost)>> {
     let mfs = calculate_manifest_requests_world(conf).await?;
     let team_requests = fold_manifests_by_squad(mfs)?;
     let sorted = sort_and_print_team_resources(team_requests, "squad", order, fmt, ub)?;
@@ -358,7 +405,7 @@ pub async fn world_tribe_requests(
     ub: bool,
     fmt: OutputFormat,
     conf: &Config,
-) -> Result<Vec<(String, ResourceTotals)>> {
+) -> Result<Vec<(String, ResourceTotals, Cost)>> {
     let mfs = calculate_manifest_requests_world(conf).await?;
     let team_requests = fold_manifests_by_tribe(mfs)?;
     let sorted = sort_and_print_team_resources(team_requests, "tribe", order, fmt, ub)?;
@@ -366,63 +413,33 @@ pub async fn world_tribe_requests(
 }
 
 fn sort_and_print_team_resources(
-    mut reqs: Vec<(String, ResourceTotals)>,
+    mut reqs: Vec<(String, ResourceTotals, Cost)>,
     team_type: &str,
     order: ResourceOrder,
     formatting: OutputFormat,
     upper_bounds: bool,
-) -> Result<Vec<(String, ResourceTotals)>> {
-    match order {
-        ResourceOrder::Cpu => {
-            reqs.sort_by(|(_, r1), (_, r2)| {
-                if upper_bounds {
-                    (r2.base.requests.cpu + r2.extra.requests.cpu)
-                        .partial_cmp(&(r1.base.requests.cpu + r1.extra.requests.cpu))
-                        .unwrap()
-                } else {
-                    r2.base.requests.cpu.partial_cmp(&r1.base.requests.cpu).unwrap()
-                }
-            });
-        }
-        ResourceOrder::Memory => {
-            reqs.sort_by(|(_, r1), (_, r2)| {
-                if upper_bounds {
-                    (r2.base.requests.memory + r2.extra.requests.memory)
-                        .partial_cmp(&(r1.base.requests.memory + r1.extra.requests.memory))
-                        .unwrap()
-                } else {
-                    r2.base
-                        .requests
-                        .memory
-                        .partial_cmp(&r1.base.requests.memory)
-                        .unwrap()
-                }
-            });
-        }
-    }
+) -> Result<Vec<(String, ResourceTotals, Cost)>> {
+    reqs.sort_by(|(_, r1, c1), (_, r2, c2)| {
+        sort_key(r2, c2, &order, upper_bounds)
+            .partial_cmp(&sort_key(r1, c1, &order, upper_bounds))
+            .unwrap()
+    });
     // Convert the sorted data into a printable structure.
     #[derive(Serialize)]
     struct YamlOutput {
         team: String,
         cpu: u64,
         memory: u64,
+        cost: f64,
     }
     let output = reqs
         .iter()
-        .map(|(team, r)| {
-            // Convert to Millicores and Bytes
-            let (cpu, memory) = if upper_bounds {
-                let ub_cpu = (1000.0 * (r.base.requests.cpu + r.extra.requests.cpu)) as u64;
-                let ub_memory = (r.base.requests.memory + r.extra.requests.memory) as u64;
-                (ub_cpu, ub_memory)
-            } else {
-                let lb_cpu = (1000.0 * r.base.requests.cpu) as u64;
-                let lb_memory = r.base.requests.memory as u64;
-                (lb_cpu, lb_memory)
-            };
+        .map(|(team, r, c)| {
+            let (cpu, memory, cost) = printable_numbers(r, c, upper_bounds);
             YamlOutput {
                 memory,
                 cpu,
+                cost,
                 team: team.to_string(),
             }
         })
@@ -430,16 +447,23 @@ fn sort_and_print_team_resources(
 
     match formatting {
         OutputFormat::Table => {
-            println!("{0:<45} {1:<8} {2:<8}", team_type.to_uppercase(), "CPU", "MEMORY");
+            println!(
+                "{0:<45} {1:<8} {2:<8} {3:<10}",
+                team_type.to_uppercase(),
+                "CPU",
+                "MEMORY",
+                "COST"
+            );
             output.into_iter().for_each(|o| {
                 println!(
-                    "{0:<45} {1:width$} {2:width$}",
+                    "{0:<45} {1:width$} {2:width$} {3:<10}",
                     o.team,
                     format!(
                         "{:.0}",
                         SizeFormatter::<u64, Millicores, PointSeparated>::new(o.cpu)
                     ),
                     format!("{:.0}", SizeFormatterBinary::new(o.memory)),
+                    format!("${:.0}", o.cost),
                     width = 8,
                 );
             });
@@ -447,6 +471,357 @@ fn sort_and_print_team_resources(
         OutputFormat::Yaml => {
             println!("{}", serde_yaml::to_string(&output)?);
         }
+        OutputFormat::Csv => {
+            println!("{},cpu,memory,cost", team_type);
+            for o in output {
+                println!("{},{},{},{:.2}", csv_field(&o.team), o.cpu, o.memory, o.cost);
+            }
+        }
     }
     Ok(reqs)
 }
+
+// ----------------------------------------------------------------------------------
+// rightsizing against actual usage
+// ----------------------------------------------------------------------------------
+
+/// Usage below this fraction of requests is considered over-provisioned
+const OVERPROVISIONED_RATIO: f64 = 0.5;
+/// Usage above this fraction of limits is considered near the limit
+const NEAR_LIMIT_RATIO: f64 = 0.9;
+/// Headroom on top of observed usage when suggesting requests
+const HEADROOM: f64 = 1.25;
+
+/// Rightsizing verdict for a service or team
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum Sizing {
+    /// Using less than half of a requested resource
+    OverProvisioned,
+    /// Using close to the limit of a resource
+    NearLimit,
+    Ok,
+}
+
+/// Requests of running pods compared to their actual usage
+#[derive(Serialize, Default, Clone, Debug)]
+pub struct Rightsizing {
+    /// Requests and limits of the observed pods
+    pub resources: ResourceRequirements<f64>,
+    /// Observed usage of the same pods
+    pub usage: ResourceUsage,
+    /// Monthly cost estimate of the observed pods
+    pub cost: f64,
+}
+
+impl AddAssign for Rightsizing {
+    fn add_assign(&mut self, rhs: Rightsizing) {
+        self.resources += rhs.resources;
+        self.usage += rhs.usage;
+        self.cost += rhs.cost;
+    }
+}
+
+impl Rightsizing {
+    /// Scale the analytical totals of a manifest to the number of observed pods
+    ///
+    /// Autoscaling means the running pod count can differ from the minimum in the manifest.
+    fn new(mf: &Manifest, totals: &ResourceTotals, cost: &Cost, usage: ResourceUsage) -> Self {
+        let expected = mf.min_replicas() + mf.workers.iter().map(|w| w.replicaCount).sum::<u32>();
+        let scale = f64::from(usage.pods) / f64::from(std::cmp::max(expected, 1));
+        let (req, lim) = (&totals.base.requests, &totals.base.limits);
+        let resources = ResourceRequirements {
+            requests: Resources {
+                cpu: req.cpu * scale,
+                memory: req.memory * scale,
+            },
+            limits: Resources {
+                cpu: lim.cpu * scale,
+                memory: lim.memory * scale,
+            },
+        };
+        Rightsizing {
+            resources,
+            usage,
+            cost: cost.lower * scale,
+        }
+    }
+
+    /// Compare usage against requests and limits
+    pub fn sizing(&self) -> Sizing {
+        let (req, lim, used) = (&self.resources.requests, &self.resources.limits, &self.usage);
+        if (lim.cpu > 0.0 && used.cpu >= NEAR_LIMIT_RATIO * lim.cpu)
+            || (lim.memory > 0.0 && used.memory >= NEAR_LIMIT_RATIO * lim.memory)
+        {
+            Sizing::NearLimit
+        } else if used.cpu < OVERPROVISIONED_RATIO * req.cpu
+            || used.memory < OVERPROVISIONED_RATIO * req.memory
+        {
+            Sizing::OverProvisioned
+        } else {
+            Sizing::Ok
+        }
+    }
+
+    /// Suggested requests per pod based on observed usage plus some headroom
+    ///
+    /// Rounded up to 10 millicores and a MiB.
+    pub fn suggested(&self) -> Resources<f64> {
+        let pods = f64::from(std::cmp::max(self.usage.pods, 1));
+        let mib = 1024.0 * 1024.0;
+        Resources {
+            cpu: (self.usage.cpu / pods * HEADROOM * 100.0).ceil() / 100.0,
+            memory: (self.usage.memory / pods * HEADROOM / mib).ceil() * mib,
+        }
+    }
+
+    /// Value to sort by (descending)
+    ///
+    /// Resources are sorted by how much is requested but not used.
+    fn sort_key(&self, order: &ResourceOrder) -> f64 {
+        match order {
+            ResourceOrder::Cpu => self.resources.requests.cpu - self.usage.cpu,
+            ResourceOrder::Memory => self.resources.requests.memory - self.usage.memory,
+            ResourceOrder::Cost => self.cost,
+        }
+    }
+}
+
+/// Join analytical requests in a region with the usage of its running pods
+async fn calculate_rightsizing(
+    conf: &Config,
+    reg: &Region,
+    source: &UsageSource,
+) -> Result<Vec<(Manifest, Rightsizing)>> {
+    let usage = usage::fetch(source, &reg.namespace).await?;
+    let mut res = vec![];
+    for (mf, totals, cost) in calculate_manifest_requests(conf, reg).await? {
+        // workers run as separate deployments labelled with their own name
+        let apps = std::iter::once(&mf.name).chain(mf.workers.iter().map(|w| &w.container.name));
+        let mut used = ResourceUsage::default();
+        for app in apps {
+            if let Some(u) = usage.get(app) {
+                used += u.clone();
+            }
+        }
+        if used.pods == 0 {
+            debug!("No running pods found for {}", mf.name);
+            continue;
+        }
+        let rs = Rightsizing::new(&mf, &totals, &cost, used);
+        res.push((mf, rs));
+    }
+    Ok(res)
+}
+
+fn fold_rightsizing<F>(reqs: Vec<(Manifest, Rightsizing)>, team: F) -> Vec<(String, Rightsizing)>
+where
+    F: Fn(&Manifest) -> Option<String>,
+{
+    reqs.into_iter()
+        .fold(BTreeMap::<String, Rightsizing>::new(), |mut acc, (mf, rs)| {
+            if let Some(t) = team(&mf) {
+                *acc.entry(t).or_default() += rs;
+            } else {
+                // Can happen if ewok orphaned_squads is not set to hard error
+                warn!("Could not find a matching team for {}", mf.name);
+            }
+            acc
+        })
+        .into_iter()
+        .collect()
+}
+
+/// Rightsizing report for a single region
+///
+/// Compares the analytical requests of each service with the actual usage of its pods,
+/// and suggests per-pod requests based on that usage.
+pub async fn region_rightsizing(
+    order: ResourceOrder,
+    fmt: OutputFormat,
+    conf: &Config,
+    reg: &Region,
+    source: UsageSource,
+) -> Result<Vec<(String, Rightsizing)>> {
+    let rows = calculate_rightsizing(conf, reg, &source)
+        .await?
+        .into_iter()
+        .map(|(mf, rs)| (mf.name, rs))
+        .collect();
+    sort_and_print_rightsizing(rows, "service", order, fmt, true)
+}
+
+/// Rightsizing report for a single region aggregated across squads
+pub async fn region_squad_rightsizing(
+    order: ResourceOrder,
+    fmt: OutputFormat,
+    conf: &Config,
+    reg: &Region,
+    source: UsageSource,
+) -> Result<Vec<(String, Rightsizing)>> {
+    let rows = calculate_rightsizing(conf, reg, &source).await?;
+    let team_rows = fold_rightsizing(rows, |mf| mf.metadata.as_ref().unwrap().squad.clone());
+    sort_and_print_rightsizing(team_rows, "squad", order, fmt, false)
+}
+
+/// Rightsizing report for a single region aggregated across tribes
+pub async fn region_tribe_rightsizing(
+    order: ResourceOrder,
+    fmt: OutputFormat,
+    conf: &Config,
+    reg: &Region,
+    source: UsageSource,
+) -> Result<Vec<(String, Rightsizing)>> {
+    let rows = calculate_rightsizing(conf, reg, &source).await?;
+    let team_rows = fold_rightsizing(rows, |mf| mf.metadata.as_ref().unwrap().tribe.clone());
+    sort_and_print_rightsizing(team_rows, "tribe", order, fmt, false)
+}
+
+fn sort_and_print_rightsizing(
+    mut rows: Vec<(String, Rightsizing)>,
+    row_type: &str,
+    order: ResourceOrder,
+    formatting: OutputFormat,
+    suggestions: bool,
+) -> Result<Vec<(String, Rightsizing)>> {
+    rows.sort_by(|(_, r1), (_, r2)| r2.sort_key(&order).partial_cmp(&r1.sort_key(&order)).unwrap());
+    // Convert the sorted data into a printable structure (millicores and Bytes)
+    #[derive(Serialize)]
+    struct YamlOutput {
+        name: String,
+        pods: u32,
+        cpu_requested: u64,
+        cpu_used: u64,
+        memory_requested: u64,
+        memory_used: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        suggested_cpu: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        suggested_memory: Option<u64>,
+        cost: f64,
+        sizing: Sizing,
+    }
+    let output = rows
+        .iter()
+        .map(|(name, rs)| {
+            let suggested = if suggestions { Some(rs.suggested()) } else { None };
+            YamlOutput {
+                name: name.to_string(),
+                pods: rs.usage.pods,
+                cpu_requested: (1000.0 * rs.resources.requests.cpu) as u64,
+                cpu_used: (1000.0 * rs.usage.cpu) as u64,
+                memory_requested: rs.resources.requests.memory as u64,
+                memory_used: rs.usage.memory as u64,
+                suggested_cpu: suggested.as_ref().map(|s| (1000.0 * s.cpu) as u64),
+                suggested_memory: suggested.as_ref().map(|s| s.memory as u64),
+                cost: (rs.cost * 100.0).round() / 100.0,
+                sizing: rs.sizing(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let cpu = |c: u64| format!("{:.0}", SizeFormatter::<u64, Millicores, PointSeparated>::new(c));
+    let mem = |m: u64| format!("{:.0}", SizeFormatterBinary::new(m));
+    match formatting {
+        OutputFormat::Table => {
+            println!(
+                "{0:<45} {1:<5} {2:<17} {3:<17} {4:<17} {5:<10} {6:<15}",
+                row_type.to_uppercase(),
+                "PODS",
+                "CPU USED/REQ",
+                "MEMORY USED/REQ",
+                "SUGGESTED/POD",
+                "COST",
+                "SIZING"
+            );
+            for o in output {
+                let suggested = match (o.suggested_cpu, o.suggested_memory) {
+                    (Some(c), Some(m)) => format!("{} {}", cpu(c), mem(m)),
+                    _ => "".to_string(),
+                };
+                println!(
+                    "{0:<45} {1:<5} {2:<17} {3:<17} {4:<17} {5:<10} {6:<15}",
+                    o.name,
+                    o.pods,
+                    format!("{}/{}", cpu(o.cpu_used), cpu(o.cpu_requested)),
+                    format!("{}/{}", mem(o.memory_used), mem(o.memory_requested)),
+                    suggested,
+                    format!("${:.0}", o.cost),
+                    format!("{:?}", o.sizing),
+                );
+            }
+        }
+        OutputFormat::Yaml => {
+            println!("{}", serde_yaml::to_string(&output)?);
+        }
+        OutputFormat::Csv => {
+            println!(
+                "{},pods,cpu_requested,cpu_used,memory_requested,memory_used,suggested_cpu,suggested_memory,cost,sizing",
+                row_type
+            );
+            for o in output {
+                println!(
+                    "{},{},{},{},{},{},{},{},{:.2},{:?}",
+                    csv_field(&o.name),
+                    o.pods,
+                    o.cpu_requested,
+                    o.cpu_used,
+                    o.memory_requested,
+                    o.memory_used,
+                    o.suggested_cpu.map(|c| c.to_string()).unwrap_or_default(),
+                    o.suggested_memory.map(|m| m.to_string()).unwrap_or_default(),
+                    o.cost,
+                    o.sizing
+                );
+            }
+        }
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rightsizing, Sizing};
+    use crate::usage::ResourceUsage;
+    use shipcat_definitions::structs::{ResourceRequirements, Resources};
+
+    fn rightsizing(cpu_used: f64, memory_used: f64) -> Rightsizing {
+        let gib = 1024.0 * 1024.0 * 1024.0;
+        Rightsizing {
+            resources: ResourceRequirements {
+                requests: Resources {
+                    cpu: 1.0,
+                    memory: gib,
+                },
+                limits: Resources {
+                    cpu: 2.0,
+                    memory: 2.0 * gib,
+                },
+            },
+            usage: ResourceUsage {
+                cpu: cpu_used,
+                memory: memory_used * gib,
+                pods: 2,
+            },
+            cost: 0.0,
+        }
+    }
+
+    #[test]
+    fn rightsizing_verdicts() {
+        assert_eq!(rightsizing(0.8, 0.8).sizing(), Sizing::Ok);
+        assert_eq!(rightsizing(0.2, 0.8).sizing(), Sizing::OverProvisioned);
+        assert_eq!(rightsizing(0.8, 0.4).sizing(), Sizing::OverProvisioned);
+        // near limit trumps over-provisioned
+        assert_eq!(rightsizing(1.9, 0.1).sizing(), Sizing::NearLimit);
+        assert_eq!(rightsizing(0.8, 1.9).sizing(), Sizing::NearLimit);
+    }
+
+    #[test]
+    fn rightsizing_suggestions() {
+        let suggested = rightsizing(0.2, 0.5).suggested();
+        // 0.1 cores per pod + 25% headroom
+        assert_eq!(suggested.cpu, 0.13);
+        // 256Mi per pod + 25% headroom
+        assert_eq!(suggested.memory, 320.0 * 1024.0 * 1024.0);
+    }
+}
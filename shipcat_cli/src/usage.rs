@@ -0,0 +1,278 @@
+use std::{collections::BTreeMap, ops::AddAssign};
+
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::{Api, ListParams, Resource};
+use url::Url;
+
+use super::{
+    kubeapi::make_client,
+    structs::{parse_cpu, parse_memory},
+    ErrorKind, Result, ResultExt,
+};
+
+/// Where to fetch actual resource usage from
+pub enum UsageSource {
+    /// The kubernetes resource metrics api (metrics.k8s.io)
+    MetricsApi,
+    /// A prometheus server scraping cadvisor and kube-state-metrics
+    Prometheus(Url),
+}
+
+/// Observed resource usage summed across pods
+#[derive(Serialize, Default, Clone, Debug)]
+pub struct ResourceUsage {
+    /// Cores in use
+    pub cpu: f64,
+    /// Bytes of memory in use
+    pub memory: f64,
+    /// Number of pods observed
+    pub pods: u32,
+}
+
+impl AddAssign for ResourceUsage {
+    fn add_assign(&mut self, rhs: ResourceUsage) {
+        self.cpu += rhs.cpu;
+        self.memory += rhs.memory;
+        self.pods += rhs.pods;
+    }
+}
+
+/// Fetch current resource usage in a namespace keyed by `app` label
+pub async fn fetch(source: &UsageSource, namespace: &str) -> Result<BTreeMap<String, ResourceUsage>> {
+    match source {
+        UsageSource::MetricsApi => fetch_from_metrics_api(namespace).await,
+        UsageSource::Prometheus(url) => fetch_from_prometheus(url, namespace).await,
+    }
+}
+
+// ----------------------------------------------------------------------------------
+// metrics.k8s.io
+// ----------------------------------------------------------------------------------
+
+#[derive(Deserialize)]
+struct PodMetricsList {
+    items: Vec<PodMetrics>,
+}
+#[derive(Deserialize)]
+struct PodMetrics {
+    metadata: PodMetricsMeta,
+    containers: Vec<ContainerMetrics>,
+}
+#[derive(Deserialize)]
+struct PodMetricsMeta {
+    name: String,
+}
+#[derive(Deserialize)]
+struct ContainerMetrics {
+    usage: ContainerUsage,
+}
+#[derive(Deserialize)]
+struct ContainerUsage {
+    cpu: String,
+    memory: String,
+}
+
+// The metrics api reports usage in nanocores or microcores,
+// which we do not accept as manifest cpu values
+fn parse_usage_cpu(s: &str) -> Result<f64> {
+    if let Some(n) = s.strip_suffix('n') {
+        Ok(n.parse::<f64>()? / 1_000_000_000.0)
+    } else if let Some(u) = s.strip_suffix('u') {
+        Ok(u.parse::<f64>()? / 1_000_000.0)
+    } else {
+        Ok(parse_cpu(s)?)
+    }
+}
+
+async fn fetch_from_metrics_api(namespace: &str) -> Result<BTreeMap<String, ResourceUsage>> {
+    let client = make_client().await?;
+
+    // pod metrics do not reliably carry labels, so find the app label via the pods
+    let pods: Api<Pod> = Api::namespaced(client.clone(), namespace);
+    let mut apps = BTreeMap::new();
+    for p in pods
+        .list(&ListParams::default())
+        .await
+        .map_err(ErrorKind::KubeError)?
+    {
+        if let Some(meta) = p.metadata {
+            let app = meta.labels.and_then(|l| l.get("app").cloned());
+            if let (Some(name), Some(app)) = (meta.name, app) {
+                apps.insert(name, app);
+            }
+        }
+    }
+
+    // PodMetrics live at /apis/metrics.k8s.io/v1beta1/namespaces/{ns}/pods
+    let metrics = Resource {
+        api_version: "metrics.k8s.io/v1beta1".into(),
+        group: "metrics.k8s.io".into(),
+        kind: "Pod".into(),
+        version: "v1beta1".into(),
+        namespace: Some(namespace.into()),
+    };
+    let req = metrics
+        .list(&ListParams::default())
+        .map_err(ErrorKind::KubeError)?;
+    let list: PodMetricsList = client.request(req).await.map_err(ErrorKind::KubeError)?;
+
+    let mut res: BTreeMap<String, ResourceUsage> = BTreeMap::new();
+    for pm in list.items {
+        if let Some(app) = apps.get(&pm.metadata.name) {
+            let mut usage = ResourceUsage {
+                pods: 1,
+                ..Default::default()
+            };
+            for c in pm.containers {
+                usage.cpu += parse_usage_cpu(&c.usage.cpu)?;
+                usage.memory += parse_memory(&c.usage.memory)?;
+            }
+            *res.entry(app.clone()).or_default() += usage;
+        } else {
+            debug!("Ignoring metrics for unlabelled pod {}", pm.metadata.name);
+        }
+    }
+    Ok(res)
+}
+
+// ----------------------------------------------------------------------------------
+// prometheus
+// ----------------------------------------------------------------------------------
+
+#[derive(Deserialize)]
+struct PromResponse {
+    data: PromData,
+}
+#[derive(Deserialize)]
+struct PromData {
+    result: Vec<PromSample>,
+}
+#[derive(Deserialize)]
+struct PromSample {
+    metric: BTreeMap<String, String>,
+    /// Timestamp and stringified value
+    value: (f64, String),
+}
+
+/// Run an instant query and return the labels and value of each sample
+pub(crate) async fn prometheus_vector(
+    url: &Url,
+    query: &str,
+) -> Result<Vec<(BTreeMap<String, String>, f64)>> {
+    let endpoint = url.join("api/v1/query")?;
+    debug!("Querying {}: {}", endpoint, query);
+    let res: PromResponse = reqwest::Client::new()
+        .get(endpoint.clone())
+        .query(&[("query", query)])
+        .send()
+        .await
+        .chain_err(|| ErrorKind::Url(endpoint.clone()))?
+        .error_for_status()
+        .chain_err(|| ErrorKind::Url(endpoint.clone()))?
+        .json()
+        .await?;
+    let mut samples = vec![];
+    for s in res.data.result {
+        samples.push((s.metric, s.value.1.parse::<f64>()?));
+    }
+    Ok(samples)
+}
+
+/// Run an instant query and return values keyed by the `label_app` label
+async fn prometheus_query(url: &Url, query: &str) -> Result<BTreeMap<String, f64>> {
+    let mut values = BTreeMap::new();
+    for (metric, value) in prometheus_vector(url, query).await? {
+        if let Some(app) = metric.get("label_app") {
+            values.insert(app.clone(), value);
+        }
+    }
+    Ok(values)
+}
+
+async fn fetch_from_prometheus(url: &Url, namespace: &str) -> Result<BTreeMap<String, ResourceUsage>> {
+    // join container metrics with kube-state-metrics pod labels to get the app label
+    let labels = format!(
+        "max by (namespace, pod, label_app) (kube_pod_labels{{namespace=\"{}\"}})",
+        namespace
+    );
+    let containers = format!("namespace=\"{}\",container!=\"\",container!=\"POD\"", namespace);
+    let cpu = format!(
+        "sum by (label_app) (rate(container_cpu_usage_seconds_total{{{}}}[5m]) * on (namespace, pod) group_left(label_app) {})",
+        containers, labels
+    );
+    let memory = format!(
+        "sum by (label_app) (container_memory_working_set_bytes{{{}}} * on (namespace, pod) group_left(label_app) {})",
+        containers, labels
+    );
+    let pods = format!("count by (label_app) ({})", labels);
+
+    let mut res: BTreeMap<String, ResourceUsage> = BTreeMap::new();
+    for (app, n) in prometheus_query(url, &pods).await? {
+        res.entry(app).or_default().pods = n as u32;
+    }
+    for (app, v) in prometheus_query(url, &cpu).await? {
+        res.entry(app).or_default().cpu = v;
+    }
+    for (app, v) in prometheus_query(url, &memory).await? {
+        res.entry(app).or_default().memory = v;
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fetch, parse_usage_cpu, UsageSource};
+    use crate::Result;
+    use url::Url;
+
+    #[test]
+    fn usage_cpu_units() {
+        assert_eq!(parse_usage_cpu("250000000n").unwrap(), 0.25);
+        assert_eq!(parse_usage_cpu("500u").unwrap(), 0.0005);
+        assert_eq!(parse_usage_cpu("100m").unwrap(), 0.1);
+        assert!(shipcat_definitions::structs::parse_cpu("100n").is_err());
+    }
+
+    #[tokio::test]
+    async fn usage_from_prometheus() -> Result<()> {
+        let vector = |values: &[(&str, &str)]| {
+            let result = values
+                .iter()
+                .map(|(app, v)| serde_json::json!({ "metric": { "label_app": app }, "value": [1588000000.0, v] }))
+                .collect::<Vec<_>>();
+            serde_json::json!({ "status": "success", "data": { "resultType": "vector", "result": result } })
+                .to_string()
+        };
+        let pods = mockito::mock("GET", "/api/v1/query")
+            .match_query(mockito::Matcher::Regex("count".into()))
+            .with_body(vector(&[("fake-ask", "2"), ("fake-storage", "1")]))
+            .create();
+        let cpu = mockito::mock("GET", "/api/v1/query")
+            .match_query(mockito::Matcher::Regex(
+                "container_cpu_usage_seconds_total".into(),
+            ))
+            .with_body(vector(&[("fake-ask", "0.25")]))
+            .create();
+        let memory = mockito::mock("GET", "/api/v1/query")
+            .match_query(mockito::Matcher::Regex(
+                "container_memory_working_set_bytes".into(),
+            ))
+            .with_body(vector(&[("fake-ask", "536870912")]))
+            .create();
+
+        let url = Url::parse(&format!("{}/", mockito::server_url()))?;
+        let usage = fetch(&UsageSource::Prometheus(url), "apps").await?;
+        pods.assert();
+        cpu.assert();
+        memory.assert();
+
+        let ask = &usage["fake-ask"];
+        assert_eq!(ask.pods, 2);
+        assert_eq!(ask.cpu, 0.25);
+        assert_eq!(ask.memory, 512.0 * 1024.0 * 1024.0);
+        let storage = &usage["fake-storage"];
+        assert_eq!(storage.pods, 1);
+        assert_eq!(storage.cpu, 0.0);
+        Ok(())
+    }
+}
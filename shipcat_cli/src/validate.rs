@@ -1,6 +1,7 @@
 use super::{Config, Manifest, Region, Result};
 use crate::{error_chain::ChainedError, git};
 use futures::stream::{self, StreamExt};
+use std::fmt::Display;
 
 async fn verify_manifest(svc: String, conf: &Config, reg: &Region) -> Result<Manifest> {
     let mf = shipcat_filebacked::load_manifest(&svc, &conf, &reg)
@@ -11,11 +12,23 @@ async fn verify_manifest(svc: String, conf: &Config, reg: &Region) -> Result<Man
     Ok(mf)
 }
 
+// Log every error before failing with a summary
+fn fail_on<E: Display>(errs: &[E], what: &str) -> Result<()> {
+    if errs.is_empty() {
+        return Ok(());
+    }
+    for e in errs {
+        error!("{}", e);
+    }
+    bail!("{}: {} errors", what, errs.len())
+}
+
 /// Validate all manifests in a service directory for a region
 ///
 /// This is meant to replace `shipcat validate ..all_services`
 /// This does not check secrets.
-pub async fn regional_manifests(conf: &Config, reg: &Region) -> Result<()> {
+/// Dependency graph problems are warnings unless `strict_deps` is set.
+pub async fn regional_manifests(conf: &Config, reg: &Region, strict_deps: bool) -> Result<()> {
     let available = shipcat_filebacked::available(conf, &reg).await?;
 
     let mut buffered = stream::iter(available)
@@ -23,25 +36,13 @@ pub async fn regional_manifests(conf: &Config, reg: &Region) -> Result<()> {
         .buffer_unordered(16);
 
     let mut errs = vec![];
-    let mut used_stream_names = vec![];
-    let mut used_topic_names = vec![];
-    let mut used_user_names = vec![];
+    let mut mfs = vec![];
     while let Some(r) = buffered.next().await {
         match r {
             Err(e) => errs.push(e),
             Ok(mf) => {
-                // uniqueness validation
-                for es in mf.eventStreams {
-                    if used_stream_names.contains(&es.name) {
-                        bail!("{} cannot reuse eventStream names {}", mf.name, es.name);
-                    }
-                    used_stream_names.push(es.name.clone());
-                }
-                if let Some(kr) = mf.kafkaResources {
-                    for topic in kr.topics {
-                        if used_topic_names.contains(&topic.name) {
-                            bail!("{}, Topic name already exists: {}", mf.name, &topic.name);
-                        }
+                if let Some(kr) = &mf.kafkaResources {
+                    for topic in &kr.topics {
                         if topic.name.contains('_') {
                             bail!(
                                 "{}, cannot use underscores in kafka topic name: {}",
@@ -49,25 +50,32 @@ pub async fn regional_manifests(conf: &Config, reg: &Region) -> Result<()> {
                                 &topic.name
                             );
                         }
-                        used_topic_names.push(topic.name.clone());
-                    }
-                    for user in kr.users {
-                        if used_user_names.contains(&user.name) {
-                            bail!("{}, Kafka User name already exists: {}", mf.name, &user.name);
-                        }
-                        used_user_names.push(user.name.clone());
                     }
                 }
+                mfs.push(mf);
             }
         }
     }
 
-    if !errs.is_empty() {
-        for e in &errs {
-            error!("{}", e.display_chain());
-            debug!("{:?}", e.display_chain());
+    let errs = errs.iter().map(|e| e.display_chain()).collect::<Vec<_>>();
+    fail_on(&errs, "Invalid shipcat data")?;
+    let graph_errs = crate::graph::check_dependencies(&mfs);
+    if strict_deps {
+        fail_on(&graph_errs, &format!("Invalid dependency graph in {}", reg.name))?;
+    } else {
+        for e in &graph_errs {
+            warn!("{}", e);
         }
-        bail!("Invalid shipcat data in {} files", errs.len());
+    }
+    let services = shipcat_filebacked::all(conf)
+        .await?
+        .into_iter()
+        .map(|mf| mf.name)
+        .collect::<Vec<_>>();
+    let kafka_errs = crate::kafka::check_kafka(&mfs, &services);
+    fail_on(&kafka_errs, &format!("Invalid kafka resources in {}", reg.name))?;
+    if reg.kong.is_some() {
+        crate::kong::verify(conf, reg).await?;
     }
     // TODO: cross reference uniqueness values here
     Ok(())
@@ -76,7 +84,7 @@ pub async fn regional_manifests(conf: &Config, reg: &Region) -> Result<()> {
 async fn verify_region(r: String) -> Result<()> {
     use crate::ConfigState;
     let (conf, region) = Config::new(ConfigState::Base, &r).await?;
-    regional_manifests(&conf, &region).await?;
+    regional_manifests(&conf, &region, false).await?;
     Ok(())
 }
 
@@ -0,0 +1,327 @@
+use futures::stream::{self, StreamExt};
+use std::{collections::BTreeMap, fmt};
+
+use super::{Config, Region, Result};
+use crate::diff;
+use shipcat_definitions::{BaseManifest, Vault};
+
+/// First line of every policy written by shipcat
+///
+/// Only policies starting with this line are considered for pruning.
+pub const MANAGED_HEADER: &str = "# Managed by shipcat - changes will be overwritten";
+
+/// A policy to be written to vault, named after the github team granted it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TeamPolicy {
+    /// Github team (and policy) name
+    pub team: String,
+    /// Squads whose services are covered
+    pub squads: Vec<String>,
+    /// Policy rules in hcl
+    pub rules: String,
+}
+
+/// Generate the policies for every squad with a github team in `teams.yml`
+///
+/// Squads map to their `github.admins` team, or their `github.team` when
+/// the region sets `vault.squadTeams`. Squads sharing a team share a policy.
+pub async fn desired(conf: &Config, region: &Region) -> Result<Vec<TeamPolicy>> {
+    let mfs = shipcat_filebacked::all(conf).await?;
+    let mut teams: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (name, squad) in &conf.owners.squads {
+        let team = match &squad.github.admins {
+            Some(admins) => admins.clone(),
+            None if region.vault.squadTeams => squad.github.team.clone(),
+            None => {
+                debug!("'{}' does not have a github admins team - ignoring", name);
+                continue;
+            }
+        };
+        teams.entry(team).or_default().push(name.clone());
+    }
+    let mut res = vec![];
+    for (team, squads) in teams {
+        let rules = render(region, &mfs, &squads).await?;
+        res.push(TeamPolicy { team, squads, rules });
+    }
+    Ok(res)
+}
+
+/// Render the policy covering the services of some squads, starting with the `MANAGED_HEADER`
+pub async fn render(region: &Region, mfs: &[BaseManifest], squads: &[String]) -> Result<String> {
+    let owned = mfs
+        .iter()
+        .filter(|mf| squads.contains(&mf.metadata.team))
+        .map(|mf| mf.name.clone())
+        .collect::<Vec<_>>();
+    let rules = region.vault.template(owned, region.environment.clone()).await?;
+    Ok(format!("{}\n{}", MANAGED_HEADER, rules))
+}
+
+/// Policies and github team mappings currently in vault
+#[derive(Debug, Default)]
+pub struct LiveState {
+    /// Policy name -> rules
+    pub policies: BTreeMap<String, String>,
+    /// Github team -> comma separated policies
+    pub teams: BTreeMap<String, String>,
+}
+
+impl LiveState {
+    pub async fn fetch(vault: &Vault, n_workers: usize) -> Result<LiveState> {
+        let names = vault
+            .list_policies()
+            .await?
+            .into_iter()
+            .filter(|p| p != "root" && p != "default");
+        let mut buffered = stream::iter(names)
+            .map(|name| async move {
+                let rules = vault.read_policy(&name).await?;
+                Ok::<_, crate::Error>((name, rules))
+            })
+            .buffer_unordered(n_workers);
+        let mut policies = BTreeMap::new();
+        while let Some(r) = buffered.next().await {
+            if let (name, Some(rules)) = r? {
+                policies.insert(name, rules);
+            }
+        }
+        let teams = vault.list_github_teams().await?;
+        Ok(LiveState { policies, teams })
+    }
+}
+
+/// A change to make to vault
+#[derive(Debug, Clone, PartialEq)]
+pub enum VaultPolicyChange {
+    WritePolicy {
+        policy: String,
+        before: Option<String>,
+        after: String,
+    },
+    DeletePolicy {
+        policy: String,
+        before: String,
+    },
+    MapTeam {
+        team: String,
+        before: Option<String>,
+        after: String,
+    },
+    UnmapTeam {
+        team: String,
+        before: String,
+    },
+}
+
+impl fmt::Display for VaultPolicyChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VaultPolicyChange::WritePolicy {
+                policy, before: None, ..
+            } => write!(f, "+ policy {}", policy),
+            VaultPolicyChange::WritePolicy { policy, .. } => write!(f, "~ policy {}", policy),
+            VaultPolicyChange::DeletePolicy { policy, .. } => write!(f, "- policy {}", policy),
+            VaultPolicyChange::MapTeam {
+                team,
+                before: None,
+                after,
+            } => write!(f, "+ github team {} -> {}", team, after),
+            VaultPolicyChange::MapTeam { team, before, after } => write!(
+                f,
+                "~ github team {} -> {} (was {})",
+                team,
+                after,
+                before.as_deref().unwrap_or_default()
+            ),
+            VaultPolicyChange::UnmapTeam { team, before } => {
+                write!(f, "- github team {} -> {}", team, before)
+            }
+        }
+    }
+}
+
+/// The policies in a comma separated github team mapping
+fn mapped_policies(mapping: &str) -> Vec<String> {
+    mapping
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Compare generated policies with vault
+///
+/// Each team's policy is added to its existing github team mapping, keeping any other policies.
+/// Pruning only removes policies carrying the `MANAGED_HEADER`, and removes them from
+/// every mapping, unmapping teams that are left with nothing.
+pub fn diff(desired: &[TeamPolicy], live: &LiveState, prune: bool) -> Vec<VaultPolicyChange> {
+    let mut res = vec![];
+    for tp in desired {
+        let before = live.policies.get(&tp.team);
+        if before.map(|b| b.trim_end()) != Some(tp.rules.trim_end()) {
+            res.push(VaultPolicyChange::WritePolicy {
+                policy: tp.team.clone(),
+                before: before.cloned(),
+                after: tp.rules.clone(),
+            });
+        }
+    }
+    let pruned = live
+        .policies
+        .iter()
+        .filter(|(name, rules)| {
+            prune && rules.starts_with(MANAGED_HEADER) && !desired.iter().any(|tp| &tp.team == *name)
+        })
+        .collect::<Vec<_>>();
+
+    let mut teams = live.teams.keys().collect::<Vec<_>>();
+    teams.extend(
+        desired
+            .iter()
+            .map(|tp| &tp.team)
+            .filter(|t| !live.teams.contains_key(*t)),
+    );
+    for team in teams {
+        let before = live.teams.get(team);
+        let current = before.map(|m| mapped_policies(m)).unwrap_or_default();
+        let mut after = current
+            .iter()
+            .filter(|p| !pruned.iter().any(|(name, _)| name == p))
+            .cloned()
+            .collect::<Vec<_>>();
+        if desired.iter().any(|tp| &tp.team == team) && !after.contains(team) {
+            after.push(team.clone());
+        }
+        if after == current {
+            continue;
+        }
+        if after.is_empty() {
+            res.push(VaultPolicyChange::UnmapTeam {
+                team: team.clone(),
+                before: before.cloned().unwrap_or_default(),
+            });
+        } else {
+            res.push(VaultPolicyChange::MapTeam {
+                team: team.clone(),
+                before: before.cloned(),
+                after: after.join(","),
+            });
+        }
+    }
+
+    for (name, rules) in pruned {
+        res.push(VaultPolicyChange::DeletePolicy {
+            policy: name.clone(),
+            before: rules.clone(),
+        });
+    }
+    res
+}
+
+/// Reconcile vault policies and github team mappings with the generated policies
+///
+/// With `dry_run` the changes are printed along with an hcl diff per policy.
+/// Requires a vault token sufficiently elevated to manage policies and auth mappings.
+pub async fn reconcile(
+    conf: &Config,
+    region: &Region,
+    n_workers: usize,
+    dry_run: bool,
+    prune: bool,
+) -> Result<()> {
+    let vault = Vault::regional(&region.vault)?;
+    let wanted = desired(conf, region).await?;
+    let live = LiveState::fetch(&vault, n_workers).await?;
+    let changes = diff(&wanted, &live, prune);
+    if changes.is_empty() {
+        info!("Vault policies in {} are up to date", region.name);
+    }
+    for c in &changes {
+        if dry_run {
+            println!("{}", c);
+            match c {
+                VaultPolicyChange::WritePolicy {
+                    policy,
+                    before,
+                    after,
+                } => {
+                    let before = before.clone().unwrap_or_default();
+                    let live_name = format!("{}-policy.live", policy);
+                    diff::shell_diff(&before, after, &live_name, &format!("{}-policy", policy))?;
+                }
+                VaultPolicyChange::DeletePolicy { policy, before } => {
+                    let live_name = format!("{}-policy.live", policy);
+                    diff::shell_diff(before, "", &live_name, &format!("{}-policy", policy))?;
+                }
+                _ => {}
+            }
+            continue;
+        }
+        info!("{} in {}", c, region.name);
+        match c {
+            VaultPolicyChange::WritePolicy { policy, after, .. } => vault.write_policy(policy, after).await?,
+            VaultPolicyChange::DeletePolicy { policy, .. } => vault.delete_policy(policy).await?,
+            VaultPolicyChange::MapTeam { team, after, .. } => vault.map_github_team(team, after).await?,
+            VaultPolicyChange::UnmapTeam { team, .. } => vault.unmap_github_team(team).await?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, LiveState, TeamPolicy, VaultPolicyChange, MANAGED_HEADER};
+
+    fn policy(team: &str, rules: &str) -> TeamPolicy {
+        TeamPolicy {
+            team: team.into(),
+            squads: vec![team.into()],
+            rules: format!("{}\n{}", MANAGED_HEADER, rules),
+        }
+    }
+
+    #[test]
+    fn vault_policy_diff() {
+        let desired = vec![policy("o11y", "path \"a\" {}"), policy("devops", "path \"b\" {}")];
+        let mut live = LiveState::default();
+        live.policies
+            .insert("o11y".into(), format!("{}\npath \"a\" {{}}\n", MANAGED_HEADER));
+        live.policies.insert("devops".into(), "path \"b\" {}".into());
+        live.policies
+            .insert("disbanded".into(), format!("{}\npath \"c\" {{}}", MANAGED_HEADER));
+        live.policies.insert("handwritten".into(), "path \"d\" {}".into());
+        live.teams.insert("o11y".into(), "o11y".into());
+        live.teams.insert("devops".into(), "handwritten,disbanded".into());
+        live.teams.insert("disbanded".into(), "disbanded".into());
+
+        // the devops policy joins the policies already mapped to the team
+        let changes = diff(&desired, &live, false);
+        let summary = changes.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            "~ policy devops",
+            "~ github team devops -> handwritten,disbanded,devops (was handwritten,disbanded)",
+        ]);
+        if let VaultPolicyChange::WritePolicy { before, .. } = &changes[0] {
+            assert_eq!(before.as_deref(), Some("path \"b\" {}"));
+        }
+
+        // pruning only removes managed leftovers, from the policies and the mappings
+        let pruned = diff(&desired, &live, true)
+            .iter()
+            .skip(1)
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(pruned, vec![
+            "~ github team devops -> handwritten,devops (was handwritten,disbanded)",
+            "- github team disbanded -> disbanded",
+            "- policy disbanded",
+        ]);
+
+        // everything is new in an empty vault
+        let fresh = diff(&desired[..1], &LiveState::default(), true);
+        assert_eq!(fresh.len(), 2);
+        assert_eq!(fresh[0].to_string(), "+ policy o11y");
+    }
+}
@@ -1,13 +1,18 @@
 mod common;
 use crate::common::setup;
-use shipcat::graph::{generate, nodeidx_from_name};
+use shipcat::{
+    graph::{full, generate, nodeidx_from_name, render, verify, GraphFilter, GraphFormat},
+    structs::DependencyProtocol,
+};
 use shipcat_definitions::{Config, ConfigState};
 
 #[tokio::test]
 async fn graph_generate() {
     setup();
     let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
-    let graph = generate("fake-ask", &conf, &reg, true).await.unwrap();
+    let graph = generate("fake-ask", &conf, &reg, GraphFormat::Dot, &GraphFilter::default())
+        .await
+        .unwrap();
     assert!(graph.edge_count() > 0);
     print!("got struct: \n{:?}\n", serde_yaml::to_string(&graph));
     let askidx = nodeidx_from_name("fake-ask", &graph).unwrap();
@@ -20,3 +25,42 @@ async fn graph_generate() {
     println!("edge: {:?}", edge);
     assert_eq!(edge.intent, Some("testing graph module".into()));
 }
+
+#[tokio::test]
+async fn graph_formats_and_filters() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let graph = full(GraphFormat::Yaml, &GraphFilter::default(), &conf, &reg)
+        .await
+        .unwrap();
+
+    let mermaid = render(&graph, GraphFormat::Mermaid).unwrap();
+    assert!(mermaid.starts_with("graph LR"));
+    assert!(mermaid.contains("fake_ask -->|http| fake_storage"));
+
+    let json: serde_json::Value = serde_json::from_str(&render(&graph, GraphFormat::Json).unwrap()).unwrap();
+    assert_eq!(json["fake-ask"][0]["name"], "fake-storage");
+    assert_eq!(json["fake-ask"][0]["protocol"], "http");
+    assert_eq!(json["fake-storage"].as_array().unwrap().len(), 0);
+
+    let graphml = render(&graph, GraphFormat::GraphMl).unwrap();
+    assert!(graphml.contains(r#"<edge source="fake-ask" target="fake-storage">"#));
+
+    let kafka = GraphFilter {
+        protocol: Some(DependencyProtocol::Kafka),
+        ..Default::default()
+    };
+    assert_eq!(kafka.apply(&graph).edge_count(), 0);
+    let other_team = GraphFilter {
+        team: Some("someteam".into()),
+        ..Default::default()
+    };
+    assert_eq!(other_team.apply(&graph).node_count(), 0);
+    let observability = GraphFilter {
+        team: Some("observability".into()),
+        protocol: Some(DependencyProtocol::Http),
+    };
+    assert_eq!(observability.apply(&graph).edge_count(), 1);
+
+    verify(&conf, &reg).await.unwrap();
+}
@@ -0,0 +1,44 @@
+mod common;
+use crate::common::setup;
+use shipcat::{istio::populate, populate::region_manifests};
+use shipcat_definitions::{Config, ConfigState};
+
+#[tokio::test]
+async fn istio_populate() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let mut mf = shipcat_filebacked::load_manifest("fake-storage", &conf, &reg)
+        .await
+        .unwrap();
+    let mfs = region_manifests(&conf, &reg).await.unwrap();
+    populate(&mut mf, &mfs, &reg);
+    let istio = mf.istio.unwrap();
+    // no trafficPolicy or destinationRules
+    assert!(istio.destinationRules.is_empty());
+    assert!(istio.virtualServices.is_empty());
+    let principals =
+        &istio.authorizationPolicies["fake-storage"]["rules"][0]["from"][0]["source"]["principals"];
+    assert_eq!(principals[0], "cluster.local/ns/dev/sa/fake-ask");
+    assert_eq!(
+        principals[1],
+        "cluster.local/ns/istio-system/sa/istio-ingressgateway-service-account"
+    );
+}
+
+#[tokio::test]
+async fn istio_principals_use_service_accounts() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let mut mf = shipcat_filebacked::load_manifest("fake-storage", &conf, &reg)
+        .await
+        .unwrap();
+    let mut mfs = region_manifests(&conf, &reg).await.unwrap();
+    for caller in mfs.iter_mut().filter(|m| m.name == "fake-ask") {
+        caller.serviceAccount = Some("shared-sa".into());
+    }
+    populate(&mut mf, &mfs, &reg);
+    let istio = mf.istio.unwrap();
+    let principals =
+        &istio.authorizationPolicies["fake-storage"]["rules"][0]["from"][0]["source"]["principals"];
+    assert_eq!(principals[0], "cluster.local/ns/dev/sa/shared-sa");
+}
@@ -1,7 +1,7 @@
 mod common;
 use crate::common::setup;
 
-use shipcat::kong::{generate_kong_output, KongfigOutput};
+use shipcat::kong::{self, deck_output, generate_kong_output, route_conflicts, KongfigOutput};
 use shipcat_definitions::{
     structs::kongfig::{ApiPlugin, ConsumerCredentials, HeadersQueryBody, PluginBase},
     Config, ConfigState,
@@ -179,6 +179,82 @@ async fn kong_test() {
     assert!(api.plugins.is_empty());
 }
 
+#[tokio::test]
+async fn kong_deck_test() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let legacy = KongfigOutput::new(generate_kong_output(&conf, &reg).await.unwrap(), &reg);
+    let deck = deck_output(generate_kong_output(&conf, &reg).await.unwrap(), &reg);
+
+    // golden file comparison
+    let golden: serde_yaml::Value = serde_yaml::from_str(include_str!("kong-deck.yml")).unwrap();
+    assert_eq!(serde_yaml::to_value(&deck).unwrap(), golden);
+
+    // same services, routes and consumers as kongfig
+    assert_eq!(deck.services.len(), legacy.apis.len());
+    for (svc, api) in deck.services.iter().zip(&legacy.apis) {
+        assert_eq!(svc.name, api.name);
+        assert_eq!(svc.url, api.attributes.upstream_url);
+        assert_eq!(svc.routes.len(), 1);
+        assert_eq!(svc.routes[0].hosts, api.attributes.hosts);
+        assert_eq!(Some(&svc.routes[0].paths), api.attributes.uris.as_ref());
+
+        // every present kongfig plugin, and only those, in the same order
+        let present = api
+            .plugins
+            .iter()
+            .map(|p| serde_json::to_value(p).unwrap())
+            .filter(|p| p["ensure"] == "present")
+            .map(|p| p["name"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        let names = svc.plugins.iter().map(|p| p.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names, present);
+    }
+    let usernames = deck.consumers.iter().map(|c| &c.username).collect::<Vec<_>>();
+    let legacy_usernames = legacy.consumers.iter().map(|c| &c.username).collect::<Vec<_>>();
+    assert_eq!(usernames, legacy_usernames);
+}
+
+#[tokio::test]
+async fn kong_route_conflicts_test() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    kong::verify(&conf, &reg).await.unwrap();
+
+    // an extra api reusing a service uri conflicts with it
+    let mut data = generate_kong_output(&conf, &reg).await.unwrap();
+    let mut clash = data.apis["fake-storage"].clone();
+    clash.name = "fake-storage-copy".into();
+    data.apis.insert(clash.name.clone(), clash);
+    assert_eq!(route_conflicts(&data.apis), vec![
+        "fake-storage and fake-storage-copy both claim uri /fake-storage on any host"
+    ]);
+
+    // prefixes only conflict on whole path segments
+    let mut data = generate_kong_output(&conf, &reg).await.unwrap();
+    let mut sibling = data.apis["fake-storage"].clone();
+    sibling.name = "fake-storage-v2".into();
+    sibling.uris = Some("/fake-storage-v2".into());
+    let mut nested = data.apis["fake-storage"].clone();
+    nested.name = "fake-storage-nested".into();
+    nested.uris = Some("/fake-storage/nested".into());
+    data.apis.insert(sibling.name.clone(), sibling);
+    data.apis.insert(nested.name.clone(), nested);
+    assert_eq!(route_conflicts(&data.apis), vec![
+        "fake-storage uri /fake-storage shadows fake-storage-nested uri /fake-storage/nested on any host"
+    ]);
+
+    // an api without hosts is routed on every host
+    let mut data = generate_kong_output(&conf, &reg).await.unwrap();
+    let mut limited = data.apis["fake-storage"].clone();
+    limited.name = "fake-storage-limited".into();
+    limited.hosts = vec!["storage.example.com".into()];
+    data.apis.insert(limited.name.clone(), limited);
+    assert_eq!(route_conflicts(&data.apis), vec![
+        "fake-storage and fake-storage-limited both claim uri /fake-storage on storage.example.com"
+    ]);
+}
+
 #[cfg(test)]
 fn assert_upstream_header_transform(plugin: ApiPlugin, service: &str) {
     let attr = plugin_attributes!("RequestTransformer", plugin, ApiPlugin::RequestTransformer);
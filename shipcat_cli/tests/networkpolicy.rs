@@ -0,0 +1,39 @@
+mod common;
+use crate::common::setup;
+use shipcat::{networkpolicy::populate, populate::region_manifests};
+use shipcat_definitions::{Config, ConfigState};
+
+#[tokio::test]
+async fn networkpolicy_populate() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let mut mf = shipcat_filebacked::load_manifest("fake-storage", &conf, &reg)
+        .await
+        .unwrap();
+    let mfs = region_manifests(&conf, &reg).await.unwrap();
+    populate(&mut mf, &mfs, &reg);
+    let np = mf.networkPolicy.unwrap();
+    // fake-ask over http, kong and prometheus
+    assert_eq!(np.ingress.len(), 3);
+    let ask = &np.ingress[0];
+    assert_eq!(
+        ask.from[0].podSelector.as_ref().unwrap().matchLabels["app"],
+        "fake-ask"
+    );
+    assert_eq!(ask.ports[0].port, 3000);
+    let kong = &np.ingress[1];
+    assert_eq!(
+        kong.from[0].namespaceSelector.as_ref().unwrap().matchLabels["name"],
+        "kong"
+    );
+    assert_eq!(np.ingress[2].ports[0].port, 9102);
+
+    // regions without networkPolicies get nothing
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-global").await.unwrap();
+    let mut mf = shipcat_filebacked::load_manifest("fake-storage", &conf, &reg)
+        .await
+        .unwrap();
+    let mfs = region_manifests(&conf, &reg).await.unwrap();
+    populate(&mut mf, &mfs, &reg);
+    assert!(mf.networkPolicy.is_none());
+}
@@ -0,0 +1,39 @@
+mod common;
+use crate::common::setup;
+
+use shipcat::prometheusrule;
+use shipcat_definitions::{Config, ConfigState};
+
+#[tokio::test]
+async fn prometheus_rule_from_alerts() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let mut mf = shipcat_filebacked::load_manifest("fake-ask", &conf, &reg)
+        .await
+        .unwrap();
+    prometheusrule::populate(&mut mf, &conf, &reg);
+    let rule = mf.prometheusRule.unwrap();
+    assert_eq!(rule["kind"], "PrometheusRule");
+    assert_eq!(rule["metadata"]["labels"]["app.kubernetes.io/name"], "fake-ask");
+    assert_eq!(rule["metadata"]["labels"]["custom-metrics"], "true");
+
+    let alert = &rule["spec"]["groups"][0]["rules"][0];
+    assert_eq!(alert["alert"], "FakeSvcContainerRestarts");
+    assert_eq!(alert["for"], "5m");
+    // owners from teams.yml
+    assert_eq!(alert["labels"]["squad"], "observability");
+    assert_eq!(alert["labels"]["tribe"], "platform-engineering");
+    assert_eq!(alert["labels"]["slack_channel"], "CA04UJ8S0");
+    assert_eq!(alert["labels"]["severity"], "warning");
+    assert!(alert["annotations"]["dashboard"]
+        .as_str()
+        .unwrap()
+        .starts_with("https://dev-grafana.something.domain.com/d/oHzT4g0iz/"));
+
+    // services without alerts get no rule
+    let mut mf = shipcat_filebacked::load_manifest("fake-storage", &conf, &reg)
+        .await
+        .unwrap();
+    prometheusrule::populate(&mut mf, &conf, &reg);
+    assert!(mf.prometheusRule.is_none());
+}
@@ -0,0 +1,29 @@
+mod common;
+use crate::common::setup;
+use reqwest::Url;
+use shipcat::registry::{populate, RegistryClient};
+use shipcat_definitions::{region::RegistryConfig, Config, ConfigState};
+
+#[tokio::test]
+async fn registry_populate_needs_a_version() {
+    setup();
+    let (conf, mut reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    reg.registry = Some(RegistryConfig {
+        resolveDigests: false,
+    });
+    let mut mf = shipcat_filebacked::load_manifest("fake-storage", &conf, &reg)
+        .await
+        .unwrap();
+    // nothing listens here, so any lookup fails
+    let client = RegistryClient::new(Some(Url::parse("http://127.0.0.1:1/").unwrap()), None);
+
+    // templates without a version are left without registry data
+    mf.version = None;
+    let size = mf.imageSize;
+    populate(&mut mf, &reg, &client).await.unwrap();
+    assert_eq!(mf.imageSize, size);
+    assert_eq!(mf.imageDigest, None);
+
+    mf.version = Some("1.0.0".into());
+    assert!(populate(&mut mf, &reg, &client).await.is_err());
+}
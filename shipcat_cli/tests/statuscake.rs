@@ -0,0 +1,38 @@
+mod common;
+use crate::common::setup;
+
+use shipcat::statuscake::{desired, Check};
+use shipcat_definitions::{Config, ConfigState};
+
+#[tokio::test]
+async fn statuscake_desired_checks() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let checks = desired(&conf, &reg).await.unwrap();
+    let keys = checks.iter().map(Check::key).collect::<Vec<_>>();
+    assert_eq!(keys, vec![
+        "dev-uk fake-ask healthcheck squad=observability,tribe=platform-engineering",
+        "dev-uk fake-storage healthcheck squad=observability,tribe=platform-engineering",
+        "https://fake-ask.dev.something.domain.com",
+        "https://fake.example.com",
+    ]);
+
+    match &checks[1] {
+        Check::Uptime(u) => {
+            assert_eq!(
+                u.website_url,
+                "https://services.dev.something.domain.com/status/fake-storage/health"
+            );
+            // region check rate, and the region contact group plus the squad's from teams.yml
+            assert_eq!(u.check_rate, 120);
+            assert_eq!(u.contact_groups, vec!["1000", "2000"]);
+            assert!(u.tags.contains(&"shipcat".to_string()));
+            assert!(u.tags.contains(&"dev-uk".to_string()));
+        }
+        c => panic!("expected an uptime check, got {:?}", c),
+    }
+    match &checks[2] {
+        Check::Ssl(s) => assert_eq!(s.check_rate, 86400),
+        c => panic!("expected an ssl check, got {:?}", c),
+    }
+}
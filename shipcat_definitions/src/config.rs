@@ -9,8 +9,11 @@ use crate::teams;
 
 #[allow(unused_imports)] use super::{Error, Result};
 use crate::{
+    policy::Policy,
+    pricing::PricingModel,
     region::{Environment, Region},
     states::ConfigState,
+    structs::{parse_cpu, parse_memory, security::parse_period, Resources},
 };
 
 /// Kubernetes cluster information
@@ -29,6 +32,55 @@ pub struct Cluster {
     pub clustername: Option<String>,
     /// What regions this cluster control (perhaps not exclusively)
     pub regions: Vec<String>,
+    /// Pricing model for cost estimates of services in this cluster
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<PricingModel>,
+    /// Node pools serving workloads in this cluster
+    ///
+    /// Used to check that the requests of a region fits within the cluster.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nodePools: Vec<NodePool>,
+}
+
+/// A group of identical autoscaled nodes in a cluster
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct NodePool {
+    /// Name of the node pool
+    pub name: String,
+    /// Minimum number of nodes the autoscaler keeps
+    pub minCount: u32,
+    /// Maximum number of nodes the autoscaler can scale to
+    pub maxCount: u32,
+    /// Resources allocatable to pods on a single node
+    pub allocatable: Resources<String>,
+    /// Taint key reserving this pool for services tolerating it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taint: Option<String>,
+}
+
+impl NodePool {
+    fn verify(&self) -> Result<()> {
+        if self.maxCount == 0 || self.minCount > self.maxCount {
+            bail!(
+                "Node pool {} needs a positive maxCount at least as large as minCount",
+                self.name
+            );
+        }
+        let cap = self.capacity(1)?;
+        if cap.cpu <= 0.0 || cap.memory <= 0.0 {
+            bail!("Node pool {} must have allocatable cpu and memory", self.name);
+        }
+        Ok(())
+    }
+
+    /// Total allocatable cores and bytes of memory for a number of nodes
+    pub fn capacity(&self, nodes: u32) -> Result<Resources<f64>> {
+        Ok(Resources {
+            cpu: parse_cpu(&self.allocatable.cpu)? * f64::from(nodes),
+            memory: parse_memory(&self.allocatable.memory)? * f64::from(nodes),
+        })
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -109,6 +161,12 @@ pub struct Config {
     /// Shipcat version pins
     pub versions: BTreeMap<Environment, Version>,
 
+    /// Policies that manifests must satisfy in every region
+    ///
+    /// Policies can be limited to certain environments, and regions can add their own.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub policies: Vec<Policy>,
+
     /// Owners of services, squads, tribes
     ///
     /// Populated from teams.yml
@@ -129,6 +187,16 @@ impl Config {
                     cname
                 );
             }
+            if let Some(pricing) = &clst.pricing {
+                pricing.verify()?;
+            }
+            let mut pool_names = BTreeSet::new();
+            for np in &clst.nodePools {
+                np.verify()?;
+                if !pool_names.insert(np.name.clone()) {
+                    bail!("Cannot reuse node pool name {} in cluster {}", np.name, cname);
+                }
+            }
             // can't actually verify this in a smaller manifest..
             #[cfg(feature = "filesystem")]
             for r in &clst.regions {
@@ -149,6 +217,14 @@ impl Config {
             }
         }
 
+        let mut used_policy_names = BTreeSet::new();
+        for p in &self.policies {
+            p.verify()?;
+            if !used_policy_names.insert(p.name.clone()) {
+                bail!("Cannot reuse policy name {}", p.name);
+            }
+        }
+
         let mut used_kong_urls = vec![];
         for r in &self.regions {
             if r.namespace == "" {
@@ -173,6 +249,23 @@ impl Config {
                 }
                 used_kong_urls.push(kong.config_url.clone());
             }
+            for p in &r.policies {
+                p.verify()?;
+                if used_policy_names.contains(&p.name) {
+                    bail!("Region {} cannot reuse the global policy name {}", r.name, p.name);
+                }
+            }
+            for (class, period) in &r.maxRetention {
+                if let Err(e) = parse_period(period) {
+                    bail!("Invalid maxRetention for {} in {}: {}", class, r.name, e);
+                }
+            }
+            if let Some(np) = &r.networkPolicies {
+                np.verify()?;
+            }
+            if let Some(istio) = &r.istio {
+                istio.verify()?;
+            }
         }
         Ok(())
     }
@@ -346,6 +439,15 @@ impl Config {
         }
         None
     }
+
+    /// Pricing model to use for cost estimates in a region
+    ///
+    /// Falls back to on-demand m5.2xlarge nodes when the owning cluster has no pricing.
+    pub fn pricing_for(&self, region: &Region) -> PricingModel {
+        self.find_owning_cluster(region)
+            .and_then(|c| c.pricing)
+            .unwrap_or_default()
+    }
 }
 
 /// Simplified config with version information only
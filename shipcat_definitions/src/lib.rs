@@ -6,6 +6,7 @@
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate log;
 #[macro_use] extern crate maplit;
+#[macro_use] extern crate lazy_static;
 
 #[macro_use] extern crate error_chain; // bail and error_chain macro
 error_chain! {
@@ -80,7 +81,7 @@ pub mod region;
 pub use crate::region::{Environment, KongConfig, ReconciliationMode, Region, VaultConfig, VersionScheme};
 /// Master config with cross-region data
 pub mod config;
-pub use crate::config::{Cluster, Config, ConfigFallback, ShipcatConfig};
+pub use crate::config::{Cluster, Config, ConfigFallback, NodePool, ShipcatConfig};
 
 /// Structs for the manifest
 pub mod structs;
@@ -109,6 +110,21 @@ pub use crate::states::{ConfigState, PrimaryWorkload};
 /// Computational helpers
 pub mod math;
 
+/// A PromQL evaluator for testing alerts against in-memory series
+pub mod promql;
+
+/// Cloud pricing models for cost estimates
+pub mod pricing;
+pub use crate::pricing::PricingModel;
+
+/// Declarative policies evaluated against manifests
+pub mod policy;
+pub use crate::policy::Policy;
+
+/// Pod Security Standards checks for manifests and pod specs
+pub mod podsecurity;
+pub use crate::podsecurity::PodSecurityLevel;
+
 /// A renderer of `tera` templates (jinja style)
 ///
 /// Used for small app configs that are inlined in the completed manifests.
@@ -1,11 +1,11 @@
 use crate::vault::Vault;
 use kube_derive::CustomResource;
-use regex::Regex;
 use std::collections::{BTreeMap, BTreeSet};
 
 use super::Result;
 use crate::{
     config::Config,
+    podsecurity::PodSecurityExemption,
     region::{Region, VaultConfig},
     states::{ManifestState, PrimaryWorkload},
     ManifestStatus,
@@ -20,8 +20,9 @@ use super::structs::{
     tolerations::Tolerations,
     volume::{Volume, VolumeMount},
     ConfigMap, Container, CronJob, Dependency, DestinationRule, EnvVars, EventStream, Gate, HealthCheck,
-    HostAlias, Kafka, KafkaResources, Kong, LifeCycle, Metadata, NotificationMode, PersistentVolume, Port,
-    Probe, PrometheusAlert, Rbac, ResourceRequirements, RollingUpdate, SecurityContext, VaultOpts, Worker,
+    HostAlias, IstioResources, Kafka, KafkaResources, Kong, LifeCycle, Metadata, NetworkPolicy,
+    NotificationMode, PersistentVolume, Port, Probe, PrometheusAlert, Rbac, ResourceRequirements,
+    RollingUpdate, SecurityContext, StrimziResources, TrafficPolicy, VaultOpts, Worker,
 };
 
 /// Main manifest, serializable from manifest.yml or the shipcat CRD.
@@ -193,16 +194,33 @@ pub struct Manifest {
 
     /// Extend the workload with a securityContext
     ///
-    /// This allows changing the ownership of mounted volumes
+    /// This allows changing the ownership of mounted volumes,
+    /// and restricting the privileges of the main container.
     ///
     /// ```yaml
     /// securityContext:
     ///   runAsUser: 1000
     ///   fsGroup: 1000
+    ///   runAsNonRoot: true
+    ///   allowPrivilegeEscalation: false
+    ///   capabilities:
+    ///     drop: [ALL]
     /// ```
     #[serde(skip_serializing_if = "Option::is_none")]
     pub securityContext: Option<SecurityContext>,
 
+    /// Pod security checks this service is exempt from
+    ///
+    /// Checked against the `podSecurity` level of the region.
+    ///
+    /// ```yaml
+    /// podSecurityExemptions:
+    /// - check: hostPath
+    ///   reason: Reads node logs from /var/log
+    /// ```
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub podSecurityExemptions: Vec<PodSecurityExemption>,
+
     /// Data sources and handling strategies
     ///
     /// An experimental abstraction around GDPR
@@ -399,6 +417,24 @@ pub struct Manifest {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub destinationRules: Option<Vec<DestinationRule>>,
 
+    /// Traffic policy for requests to this service
+    ///
+    /// Timeouts, retries, outlier detection, connection pool limits and version subsets.
+    /// Generates istio objects in regions with `istio` configured.
+    ///
+    /// ```yaml
+    /// trafficPolicy:
+    ///   timeout: 10s
+    ///   retries:
+    ///     attempts: 3
+    ///   subsets:
+    ///   - name: stable
+    ///     version: 1.2.3
+    ///     weight: 100
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trafficPolicy: Option<TrafficPolicy>,
+
     /// Worker `Deployment` objects to additionally include
     ///
     /// These are more flexible than `sidecars`, because they scale independently of
@@ -711,6 +747,17 @@ pub struct Manifest {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub rbac: Vec<Rbac>,
 
+    /// ServiceAccount to run pods as
+    ///
+    /// Defaults to a ServiceAccount named after the service, created by the chart.
+    /// When set, the chart uses this existing ServiceAccount, and binds `rbac` to it.
+    ///
+    /// ```yaml
+    /// serviceAccount: payments-shared
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serviceAccount: Option<String>,
+
     /// Kafka / EventStream configuration
     ///
     /// A list of resources that will interact with the Kafka-operator CRD /
@@ -850,6 +897,69 @@ pub struct Manifest {
     )]
     pub uid: Option<String>,
 
+    /// NetworkPolicy ingress rules injected into the helm chart
+    ///
+    /// Derived from the dependencies of other services in the region,
+    /// and only set in regions that configure `networkPolicies`.
+    ///
+    /// Exposed from shipcat, but not overrideable.
+    #[serde(default)]
+    #[cfg_attr(
+        feature = "filesystem",
+        serde(skip_deserializing, skip_serializing_if = "Option::is_none")
+    )]
+    pub networkPolicy: Option<NetworkPolicy>,
+
+    /// Istio objects injected into the helm chart
+    ///
+    /// Derived from `trafficPolicy`, `destinationRules` and the dependencies of other services,
+    /// and only set in regions that configure `istio`.
+    ///
+    /// Exposed from shipcat, but not overrideable.
+    #[serde(default)]
+    #[cfg_attr(
+        feature = "filesystem",
+        serde(skip_deserializing, skip_serializing_if = "Option::is_none")
+    )]
+    pub istio: Option<IstioResources>,
+
+    /// Strimzi `KafkaTopic` and `KafkaUser` objects injected into the helm chart
+    ///
+    /// Derived from `kafkaResources` and the `eventStreams` the service owns, produces to,
+    /// or consumes from, and only set in regions with a `kafka.strimziCluster`.
+    ///
+    /// Exposed from shipcat, but not overrideable.
+    #[serde(default)]
+    #[cfg_attr(
+        feature = "filesystem",
+        serde(skip_deserializing, skip_serializing_if = "Option::is_none")
+    )]
+    pub strimzi: Option<StrimziResources>,
+
+    /// `PrometheusRule` object for the `prometheusAlerts` injected into the helm chart
+    ///
+    /// Alerts are labelled with the owning squad, tribe and slack channel for alertmanager routing.
+    ///
+    /// Exposed from shipcat, but not overrideable.
+    #[serde(default)]
+    #[cfg_attr(
+        feature = "filesystem",
+        serde(skip_deserializing, skip_serializing_if = "Option::is_none")
+    )]
+    pub prometheusRule: Option<serde_json::Value>,
+
+    /// Immutable digest of `image:version` in the registry
+    ///
+    /// Charts should pin the image to this digest when it is set.
+    ///
+    /// Exposed from shipcat, but not overrideable.
+    #[serde(default)]
+    #[cfg_attr(
+        feature = "filesystem",
+        serde(skip_deserializing, skip_serializing_if = "Option::is_none")
+    )]
+    pub imageDigest: Option<String>,
+
     /// Raw secrets from environment variables.
     ///
     /// The `env` map fills in secrets in this via the `vault` client.
@@ -923,13 +1033,20 @@ impl Manifest {
     pub fn verify_destination_rules(&self, region: &Region) -> Result<()> {
         if let Some(ref _destinationRules) = &self.destinationRules {
             if let Some(ref destinationRuleHostRegex) = &region.destinationRuleHostRegex {
+                let mut identifiers = BTreeSet::new();
                 for dr in _destinationRules {
                     dr.verify(destinationRuleHostRegex)?;
+                    if !identifiers.insert(&dr.identifier) {
+                        bail!("Cannot reuse destinationRule identifier {}", dr.identifier);
+                    }
                 }
             } else {
                 bail!("Cannot use `destinationRules` in a region without a `destinationRuleHostRegex`")
             }
         }
+        if let Some(tp) = &self.trafficPolicy {
+            tp.verify()?;
+        }
         Ok(())
     }
 
@@ -938,21 +1055,15 @@ impl Manifest {
     /// Assumes the manifest has been populated with `implicits`
     pub fn verify(&self, conf: &Config, region: &Region) -> Result<()> {
         self.verify_region()?;
-        // limit to 50 characters, alphanumeric, dashes for sanity.
+        // service names are limited by the builtin service-name policy
         // 63 is kube dns limit (13 char suffix buffer)
-        let re = Regex::new(r"^[0-9a-z\-]{1,50}$").unwrap();
-        if !re.is_match(&self.name) {
-            bail!("Please use a short, lower case service names with dashes");
-        }
-        if self.name.ends_with('-') || self.name.starts_with('-') {
-            bail!("Please use dashes to separate words only");
-        }
 
         self.verify_destination_rules(region)?;
 
         // TODO: remove?
         if let Some(ref dh) = self.dataHandling {
-            dh.verify()?
+            dh.verify()?;
+            dh.verify_retention(&region.maxRetention)?;
         }
 
         if let Some(ref md) = self.metadata {
@@ -1002,6 +1113,9 @@ impl Manifest {
         for r in &self.rbac {
             r.verify()?;
         }
+        if let Some(rp) = &region.rbacPolicy {
+            rp.check(&self.name, &self.rbac)?;
+        }
         for pv in &self.persistentVolumes {
             pv.verify()?;
         }
@@ -1022,6 +1136,12 @@ impl Manifest {
         for pa in &self.prometheusAlerts {
             pa.verify(&self.name)?;
         }
+        if let Some(nr) = &self.newrelic {
+            nr.verify()?;
+        }
+        for ex in &self.podSecurityExemptions {
+            ex.verify()?;
+        }
         // misc minor properties
         if self.replicaCount.unwrap() == 0 {
             bail!("Need replicaCount to be at least 1");
@@ -1055,6 +1175,10 @@ impl Manifest {
             bail!("Service {} ended up with an empty namespace", self.name);
         }
 
+        // region and global policies
+        self.verify_policies(conf, region)?;
+        self.verify_pod_security(region)?;
+
         // health check
         if self.health.is_none() && self.readinessProbe.is_none() {
             warn!("{} does not set a health check", self.name)
@@ -1063,14 +1187,52 @@ impl Manifest {
         Ok(())
     }
 
-    fn get_vault_path(&self, vc: &VaultConfig) -> String {
-        // some services use keys from other services
-        let (svc, reg) = if let Some(ref vopts) = self.vault {
-            (vopts.name.clone(), vc.folder.clone())
+    /// Name of the ServiceAccount the pods of the service run as
+    pub fn service_account(&self) -> &str {
+        self.serviceAccount.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Name of the vault folder the service reads secrets from
+    ///
+    /// Some services use keys from other services via `vault.name`.
+    pub fn vault_name(&self) -> String {
+        if let Some(ref vopts) = self.vault {
+            vopts.name.clone()
         } else {
-            (self.name.clone(), vc.folder.clone())
-        };
-        format!("{}/{}", reg, svc)
+            self.name.clone()
+        }
+    }
+
+    fn get_vault_path(&self, vc: &VaultConfig) -> String {
+        format!("{}/{}", vc.folder, self.vault_name())
+    }
+
+    /// Vault keys referenced by the manifest, along with what references them
+    ///
+    /// Covers the env of every container, and `secretFiles` (referenced as `secretFiles`).
+    pub fn vault_references(&self) -> BTreeMap<String, BTreeSet<String>> {
+        let mut envs = vec![(&self.name, &self.env)];
+        envs.extend(self.sidecars.iter().map(|c| (&c.name, &c.env)));
+        envs.extend(self.workers.iter().map(|w| (&w.container.name, &w.container.env)));
+        envs.extend(
+            self.cronJobs
+                .iter()
+                .map(|c| (&c.container.name, &c.container.env)),
+        );
+        envs.extend(self.initContainers.iter().map(|c| (&c.name, &c.env)));
+
+        let mut res: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for (container, env) in envs {
+            for k in env.vault_keys() {
+                res.entry(k).or_default().insert(container.clone());
+            }
+        }
+        for (k, v) in &self.secretFiles {
+            if v == "IN_VAULT" {
+                res.entry(k.clone()).or_default().insert("secretFiles".into());
+            }
+        }
+        res
     }
 
     // Get EnvVars for all containers, workers etc. for this Manifest.
@@ -1159,23 +1321,7 @@ impl Manifest {
     pub async fn verify_secrets_exist(&self, vc: &VaultConfig) -> Result<()> {
         use std::collections::HashSet;
         // what are we requesting
-        // TODO: Use envvars directly
-        let keys = self
-            .env
-            .plain
-            .clone()
-            .into_iter()
-            .filter(|(_, v)| v == "IN_VAULT")
-            .map(|(k, _)| k)
-            .collect::<HashSet<_>>();
-        let files = self
-            .secretFiles
-            .clone()
-            .into_iter()
-            .filter(|(_, v)| v == "IN_VAULT")
-            .map(|(k, _)| k)
-            .collect::<HashSet<_>>();
-        let expected = keys.union(&files).cloned().collect::<HashSet<_>>();
+        let expected = self.vault_references().keys().cloned().collect::<HashSet<_>>();
         if expected.is_empty() {
             return Ok(()); // no point trying to cross reference
         }
@@ -1,17 +1,20 @@
 use super::{
-    structs::{rollingupdate::RollingUpdate, ResourceRequirements},
+    structs::{parse_memory, rollingupdate::RollingUpdate, ResourceRequirements},
     Manifest, Result,
 };
+use std::ops::AddAssign;
 
 /// Total resource usage for a Manifest
 ///
 /// Accounting for workers, replicas, sidecars, and autoscaling policies for these.
-#[derive(Serialize, Default)]
+#[derive(Serialize, Default, Clone)]
 pub struct ResourceTotals {
     /// Sum of basic resource structs (ignoring autoscaling limits)
     pub base: ResourceRequirements<f64>,
     /// Autoscaling Ceilings on top of required
     pub extra: ResourceRequirements<f64>,
+    /// Persistent volume storage claimed across replicas
+    pub storage: f64,
 }
 
 impl ResourceTotals {
@@ -19,33 +22,17 @@ impl ResourceTotals {
     pub fn normalise(mut self) -> Self {
         self.base.round();
         self.extra.round();
+        self.storage = (self.storage * 100.0 / (1024.0 * 1024.0 * 1024.0)).round() / 100.0;
         self
     }
+}
 
-    /// Compute daily cost lower + upper bounds based on instance cost
-    ///
-    /// Assumes the resource totals have been normalise first!
-    pub fn daily_cost(&self) -> (f64, f64) {
-        // instance cost is hourly cost for a resource.
-        // E.g. m5.2xlarge => $0.384 per Hour, but has 31GB ram, 8vCPU
-        let icost = 0.384 * 24.0;
-        let node_mem = 31.0;
-        let node_cpu = 8.0;
-
-        let memory_cost = (
-            (self.base.requests.memory * icost / node_mem).round(),
-            ((self.base.requests.memory + self.extra.requests.memory) * icost / node_mem).round(),
-        );
-        let cpu_cost = (
-            (self.base.requests.cpu * icost / node_cpu).round(),
-            ((self.base.requests.cpu + self.extra.requests.cpu * icost) * icost / node_cpu).round(),
-        );
-        // quick extimate at what would be more expensive
-        if cpu_cost.1 > memory_cost.1 {
-            cpu_cost
-        } else {
-            memory_cost
-        }
+// For aggregation of resource use across services
+impl AddAssign for ResourceTotals {
+    fn add_assign(&mut self, rhs: ResourceTotals) {
+        self.base += rhs.base;
+        self.extra += rhs.extra;
+        self.storage += rhs.storage;
     }
 }
 
@@ -76,6 +63,15 @@ impl Manifest {
         }
     }
 
+    /// Fraction of extra pods created while rolling out a new version
+    ///
+    /// Used to estimate the temporary capacity needed during mass reconciles.
+    pub fn rollout_surge(&self) -> f64 {
+        let rcount = self.min_replicas();
+        let ru = self.rollingUpdate.clone().unwrap_or_default();
+        f64::from(ru.surge(rcount)) / f64::from(std::cmp::max(rcount, 1))
+    }
+
     /// Estimate how long to wait for a kube rolling upgrade
     ///
     /// Was used by helm, now used by the internal upgrade wait time.
@@ -147,7 +143,12 @@ impl Manifest {
                 // TODO: mandatory? sidecar resources when using sidecars?
             }
         }
-        Ok(ResourceTotals { base, extra })
+        // persistent volumes are claimed per replica
+        let mut storage = 0.0;
+        for pv in &self.persistentVolumes {
+            storage += parse_memory(&pv.size)? * f64::from(self.min_replicas());
+        }
+        Ok(ResourceTotals { base, extra, storage })
     }
 }
 
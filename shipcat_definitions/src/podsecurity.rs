@@ -0,0 +1,468 @@
+use serde_json::{json, Value};
+use std::{fmt, str::FromStr};
+
+use super::{
+    region::Region,
+    structs::{Container, SecurityContext},
+    Error, Manifest, Result,
+};
+
+/// Kubernetes Pod Security Standards level
+///
+/// Each level is a superset of the restrictions of the previous one.
+/// See https://kubernetes.io/docs/concepts/security/pod-security-standards/
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum PodSecurityLevel {
+    /// Unrestricted
+    Privileged,
+    /// Prevents known privilege escalations
+    Baseline,
+    /// Hardened pods following current best practices
+    Restricted,
+}
+
+impl fmt::Display for PodSecurityLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PodSecurityLevel::Privileged => write!(f, "privileged"),
+            PodSecurityLevel::Baseline => write!(f, "baseline"),
+            PodSecurityLevel::Restricted => write!(f, "restricted"),
+        }
+    }
+}
+
+impl FromStr for PodSecurityLevel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "privileged" => Ok(PodSecurityLevel::Privileged),
+            "baseline" => Ok(PodSecurityLevel::Baseline),
+            "restricted" => Ok(PodSecurityLevel::Restricted),
+            _ => bail!("Unknown pod security level {}", s),
+        }
+    }
+}
+
+/// The individual checks making up the pod security levels
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum PodSecurityCheck {
+    /// Sharing the host network, pid or ipc namespaces (baseline)
+    HostNamespaces,
+    /// Mounting hostPath volumes (baseline)
+    HostPath,
+    /// Privileged containers (baseline)
+    Privileged,
+    /// Adding capabilities (baseline), and not dropping ALL (restricted)
+    Capabilities,
+    /// Allowing privilege escalation (restricted)
+    AllowPrivilegeEscalation,
+    /// Running as root (restricted)
+    RunAsNonRoot,
+}
+
+impl fmt::Display for PodSecurityCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PodSecurityCheck::HostNamespaces => write!(f, "hostNamespaces"),
+            PodSecurityCheck::HostPath => write!(f, "hostPath"),
+            PodSecurityCheck::Privileged => write!(f, "privileged"),
+            PodSecurityCheck::Capabilities => write!(f, "capabilities"),
+            PodSecurityCheck::AllowPrivilegeEscalation => write!(f, "allowPrivilegeEscalation"),
+            PodSecurityCheck::RunAsNonRoot => write!(f, "runAsNonRoot"),
+        }
+    }
+}
+
+/// An exemption from a pod security check for a service
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct PodSecurityExemption {
+    /// The check the service is exempt from
+    pub check: PodSecurityCheck,
+    /// Why the service needs the exemption
+    pub reason: String,
+}
+
+impl PodSecurityExemption {
+    pub fn verify(&self) -> Result<()> {
+        if self.reason.trim().is_empty() {
+            bail!("Pod security exemption for {} needs a reason", self.check);
+        }
+        Ok(())
+    }
+}
+
+/// A failed pod security check
+#[derive(Clone, Debug)]
+pub struct PodSecurityViolation {
+    /// The failed check
+    pub check: PodSecurityCheck,
+    /// The lowest level the check is part of
+    pub level: PodSecurityLevel,
+    /// Workload the pod spec belongs to
+    pub workload: String,
+    /// Offending container (unset for pod level violations)
+    pub container: Option<String>,
+    /// Human readable explanation
+    pub message: String,
+}
+
+impl fmt::Display for PodSecurityViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.workload)?;
+        if let Some(c) = &self.container {
+            write!(f, "/{}", c)?;
+        }
+        write!(f, ": {} ({} check {})", self.message, self.level, self.check)
+    }
+}
+
+/// Capabilities the baseline level allows containers to add
+const BASELINE_CAPABILITIES: &[&str] = &[
+    "AUDIT_WRITE",
+    "CHOWN",
+    "DAC_OVERRIDE",
+    "FOWNER",
+    "FSETID",
+    "KILL",
+    "MKNOD",
+    "NET_BIND_SERVICE",
+    "SETFCAP",
+    "SETGID",
+    "SETPCAP",
+    "SETUID",
+    "SYS_CHROOT",
+];
+
+fn strings(v: &Value) -> Vec<&str> {
+    v.as_array()
+        .map(|xs| xs.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default()
+}
+
+/// Evaluate a kubernetes `PodSpec` against every pod security check
+///
+/// Returns violations of all levels; callers filter on the level they require.
+pub fn evaluate(workload: &str, spec: &Value) -> Vec<PodSecurityViolation> {
+    let mut res = vec![];
+    let mut violation = |check, level, container: Option<&str>, message: String| {
+        res.push(PodSecurityViolation {
+            check,
+            level,
+            workload: workload.to_string(),
+            container: container.map(String::from),
+            message,
+        })
+    };
+
+    for ns in &["hostNetwork", "hostPID", "hostIPC"] {
+        if spec[ns] == Value::Bool(true) {
+            violation(
+                PodSecurityCheck::HostNamespaces,
+                PodSecurityLevel::Baseline,
+                None,
+                format!("{} is enabled", ns),
+            );
+        }
+    }
+    for v in spec["volumes"].as_array().into_iter().flatten() {
+        if !v["hostPath"].is_null() {
+            violation(
+                PodSecurityCheck::HostPath,
+                PodSecurityLevel::Baseline,
+                None,
+                format!("volume {} is a hostPath", v["name"].as_str().unwrap_or("unnamed")),
+            );
+        }
+    }
+
+    let pod = &spec["securityContext"];
+    let containers = spec["initContainers"]
+        .as_array()
+        .into_iter()
+        .chain(spec["containers"].as_array())
+        .flatten();
+    for c in containers {
+        let name = Some(c["name"].as_str().unwrap_or("unnamed"));
+        let sc = &c["securityContext"];
+        if sc["privileged"] == Value::Bool(true) {
+            violation(
+                PodSecurityCheck::Privileged,
+                PodSecurityLevel::Baseline,
+                name,
+                "runs privileged".into(),
+            );
+        }
+        let added = strings(&sc["capabilities"]["add"]);
+        let disallowed = added
+            .iter()
+            .filter(|cap| !BASELINE_CAPABILITIES.contains(cap))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !disallowed.is_empty() {
+            violation(
+                PodSecurityCheck::Capabilities,
+                PodSecurityLevel::Baseline,
+                name,
+                format!("adds capabilities {}", disallowed.join(", ")),
+            );
+        } else if added.iter().any(|cap| *cap != "NET_BIND_SERVICE") {
+            violation(
+                PodSecurityCheck::Capabilities,
+                PodSecurityLevel::Restricted,
+                name,
+                "adds capabilities other than NET_BIND_SERVICE".into(),
+            );
+        }
+        if !strings(&sc["capabilities"]["drop"]).contains(&"ALL") {
+            violation(
+                PodSecurityCheck::Capabilities,
+                PodSecurityLevel::Restricted,
+                name,
+                "does not drop ALL capabilities".into(),
+            );
+        }
+        if sc["allowPrivilegeEscalation"] != Value::Bool(false) {
+            violation(
+                PodSecurityCheck::AllowPrivilegeEscalation,
+                PodSecurityLevel::Restricted,
+                name,
+                "does not set allowPrivilegeEscalation to false".into(),
+            );
+        }
+        // container settings take precedence over the pod ones
+        let non_root = match &sc["runAsNonRoot"] {
+            Value::Null => &pod["runAsNonRoot"],
+            v => v,
+        };
+        let user = match &sc["runAsUser"] {
+            Value::Null => &pod["runAsUser"],
+            v => v,
+        };
+        if user.as_u64() == Some(0) {
+            violation(
+                PodSecurityCheck::RunAsNonRoot,
+                PodSecurityLevel::Restricted,
+                name,
+                "runs as uid 0".into(),
+            );
+        } else if *non_root != Value::Bool(true) {
+            violation(
+                PodSecurityCheck::RunAsNonRoot,
+                PodSecurityLevel::Restricted,
+                name,
+                "does not set runAsNonRoot".into(),
+            );
+        }
+    }
+    res
+}
+
+/// Find the `PodSpec` of a kubernetes object, if it has one
+pub fn pod_spec<'a>(kind: &str, obj: &'a Value) -> Option<&'a Value> {
+    let spec = match kind {
+        "Pod" => &obj["spec"],
+        "Deployment" | "StatefulSet" | "DaemonSet" | "ReplicaSet" | "Job" => &obj["spec"]["template"]["spec"],
+        "CronJob" => &obj["spec"]["jobTemplate"]["spec"]["template"]["spec"],
+        _ => return None,
+    };
+    if spec.is_null() {
+        None
+    } else {
+        Some(spec)
+    }
+}
+
+/// Pod security evaluation for manifests
+impl Manifest {
+    /// The pod specs of every workload, as the charts are expected to render them
+    ///
+    /// The manifest `securityContext` is split between the pod, and the primary container
+    /// of each workload that does not set its own, which gets the container only fields.
+    /// Sidecars and init containers only get their own `securityContext`.
+    pub fn pod_specs(&self) -> Result<Vec<(String, Value)>> {
+        let primary = |c: &Container| -> Result<Value> {
+            let sc = c
+                .security_context
+                .clone()
+                .or_else(|| self.securityContext.as_ref().map(SecurityContext::container));
+            Ok(json!({ "name": c.name, "securityContext": serde_json::to_value(sc)? }))
+        };
+        let secondary = |cs: &[Container]| -> Result<Vec<Value>> {
+            let mut res = vec![];
+            for c in cs {
+                let sc = serde_json::to_value(&c.security_context)?;
+                res.push(json!({ "name": c.name, "securityContext": sc }));
+            }
+            Ok(res)
+        };
+        let pod = |containers: Vec<Value>| -> Result<Value> {
+            Ok(json!({
+                "securityContext": serde_json::to_value(self.securityContext.as_ref().map(SecurityContext::pod))?,
+                "volumes": serde_json::to_value(&self.volumes)?,
+                "initContainers": secondary(&self.initContainers)?,
+                "containers": containers,
+            }))
+        };
+
+        let main = Container {
+            name: self.name.clone(),
+            ..Container::default()
+        };
+        let mut res = vec![];
+        let mut containers = vec![primary(&main)?];
+        containers.extend(secondary(&self.sidecars)?);
+        res.push((self.name.clone(), pod(containers)?));
+        for w in &self.workers {
+            let mut containers = vec![primary(&w.container)?];
+            containers.extend(secondary(&self.sidecars)?);
+            res.push((w.container.name.clone(), pod(containers)?));
+        }
+        for cj in &self.cronJobs {
+            res.push((cj.container.name.clone(), pod(vec![primary(&cj.container)?])?));
+        }
+        Ok(res)
+    }
+
+    /// Filter violations down to the ones relevant at a level that are not exempted
+    pub fn pod_security_violations(
+        &self,
+        level: PodSecurityLevel,
+        violations: Vec<PodSecurityViolation>,
+    ) -> Vec<PodSecurityViolation> {
+        violations
+            .into_iter()
+            .filter(|v| v.level <= level)
+            .filter(|v| !self.podSecurityExemptions.iter().any(|ex| ex.check == v.check))
+            .collect()
+    }
+
+    /// Verify that every workload satisfies the pod security level of the region
+    ///
+    /// Every violation is reported before failing.
+    pub fn verify_pod_security(&self, region: &Region) -> Result<()> {
+        let level = match region.podSecurity {
+            Some(l) => l,
+            None => return Ok(()),
+        };
+        let mut all = vec![];
+        for (workload, spec) in self.pod_specs()? {
+            all.extend(evaluate(&workload, &spec));
+        }
+        let violations = self.pod_security_violations(level, all);
+        for v in &violations {
+            error!("{} violates pod security: {}", self.name, v);
+        }
+        if !violations.is_empty() {
+            bail!(
+                "{} does not satisfy the {} pod security level in {}: {} violations",
+                self.name,
+                level,
+                region.name,
+                violations.len()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate, pod_spec, PodSecurityCheck, PodSecurityLevel};
+    use crate::Manifest;
+    use serde_json::json;
+
+    #[test]
+    fn pod_security_levels() {
+        let spec = json!({
+            "hostNetwork": true,
+            "securityContext": { "runAsNonRoot": true },
+            "volumes": [{ "name": "logs", "hostPath": { "path": "/var/log" } }],
+            "containers": [
+                {
+                    "name": "app",
+                    "securityContext": {
+                        "allowPrivilegeEscalation": false,
+                        "capabilities": { "drop": ["ALL"], "add": ["NET_BIND_SERVICE"] }
+                    }
+                },
+                {
+                    "name": "proxy",
+                    "securityContext": { "runAsUser": 0, "capabilities": { "add": ["NET_ADMIN"] } }
+                }
+            ]
+        });
+        let vs = evaluate("fake-ask", &spec);
+        let baseline = vs
+            .iter()
+            .filter(|v| v.level == PodSecurityLevel::Baseline)
+            .map(|v| (v.check, v.container.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(baseline, vec![
+            (PodSecurityCheck::HostNamespaces, None),
+            (PodSecurityCheck::HostPath, None),
+            (PodSecurityCheck::Capabilities, Some("proxy".into())),
+        ]);
+        // the app container is restricted, the proxy is not
+        assert!(vs.iter().all(|v| v.container.as_deref() != Some("app")));
+        let restricted = vs
+            .iter()
+            .filter(|v| v.level == PodSecurityLevel::Restricted)
+            .map(|v| v.check)
+            .collect::<Vec<_>>();
+        assert_eq!(restricted, vec![
+            PodSecurityCheck::Capabilities,
+            PodSecurityCheck::AllowPrivilegeEscalation,
+            PodSecurityCheck::RunAsNonRoot,
+        ]);
+    }
+
+    #[test]
+    fn pod_security_manifests() {
+        let mut mf = Manifest::test("fake-ask");
+        mf.securityContext = Some(
+            serde_yaml::from_str(
+                "
+runAsNonRoot: true
+allowPrivilegeEscalation: false
+capabilities:
+  drop: [ALL]",
+            )
+            .unwrap(),
+        );
+        mf.sidecars =
+            serde_yaml::from_str("[{ name: redis, securityContext: { privileged: true } }]").unwrap();
+        mf.cronJobs = serde_yaml::from_str("[{ name: fake-ask-cleanup, schedule: '1 0 * * *' }]").unwrap();
+        mf.podSecurityExemptions =
+            serde_yaml::from_str("[{ check: privileged, reason: redis needs sysctls }]").unwrap();
+
+        let specs = mf.pod_specs().unwrap();
+        assert_eq!(specs.len(), 2);
+        let main = &specs[0].1;
+        assert_eq!(main["securityContext"], json!({ "runAsNonRoot": true }));
+        assert_eq!(
+            main["containers"][0]["securityContext"],
+            json!({ "allowPrivilegeEscalation": false, "capabilities": { "drop": ["ALL"] } })
+        );
+        let all = specs.iter().flat_map(|(w, s)| evaluate(w, s)).collect::<Vec<_>>();
+        // only the redis sidecar fails: it is exempt from baseline, but not restricted
+        assert!(all.iter().all(|v| v.container.as_deref() == Some("redis")));
+        assert!(mf
+            .pod_security_violations(PodSecurityLevel::Baseline, all.clone())
+            .is_empty());
+        let restricted = mf.pod_security_violations(PodSecurityLevel::Restricted, all.clone());
+        assert_eq!(restricted.len(), 2);
+        mf.podSecurityExemptions.clear();
+        assert_eq!(
+            mf.pod_security_violations(PodSecurityLevel::Baseline, all).len(),
+            1
+        );
+
+        let cj = json!({ "spec": { "jobTemplate": { "spec": { "template": { "spec": specs[1].1 } } } } });
+        assert_eq!(pod_spec("CronJob", &cj), Some(&specs[1].1));
+        assert_eq!(pod_spec("Service", &cj), None);
+    }
+}
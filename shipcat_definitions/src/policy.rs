@@ -0,0 +1,747 @@
+use regex::Regex;
+use serde_json::Value;
+use std::{convert::TryFrom, fmt};
+
+use super::{
+    config::Config,
+    region::{Environment, Region},
+    structs::{parse_cpu, parse_memory},
+    Error, Manifest, Result,
+};
+
+/// A declarative rule that manifests must satisfy
+///
+/// Policies can be defined globally in `shipcat.conf`, or on a `Region`.
+/// The `rule` is a small expression evaluated against the manifest:
+///
+/// ```yaml
+/// policies:
+/// - name: prod-requires-data-handling
+///   environments: [prod]
+///   rule: exists(dataHandling)
+/// - name: redundant-replicas
+///   rule: replicaCount >= 2 || exists(autoScaling)
+/// - name: small-volumes
+///   rule: memory(persistentVolumes[*].size) <= memory("2Ti")
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct Policy {
+    /// Name of the policy (used when reporting violations)
+    pub name: String,
+    /// Expression that must evaluate to true for every manifest
+    pub rule: Rule,
+    /// Optional explanation shown alongside violations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Environments to restrict this policy to (all if empty)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub environments: Vec<Environment>,
+}
+
+/// A policy rule, parsed when the config is loaded
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rule {
+    source: String,
+    expr: Expr,
+}
+
+impl TryFrom<String> for Rule {
+    type Error = Error;
+
+    fn try_from(source: String) -> Result<Self> {
+        let expr = Expr::parse(&source).map_err(|e| format!("invalid rule '{}': {}", source, e))?;
+        Ok(Rule { source, expr })
+    }
+}
+
+impl From<Rule> for String {
+    fn from(r: Rule) -> String {
+        r.source
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Sanity limits enforced in every region
+///
+/// Configured policies cannot reuse these names.
+const BUILTIN_POLICIES: &str = r#"
+- name: service-name
+  description: Service names must be at most 50 lower case characters, using dashes to separate words only
+  rule: matches(name, '^[0-9a-z-]{1,50}$') && !matches(name, '^-|-$')
+- name: bounded-resources
+  description: Containers cannot request or be limited to more than a c5.9xlarge (36 cores, 72 GB of memory)
+  rule: >-
+    cpu(resources.requests.cpu) <= 36 && cpu(resources.limits.cpu) <= 36
+    && memory(resources.requests.memory) <= memory('72Gi')
+    && memory(resources.limits.memory) <= memory('72Gi')
+- name: bounded-volumes
+  description: Persistent volumes cannot exceed the 16 TB limit of EBS volumes
+  rule: memory(persistentVolumes[*].size) <= memory('16Ti')
+"#;
+
+lazy_static! {
+    static ref BUILTIN: Vec<Policy> = serde_yaml::from_str(BUILTIN_POLICIES).expect("builtin policies parse");
+}
+
+impl Policy {
+    /// Policies shipcat enforces regardless of configuration
+    pub fn builtin() -> &'static [Policy] {
+        &BUILTIN
+    }
+
+    /// Verify that the policy name is sane
+    ///
+    /// Rules are verified when they are deserialized.
+    pub fn verify(&self) -> Result<()> {
+        let re = Regex::new(r"^[0-9a-z\-]{1,50}$").unwrap();
+        if !re.is_match(&self.name) {
+            bail!(
+                "Policy name '{}' must be short, lower case, and dash separated",
+                self.name
+            );
+        }
+        if Policy::builtin().iter().any(|b| b.name == self.name) {
+            bail!("Policy name {} is reserved for a builtin policy", self.name);
+        }
+        Ok(())
+    }
+
+    /// Whether the policy is enforced in an environment
+    pub fn applies_to(&self, env: &Environment) -> bool {
+        self.environments.is_empty() || self.environments.contains(env)
+    }
+
+    /// Evaluate the rule against a policy context
+    pub fn evaluate(&self, ctx: &Value) -> Result<bool> {
+        match self.rule.expr.eval(ctx)? {
+            Value::Bool(b) => Ok(b),
+            v => bail!("rule evaluated to {} rather than a boolean", v),
+        }
+    }
+}
+
+/// Policy evaluation for manifests
+impl Manifest {
+    /// The value tree policies are evaluated against
+    ///
+    /// This is the serialized manifest, plus the global properties we do not serialize.
+    pub fn policy_context(&self) -> Result<Value> {
+        let mut ctx = serde_json::to_value(self)?;
+        if let Value::Object(ref mut o) = ctx {
+            o.remove("secrets");
+            o.insert("publiclyAccessible".into(), Value::Bool(self.publiclyAccessible));
+            o.insert("external".into(), Value::Bool(self.external));
+            o.insert("disabled".into(), Value::Bool(self.disabled));
+            o.insert("regions".into(), serde_json::to_value(&self.regions)?);
+            o.insert("imageSize".into(), serde_json::to_value(self.imageSize)?);
+            o.insert("dataHandling".into(), serde_json::to_value(&self.dataHandling)?);
+        }
+        Ok(ctx)
+    }
+
+    /// Names of the policies in scope for an environment that do not hold
+    ///
+    /// Every violation is logged. Rules that cannot be evaluated count as violations.
+    pub fn policy_violations<'a>(
+        &self,
+        policies: impl IntoIterator<Item = &'a Policy>,
+        env: &Environment,
+    ) -> Result<Vec<String>> {
+        let ctx = self.policy_context()?;
+        let mut violations = vec![];
+        for p in policies.into_iter().filter(|p| p.applies_to(env)) {
+            match p.evaluate(&ctx) {
+                Ok(true) => {}
+                Ok(false) => {
+                    if let Some(d) = &p.description {
+                        error!("{} violates policy {}: {}", self.name, p.name, d);
+                    } else {
+                        error!("{} violates policy {}: {}", self.name, p.name, p.rule);
+                    }
+                    violations.push(p.name.clone());
+                }
+                Err(e) => {
+                    error!("{} could not evaluate policy {}: {}", self.name, p.name, e);
+                    violations.push(p.name.clone());
+                }
+            }
+        }
+        Ok(violations)
+    }
+
+    /// Verify that builtin, global and region policies hold
+    ///
+    /// Every violated policy is reported before failing.
+    pub fn verify_policies(&self, conf: &Config, region: &Region) -> Result<()> {
+        let policies = Policy::builtin()
+            .iter()
+            .chain(conf.policies.iter())
+            .chain(region.policies.iter());
+        let violations = self.policy_violations(policies, &region.environment)?;
+        if !violations.is_empty() {
+            bail!(
+                "{} violates policies in {}: {}",
+                self.name,
+                region.name,
+                violations.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------------
+// Expression language
+//
+// A deliberately small language:
+// - literals: numbers, "strings", 'strings', true, false, null
+// - paths into the manifest: resources.requests.cpu, labels["custom-metrics"], workers[0].name
+// - wildcards collecting lists: sidecars[*].name
+// - comparisons: == != < <= > >= (a list compared to a scalar must hold for every element)
+// - logic: && || ! and parentheses
+// - functions: exists, len, cpu, memory, matches, contains, any, all, sum, min, max
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Num(f64),
+    Str(String),
+    Op(&'static str),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let num: String = chars[start..i].iter().collect();
+            tokens.push(Token::Num(num.parse()?));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if c == '"' || c == '\'' {
+            let start = i + 1;
+            i = start;
+            while i < chars.len() && chars[i] != c {
+                i += 1;
+            }
+            if i == chars.len() {
+                bail!("unterminated string starting at position {}", start - 1);
+            }
+            tokens.push(Token::Str(chars[start..i].iter().collect()));
+            i += 1;
+        } else {
+            let rest: String = chars[i..].iter().take(2).collect();
+            let op = ["==", "!=", "<=", ">=", "&&", "||"]
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .cloned()
+                .or_else(|| {
+                    ["<", ">", "!", "(", ")", "[", "]", ".", ",", "*"]
+                        .iter()
+                        .find(|op| rest.starts_with(*op))
+                        .cloned()
+                });
+            match op {
+                Some(op) => {
+                    i += op.len();
+                    tokens.push(Token::Op(op));
+                }
+                None => bail!("unexpected character '{}' at position {}", c, i),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+enum Segment {
+    Key(String),
+    Index(usize),
+    Wildcard,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Literal(Value),
+    Path(Vec<Segment>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(&'static str, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        match self.peek() {
+            Some(Token::Op(o)) if *o == op => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<()> {
+        if !self.eat(op) {
+            bail!("expected '{}' but found {:?}", op, self.peek());
+        }
+        Ok(())
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut lhs = self.and()?;
+        while self.eat("||") {
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut lhs = self.not()?;
+        while self.eat("&&") {
+            lhs = Expr::And(Box::new(lhs), Box::new(self.not()?));
+        }
+        Ok(lhs)
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let lhs = self.primary()?;
+        for op in &["==", "!=", "<=", ">=", "<", ">"] {
+            if self.eat(op) {
+                let rhs = self.primary()?;
+                return Ok(Expr::Compare(op, Box::new(lhs), Box::new(rhs)));
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Num(n)) => Ok(Expr::Literal(n.into())),
+            Some(Token::Str(s)) => Ok(Expr::Literal(Value::String(s))),
+            Some(Token::Op("(")) => {
+                let e = self.or()?;
+                self.expect(")")?;
+                Ok(e)
+            }
+            Some(Token::Ident(id)) => match id.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                _ if self.eat("(") => {
+                    let mut args = vec![];
+                    if !self.eat(")") {
+                        loop {
+                            args.push(self.or()?);
+                            if self.eat(")") {
+                                break;
+                            }
+                            self.expect(",")?;
+                        }
+                    }
+                    check_arity(&id, args.len())?;
+                    Ok(Expr::Call(id, args))
+                }
+                _ => self.path(id),
+            },
+            t => bail!("unexpected token {:?}", t),
+        }
+    }
+
+    fn path(&mut self, first: String) -> Result<Expr> {
+        let mut segments = vec![Segment::Key(first)];
+        loop {
+            if self.eat(".") {
+                match self.next() {
+                    Some(Token::Ident(id)) => segments.push(Segment::Key(id)),
+                    t => bail!("expected a field name after '.' but found {:?}", t),
+                }
+            } else if self.eat("[") {
+                match self.next() {
+                    Some(Token::Op("*")) => segments.push(Segment::Wildcard),
+                    Some(Token::Str(s)) => segments.push(Segment::Key(s)),
+                    Some(Token::Num(n)) if n >= 0.0 && n.fract() == 0.0 => {
+                        segments.push(Segment::Index(n as usize))
+                    }
+                    t => bail!("expected an index, a quoted key, or '*' but found {:?}", t),
+                }
+                self.expect("]")?;
+            } else {
+                return Ok(Expr::Path(segments));
+            }
+        }
+    }
+}
+
+fn check_arity(func: &str, n: usize) -> Result<()> {
+    let expected = match func {
+        "exists" | "len" | "cpu" | "memory" | "any" | "all" | "sum" | "min" | "max" => 1,
+        "matches" | "contains" => 2,
+        _ => bail!("unknown function {}", func),
+    };
+    if n != expected {
+        bail!("{} takes {} argument(s) but was given {}", func, expected, n);
+    }
+    Ok(())
+}
+
+impl Expr {
+    fn parse(input: &str) -> Result<Expr> {
+        let mut p = Parser {
+            tokens: tokenize(input)?,
+            pos: 0,
+        };
+        let e = p.or()?;
+        if let Some(t) = p.peek() {
+            bail!("unexpected trailing token {:?}", t);
+        }
+        Ok(e)
+    }
+
+    fn eval(&self, ctx: &Value) -> Result<Value> {
+        Ok(match self {
+            Expr::Literal(v) => v.clone(),
+            Expr::Path(segments) => lookup(ctx, segments),
+            Expr::Not(e) => Value::Bool(!as_bool(&e.eval(ctx)?)?),
+            Expr::And(l, r) => Value::Bool(as_bool(&l.eval(ctx)?)? && as_bool(&r.eval(ctx)?)?),
+            Expr::Or(l, r) => Value::Bool(as_bool(&l.eval(ctx)?)? || as_bool(&r.eval(ctx)?)?),
+            Expr::Compare(op, l, r) => Value::Bool(compare(op, &l.eval(ctx)?, &r.eval(ctx)?)?),
+            Expr::Call(func, args) => {
+                let args = args.iter().map(|a| a.eval(ctx)).collect::<Result<Vec<_>>>()?;
+                call(func, &args)?
+            }
+        })
+    }
+}
+
+fn lookup(ctx: &Value, segments: &[Segment]) -> Value {
+    let mut current = vec![ctx.clone()];
+    let mut collecting = false;
+    for s in segments {
+        current = current
+            .into_iter()
+            .flat_map(|v| match (s, v) {
+                (Segment::Key(k), Value::Object(mut o)) => vec![o.remove(k).unwrap_or(Value::Null)],
+                (Segment::Index(i), Value::Array(mut a)) if *i < a.len() => vec![a.swap_remove(*i)],
+                (Segment::Wildcard, Value::Array(a)) => a,
+                (Segment::Wildcard, Value::Object(o)) => o.into_iter().map(|(_, v)| v).collect(),
+                (Segment::Wildcard, _) => vec![],
+                _ => vec![Value::Null],
+            })
+            .collect();
+        if let Segment::Wildcard = s {
+            collecting = true;
+        }
+    }
+    if collecting {
+        Value::Array(current.into_iter().filter(|v| !v.is_null()).collect())
+    } else {
+        current.pop().unwrap_or(Value::Null)
+    }
+}
+
+fn as_bool(v: &Value) -> Result<bool> {
+    match v {
+        Value::Bool(b) => Ok(*b),
+        _ => bail!("expected a boolean but found {}", v),
+    }
+}
+
+fn compare(op: &str, lhs: &Value, rhs: &Value) -> Result<bool> {
+    // lists compared against scalars must hold for every element
+    match (lhs, rhs) {
+        (Value::Array(xs), r) if !r.is_array() => {
+            return xs.iter().try_fold(true, |acc, x| Ok(acc && compare(op, x, r)?));
+        }
+        (l, Value::Array(ys)) if !l.is_array() => {
+            return ys.iter().try_fold(true, |acc, y| Ok(acc && compare(op, l, y)?));
+        }
+        _ => {}
+    }
+    let ord = match (lhs, rhs) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+    Ok(match op {
+        "==" => ord.map(|o| o == std::cmp::Ordering::Equal).unwrap_or(lhs == rhs),
+        "!=" => ord.map(|o| o != std::cmp::Ordering::Equal).unwrap_or(lhs != rhs),
+        _ if lhs.is_null() || rhs.is_null() => false, // missing values never satisfy bounds
+        _ => {
+            let o = match ord {
+                Some(o) => o,
+                None => bail!("cannot order {} against {}", lhs, rhs),
+            };
+            match op {
+                "<" => o == std::cmp::Ordering::Less,
+                "<=" => o != std::cmp::Ordering::Greater,
+                ">" => o == std::cmp::Ordering::Greater,
+                ">=" => o != std::cmp::Ordering::Less,
+                _ => unreachable!("parser only emits known comparison operators"),
+            }
+        }
+    })
+}
+
+fn numbers(v: &Value) -> Result<Vec<f64>> {
+    let xs = match v {
+        Value::Array(xs) => xs.iter().collect(),
+        _ => vec![v],
+    };
+    xs.into_iter()
+        .map(|x| match x.as_f64() {
+            Some(n) => Ok(n),
+            None => bail!("expected numbers but found {}", x),
+        })
+        .collect()
+}
+
+// Apply a quantity parser to a string, number, or a list of those
+fn quantity(v: &Value, parse: fn(&str) -> Result<f64>) -> Result<Value> {
+    Ok(match v {
+        Value::Null => Value::Null,
+        Value::Number(_) => v.clone(),
+        Value::String(s) => parse(s)?.into(),
+        Value::Array(xs) => Value::Array(
+            xs.iter()
+                .map(|x| quantity(x, parse))
+                .collect::<Result<Vec<_>>>()?,
+        ),
+        _ => bail!("cannot parse {} as a quantity", v),
+    })
+}
+
+fn call(func: &str, args: &[Value]) -> Result<Value> {
+    let arg = &args[0];
+    Ok(match func {
+        "exists" => match arg {
+            Value::Null => false,
+            Value::Array(xs) => !xs.is_empty(),
+            _ => true,
+        }
+        .into(),
+        "len" => match arg {
+            Value::Null => 0,
+            Value::String(s) => s.chars().count(),
+            Value::Array(xs) => xs.len(),
+            Value::Object(o) => o.len(),
+            _ => bail!("len is not defined for {}", arg),
+        }
+        .into(),
+        "cpu" => quantity(arg, parse_cpu)?,
+        "memory" => quantity(arg, parse_memory)?,
+        "matches" => {
+            let re = match &args[1] {
+                Value::String(s) => Regex::new(s).map_err(|e| format!("invalid regex: {}", e))?,
+                v => bail!("matches needs a regex string but found {}", v),
+            };
+            let is_match = |v: &Value| -> Result<bool> {
+                match v {
+                    Value::String(s) => Ok(re.is_match(s)),
+                    Value::Null => Ok(false),
+                    _ => bail!("matches needs strings but found {}", v),
+                }
+            };
+            match arg {
+                Value::Array(xs) => xs
+                    .iter()
+                    .try_fold(true, |acc, x| -> Result<bool> { Ok(acc && is_match(x)?) })?,
+                _ => is_match(arg)?,
+            }
+            .into()
+        }
+        "contains" => match (arg, &args[1]) {
+            (Value::Array(xs), y) => xs.contains(y),
+            (Value::Object(o), Value::String(k)) => o.contains_key(k),
+            (Value::String(s), Value::String(sub)) => s.contains(sub.as_str()),
+            (Value::Null, _) => false,
+            (x, y) => bail!("cannot check if {} contains {}", x, y),
+        }
+        .into(),
+        "any" | "all" => {
+            let xs = match arg {
+                Value::Array(xs) => xs.clone(),
+                _ => bail!("{} needs a list but found {}", func, arg),
+            };
+            let bools = xs.iter().map(as_bool).collect::<Result<Vec<_>>>()?;
+            if func == "any" {
+                bools.into_iter().any(|b| b)
+            } else {
+                bools.into_iter().all(|b| b)
+            }
+            .into()
+        }
+        "sum" => numbers(arg)?.into_iter().sum::<f64>().into(),
+        "min" => numbers(arg)?
+            .into_iter()
+            .fold(None, |acc: Option<f64>, x| Some(acc.map_or(x, |a| a.min(x))))
+            .map_or(Value::Null, Value::from),
+        "max" => numbers(arg)?
+            .into_iter()
+            .fold(None, |acc: Option<f64>, x| Some(acc.map_or(x, |a| a.max(x))))
+            .map_or(Value::Null, Value::from),
+        _ => bail!("unknown function {}", func),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Expr, Policy};
+    use crate::{region::Environment, Manifest, Result};
+    use serde_json::{json, Value};
+
+    fn evaluate(rule: &str) -> Result<Value> {
+        let ctx = json!({
+            "name": "fake-ask",
+            "replicaCount": 2,
+            "environment": "dev",
+            "labels": { "custom-metrics": "true" },
+            "resources": { "requests": { "cpu": "250m", "memory": "1Gi" } },
+            "sidecars": [
+                { "name": "redis", "resources": { "requests": { "cpu": "100m" } } },
+                { "name": "proxy" },
+            ],
+            "persistentVolumes": [],
+        });
+        Expr::parse(rule)?.eval(&ctx)
+    }
+
+    fn check(rule: &str) -> bool {
+        evaluate(rule).unwrap() == Value::Bool(true)
+    }
+
+    #[test]
+    fn policy_comparisons() {
+        assert!(check("replicaCount >= 2"));
+        assert!(!check("replicaCount > 2"));
+        assert!(check("name == 'fake-ask' && environment != \"prod\""));
+        assert!(check("!(replicaCount < 2) || false"));
+        assert!(check("labels[\"custom-metrics\"] == 'true'"));
+        assert!(check("sidecars[1].name == 'proxy'"));
+        // missing values never satisfy bounds
+        assert!(!check("autoScaling.maxReplicas <= 10"));
+        assert!(check("autoScaling == null"));
+    }
+
+    #[test]
+    fn policy_functions() {
+        assert!(check("cpu(resources.requests.cpu) <= 0.25"));
+        assert!(check("memory(resources.requests.memory) == memory('1024Mi')"));
+        assert!(check("exists(resources) && !exists(dataHandling)"));
+        assert!(check("len(sidecars[*].name) == 2"));
+        assert!(check("matches(name, '^[a-z-]+$')"));
+        assert!(check("contains(sidecars[*].name, 'redis')"));
+        assert!(check("sum(cpu(sidecars[*].resources.requests.cpu)) == 0.1"));
+        // list comparisons must hold for all elements (vacuously true when empty)
+        assert!(check("cpu(sidecars[*].resources.requests.cpu) < 1"));
+        assert!(check("memory(persistentVolumes[*].size) <= memory('16Ti')"));
+        assert!(check("max(persistentVolumes[*].size) == null"));
+    }
+
+    #[test]
+    fn policy_errors() {
+        assert!(evaluate("replicaCount >").is_err());
+        assert!(evaluate("nope(name)").is_err());
+        assert!(evaluate("len(name, name)").is_err());
+        assert!(evaluate("name && true").is_err());
+        assert!(evaluate("'unterminated").is_err());
+        assert!(evaluate("name < 2").is_err());
+    }
+
+    #[test]
+    fn policy_manifest_context() {
+        let mut mf = Manifest::test("fake-ask");
+        mf.replicaCount = Some(1);
+        let ctx = mf.policy_context().unwrap();
+        let p: Policy = serde_yaml::from_str("name: redundant\nrule: replicaCount >= 2").unwrap();
+        assert!(p.verify().is_ok());
+        assert!(!p.evaluate(&ctx).unwrap());
+        let p: Policy = serde_yaml::from_str("name: gdpr\nrule: exists(dataHandling)").unwrap();
+        assert!(!p.evaluate(&ctx).unwrap());
+        let p: Policy = serde_yaml::from_str("name: Bad_Name\nrule: true").unwrap();
+        assert!(p.verify().is_err());
+        let p: Policy = serde_yaml::from_str("name: service-name\nrule: true").unwrap();
+        assert!(p.verify().is_err());
+        // rules are parsed when loaded
+        assert!(serde_yaml::from_str::<Policy>("name: broken\nrule: replicaCount >").is_err());
+    }
+
+    #[test]
+    fn policy_violations() {
+        let mut mf = Manifest::test("fake-ask");
+        mf.resources = Some(
+            serde_json::from_value(json!({
+                "requests": { "cpu": "1", "memory": "1Gi" },
+                "limits": { "cpu": "2", "memory": "2Gi" },
+            }))
+            .unwrap(),
+        );
+        let dev = Environment::Dev;
+        assert!(mf.policy_violations(Policy::builtin(), &dev).unwrap().is_empty());
+
+        mf.name = "-fake-ask".into();
+        mf.resources.as_mut().unwrap().limits.cpu = "40".into();
+        mf.persistentVolumes = vec![serde_json::from_value(json!({
+            "name": "data", "mountPath": "/data", "size": "20Ti"
+        }))
+        .unwrap()];
+        let violations = mf.policy_violations(Policy::builtin(), &dev).unwrap();
+        assert_eq!(violations, vec![
+            "service-name",
+            "bounded-resources",
+            "bounded-volumes"
+        ]);
+
+        // configured policies are scoped to environments
+        let p: Policy =
+            serde_yaml::from_str("name: no-sidecars\nrule: len(sidecars) == 0\nenvironments: [prod]")
+                .unwrap();
+        mf.sidecars = vec![Default::default()];
+        assert!(mf.policy_violations(vec![&p], &dev).unwrap().is_empty());
+        assert_eq!(mf.policy_violations(vec![&p], &Environment::Prod).unwrap(), vec![
+            "no-sidecars"
+        ]);
+    }
+}
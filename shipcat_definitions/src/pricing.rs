@@ -0,0 +1,243 @@
+use std::ops::AddAssign;
+
+use super::{
+    math::ResourceTotals,
+    structs::{parse_cpu, parse_memory, Resources},
+    Result,
+};
+
+/// Hours in an average month (365 * 24 / 12)
+const HOURS_PER_MONTH: f64 = 730.0;
+
+/// Bytes in a GiB
+const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+/// Pricing of a node type serving a cluster
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct InstancePricing {
+    /// Instance type name, e.g. m5.2xlarge
+    pub name: String,
+    /// On-demand price per hour in dollars
+    pub hourlyCost: f64,
+    /// Spot price per hour in dollars (defaults to the on-demand price)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spotHourlyCost: Option<f64>,
+    /// Resources allocatable to pods on one node
+    pub allocatable: Resources<String>,
+    /// Relative share of the cluster's nodes using this instance type
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+}
+fn default_weight() -> f64 {
+    1.0
+}
+
+impl InstancePricing {
+    /// Blended hourly cost of a node given a fraction of spot nodes
+    fn node_cost(&self, spot_ratio: f64) -> f64 {
+        let spot = self.spotHourlyCost.unwrap_or(self.hourlyCost);
+        (1.0 - spot_ratio) * self.hourlyCost + spot_ratio * spot
+    }
+
+    /// Hourly cost of a core and a GiB of memory on this instance type
+    fn unit_costs(&self, spot_ratio: f64) -> Result<(f64, f64)> {
+        let node = self.node_cost(spot_ratio);
+        let cpu = parse_cpu(&self.allocatable.cpu)?;
+        let memory = parse_memory(&self.allocatable.memory)? / GIB;
+        Ok((node / cpu, node / memory))
+    }
+
+    fn verify(&self) -> Result<()> {
+        if self.hourlyCost <= 0.0 {
+            bail!("Instance {} must have a positive hourlyCost", self.name);
+        }
+        if let Some(spot) = self.spotHourlyCost {
+            if spot <= 0.0 {
+                bail!("Instance {} must have a positive spotHourlyCost", self.name);
+            }
+        }
+        if self.weight <= 0.0 {
+            bail!("Instance {} must have a positive weight", self.name);
+        }
+        if parse_cpu(&self.allocatable.cpu)? <= 0.0 || parse_memory(&self.allocatable.memory)? <= 0.0 {
+            bail!("Instance {} must have allocatable cpu and memory", self.name);
+        }
+        Ok(())
+    }
+}
+
+/// Pricing model for a cluster
+///
+/// Used to turn analytical resource requests into dollar estimates.
+/// Services are charged for their dominant resource (cpu or memory),
+/// as that is what forces extra nodes into the cluster.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct PricingModel {
+    /// Instance types serving the cluster's worker nodes
+    pub instances: Vec<InstancePricing>,
+    /// Fraction of nodes running on spot instances (between 0 and 1)
+    #[serde(default)]
+    pub spotRatio: f64,
+    /// Monthly price per GiB of persistent volume storage
+    #[serde(default)]
+    pub storageCost: f64,
+}
+
+/// The old hardcoded estimate: on-demand m5.2xlarge nodes
+impl Default for PricingModel {
+    fn default() -> Self {
+        PricingModel {
+            instances: vec![InstancePricing {
+                name: "m5.2xlarge".into(),
+                hourlyCost: 0.384,
+                spotHourlyCost: None,
+                allocatable: Resources {
+                    cpu: "8".into(),
+                    memory: "31Gi".into(),
+                },
+                weight: 1.0,
+            }],
+            spotRatio: 0.0,
+            storageCost: 0.0,
+        }
+    }
+}
+
+/// Lower and upper bounds of a monthly cost estimate in dollars
+///
+/// The upper bound accounts for autoscaling up to the maximum replicas.
+#[derive(Serialize, Default, Clone, Debug, PartialEq)]
+pub struct Cost {
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl AddAssign for Cost {
+    fn add_assign(&mut self, rhs: Cost) {
+        self.lower += rhs.lower;
+        self.upper += rhs.upper;
+    }
+}
+
+impl PricingModel {
+    pub fn verify(&self) -> Result<()> {
+        if self.instances.is_empty() {
+            bail!("A pricing model needs at least one instance type");
+        }
+        for i in &self.instances {
+            i.verify()?;
+        }
+        if self.spotRatio < 0.0 || self.spotRatio > 1.0 {
+            bail!("spotRatio must be between 0 and 1");
+        }
+        if self.storageCost < 0.0 {
+            bail!("storageCost cannot be negative");
+        }
+        Ok(())
+    }
+
+    /// Hourly cost of a core and a GiB of memory, weighted across instance types
+    fn unit_costs(&self) -> Result<(f64, f64)> {
+        let (mut cpu, mut memory, mut weights) = (0.0, 0.0, 0.0);
+        for i in &self.instances {
+            let (c, m) = i.unit_costs(self.spotRatio)?;
+            cpu += c * i.weight;
+            memory += m * i.weight;
+            weights += i.weight;
+        }
+        Ok((cpu / weights, memory / weights))
+    }
+
+    /// Compute the monthly cost bounds of a set of resource totals
+    ///
+    /// Expects raw totals (cores and bytes) as returned by `compute_resource_totals`.
+    pub fn monthly_cost(&self, totals: &ResourceTotals) -> Result<Cost> {
+        let (cpu_rate, memory_rate) = self.unit_costs()?;
+        let storage = totals.storage / GIB * self.storageCost;
+        let hourly = |cpu: f64, memory: f64| f64::max(cpu * cpu_rate, memory / GIB * memory_rate);
+
+        let base = &totals.base.requests;
+        let extra = &totals.extra.requests;
+        let lower = hourly(base.cpu, base.memory) * HOURS_PER_MONTH + storage;
+        let upper = hourly(base.cpu + extra.cpu, base.memory + extra.memory) * HOURS_PER_MONTH + storage;
+        Ok(Cost {
+            lower: (lower * 100.0).round() / 100.0,
+            upper: (upper * 100.0).round() / 100.0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PricingModel, GIB};
+    use crate::{math::ResourceTotals, structs::Resources};
+
+    fn totals(cpu: f64, memory: f64, extra_cpu: f64) -> ResourceTotals {
+        let mut t = ResourceTotals::default();
+        t.base.requests = Resources {
+            cpu,
+            memory: memory * GIB,
+        };
+        t.extra.requests = Resources {
+            cpu: extra_cpu,
+            memory: 0.0,
+        };
+        t
+    }
+
+    #[test]
+    fn pricing_default_model() {
+        let model = PricingModel::default();
+        assert!(model.verify().is_ok());
+        // a full node costs the same as the node
+        let cost = model.monthly_cost(&totals(8.0, 1.0, 0.0)).unwrap();
+        assert_eq!(cost.lower, 280.32); // 0.384 * 730
+        assert_eq!(cost.lower, cost.upper);
+        // memory dominates here
+        let cost = model.monthly_cost(&totals(1.0, 31.0, 0.0)).unwrap();
+        assert_eq!(cost.lower, 280.32);
+        // autoscaling only affects the upper bound (and only once)
+        let cost = model.monthly_cost(&totals(4.0, 1.0, 4.0)).unwrap();
+        assert_eq!(cost.lower, 140.16);
+        assert_eq!(cost.upper, 280.32);
+    }
+
+    #[test]
+    fn pricing_spot_and_storage() {
+        let model: PricingModel = serde_yaml::from_str(
+            "
+instances:
+- name: m5.2xlarge
+  hourlyCost: 0.4
+  spotHourlyCost: 0.2
+  allocatable: { cpu: '8', memory: 32Gi }
+- name: r5.2xlarge
+  hourlyCost: 0.5
+  allocatable: { cpu: '8', memory: 64Gi }
+  weight: 0
+spotRatio: 0.5
+storageCost: 0.1",
+        )
+        .unwrap();
+        assert!(model.verify().is_err()); // zero weight
+
+        let model: PricingModel = serde_yaml::from_str(
+            "
+instances:
+- name: m5.2xlarge
+  hourlyCost: 0.4
+  spotHourlyCost: 0.2
+  allocatable: { cpu: '8', memory: 32Gi }
+spotRatio: 0.5
+storageCost: 0.1",
+        )
+        .unwrap();
+        assert!(model.verify().is_ok());
+        let mut t = totals(8.0, 1.0, 0.0);
+        t.storage = 100.0 * GIB;
+        let cost = model.monthly_cost(&t).unwrap();
+        assert_eq!(cost.lower, 229.0); // 0.3 * 730 + 10
+    }
+}
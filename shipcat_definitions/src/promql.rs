@@ -0,0 +1,692 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use prometheus_parser::{
+    AggregationOp, Expression, Function, LabelOp, Matching, MatchingGroupOp, MatchingOp, Operator,
+    OperatorKind, PromDuration, Selector,
+};
+use regex::Regex;
+
+use super::Result;
+
+/// Labels of a series, with the metric name in `__name__`
+pub type Labels = BTreeMap<String, String>;
+
+/// Instant vector elements
+pub type Vector = Vec<(Labels, f64)>;
+
+/// How far back instant selectors look for a sample, in seconds
+const LOOKBACK: i64 = 5 * 60;
+
+/// A timeseries with samples at offsets in seconds
+#[derive(Clone, Debug, PartialEq)]
+pub struct Series {
+    pub labels: Labels,
+    pub samples: Vec<(i64, f64)>,
+}
+
+impl Series {
+    /// Parse a `metric{label="value"}` series with values in promtool notation
+    ///
+    /// Values are space separated, one every `interval` seconds starting at 0.
+    /// `a+bxn` expands to `n+1` values starting at `a` and growing by `b`, `axn` repeats `a`,
+    /// and `_` skips a sample.
+    pub fn parse(series: &str, values: &str, interval: i64) -> Result<Series> {
+        let sel = match prometheus_parser::parse_expr(series) {
+            Ok(Expression::Selector(s)) => s,
+            _ => bail!("invalid input series {}", series),
+        };
+        let mut labels = Labels::new();
+        if let Some(name) = sel.metric {
+            labels.insert("__name__".into(), name);
+        }
+        for l in sel.labels {
+            if l.op != LabelOp::Equal {
+                bail!("input series {} can only use = label matchers", series);
+            }
+            labels.insert(l.key, l.value);
+        }
+        let samples = expand_values(values)?
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, v)| v.map(|v| (i as i64 * interval, v)))
+            .collect();
+        Ok(Series { labels, samples })
+    }
+}
+
+fn expand_values(values: &str) -> Result<Vec<Option<f64>>> {
+    let series_re = Regex::new(r"^([-+]?[\d.]+)(?:([+-])([\d.]+))?x(\d+)$").unwrap();
+    let mut res = vec![];
+    for token in values.split_whitespace() {
+        if token == "_" {
+            res.push(None);
+        } else if let Some(n) = token.strip_prefix("_x") {
+            let n: usize = n.parse()?;
+            res.resize(res.len() + n, None);
+        } else if let Some(caps) = series_re.captures(token) {
+            let start: f64 = caps[1].parse()?;
+            let step: f64 = caps.get(3).map_or("0", |m| m.as_str()).parse()?;
+            let step = if caps.get(2).map(|m| m.as_str()) == Some("-") {
+                -step
+            } else {
+                step
+            };
+            let n: usize = caps[4].parse()?;
+            res.extend((0..=n).map(|i| Some(start + step * i as f64)));
+        } else {
+            res.push(Some(token.parse()?));
+        }
+    }
+    Ok(res)
+}
+
+/// Parse a duration like `5m` into seconds
+pub fn parse_duration(s: &str) -> Result<i64> {
+    let re = Regex::new(r"^(\d+)([smhdwy])$").unwrap();
+    match re.captures(s) {
+        Some(caps) => {
+            let value: u64 = caps[1].parse()?;
+            Ok(seconds(PromDuration::from_pair(&caps[2], value)?))
+        }
+        None => bail!("invalid duration {} (needs to be like '30s' or '5m')", s),
+    }
+}
+
+fn seconds(d: PromDuration) -> i64 {
+    let (v, unit) = match d {
+        PromDuration::Seconds(v) => (v, 1),
+        PromDuration::Minutes(v) => (v, 60),
+        PromDuration::Hours(v) => (v, 60 * 60),
+        PromDuration::Days(v) => (v, 24 * 60 * 60),
+        PromDuration::Weeks(v) => (v, 7 * 24 * 60 * 60),
+        PromDuration::Years(v) => (v, 365 * 24 * 60 * 60),
+    };
+    v as i64 * unit
+}
+
+/// Result of evaluating an expression
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Scalar(f64),
+    String(String),
+    Vector(Vector),
+    /// Range vector with the selected range in seconds
+    Matrix(Vec<Series>, i64),
+}
+
+/// A PromQL evaluator over in-memory series
+///
+/// Covers the subset of PromQL used in alerts: selectors, `rate` and friends,
+/// `*_over_time`, aggregations, arithmetic, comparisons and set operators.
+/// Subqueries and functions outside this subset are rejected.
+pub struct Evaluator<'a> {
+    series: &'a [Series],
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(series: &'a [Series]) -> Self {
+        Evaluator { series }
+    }
+
+    /// Evaluate an expression at time `t` in seconds
+    pub fn eval(&self, expr: &Expression, t: i64) -> Result<Value> {
+        match expr {
+            Expression::Float(f) => Ok(Value::Scalar(*f)),
+            Expression::String(s) => Ok(Value::String(s.clone())),
+            Expression::Selector(s) => self.select(s, t),
+            Expression::Group(g) => {
+                if g.subquery.is_some() {
+                    bail!("subqueries are not supported");
+                }
+                self.eval(&g.expression, t)
+            }
+            Expression::Function(f) => self.function(f, t),
+            Expression::Operator(o) => self.binary(o, t),
+        }
+    }
+
+    /// Whether an alert with this expression and hold duration is firing at `at`
+    ///
+    /// The expression is evaluated every `interval` seconds from 0, and each returned
+    /// series has to stay active for `hold` seconds before the alert fires.
+    pub fn firing(&self, expr: &Expression, hold: i64, interval: i64, at: i64) -> Result<bool> {
+        let mut active: BTreeMap<Labels, i64> = BTreeMap::new();
+        let mut t = 0;
+        let mut last = 0;
+        while t <= at {
+            let v = match self.eval(expr, t)? {
+                Value::Vector(v) => v,
+                _ => bail!("alert expression must return an instant vector"),
+            };
+            active = v
+                .into_iter()
+                .map(|(l, _)| {
+                    let since = active.get(&l).cloned().unwrap_or(t);
+                    (l, since)
+                })
+                .collect();
+            last = t;
+            t += interval;
+        }
+        Ok(active.values().any(|since| last - since >= hold))
+    }
+
+    fn select(&self, sel: &Selector, t: i64) -> Result<Value> {
+        if sel.subquery.is_some() {
+            bail!("subqueries are not supported");
+        }
+        let t = t - sel.offset.map(seconds).unwrap_or(0);
+        let mut matchers = vec![];
+        for l in &sel.labels {
+            let re = match l.op {
+                LabelOp::RegexEqual | LabelOp::RegexNotEqual => {
+                    let re = Regex::new(&format!("^(?:{})$", l.value))
+                        .map_err(|e| format!("invalid regex {}: {}", l.value, e))?;
+                    Some(re)
+                }
+                _ => None,
+            };
+            matchers.push((l, re));
+        }
+        let matching = self.series.iter().filter(|s| {
+            let name_ok = sel.metric.as_ref().map(|m| s.labels.get("__name__") == Some(m));
+            name_ok.unwrap_or(true)
+                && matchers.iter().all(|(l, re)| {
+                    let v = s.labels.get(&l.key).map(String::as_str).unwrap_or("");
+                    match (l.op, re) {
+                        (LabelOp::Equal, _) => v == l.value,
+                        (LabelOp::NotEqual, _) => v != l.value,
+                        (LabelOp::RegexEqual, Some(re)) => re.is_match(v),
+                        (LabelOp::RegexNotEqual, Some(re)) => !re.is_match(v),
+                        _ => false,
+                    }
+                })
+        });
+        match sel.range {
+            Some(range) => {
+                let range = seconds(range);
+                let matrix = matching
+                    .map(|s| Series {
+                        labels: s.labels.clone(),
+                        samples: s
+                            .samples
+                            .iter()
+                            .filter(|(ts, _)| *ts >= t - range && *ts <= t)
+                            .cloned()
+                            .collect(),
+                    })
+                    .filter(|s| !s.samples.is_empty())
+                    .collect();
+                Ok(Value::Matrix(matrix, range))
+            }
+            None => {
+                let vector = matching
+                    .filter_map(|s| {
+                        s.samples
+                            .iter()
+                            .rev()
+                            .find(|(ts, _)| *ts <= t && *ts > t - LOOKBACK)
+                            .map(|(_, v)| (s.labels.clone(), *v))
+                    })
+                    .collect();
+                Ok(Value::Vector(vector))
+            }
+        }
+    }
+
+    fn function(&self, f: &Function, t: i64) -> Result<Value> {
+        if f.subquery.is_some() {
+            bail!("subqueries are not supported");
+        }
+        let args = f
+            .args
+            .iter()
+            .map(|a| self.eval(a, t))
+            .collect::<Result<Vec<_>>>()?;
+        let name = f.name.as_str();
+        let res = match name {
+            "sum" | "avg" | "min" | "max" | "count" => {
+                let v = vector_arg(name, &args, 0)?;
+                Value::Vector(aggregate(name, f, v))
+            }
+            "rate" | "increase" | "delta" | "irate" | "avg_over_time" | "min_over_time" | "max_over_time"
+            | "sum_over_time" | "count_over_time" => {
+                let (matrix, range) = match args.first() {
+                    Some(Value::Matrix(m, r)) => (m, *r),
+                    _ => bail!("{} needs a range vector", name),
+                };
+                let vector = matrix
+                    .iter()
+                    .filter_map(|s| {
+                        over_range(name, &s.samples, t - range, t).map(|v| (without_name(&s.labels), v))
+                    })
+                    .collect();
+                Value::Vector(vector)
+            }
+            "abs" | "ceil" | "floor" => {
+                let op: fn(f64) -> f64 = match name {
+                    "abs" => f64::abs,
+                    "ceil" => f64::ceil,
+                    _ => f64::floor,
+                };
+                let v = vector_arg(name, &args, 0)?;
+                Value::Vector(v.iter().map(|(l, x)| (without_name(l), op(*x))).collect())
+            }
+            "clamp_min" | "clamp_max" => {
+                let v = vector_arg(name, &args, 0)?;
+                let bound = match args.get(1) {
+                    Some(Value::Scalar(b)) => *b,
+                    _ => bail!("{} needs a scalar bound", name),
+                };
+                let clamp = |x: f64| {
+                    if name == "clamp_min" {
+                        x.max(bound)
+                    } else {
+                        x.min(bound)
+                    }
+                };
+                Value::Vector(v.iter().map(|(l, x)| (without_name(l), clamp(*x))).collect())
+            }
+            "absent" => {
+                let v = vector_arg(name, &args, 0)?;
+                if v.is_empty() {
+                    let mut labels = Labels::new();
+                    if let Some(Expression::Selector(s)) = f.args.first().map(|a| a.as_ref()) {
+                        for l in s.labels.iter().filter(|l| l.op == LabelOp::Equal) {
+                            labels.insert(l.key.clone(), l.value.clone());
+                        }
+                    }
+                    Value::Vector(vec![(labels, 1.0)])
+                } else {
+                    Value::Vector(vec![])
+                }
+            }
+            "time" => Value::Scalar(t as f64),
+            "vector" => match args.first() {
+                Some(Value::Scalar(s)) => Value::Vector(vec![(Labels::new(), *s)]),
+                _ => bail!("vector needs a scalar"),
+            },
+            "scalar" => {
+                let v = vector_arg(name, &args, 0)?;
+                Value::Scalar(if v.len() == 1 { v[0].1 } else { f64::NAN })
+            }
+            _ => bail!("unsupported function {}", name),
+        };
+        Ok(res)
+    }
+
+    fn binary(&self, o: &Operator, t: i64) -> Result<Value> {
+        let lhs = self.eval(&o.lhs, t)?;
+        let rhs = self.eval(&o.rhs, t)?;
+        let kind = o.kind;
+        let res = match (lhs, rhs) {
+            (Value::Scalar(a), Value::Scalar(b)) => match arithmetic(kind, a, b) {
+                Some(x) => Value::Scalar(x),
+                None => bail!("{} between scalars is not supported", kind),
+            },
+            (Value::Vector(v), Value::Scalar(b)) => Value::Vector(with_scalar(kind, v, |x| (x, b))?),
+            (Value::Scalar(a), Value::Vector(v)) => Value::Vector(with_scalar(kind, v, |x| (a, x))?),
+            (Value::Vector(l), Value::Vector(r)) => Value::Vector(vectors(kind, o.matching.as_ref(), l, r)?),
+            _ => bail!("{} needs scalar or instant vector operands", kind),
+        };
+        Ok(res)
+    }
+}
+
+fn vector_arg<'v>(name: &str, args: &'v [Value], i: usize) -> Result<&'v Vector> {
+    match args.get(i) {
+        Some(Value::Vector(v)) => Ok(v),
+        _ => bail!("{} needs an instant vector", name),
+    }
+}
+
+fn without_name(labels: &Labels) -> Labels {
+    let mut labels = labels.clone();
+    labels.remove("__name__");
+    labels
+}
+
+fn aggregate(op: &str, f: &Function, v: &Vector) -> Vector {
+    let mut groups: BTreeMap<Labels, Vec<f64>> = BTreeMap::new();
+    for (labels, x) in v {
+        let key = match &f.aggregation {
+            Some(agg) if agg.op == AggregationOp::By => labels
+                .iter()
+                .filter(|(k, _)| agg.labels.contains(k))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            Some(agg) => without_name(labels)
+                .into_iter()
+                .filter(|(k, _)| !agg.labels.contains(k))
+                .collect(),
+            None => Labels::new(),
+        };
+        groups.entry(key).or_default().push(*x);
+    }
+    groups
+        .into_iter()
+        .map(|(labels, xs)| {
+            let sum: f64 = xs.iter().sum();
+            let x = match op {
+                "sum" => sum,
+                "avg" => sum / xs.len() as f64,
+                "min" => xs.iter().cloned().fold(f64::INFINITY, f64::min),
+                "max" => xs.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+                _ => xs.len() as f64,
+            };
+            (labels, x)
+        })
+        .collect()
+}
+
+/// Evaluate a range function over the samples of one series
+fn over_range(name: &str, samples: &[(i64, f64)], start: i64, end: i64) -> Option<f64> {
+    let values = samples.iter().map(|(_, v)| *v);
+    match name {
+        "rate" => extrapolated_delta(samples, start, end, true).map(|d| d / (end - start) as f64),
+        "increase" => extrapolated_delta(samples, start, end, true),
+        "delta" => extrapolated_delta(samples, start, end, false),
+        "irate" => {
+            if samples.len() < 2 {
+                return None;
+            }
+            let (t1, v1) = samples[samples.len() - 2];
+            let (t2, v2) = samples[samples.len() - 1];
+            let d = if v2 < v1 { v2 } else { v2 - v1 };
+            Some(d / (t2 - t1) as f64)
+        }
+        "avg_over_time" => Some(values.sum::<f64>() / samples.len() as f64),
+        "min_over_time" => Some(values.fold(f64::INFINITY, f64::min)),
+        "max_over_time" => Some(values.fold(f64::NEG_INFINITY, f64::max)),
+        "sum_over_time" => Some(values.sum()),
+        _ => Some(samples.len() as f64),
+    }
+}
+
+/// Increase over a range, extrapolated to the range boundaries like Prometheus does
+fn extrapolated_delta(samples: &[(i64, f64)], start: i64, end: i64, counter: bool) -> Option<f64> {
+    if samples.len() < 2 {
+        return None;
+    }
+    let (first_t, first_v) = samples[0];
+    let (last_t, last_v) = samples[samples.len() - 1];
+    let mut delta = last_v - first_v;
+    if counter {
+        for w in samples.windows(2) {
+            if w[1].1 < w[0].1 {
+                delta += w[0].1;
+            }
+        }
+    }
+    let sampled = (last_t - first_t) as f64;
+    let average = sampled / (samples.len() - 1) as f64;
+    let mut to_start = (first_t - start) as f64;
+    let to_end = (end - last_t) as f64;
+    if counter && delta > 0.0 && first_v >= 0.0 {
+        // counters do not go below zero
+        to_start = to_start.min(sampled * (first_v / delta));
+    }
+    let threshold = average * 1.1;
+    let mut interval = sampled;
+    interval += if to_start < threshold {
+        to_start
+    } else {
+        average / 2.0
+    };
+    interval += if to_end < threshold { to_end } else { average / 2.0 };
+    Some(delta * interval / sampled)
+}
+
+fn arithmetic(kind: OperatorKind, a: f64, b: f64) -> Option<f64> {
+    use OperatorKind::*;
+    Some(match kind {
+        Power => a.powf(b),
+        Multiply => a * b,
+        Divide => a / b,
+        Modulo => a % b,
+        Add => a + b,
+        Subtract => a - b,
+        _ => return None,
+    })
+}
+
+fn comparison(kind: OperatorKind, a: f64, b: f64) -> Option<bool> {
+    use OperatorKind::*;
+    Some(match kind {
+        Equal => (a - b).abs() < f64::EPSILON,
+        NotEqual => (a - b).abs() >= f64::EPSILON,
+        LessThan => a < b,
+        LessThanEqual => a <= b,
+        GreaterThan => a > b,
+        GreaterThanEqual => a >= b,
+        _ => return None,
+    })
+}
+
+/// Apply an operator between a vector and a scalar
+///
+/// `operands` orders the vector element and the scalar as in the expression.
+fn with_scalar(kind: OperatorKind, v: Vector, operands: impl Fn(f64) -> (f64, f64)) -> Result<Vector> {
+    let mut res = vec![];
+    for (labels, x) in v {
+        let (a, b) = operands(x);
+        if let Some(y) = arithmetic(kind, a, b) {
+            res.push((without_name(&labels), y));
+        } else if let Some(keep) = comparison(kind, a, b) {
+            if keep {
+                res.push((labels, x));
+            }
+        } else {
+            bail!("{} needs instant vectors on both sides", kind);
+        }
+    }
+    Ok(res)
+}
+
+/// Labels used to match elements of two vectors
+fn signature(labels: &Labels, matching: Option<&Matching>) -> Labels {
+    match matching {
+        Some(m) if m.op == MatchingOp::On => labels
+            .iter()
+            .filter(|(k, _)| m.labels.contains(k))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect(),
+        Some(m) => without_name(labels)
+            .into_iter()
+            .filter(|(k, _)| !m.labels.contains(k))
+            .collect(),
+        None => without_name(labels),
+    }
+}
+
+fn vectors(kind: OperatorKind, matching: Option<&Matching>, lhs: Vector, rhs: Vector) -> Result<Vector> {
+    let sig = |l: &Labels| signature(l, matching);
+    let rhs_sigs = rhs.iter().map(|(l, _)| sig(l)).collect::<BTreeSet<_>>();
+    match kind {
+        OperatorKind::And => {
+            return Ok(lhs
+                .into_iter()
+                .filter(|(l, _)| rhs_sigs.contains(&sig(l)))
+                .collect())
+        }
+        OperatorKind::Unless => {
+            return Ok(lhs
+                .into_iter()
+                .filter(|(l, _)| !rhs_sigs.contains(&sig(l)))
+                .collect())
+        }
+        OperatorKind::Or => {
+            let lhs_sigs = lhs.iter().map(|(l, _)| sig(l)).collect::<BTreeSet<_>>();
+            let extra = rhs.into_iter().filter(|(l, _)| !lhs_sigs.contains(&sig(l)));
+            return Ok(lhs.into_iter().chain(extra).collect());
+        }
+        _ => {}
+    }
+
+    // the "many" side keeps its labels, the "one" side is looked up by signature
+    let group = matching.and_then(|m| m.group.as_ref());
+    let swapped = group.map(|g| g.op == MatchingGroupOp::Right).unwrap_or(false);
+    let (many, one) = if swapped { (rhs, lhs) } else { (lhs, rhs) };
+    let mut ones: BTreeMap<Labels, &(Labels, f64)> = BTreeMap::new();
+    for el in &one {
+        if ones.insert(sig(&el.0), el).is_some() {
+            bail!("many-to-many matching for {}: {:?}", kind, sig(&el.0));
+        }
+    }
+    let mut seen = BTreeSet::new();
+    let mut res = vec![];
+    for (labels, x) in many {
+        let s = sig(&labels);
+        let (other_labels, y) = match ones.get(&s) {
+            Some(el) => (&el.0, el.1),
+            None => continue,
+        };
+        if group.is_none() && !seen.insert(s.clone()) {
+            bail!(
+                "many-to-one matching for {} needs group_left or group_right",
+                kind
+            );
+        }
+        let (a, b) = if swapped { (y, x) } else { (x, y) };
+        let value = if let Some(v) = arithmetic(kind, a, b) {
+            v
+        } else if comparison(kind, a, b) == Some(true) {
+            x
+        } else {
+            continue;
+        };
+        let mut out = match group {
+            Some(g) => {
+                let mut out = labels.clone();
+                for k in &g.labels {
+                    match other_labels.get(k) {
+                        Some(v) => out.insert(k.clone(), v.clone()),
+                        None => out.remove(k),
+                    };
+                }
+                out
+            }
+            None => match matching {
+                Some(m) if m.op == MatchingOp::On => s,
+                _ => labels.clone(),
+            },
+        };
+        if arithmetic(kind, a, b).is_some() {
+            out.remove("__name__");
+        }
+        if let Some(m) = matching {
+            if m.op == MatchingOp::Ignoring && group.is_none() {
+                for k in &m.labels {
+                    out.remove(k);
+                }
+            }
+        }
+        res.push((out, value));
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_duration, Evaluator, Series, Value};
+
+    fn eval(series: &[Series], expr: &str, t: i64) -> Vec<(String, f64)> {
+        let expr = prometheus_parser::parse_expr(expr).unwrap();
+        match Evaluator::new(series).eval(&expr, t).unwrap() {
+            Value::Vector(v) => v
+                .into_iter()
+                .map(|(l, x)| {
+                    let labels = l.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>();
+                    (labels.join(","), x)
+                })
+                .collect(),
+            v => panic!("expected a vector, got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn promql_series_notation() {
+        let s = Series::parse(r#"up{job="api"}"#, "1 _ 3 0+2x2 5-1x1 _x2 9", 60).unwrap();
+        assert_eq!(s.labels["__name__"], "up");
+        assert_eq!(s.labels["job"], "api");
+        assert_eq!(s.samples, vec![
+            (0, 1.0),
+            (120, 3.0),
+            (180, 0.0),
+            (240, 2.0),
+            (300, 4.0),
+            (360, 5.0),
+            (420, 4.0),
+            (600, 9.0)
+        ]);
+        assert!(Series::parse(r#"up{job=~"api"}"#, "1", 60).is_err());
+        assert_eq!(parse_duration("15m").unwrap(), 900);
+        assert!(parse_duration("15").is_err());
+    }
+
+    #[test]
+    fn promql_evaluation() {
+        let series = vec![
+            Series::parse(r#"http_requests_total{code="200",pod="a"}"#, "0+60x20", 60).unwrap(),
+            Series::parse(r#"http_requests_total{code="500",pod="a"}"#, "0+6x20", 60).unwrap(),
+            Series::parse(r#"http_requests_total{code="500",pod="b"}"#, "0+6x5 0+6x14", 60).unwrap(),
+            Series::parse(r#"up{pod="a"}"#, "1x20", 60).unwrap(),
+            Series::parse(r#"up{pod="b"}"#, "1x5 0x14", 60).unwrap(),
+        ];
+        // counters grow by 1/s and 0.1/s, with a reset in pod b
+        assert_eq!(
+            eval(&series, r#"rate(http_requests_total{code="200"}[5m])"#, 600),
+            vec![("code=200,pod=a".into(), 1.0)]
+        );
+        // the reset loses the increase of one scrape
+        assert_eq!(
+            eval(&series, r#"increase(http_requests_total{pod="b"}[5m])"#, 480),
+            vec![("code=500,pod=b".into(), 24.0)]
+        );
+        assert_eq!(
+            eval(&series, r#"sum by (code) (rate(http_requests_total[5m]))"#, 900),
+            vec![("code=200".into(), 1.0), ("code=500".into(), 0.2),]
+        );
+        let ratio = eval(
+            &series,
+            r#"sum(rate(http_requests_total{code=~"5.."}[5m])) / sum(rate(http_requests_total[5m])) > 0.1"#,
+            900,
+        );
+        assert_eq!(ratio.len(), 1);
+        assert!((ratio[0].1 - 1.0 / 6.0).abs() < 1e-9);
+        assert_eq!(eval(&series, "up == 0", 600), vec![(
+            "__name__=up,pod=b".into(),
+            0.0
+        )]);
+        assert_eq!(eval(&series, r#"up{pod="b"} == 0"#, 120), vec![]);
+        assert_eq!(eval(&series, "count(up) - sum(up)", 600), vec![("".into(), 1.0)]);
+        assert_eq!(eval(&series, r#"absent(up{pod="c"})"#, 600), vec![(
+            "pod=c".into(),
+            1.0
+        )]);
+        assert_eq!(
+            eval(
+                &series,
+                "up * on(pod) group_left(code) http_requests_total{code=\"500\"}",
+                60
+            ),
+            vec![("code=500,pod=a".into(), 6.0), ("code=500,pod=b".into(), 6.0)]
+        );
+        assert_eq!(eval(&series, "up unless up offset 10m", 600), vec![]);
+        // nothing within the lookback window
+        assert_eq!(eval(&series, "up", 2000), vec![]);
+    }
+
+    #[test]
+    fn promql_alert_firing() {
+        let series = vec![Series::parse("errors_total", "0 0 0 0 0+10x10 100x10", 60).unwrap()];
+        let expr = prometheus_parser::parse_expr("increase(errors_total[2m]) > 5").unwrap();
+        let ev = Evaluator::new(&series);
+        assert!(!ev.firing(&expr, 120, 60, 240).unwrap());
+        // pending from 5m, firing once held for 2m
+        assert!(!ev.firing(&expr, 120, 60, 360).unwrap());
+        assert!(ev.firing(&expr, 120, 60, 420).unwrap());
+        // resolved once the errors stop
+        assert!(!ev.firing(&expr, 120, 60, 1200).unwrap());
+    }
+}
@@ -10,7 +10,15 @@ use uuid::Uuid;
 
 #[allow(unused_imports)] use super::{BaseManifest, ConfigState, Result, Vault};
 
-use super::structs::Authorization;
+use super::{
+    podsecurity::PodSecurityLevel,
+    policy::Policy,
+    structs::{
+        rbac::{Rbac, RbacRisk},
+        security::InformationClassification,
+        Authorization, NetworkPolicySource,
+    },
+};
 
 /// Versioning Scheme used in region
 ///
@@ -68,6 +76,9 @@ pub struct VaultConfig {
     ///
     /// Typically, the name of the region to disambiguate.
     pub folder: String,
+    /// Grant squads without a `github.admins` team their policy via their `github.team`
+    #[serde(default)]
+    pub squadTeams: bool,
 }
 
 impl VaultConfig {
@@ -87,21 +98,6 @@ impl VaultConfig {
         }
         Ok(())
     }
-
-    /// Make vault a vault policy for a team based on team ownership
-    ///
-    /// Returns plaintext hcl
-    #[cfg(feature = "filesystem")]
-    pub async fn make_policy(&self, mfs: Vec<BaseManifest>, team: &str, env: Environment) -> Result<String> {
-        let mut owned_manifests = vec![];
-        for mf in mfs {
-            if mf.metadata.team == team {
-                owned_manifests.push(mf.name);
-            }
-        }
-        let output = self.template(owned_manifests, env).await?;
-        Ok(output)
-    }
 }
 
 //#[derive(Serialize, Deserialize, Clone, Default)]
@@ -137,6 +133,13 @@ pub struct KafkaConfig {
     /// A mapping of kafka properties to environment variables (optional)
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub propertyEnvMapping: BTreeMap<String, String>,
+
+    /// Name of the Strimzi kafka cluster running the topic and user operators
+    ///
+    /// When set, `eventStreams` and `kafkaResources` generate Strimzi
+    /// `KafkaTopic` and `KafkaUser` resources for each service.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strimziCluster: Option<String>,
 }
 
 /// Webhook types that shipcat might trigger after actions
@@ -187,6 +190,64 @@ pub struct KongConfig {
     pub extra_apis: BTreeMap<String, Kong>,
 }
 
+/// NetworkPolicy configuration for a region
+///
+/// Services get an ingress NetworkPolicy allowing traffic from the services
+/// that depend on them, plus the sources configured here.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct NetworkPolicyConfig {
+    /// Where kong runs; allowed into services with `kongApis`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kong: Option<NetworkPolicySource>,
+    /// Sources allowed into every service (e.g. prometheus)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extraSources: Vec<NetworkPolicySource>,
+}
+
+impl NetworkPolicyConfig {
+    pub fn verify(&self) -> Result<()> {
+        let mut names = vec![];
+        for src in self.kong.iter().chain(self.extraSources.iter()) {
+            src.verify()?;
+            if names.contains(&src.name) {
+                bail!("Cannot reuse NetworkPolicy source name {}", src.name);
+            }
+            names.push(src.name.clone());
+        }
+        Ok(())
+    }
+}
+
+/// Istio configuration for a region
+///
+/// Services get istio objects generated from their `trafficPolicy`, `destinationRules`
+/// and the dependencies of other services.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct IstioConfig {
+    /// Request header carrying the identifier matched by `destinationRules`
+    pub identifierHeader: String,
+    /// Trust domain of the mesh used in service account principals
+    #[serde(default = "default_trust_domain")]
+    pub trustDomain: String,
+    /// Principals allowed into every service (e.g. the ingress gateway)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extraPrincipals: Vec<String>,
+}
+fn default_trust_domain() -> String {
+    "cluster.local".into()
+}
+
+impl IstioConfig {
+    pub fn verify(&self) -> Result<()> {
+        if self.identifierHeader.is_empty() || self.identifierHeader.to_lowercase() != self.identifierHeader {
+            bail!("istio identifierHeader must be a lowercase header name");
+        }
+        Ok(())
+    }
+}
+
 /// StatusCake configuration for a region
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
@@ -197,6 +258,11 @@ pub struct StatuscakeConfig {
     /// Extra tags to add to all tests in this region
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub extra_tags: Option<String>,
+    /// Seconds between uptime checks
+    ///
+    /// Defaults to 60 in prod and 300 elsewhere.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_rate: Option<u32>,
 }
 
 /// Logz.io configuration for a region
@@ -219,12 +285,78 @@ pub struct GrafanaConfig {
     pub services_dashboard_id: String,
 }
 
+/// Docker registry checks for a region
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct RegistryConfig {
+    /// Pin images to the digest of their tag in generated templates
+    #[serde(default)]
+    pub resolveDigests: bool,
+}
+
+/// RBAC privileges services may request in a region
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct RbacPolicy {
+    /// Risks any service may take
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<RbacRisk>,
+    /// Additional risks allowed for named services
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub services: BTreeMap<String, Vec<RbacRisk>>,
+}
+
+impl RbacPolicy {
+    /// Whether a service may take a risk
+    pub fn allows(&self, service: &str, risk: RbacRisk) -> bool {
+        self.allow.contains(&risk)
+            || self
+                .services
+                .get(service)
+                .filter(|rs| rs.contains(&risk))
+                .is_some()
+    }
+
+    /// The risks a service takes that are not allowed
+    pub fn disallowed(&self, service: &str, rules: &[Rbac]) -> Vec<RbacRisk> {
+        let mut res = rules.iter().flat_map(|r| r.risks(service)).collect::<Vec<_>>();
+        res.sort();
+        res.dedup();
+        res.retain(|r| !self.allows(service, *r));
+        res
+    }
+
+    /// Verify the rbac rules of a service against the policy
+    pub fn check(&self, service: &str, rules: &[Rbac]) -> Result<()> {
+        let disallowed = self.disallowed(service, rules);
+        if !disallowed.is_empty() {
+            let names = disallowed.iter().map(RbacRisk::to_string).collect::<Vec<_>>();
+            bail!(
+                "{} requests disallowed rbac privileges: {}",
+                service,
+                names.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
+
 /// Sentry details for a region
 #[derive(Serialize, Deserialize, Clone, Debug, Default)] // TODO: better Default impl
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct SentryConfig {
     /// Base URL to use (e.g. https://dev-uk-sentry.ops.babylontech.co.uk)
     pub url: String,
+    /// Organisation slug owning the teams and projects
+    #[serde(default = "default_sentry_organisation")]
+    pub organisation: String,
+    /// Id of the Slack integration that alert rules notify through
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slack_workspace: Option<String>,
+}
+
+fn default_sentry_organisation() -> String {
+    "sentry".into()
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -532,6 +664,55 @@ pub struct Region {
     /// The regular expression used to verify destination rules' regions
     #[serde(default, skip_serializing_if = "Option::is_none", with = "serde_regex")]
     pub destinationRuleHostRegex: Option<Regex>,
+
+    /// Policies that manifests must satisfy in this region
+    ///
+    /// These are evaluated on top of the global policies in `Config`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub policies: Vec<Policy>,
+
+    /// Maximum retention period of stored data per information classification
+    ///
+    /// ```yaml
+    /// maxRetention:
+    ///   strictlyConfidential: 90d
+    ///   confidentialPatientData: P8Y
+    /// ```
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub maxRetention: BTreeMap<InformationClassification, String>,
+
+    /// Pod Security Standards level required of every workload (unchecked when unset)
+    ///
+    /// ```yaml
+    /// podSecurity: baseline
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub podSecurity: Option<PodSecurityLevel>,
+
+    /// RBAC privileges services may request (unchecked when unset)
+    ///
+    /// ```yaml
+    /// rbacPolicy:
+    ///   allow: [configMaps]
+    ///   services:
+    ///     kube-janitor: [wildcardResources]
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rbacPolicy: Option<RbacPolicy>,
+
+    /// Image existence checks against the registry before apply (disabled when unset)
+    ///
+    /// When enabled, `imageSize` is also derived from the image layers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registry: Option<RegistryConfig>,
+
+    /// NetworkPolicy generation for the region (disabled when unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub networkPolicies: Option<NetworkPolicyConfig>,
+
+    /// Istio object generation for the region (disabled when unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub istio: Option<IstioConfig>,
 }
 
 impl Region {
@@ -1,10 +1,10 @@
-use super::Result;
-use std::path::Path;
+use super::{Error, Result};
+use std::{fmt, path::Path, str::FromStr};
 
 /// Supported dependency protocols
 ///
 /// Forces lowercase values of this enum to be used
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum DependencyProtocol {
     /// HTTP REST dependency
@@ -24,6 +24,33 @@ impl Default for DependencyProtocol {
     }
 }
 
+impl fmt::Display for DependencyProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DependencyProtocol::Http => write!(f, "http"),
+            DependencyProtocol::Grpc => write!(f, "grpc"),
+            DependencyProtocol::Kafka => write!(f, "kafka"),
+            DependencyProtocol::Amqp => write!(f, "amqp"),
+            DependencyProtocol::Sqs => write!(f, "sqs"),
+        }
+    }
+}
+
+impl FromStr for DependencyProtocol {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "http" => Ok(DependencyProtocol::Http),
+            "grpc" => Ok(DependencyProtocol::Grpc),
+            "kafka" => Ok(DependencyProtocol::Kafka),
+            "amqp" => Ok(DependencyProtocol::Amqp),
+            "sqs" => Ok(DependencyProtocol::Sqs),
+            _ => bail!("Dependency protocol must be one of http, grpc, kafka, amqp or sqs"),
+        }
+    }
+}
+
 /// Dependency of a service
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
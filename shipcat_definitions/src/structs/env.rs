@@ -73,6 +73,15 @@ impl EnvVars {
         Ok(())
     }
 
+    /// Names of variables with a value "IN_VAULT" (without removing them)
+    pub fn vault_keys(&self) -> BTreeSet<String> {
+        self.plain
+            .iter()
+            .filter(|(_, v)| EnvVars::is_vault_secret(v))
+            .map(|(k, _)| k.to_string())
+            .collect()
+    }
+
     // Remove variables with a value "IN_VAULT", mark them as a secret and return them.
     pub fn vault_secrets(&mut self) -> BTreeSet<String> {
         let mut plain = BTreeMap::new();
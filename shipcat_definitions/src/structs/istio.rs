@@ -0,0 +1,217 @@
+use regex::Regex;
+use std::collections::BTreeMap;
+
+use super::Result;
+
+/// Retry policy for requests to a service
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct Retries {
+    /// Number of retries for a request
+    pub attempts: u32,
+    /// Timeout per attempt (e.g. 2s)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub perTryTimeout: Option<String>,
+    /// Conditions to retry on (e.g. 5xx,connect-failure)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retryOn: Option<String>,
+}
+
+/// Ejection of unhealthy pods from the load balancing pool
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct OutlierDetection {
+    /// Number of errors before a pod is ejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consecutiveErrors: Option<u32>,
+    /// Time between ejection sweeps (e.g. 10s)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<String>,
+    /// Minimum ejection duration (e.g. 30s)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baseEjectionTime: Option<String>,
+    /// Maximum percentage of pods that can be ejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maxEjectionPercent: Option<u32>,
+}
+
+/// Tcp connection limits
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct TcpConnectionPool {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maxConnections: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connectTimeout: Option<String>,
+}
+
+/// Http connection limits
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct HttpConnectionPool {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http1MaxPendingRequests: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http2MaxRequests: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maxRequestsPerConnection: Option<u32>,
+}
+
+/// Connection pool limits for a service
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct ConnectionPool {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp: Option<TcpConnectionPool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpConnectionPool>,
+}
+
+/// A version of a service receiving a share of its traffic
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct Subset {
+    /// Name of the subset (e.g. stable)
+    pub name: String,
+    /// Value of the `version` label of the pods in this subset
+    pub version: String,
+    /// Percentage of traffic routed to this subset
+    pub weight: u32,
+}
+
+/// Traffic policy for a service
+///
+/// Used to generate an istio `DestinationRule` and `VirtualService` for the service.
+///
+/// ```yaml
+/// trafficPolicy:
+///   timeout: 10s
+///   retries:
+///     attempts: 3
+///     perTryTimeout: 2s
+///   outlierDetection:
+///     consecutiveErrors: 5
+///     interval: 10s
+///   connectionPool:
+///     http:
+///       http1MaxPendingRequests: 100
+///   subsets:
+///   - name: stable
+///     version: 1.2.3
+///     weight: 90
+///   - name: canary
+///     version: 1.3.0
+///     weight: 10
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct TrafficPolicy {
+    /// Timeout for requests (e.g. 10s)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<Retries>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outlierDetection: Option<OutlierDetection>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connectionPool: Option<ConnectionPool>,
+    /// Versions to split traffic between
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subsets: Vec<Subset>,
+}
+
+fn verify_duration(name: &str, value: &Option<String>) -> Result<()> {
+    if let Some(d) = value {
+        let re = Regex::new(r"^[0-9]+(ms|s|m|h)$").unwrap();
+        if !re.is_match(d) {
+            bail!("trafficPolicy {} must be a duration like 10s, got {}", name, d);
+        }
+    }
+    Ok(())
+}
+
+impl TrafficPolicy {
+    pub fn verify(&self) -> Result<()> {
+        verify_duration("timeout", &self.timeout)?;
+        if let Some(r) = &self.retries {
+            if r.attempts == 0 {
+                bail!("trafficPolicy retries need at least one attempt");
+            }
+            verify_duration("perTryTimeout", &r.perTryTimeout)?;
+        }
+        if let Some(od) = &self.outlierDetection {
+            verify_duration("interval", &od.interval)?;
+            verify_duration("baseEjectionTime", &od.baseEjectionTime)?;
+            if od.maxEjectionPercent.unwrap_or(0) > 100 {
+                bail!("trafficPolicy maxEjectionPercent cannot exceed 100");
+            }
+        }
+        if let Some(tcp) = self.connectionPool.as_ref().and_then(|cp| cp.tcp.as_ref()) {
+            verify_duration("connectTimeout", &tcp.connectTimeout)?;
+        }
+        if !self.subsets.is_empty() {
+            let mut names = vec![];
+            for s in &self.subsets {
+                if s.name.is_empty() || s.version.is_empty() {
+                    bail!("trafficPolicy subsets need a name and a version");
+                }
+                if names.contains(&&s.name) {
+                    bail!("trafficPolicy cannot reuse subset name {}", s.name);
+                }
+                names.push(&s.name);
+            }
+            let total: u32 = self.subsets.iter().map(|s| s.weight).sum();
+            if total != 100 {
+                bail!("trafficPolicy subset weights must sum to 100, got {}", total);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Istio objects generated for a service
+///
+/// Specs are keyed by object name and wrapped into full objects by the helm chart.
+/// This is derived by shipcat and is not something that can be set in a manifest.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct IstioResources {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub destinationRules: BTreeMap<String, serde_json::Value>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub virtualServices: BTreeMap<String, serde_json::Value>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub authorizationPolicies: BTreeMap<String, serde_json::Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TrafficPolicy;
+
+    #[test]
+    fn traffic_policy_verify() {
+        let tp: TrafficPolicy = serde_yaml::from_str(
+            "
+timeout: 10s
+retries: { attempts: 3, perTryTimeout: 2s }
+outlierDetection: { consecutiveErrors: 5, interval: 10s, maxEjectionPercent: 50 }
+subsets:
+- { name: stable, version: 1.2.3, weight: 90 }
+- { name: canary, version: 1.3.0, weight: 10 }",
+        )
+        .unwrap();
+        assert!(tp.verify().is_ok());
+
+        let mut bad = tp.clone();
+        bad.timeout = Some("10 seconds".into());
+        assert!(bad.verify().is_err());
+        let mut bad = tp.clone();
+        bad.subsets[0].weight = 80;
+        assert!(bad.verify().is_err());
+        let mut bad = tp.clone();
+        bad.subsets[1].name = "stable".into();
+        assert!(bad.verify().is_err());
+        let mut bad = tp;
+        bad.retries.as_mut().unwrap().attempts = 0;
+        assert!(bad.verify().is_err());
+    }
+}
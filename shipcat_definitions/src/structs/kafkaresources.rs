@@ -1,15 +1,28 @@
 use super::Result;
 use regex::Regex;
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, ops::Not};
+
+/// Partitions of topics that do not configure them
+pub const DEFAULT_PARTITIONS: i32 = 1;
+/// Replicas of topics that do not configure them
+pub const DEFAULT_REPLICAS: i32 = 3;
+
+fn default_partitions() -> i32 {
+    DEFAULT_PARTITIONS
+}
+
+fn default_replicas() -> i32 {
+    DEFAULT_REPLICAS
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct KafkaTopics {
     pub name: String,
 
-    #[serde(default)]
+    #[serde(default = "default_partitions")]
     pub partitions: i32,
 
-    #[serde(default)]
+    #[serde(default = "default_replicas")]
     pub replicas: i32,
 
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
@@ -54,13 +67,14 @@ pub enum KafkaUserPatternType {
     Prefix,
 }
 
-#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct AclDefinition {
     pub resource_name: String,
     pub resource_type: Option<KafkaUserResourceType>,
     pub pattern_type: Option<KafkaUserPatternType>,
-    pub operation: Option<KafkaUserOperation>,
+    /// Required, so a forgotten operation never grants `All`
+    pub operation: KafkaUserOperation,
 
     #[serde(default = "default_host")]
     pub host: String,
@@ -85,6 +99,12 @@ pub struct KafkaResources {
 
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub users: Vec<KafkaUsers>,
+
+    /// Delete `KafkaTopic` resources for topics removed from this manifest
+    ///
+    /// Off by default, as the topic operator deletes the topic and its data with them.
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub prune_topics: bool,
 }
 
 impl KafkaResources {
@@ -138,6 +158,20 @@ impl KafkaResources {
             );
         }
 
+        if !failed_partitions.is_empty() {
+            bail!(
+                "invalid topic partitions, must be between 1 and 9999: {:?}",
+                failed_partitions
+            );
+        }
+
+        if !failed_replicas.is_empty() {
+            bail!(
+                "invalid topic replicas, must be between 1 and 32766: {:?}",
+                failed_replicas
+            );
+        }
+
         if !failed_users.is_empty() {
             bail!(
                 "invalid user name(s), must match expression \"^[0-9a-z\\-\\.]{{1,63}}$\": {:?}",
@@ -211,4 +245,25 @@ mod tests {
         let kr = validKafkaResource(&INVALID_KAFKA_RESOURCE);
         kr.verify().unwrap_err();
     }
+
+    #[test]
+    fn defaults_topic_partitions_and_replicas() {
+        let kr = validKafkaResource("topics: [{ name: my-topic }]");
+        assert_eq!(kr.topics[0].partitions, super::DEFAULT_PARTITIONS);
+        assert_eq!(kr.topics[0].replicas, super::DEFAULT_REPLICAS);
+        kr.verify().unwrap();
+
+        let kr = validKafkaResource("topics: [{ name: my-topic, partitions: 0 }]");
+        kr.verify().unwrap_err();
+    }
+
+    #[test]
+    fn rejects_acl_without_operation() {
+        let NO_OPERATION = r###"
+    users:
+    - name: my-user
+      acls:
+      - resourceName: testtopic"###;
+        serde_yaml::from_str::<KafkaResources>(NO_OPERATION).unwrap_err();
+    }
 }
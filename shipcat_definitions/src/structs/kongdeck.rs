@@ -0,0 +1,203 @@
+use crate::structs::kongfig::{Api, Consumer, ConsumerCredentials};
+use serde_json::Value;
+
+/// Tag put on every object shipcat manages in kong
+pub const SHIPCAT_TAG: &str = "managed-by-shipcat";
+
+/// Kong declarative config structs (Kong 1.x+ and decK)
+/// https://docs.konghq.com/deck/
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeckConfig {
+    pub _format_version: String,
+    pub services: Vec<DeckService>,
+    pub consumers: Vec<DeckConsumer>,
+}
+
+impl DeckConfig {
+    pub fn new(apis: Vec<Api>, consumers: Vec<Consumer>) -> Self {
+        DeckConfig {
+            _format_version: "1.1".into(),
+            services: apis.into_iter().map(DeckService::from).collect(),
+            consumers: consumers.into_iter().map(DeckConsumer::from).collect(),
+        }
+    }
+}
+
+/// An upstream service with its routes and plugins
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeckService {
+    pub name: String,
+    pub url: String,
+    pub retries: u32,
+    pub connect_timeout: u32,
+    pub read_timeout: u32,
+    pub write_timeout: u32,
+    pub tags: Vec<String>,
+    pub routes: Vec<DeckRoute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub plugins: Vec<DeckPlugin>,
+}
+
+/// How requests are matched to a service
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeckRoute {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub methods: Vec<String>,
+    pub protocols: Vec<String>,
+    pub strip_path: bool,
+    pub preserve_host: bool,
+    pub tags: Vec<String>,
+}
+
+/// A plugin enabled on a service
+///
+/// Plugins that kongfig would remove are simply left out.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeckPlugin {
+    pub name: String,
+    pub enabled: bool,
+    pub config: Value,
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeckConsumer {
+    pub username: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub jwt_secrets: Vec<DeckJwtSecret>,
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeckJwtSecret {
+    pub key: String,
+    pub algorithm: String,
+    pub rsa_public_key: String,
+}
+
+fn tags() -> Vec<String> {
+    vec![SHIPCAT_TAG.into()]
+}
+
+/// Plugin config fields that kongfig serialises as `{}` when empty
+///
+/// See the `empty_as_brackets` and `none_as_brackets` fields in kongfig.
+const BRACKETED_LISTS: &[&str] = &[
+    "headers",
+    "json",
+    "querystring",
+    "body",
+    "claims_to_verify",
+    "uri_param_names",
+    "cookie_names",
+];
+
+/// Undo the kongfig idiom of serialising empty lists as `{}`
+///
+/// Only the known list fields are converted, other empty objects are left alone.
+fn brackets_as_empty(v: Value) -> Value {
+    match v {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(k, v)| match v {
+                    Value::Object(ref inner) if inner.is_empty() && BRACKETED_LISTS.contains(&k.as_str()) => {
+                        (k, Value::Array(vec![]))
+                    }
+                    v => (k, brackets_as_empty(v)),
+                })
+                .collect(),
+        ),
+        v => v,
+    }
+}
+
+impl DeckPlugin {
+    /// Translate the attributes of a kongfig plugin
+    fn from_kongfig(name: &str, attributes: &Value) -> Self {
+        let mut config = brackets_as_empty(attributes["config"].clone());
+        if name == "jwt" {
+            // kongfig resolves `anonymous_username` to a consumer id,
+            // whereas declarative config takes the username directly
+            if let Value::Object(ref mut c) = config {
+                let username = c.remove("anonymous_username");
+                c.remove("anonymous");
+                if let Some(username) = username {
+                    c.insert("anonymous".into(), username);
+                }
+            }
+        }
+        DeckPlugin {
+            name: name.into(),
+            enabled: attributes["enabled"].as_bool().unwrap_or(true),
+            config,
+            tags: tags(),
+        }
+    }
+}
+
+impl From<Api> for DeckService {
+    fn from(api: Api) -> Self {
+        let mut plugins = vec![];
+        for p in &api.plugins {
+            // plugins serialise as { name, ensure, attributes: { enabled, config } }
+            let v = serde_json::to_value(p).unwrap_or_default();
+            if v["ensure"] != "present" {
+                continue;
+            }
+            if let Some(name) = v["name"].as_str() {
+                plugins.push(DeckPlugin::from_kongfig(name, &v["attributes"]));
+            }
+        }
+        let attrs = api.attributes;
+        let protocols = if attrs.https_only {
+            vec!["https".to_string()]
+        } else {
+            vec!["http".to_string(), "https".to_string()]
+        };
+        DeckService {
+            name: api.name.clone(),
+            url: attrs.upstream_url,
+            retries: attrs.retries,
+            connect_timeout: attrs.upstream_connect_timeout,
+            read_timeout: attrs.upstream_read_timeout,
+            write_timeout: attrs.upstream_send_timeout,
+            tags: tags(),
+            routes: vec![DeckRoute {
+                name: api.name,
+                hosts: attrs.hosts,
+                paths: attrs.uris.unwrap_or_default(),
+                methods: attrs.methods.unwrap_or_default(),
+                protocols,
+                strip_path: attrs.strip_uri,
+                preserve_host: attrs.preserve_host,
+                tags: tags(),
+            }],
+            plugins,
+        }
+    }
+}
+
+impl From<Consumer> for DeckConsumer {
+    fn from(c: Consumer) -> Self {
+        DeckConsumer {
+            username: c.username,
+            jwt_secrets: c
+                .credentials
+                .into_iter()
+                .map(|cred| match cred {
+                    ConsumerCredentials::Jwt(jwt) => DeckJwtSecret {
+                        key: jwt.key,
+                        algorithm: jwt.algorithm,
+                        rsa_public_key: jwt.rsa_public_key,
+                    },
+                })
+                .collect(),
+            tags: tags(),
+        }
+    }
+}
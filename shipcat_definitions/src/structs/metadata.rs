@@ -171,6 +171,11 @@ pub struct Metadata {
     /// Monorepos that have multiple tags can use "{{ version }}-app"
     #[serde(default = "default_format_string")]
     pub gitTagTemplate: String,
+    /// Default branch of the repository
+    ///
+    /// Used for links into the repository, like the runbook. Defaults to master.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub defaultBranch: Option<String>,
 
     /// Contact person (legacy)
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
@@ -357,6 +362,7 @@ mod tests {
                 notifications: Option::None,
                 alerts: Option::None,
             },
+            statuscake: Option::None,
         });
         owners
     }
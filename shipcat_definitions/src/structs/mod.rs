@@ -5,7 +5,7 @@ pub use super::Manifest;
 /// Verify trait gets the Region and Team
 pub use super::Region;
 /// Allow normal error handling from structs
-pub use super::{ErrorKind, Result, ResultExt};
+pub use super::{Error, ErrorKind, Result, ResultExt};
 
 // Structs that exist in the manifest
 
@@ -33,6 +33,8 @@ pub use self::gate::Gate;
 /// Kongfig configs
 pub mod kongfig;
 pub use self::kongfig::{Api, Certificate, Consumer, Plugin, Upstream};
+/// Kong declarative config
+pub mod kongdeck;
 
 /// Kafka configs
 pub mod kafka;
@@ -54,7 +56,7 @@ pub use self::env::EnvVars;
 // translations - these are typically inlined in templates as yaml
 /// Kubernetes resource structs
 pub mod resources;
-pub use self::resources::{parse_memory, ResourceRequirements};
+pub use self::resources::{parse_cpu, parse_memory, ResourceRequirements, Resources};
 /// Kubernetes volumes
 pub mod volume;
 pub use self::volume::{Volume, VolumeMount};
@@ -82,7 +84,7 @@ pub use self::metadata::{Contact, Metadata, SlackChannel};
 pub mod security;
 
 mod securitycontext;
-pub use securitycontext::SecurityContext;
+pub use securitycontext::{Capabilities, SecurityContext};
 
 mod vault;
 pub use self::vault::VaultOpts;
@@ -120,5 +122,17 @@ pub use self::eventstream::EventStream;
 pub mod kafkaresources;
 pub use self::kafkaresources::KafkaResources;
 
+// Strimzi resources derived from eventStreams and kafkaResources
+pub mod strimzi;
+pub use self::strimzi::StrimziResources;
+
 pub mod prometheusalert;
 pub use self::prometheusalert::PrometheusAlert;
+
+// NetworkPolicy derived from dependencies
+pub mod networkpolicy;
+pub use self::networkpolicy::{NetworkPolicy, NetworkPolicySource};
+
+// Istio traffic policies
+pub mod istio;
+pub use self::istio::{IstioResources, TrafficPolicy};
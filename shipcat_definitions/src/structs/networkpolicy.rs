@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+
+use super::{port::PortProtocol, Result};
+
+/// Label selector for pods or namespaces
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct LabelSelector {
+    pub matchLabels: BTreeMap<String, String>,
+}
+
+impl LabelSelector {
+    /// Select pods of a service via its `app` label
+    pub fn app(name: &str) -> Self {
+        let mut matchLabels = BTreeMap::new();
+        matchLabels.insert("app".to_string(), name.to_string());
+        LabelSelector { matchLabels }
+    }
+}
+
+/// A set of pods allowed to send traffic to a service
+///
+/// Mirrors the kubernetes `NetworkPolicyPeer` without ip blocks.
+/// Both selectors must match when both are set.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct NetworkPolicyPeer {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub podSelector: Option<LabelSelector>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespaceSelector: Option<LabelSelector>,
+}
+
+/// A port that traffic is allowed on
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct NetworkPolicyPort {
+    pub port: u32,
+    #[serde(default)]
+    pub protocol: PortProtocol,
+}
+
+/// An ingress rule of a NetworkPolicy
+///
+/// An empty list of ports allows traffic on all ports.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct NetworkPolicyRule {
+    pub from: Vec<NetworkPolicyPeer>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<NetworkPolicyPort>,
+}
+
+/// Ingress rules for the NetworkPolicy of a service
+///
+/// This is derived by shipcat from the dependencies of other services,
+/// and is not something that can be set in a manifest.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct NetworkPolicy {
+    pub ingress: Vec<NetworkPolicyRule>,
+}
+
+/// A named source of traffic configured for a region
+///
+/// The name should match the `app` label of the source pods,
+/// as it is used to recognise their traffic in `shipcat networkpolicy audit`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct NetworkPolicySource {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub podSelector: Option<LabelSelector>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespaceSelector: Option<LabelSelector>,
+    /// Ports the source can reach (defaults to all ports)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<NetworkPolicyPort>,
+}
+
+impl NetworkPolicySource {
+    pub fn verify(&self) -> Result<()> {
+        if self.podSelector.is_none() && self.namespaceSelector.is_none() {
+            bail!(
+                "NetworkPolicy source {} needs a podSelector or namespaceSelector",
+                self.name
+            );
+        }
+        Ok(())
+    }
+
+    pub fn peer(&self) -> NetworkPolicyPeer {
+        NetworkPolicyPeer {
+            podSelector: self.podSelector.clone(),
+            namespaceSelector: self.namespaceSelector.clone(),
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::BTreeMap;
 
-use super::metadata::SlackChannel;
+use super::{metadata::SlackChannel, Result};
 
 /// Monitoring section covering NewRelic configuration
 ///
@@ -8,8 +8,8 @@ use super::metadata::SlackChannel;
 /// newrelic:
 ///   alerts:
 ///     alert_name_foo:
-///       name: alert_name_foo:
-///       template: appdex
+///       name: alert_name_foo
+///       template: apdex
 ///       params:
 ///         threshold: "0.5"
 ///         priority: critical
@@ -33,6 +33,164 @@ pub struct NewrelicAlert {
     pub params: BTreeMap<String, String>,
 }
 
+/// A condition template that alerts are rendered from
+///
+/// Every template takes a `threshold`, and optionally a `duration` in minutes
+/// and a `priority` of critical or warning.
+pub struct NewrelicTemplate {
+    pub name: &'static str,
+    /// APM application metric the condition watches
+    pub metric: &'static str,
+    /// Whether the condition is violated above or below the threshold
+    pub operator: &'static str,
+}
+
+/// The condition templates alerts can reference
+pub const NEWRELIC_TEMPLATES: &[NewrelicTemplate] = &[
+    NewrelicTemplate {
+        name: "apdex",
+        metric: "apdex",
+        operator: "below",
+    },
+    NewrelicTemplate {
+        name: "error_rate",
+        metric: "error_percentage",
+        operator: "above",
+    },
+    NewrelicTemplate {
+        name: "throughput",
+        metric: "throughput_web",
+        operator: "below",
+    },
+];
+
+/// Params accepted by every template, with their defaults
+const TEMPLATE_PARAMS: &[(&str, Option<&str>)] = &[
+    ("threshold", None),
+    ("duration", Some("5")),
+    ("priority", Some("critical")),
+];
+
+/// Durations in minutes that New Relic accepts for condition terms
+const DURATIONS: &[&str] = &["5", "10", "15", "30", "60", "120"];
+
+/// An APM application metric condition as used by the New Relic REST API
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NewrelicCondition {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub name: String,
+    pub enabled: bool,
+    /// Application ids the condition applies to
+    #[serde(default)]
+    pub entities: Vec<String>,
+    pub metric: String,
+    pub condition_scope: String,
+    pub terms: Vec<NewrelicTerm>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NewrelicTerm {
+    pub duration: String,
+    pub operator: String,
+    pub priority: String,
+    pub threshold: String,
+    pub time_function: String,
+}
+
+impl NewrelicAlert {
+    fn find_template(&self) -> Result<&'static NewrelicTemplate> {
+        match NEWRELIC_TEMPLATES.iter().find(|t| t.name == self.template) {
+            Some(t) => Ok(t),
+            None => {
+                let names = NEWRELIC_TEMPLATES.iter().map(|t| t.name).collect::<Vec<_>>();
+                bail!(
+                    "Newrelic alert {} uses unknown template {} (expected one of {})",
+                    self.name,
+                    self.template,
+                    names.join(", ")
+                )
+            }
+        }
+    }
+
+    /// Template params with defaults filled in
+    fn param(&self, key: &str) -> Result<String> {
+        let default = TEMPLATE_PARAMS
+            .iter()
+            .find(|(k, _)| *k == key)
+            .and_then(|(_, d)| *d);
+        match self.params.get(key).map(String::as_str).or(default) {
+            Some(v) => Ok(v.to_string()),
+            None => bail!("Newrelic alert {} needs the {} param", self.name, key),
+        }
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        self.find_template()?;
+        for key in self.params.keys() {
+            if !TEMPLATE_PARAMS.iter().any(|(k, _)| k == key) {
+                bail!("Newrelic alert {} has unknown param {}", self.name, key);
+            }
+        }
+        let threshold = self.param("threshold")?;
+        if threshold.parse::<f64>().is_err() {
+            bail!(
+                "Newrelic alert {} threshold must be a number, got {}",
+                self.name,
+                threshold
+            );
+        }
+        let duration = self.param("duration")?;
+        if !DURATIONS.contains(&duration.as_str()) {
+            bail!(
+                "Newrelic alert {} duration must be one of {} minutes, got {}",
+                self.name,
+                DURATIONS.join(", "),
+                duration
+            );
+        }
+        let priority = self.param("priority")?;
+        if priority != "critical" && priority != "warning" {
+            bail!(
+                "Newrelic alert {} priority must be critical or warning, got {}",
+                self.name,
+                priority
+            );
+        }
+        Ok(())
+    }
+
+    /// Render the alert into a condition, without entities
+    pub fn condition(&self) -> Result<NewrelicCondition> {
+        let tpl = self.find_template()?;
+        Ok(NewrelicCondition {
+            kind: "apm_app_metric".into(),
+            name: self.name.clone(),
+            enabled: true,
+            entities: vec![],
+            metric: tpl.metric.into(),
+            condition_scope: "application".into(),
+            terms: vec![NewrelicTerm {
+                duration: self.param("duration")?,
+                operator: tpl.operator.into(),
+                priority: self.param("priority")?,
+                threshold: self.param("threshold")?,
+                time_function: "all".into(),
+            }],
+        })
+    }
+}
+
+impl Newrelic {
+    pub fn verify(&self) -> Result<()> {
+        for a in self.alerts.values() {
+            a.verify()?;
+        }
+        Ok(())
+    }
+}
+
 /// NewRelic AlertPolicy attribute that we configure once per Application (service@region) monitored
 ///
 /// Details available at [this link](https://docs.newrelic.com/docs/alerts/new-relic-alerts/configuring-alert-policies/specify-when-new-relic-creates-incidents#preference-options)
@@ -63,3 +221,54 @@ impl Default for NewrelicIncidentPreference {
         NewrelicIncidentPreference::PerPolicy
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{NewrelicAlert, NewrelicTerm};
+
+    fn alert(template: &str, params: &[(&str, &str)]) -> NewrelicAlert {
+        NewrelicAlert {
+            name: "slow".into(),
+            template: template.into(),
+            params: params
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn newrelic_templates() {
+        let a = alert("apdex", &[("threshold", "0.7")]);
+        a.verify().unwrap();
+        let c = a.condition().unwrap();
+        assert_eq!(c.metric, "apdex");
+        assert_eq!(c.terms, vec![NewrelicTerm {
+            duration: "5".into(),
+            operator: "below".into(),
+            priority: "critical".into(),
+            threshold: "0.7".into(),
+            time_function: "all".into(),
+        }]);
+
+        let errs = vec![
+            alert("appdex", &[("threshold", "0.7")]),
+            alert("apdex", &[]),
+            alert("error_rate", &[("threshold", "5"), ("thresold", "5")]),
+            alert("throughput", &[("threshold", "many")]),
+            alert("throughput", &[("threshold", "10"), ("duration", "7")]),
+            alert("throughput", &[("threshold", "10"), ("priority", "high")]),
+        ]
+        .into_iter()
+        .map(|a| a.verify().unwrap_err().to_string())
+        .collect::<Vec<_>>();
+        assert_eq!(errs, vec![
+            "Newrelic alert slow uses unknown template appdex (expected one of apdex, error_rate, throughput)",
+            "Newrelic alert slow needs the threshold param",
+            "Newrelic alert slow has unknown param thresold",
+            "Newrelic alert slow threshold must be a number, got many",
+            "Newrelic alert slow duration must be one of 5, 10, 15, 30, 60, 120 minutes, got 7",
+            "Newrelic alert slow priority must be critical or warning, got high",
+        ]);
+    }
+}
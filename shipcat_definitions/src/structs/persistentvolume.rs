@@ -30,11 +30,9 @@ pub struct PersistentVolume {
 
 impl PersistentVolume {
     pub fn verify(&self) -> Result<()> {
-        let size = parse_memory(&self.size)?;
-        // sanity number; 16TB via https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/ebs-volume-types.html
-        if size > 16.0 * 1024.0 * 1024.0 * 1024.0 * 1024.0 {
-            bail!("Persistent Volume request more than 16 TB")
-        }
+        // sizes must parse; the 16TB sanity limit is the builtin bounded-volumes policy
+        // via https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/ebs-volume-types.html
+        parse_memory(&self.size)?;
         if !self.mountPath.starts_with('/') {
             bail!("Mount path '{}' must start with a slash", self.mountPath);
         }
@@ -1,4 +1,5 @@
 use super::Result;
+use crate::promql::{parse_duration, Evaluator, Series};
 use inflector::cases::pascalcase::is_pascal_case;
 use regex::Regex;
 
@@ -39,6 +40,65 @@ pub struct PrometheusAlert {
     ///
     /// Corresponds to how urgently it should be actioned if it were in production.
     pub severity: PrometheusAlertSeverity,
+
+    /// Test cases for the alert.
+    ///
+    /// Evaluated by `shipcat validate` against the given input series.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tests: Vec<PrometheusAlertTest>,
+}
+
+/// A test case for a Prometheus alert.
+///
+/// Mirrors promtool's alert tests: input series sampled every `interval`,
+/// and whether the alert should be firing at given times.
+///
+/// ```yaml
+/// tests:
+/// - interval: 1m
+///   input_series:
+///   - series: 'http_requests_total{code="500"}'
+///     values: '0+10x10'
+///   expect:
+///   - at: 2m
+///     firing: false
+///   - at: 10m
+///     firing: true
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PrometheusAlertTest {
+    /// Time between samples, and between evaluations of the alert
+    #[serde(default = "default_test_interval")]
+    pub interval: String,
+
+    /// Series available to the alert expression
+    pub input_series: Vec<PrometheusTestSeries>,
+
+    /// Expected alert states
+    pub expect: Vec<PrometheusAlertExpectation>,
+}
+fn default_test_interval() -> String {
+    "1m".into()
+}
+
+/// An input series for an alert test.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PrometheusTestSeries {
+    /// Series name and labels, e.g. `http_requests_total{code="500"}`
+    pub series: String,
+
+    /// Values in promtool notation, e.g. `0 1 2 _ 4` or `0+10x10`
+    pub values: String,
+}
+
+/// The expected state of an alert at a point in a test.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PrometheusAlertExpectation {
+    /// Time since the start of the input series, e.g. '10m'
+    pub at: String,
+
+    /// Whether the alert should be firing
+    pub firing: bool,
 }
 
 /// Alert severity enumeration.
@@ -79,10 +139,83 @@ impl PrometheusAlert {
             bail!("Prometheus alert has invalid min_duration value (needs to be like '15m' or '1h')");
         }
         // PromQL expression sanity (NB: syntax only, operator verifies properly)
-        if let Err(e) = prometheus_parser::parse_expr(&self.expr) {
-            bail!("Prometheus alert expression for {} invalid: {:?}", svc, e);
+        let expr = match prometheus_parser::parse_expr(&self.expr) {
+            Ok(expr) => expr,
+            Err(e) => bail!("Prometheus alert expression for {} invalid: {:?}", svc, e),
+        };
+        let hold = parse_duration(&self.min_duration)?;
+        for test in &self.tests {
+            let interval = parse_duration(&test.interval)?;
+            if interval == 0 {
+                bail!("Prometheus alert test for {} needs a non-zero interval", svc);
+            }
+            let series = test
+                .input_series
+                .iter()
+                .map(|s| Series::parse(&s.series, &s.values, interval))
+                .collect::<Result<Vec<_>>>()?;
+            let ev = Evaluator::new(&series);
+            for exp in &test.expect {
+                let at = parse_duration(&exp.at)?;
+                let firing = ev.firing(&expr, hold, interval, at).map_err(|e| {
+                    format!(
+                        "Prometheus alert {} for {} cannot be evaluated: {}",
+                        self.name, svc, e
+                    )
+                })?;
+                if firing != exp.firing {
+                    let state = if firing { "firing" } else { "not firing" };
+                    bail!(
+                        "Prometheus alert {} for {} is unexpectedly {} at {}",
+                        self.name,
+                        svc,
+                        state,
+                        exp.at
+                    );
+                }
+            }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::PrometheusAlert;
+
+    #[test]
+    fn prometheus_alert_tests() {
+        let mut alert: PrometheusAlert = serde_yaml::from_str(
+            r#"
+name: ErrorsRising
+summary: Errors are rising
+description: More than 5 errors in two minutes
+expr: 'increase(errors_total{app="api"}[2m]) > 5'
+min_duration: 2m
+severity: error
+tests:
+- input_series:
+  - series: 'errors_total{app="api"}'
+    values: '0x3 0+10x10 100x10'
+  expect:
+  - at: 6m
+    firing: false
+  - at: 7m
+    firing: true
+  - at: 20m
+    firing: false"#,
+        )
+        .unwrap();
+        assert_eq!(alert.tests[0].interval, "1m");
+        alert.verify("api").unwrap();
+
+        // an alert that never fires
+        alert.expr = r#"increase(errors_total{app="web"}[2m]) > 5"#.into();
+        let err = alert.verify("api").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Prometheus alert ErrorsRising for api is unexpectedly not firing at 7m"
+        );
+    }
+}
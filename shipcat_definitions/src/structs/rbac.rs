@@ -1,4 +1,5 @@
 use super::Result;
+use std::{collections::BTreeSet, fmt};
 
 /// RBAC (Role-Based Access Control) PolicyRule
 ///
@@ -37,3 +38,160 @@ impl Rbac {
         Ok(())
     }
 }
+
+/// Privilege classes of concern in an RBAC rule
+///
+/// Regions decide which of these services are allowed via `rbacPolicy`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum RbacRisk {
+    /// A `*` verb
+    WildcardVerbs,
+    /// A `*` api group or resource
+    WildcardResources,
+    /// Access to secrets not owned by the service
+    Secrets,
+    /// Access to configmaps not owned by the service
+    ConfigMaps,
+    /// Exec or attach into pods
+    PodExec,
+    /// Verbs that allow gaining extra privileges: bind, escalate, impersonate
+    Escalation,
+}
+
+impl fmt::Display for RbacRisk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RbacRisk::WildcardVerbs => write!(f, "wildcardVerbs"),
+            RbacRisk::WildcardResources => write!(f, "wildcardResources"),
+            RbacRisk::Secrets => write!(f, "secrets"),
+            RbacRisk::ConfigMaps => write!(f, "configMaps"),
+            RbacRisk::PodExec => write!(f, "podExec"),
+            RbacRisk::Escalation => write!(f, "escalation"),
+        }
+    }
+}
+
+/// Whether a named object is one the charts create for a service
+///
+/// Only exact names count; `api` does not own `api-gateway-creds`.
+fn owned_by(name: &str, service: &str) -> bool {
+    name == service || name == format!("{}-secrets", service) || name == format!("{}-config", service)
+}
+
+impl Rbac {
+    /// Classify the privileges granted by this rule to a service
+    pub fn risks(&self, service: &str) -> BTreeSet<RbacRisk> {
+        let mut res = BTreeSet::new();
+        let any_verb = self.verbs.iter().any(|v| v == "*");
+        let any_resource = self.resources.iter().any(|r| r == "*");
+        if any_verb {
+            res.insert(RbacRisk::WildcardVerbs);
+        }
+        if any_resource || self.apiGroups.iter().any(|g| g == "*") {
+            res.insert(RbacRisk::WildcardResources);
+        }
+        // only the core api group has secrets, configmaps and pods
+        let core = self.apiGroups.iter().any(|g| g.is_empty() || g == "*");
+        let has = |r: &str| core && (any_resource || self.resources.iter().any(|x| x == r));
+        let foreign =
+            self.resourceNames.is_empty() || self.resourceNames.iter().any(|n| !owned_by(n, service));
+        if has("secrets") && foreign {
+            res.insert(RbacRisk::Secrets);
+        }
+        if has("configmaps") && foreign {
+            res.insert(RbacRisk::ConfigMaps);
+        }
+        if has("pods/exec") || has("pods/attach") {
+            res.insert(RbacRisk::PodExec);
+        }
+        if any_verb
+            || self
+                .verbs
+                .iter()
+                .any(|v| ["bind", "escalate", "impersonate"].contains(&v.as_str()))
+        {
+            res.insert(RbacRisk::Escalation);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rbac, RbacRisk};
+    use crate::region::RbacPolicy;
+
+    fn rule(groups: &[&str], resources: &[&str], names: &[&str], verbs: &[&str]) -> Rbac {
+        let strs = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect();
+        Rbac {
+            apiGroups: strs(groups),
+            resources: strs(resources),
+            resourceNames: strs(names),
+            verbs: strs(verbs),
+        }
+    }
+
+    #[test]
+    fn rbac_risks() {
+        let risks = |r: Rbac| r.risks("fake-ask").into_iter().collect::<Vec<_>>();
+        assert!(risks(rule(&["extensions"], &["deployments"], &[], &["get", "list"])).is_empty());
+        assert_eq!(risks(rule(&[""], &["secrets"], &[], &["*"])), vec![
+            RbacRisk::WildcardVerbs,
+            RbacRisk::Secrets,
+            RbacRisk::Escalation
+        ]);
+        // own secrets and configmaps are fine, others are not
+        assert!(risks(rule(
+            &[""],
+            &["secrets", "configmaps"],
+            &["fake-ask-secrets", "fake-ask-config"],
+            &["get"]
+        ))
+        .is_empty());
+        assert_eq!(
+            risks(rule(&[""], &["secrets"], &["fake-ask-gateway-creds"], &["get"])),
+            vec![RbacRisk::Secrets]
+        );
+        assert_eq!(
+            risks(rule(&[""], &["configmaps"], &["fake-storage"], &["get"])),
+            vec![RbacRisk::ConfigMaps]
+        );
+        // secrets in other api groups are not kubernetes secrets
+        assert!(risks(rule(&["vault.io"], &["secrets"], &[], &["get"])).is_empty());
+        assert_eq!(risks(rule(&["*"], &["*"], &[], &["get"])), vec![
+            RbacRisk::WildcardResources,
+            RbacRisk::Secrets,
+            RbacRisk::ConfigMaps,
+            RbacRisk::PodExec
+        ]);
+        assert_eq!(risks(rule(&[""], &["pods/exec"], &[], &["create"])), vec![
+            RbacRisk::PodExec
+        ]);
+        assert_eq!(
+            risks(rule(&["rbac.authorization.k8s.io"], &["clusterroles"], &[], &[
+                "bind"
+            ])),
+            vec![RbacRisk::Escalation]
+        );
+    }
+
+    #[test]
+    fn rbac_policy() {
+        let policy: RbacPolicy = serde_yaml::from_str(
+            "
+allow: [configMaps]
+services:
+  fake-ask: [podExec]",
+        )
+        .unwrap();
+        let rules = vec![
+            rule(&[""], &["configmaps", "pods/exec"], &[], &["get", "create"]),
+            rule(&[""], &["secrets"], &["fake-storage"], &["get"]),
+        ];
+        assert_eq!(policy.disallowed("fake-ask", &rules), vec![RbacRisk::Secrets]);
+        assert_eq!(policy.disallowed("fake-storage", &rules), vec![RbacRisk::PodExec]);
+        assert!(policy.check("fake-ask", &rules[..1]).is_ok());
+        assert!(policy.check("fake-ask", &rules).is_err());
+    }
+}
@@ -112,7 +112,6 @@ impl ResourceRequirements<f64> {
 }
 
 impl ResourceRequirements<String> {
-    // TODO: look at config for limits?
     pub fn verify(&self) -> Result<()> {
         // (We can unwrap all the values as we assume implicit called!)
         let n = self.normalised()?;
@@ -126,19 +125,7 @@ impl ResourceRequirements<String> {
         if req.memory > lim.memory {
             bail!("Requested more memory than what was limited");
         }
-        // 1.2 sanity numbers (based on c5.9xlarge)
-        if req.cpu > 36.0 {
-            bail!("Requested more than 36 cores");
-        }
-        if req.memory > 72.0 * 1024.0 * 1024.0 * 1024.0 {
-            bail!("Requested more than 72 GB of memory");
-        }
-        if lim.cpu > 36.0 {
-            bail!("CPU limit set to more than 36 cores");
-        }
-        if lim.memory > 72.0 * 1024.0 * 1024.0 * 1024.0 {
-            bail!("Memory limit set to more than 72 GB of memory");
-        }
+        // 1.2 sanity numbers are enforced by the builtin bounded-resources policy
         Ok(())
     }
 }
@@ -186,9 +173,10 @@ pub fn parse_memory(s: &str) -> Result<f64> {
     Ok(res)
 }
 
-// Parse normal k8s cpu resource values into floats
-// We don't allow power of two variants here
-fn parse_cpu(s: &str) -> Result<f64> {
+/// Parse normal k8s cpu resource values into floats
+///
+/// We don't allow power of two variants here.
+pub fn parse_cpu(s: &str) -> Result<f64> {
     let digits = s
         .chars()
         .take_while(|ch| ch.is_digit(10) || *ch == '.')
@@ -101,18 +101,23 @@ impl RollingUpdate {
 }
 
 impl RollingUpdate {
-    /// Estimate how many cycles is needed to roll out a new version
-    ///
-    /// This is a bit arcane extrapolates from [rolling update documentation](https://kubernetes.io/docs/concepts/workloads/controllers/deployment/#max-unavailable)
-    /// It needs to keep into account both values.
-    pub fn rollout_iterations(&self, replicas: u32) -> u32 {
-        let surge = if let Some(surge) = self.maxSurge.clone() {
+    /// How many extra pods can exist during a rollout
+    pub fn surge(&self, replicas: u32) -> u32 {
+        if let Some(surge) = self.maxSurge.clone() {
             // surge is max number/percentage
             surge.to_replicas_ceil(replicas)
         } else {
             // default surge percentage is 25
             (f64::from(replicas * 25) / 100.0).ceil() as u32
-        };
+        }
+    }
+
+    /// Estimate how many cycles is needed to roll out a new version
+    ///
+    /// This is a bit arcane extrapolates from [rolling update documentation](https://kubernetes.io/docs/concepts/workloads/controllers/deployment/#max-unavailable)
+    /// It needs to keep into account both values.
+    pub fn rollout_iterations(&self, replicas: u32) -> u32 {
+        let surge = self.surge(replicas);
         let unavail = if let Some(unav) = self.maxUnavailable.clone() {
             // maxUnavailable is max number/percentage
             unav.to_replicas_floor(replicas)
@@ -207,4 +212,16 @@ mod tests {
         };
         assert_eq!(rusurge.rollout_iterations(8), 4); // 2 dn 2 up (x4)
     }
+
+    #[test]
+    fn rollout_surge_check() {
+        let ru = RollingUpdate::default();
+        assert_eq!(ru.surge(1), 1); // rounds up
+        assert_eq!(ru.surge(8), 2);
+        let rusurge = RollingUpdate {
+            maxUnavailable: None,
+            maxSurge: Some(AvailabilityPolicy::Unsigned(3)),
+        };
+        assert_eq!(rusurge.surge(8), 3);
+    }
 }
@@ -1,6 +1,6 @@
 use super::Result;
 use regex::Regex;
-use std::path::Path;
+use std::{cmp::Ordering, collections::BTreeMap, fmt, path::Path, str::FromStr, time::Duration};
 
 /// What sensitive data is managed and how
 ///
@@ -35,7 +35,9 @@ pub struct InformationClassificationData {
 }
 
 /// Possible levels of information classification of the data stored in the data store.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+///
+/// Ordered by `rank`, so the most sensitive classification is the greatest.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub enum InformationClassification {
     StrictlyConfidential,
@@ -45,6 +47,44 @@ pub enum InformationClassification {
     Public,
 }
 
+impl InformationClassification {
+    /// Sensitivity of the classification, higher is more sensitive
+    pub fn rank(&self) -> u8 {
+        match self {
+            InformationClassification::StrictlyConfidential => 4,
+            InformationClassification::ConfidentialPatientData => 3,
+            InformationClassification::CommercialConfidential => 2,
+            InformationClassification::ProtectedInternal => 1,
+            InformationClassification::Public => 0,
+        }
+    }
+}
+
+impl Ord for InformationClassification {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.rank().cmp(&other.rank())
+    }
+}
+
+impl PartialOrd for InformationClassification {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for InformationClassification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            InformationClassification::StrictlyConfidential => "strictlyConfidential",
+            InformationClassification::ConfidentialPatientData => "confidentialPatientData",
+            InformationClassification::CommercialConfidential => "commercialConfidential",
+            InformationClassification::ProtectedInternal => "protectedInternal",
+            InformationClassification::Public => "public",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 impl Default for InformationClassification {
     fn default() -> Self {
         InformationClassification::ConfidentialPatientData
@@ -70,9 +110,11 @@ pub struct DataStore {
     /// Cipher used to encrypt if used
     pub cipher: Option<String>,
     // Data is encryption strategies TODO: does this live in here?
-    /// Key rotator if used TODO: format?
+    /// Key rotator if used
+    ///
+    /// A known rotator, optionally with a period (`vault-transit:30d`), or just a period.
     pub keyRotator: Option<String>,
-    /// Retention period if any TODO: format? humantime?
+    /// Retention period if any, as an ISO-8601 (`P1Y`) or humantime (`30d`) duration
     pub retentionPeriod: Option<String>,
 
     /// The information classification of the data stored in the data store.
@@ -142,12 +184,235 @@ pub struct DataProcess {
     pub source: String,
 }
 
+/// Parse an ISO-8601 (`P1Y2M`, `PT12H`) or humantime (`30d`, `1y 6months`) duration
+///
+/// Months and years have the same average lengths as in humantime.
+pub fn parse_period(s: &str) -> Result<Duration> {
+    if !s.starts_with('P') {
+        return match humantime::parse_duration(s) {
+            Ok(d) => Ok(d),
+            Err(e) => bail!("invalid duration '{}': {}", s, e),
+        };
+    }
+    let re =
+        Regex::new(r"^P(?:(\d+)Y)?(?:(\d+)M)?(?:(\d+)W)?(?:(\d+)D)?(?:T(?:(\d+)H)?(?:(\d+)M)?(?:(\d+)S)?)?$")
+            .unwrap();
+    // seconds per year, month, week, day, hour, minute, second
+    let units = [31_557_600, 2_630_016, 604_800, 86400, 3600, 60, 1];
+    let caps = match re.captures(s) {
+        Some(caps) if s != "P" && !s.ends_with('T') => caps,
+        _ => bail!("invalid ISO-8601 duration '{}'", s),
+    };
+    let mut secs = 0;
+    for (i, unit) in units.iter().enumerate() {
+        if let Some(m) = caps.get(i + 1) {
+            secs += m.as_str().parse::<u64>()? * unit;
+        }
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// Normalised form of a period for reports
+pub fn format_period(d: Duration) -> String {
+    humantime::format_duration(d).to_string()
+}
+
+/// Mechanisms that rotate encryption keys
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rotator {
+    AwsKms,
+    GcpKms,
+    VaultTransit,
+    /// Rotated by hand, or by the service itself
+    Manual,
+}
+
+impl FromStr for Rotator {
+    type Err = super::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "aws-kms" => Ok(Rotator::AwsKms),
+            "gcp-kms" => Ok(Rotator::GcpKms),
+            "vault-transit" => Ok(Rotator::VaultTransit),
+            "manual" => Ok(Rotator::Manual),
+            _ => bail!(
+                "unknown key rotator '{}' (must be aws-kms, gcp-kms, vault-transit or manual)",
+                s
+            ),
+        }
+    }
+}
+
+/// A parsed `keyRotator`
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct KeyRotator {
+    pub rotator: Rotator,
+    /// How often keys are rotated, if known
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "serialize_period")]
+    pub period: Option<Duration>,
+}
+
+fn serialize_period<S: serde::Serializer>(
+    d: &Option<Duration>,
+    s: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match d {
+        Some(d) => s.serialize_str(&format_period(*d)),
+        None => s.serialize_none(),
+    }
+}
+
+impl fmt::Display for Rotator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Rotator::AwsKms => "aws-kms",
+            Rotator::GcpKms => "gcp-kms",
+            Rotator::VaultTransit => "vault-transit",
+            Rotator::Manual => "manual",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for KeyRotator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.period {
+            Some(p) => write!(f, "{}:{}", self.rotator, format_period(p)),
+            None => write!(f, "{}", self.rotator),
+        }
+    }
+}
+
+impl FromStr for KeyRotator {
+    type Err = super::Error;
+
+    /// Parse `rotator`, `rotator:period`, or a bare period (rotated manually)
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, ':');
+        let first = parts.next().unwrap_or_default();
+        if let Some(period) = parts.next() {
+            return Ok(KeyRotator {
+                rotator: first.parse()?,
+                period: Some(parse_period(period)?),
+            });
+        }
+        if let Ok(rotator) = first.parse() {
+            return Ok(KeyRotator {
+                rotator,
+                period: None,
+            });
+        }
+        match parse_period(first) {
+            Ok(period) => Ok(KeyRotator {
+                rotator: Rotator::Manual,
+                period: Some(period),
+            }),
+            Err(_) => Ok(KeyRotator {
+                rotator: first.parse()?,
+                period: None,
+            }),
+        }
+    }
+}
+
+impl DataStore {
+    /// Classification of the store, falling back to that of the service
+    pub fn classification(&self, dh: &DataHandling) -> Option<InformationClassification> {
+        self.informationClassification.clone().or_else(|| {
+            dh.informationClassification
+                .as_ref()
+                .map(|ic| ic.highestProcessed.clone())
+        })
+    }
+}
+
+impl DataField {
+    /// Parsed retention period (after cascading from the store)
+    pub fn retention(&self) -> Result<Option<Duration>> {
+        self.retentionPeriod.as_ref().map(|r| parse_period(r)).transpose()
+    }
+
+    /// Parsed key rotator (after cascading from the store)
+    pub fn key_rotator(&self) -> Result<Option<KeyRotator>> {
+        self.keyRotator.as_ref().map(|k| k.parse()).transpose()
+    }
+}
+
+/// Fail when `what` is retained longer than the `limit` of its classification, or indefinitely
+fn check_retention(
+    what: &str,
+    retention: Option<Duration>,
+    class: &InformationClassification,
+    limit: Duration,
+) -> Result<()> {
+    match retention {
+        Some(r) if r > limit => bail!(
+            "{} is retained for {}, but {} data can be retained for at most {}",
+            what,
+            format_period(r),
+            class,
+            format_period(limit)
+        ),
+        Some(_) => Ok(()),
+        None => bail!(
+            "{} needs a retentionPeriod as {} data can be retained for at most {}",
+            what,
+            class,
+            format_period(limit)
+        ),
+    }
+}
+
 impl DataHandling {
+    /// Enforce maximum retention periods per classification
+    ///
+    /// Every field in a store with a capped classification must have a retention
+    /// period no longer than the cap. Stores without fields are held to their own period.
+    pub fn verify_retention(&self, limits: &BTreeMap<InformationClassification, String>) -> Result<()> {
+        for s in &self.stores {
+            let class = match s.classification(self) {
+                Some(c) => c,
+                None => continue,
+            };
+            let limit = match limits.get(&class) {
+                Some(l) => parse_period(l)?,
+                None => continue,
+            };
+            if s.fields.is_empty() {
+                let retention = s.retentionPeriod.as_ref().map(|r| parse_period(r)).transpose()?;
+                check_retention(&s.backend, retention, &class, limit)?;
+            }
+            for f in &s.fields {
+                let what = format!("{} in {}", f.name, s.backend);
+                check_retention(&what, f.retention()?, &class, limit)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn verify(&self) -> Result<()> {
         // field names must be PascalCase
         let re = Regex::new(r"^[A-Z][[:alpha:]\d]+$").unwrap();
         for s in &self.stores {
+            if let Some(r) = &s.retentionPeriod {
+                if let Err(e) = parse_period(r) {
+                    bail!("Invalid retentionPeriod for {}: {}", s.backend, e);
+                }
+            }
+            if let Some(k) = &s.keyRotator {
+                if let Err(e) = k.parse::<KeyRotator>() {
+                    bail!("Invalid keyRotator for {}: {}", s.backend, e);
+                }
+            }
             for f in &s.fields {
+                if let Err(e) = f.retention() {
+                    bail!("Invalid retentionPeriod for {}: {}", f.name, e);
+                }
+                if let Err(e) = f.key_rotator() {
+                    bail!("Invalid keyRotator for {}: {}", f.name, e);
+                }
                 if !re.is_match(&f.name) {
                     bail!(
                         "The field {} is not valid PascalCase, or starts with a number",
@@ -165,3 +430,97 @@ impl DataHandling {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_period, DataHandling, InformationClassification, KeyRotator, Rotator};
+    use std::time::Duration;
+
+    #[test]
+    fn classification_order() {
+        use InformationClassification::*;
+        let mut levels = vec![
+            ProtectedInternal,
+            StrictlyConfidential,
+            Public,
+            ConfidentialPatientData,
+        ];
+        levels.sort();
+        assert_eq!(levels, vec![
+            Public,
+            ProtectedInternal,
+            ConfidentialPatientData,
+            StrictlyConfidential
+        ]);
+        assert!(ConfidentialPatientData > CommercialConfidential);
+    }
+
+    #[test]
+    fn retention_periods() {
+        let day = 86400;
+        assert_eq!(parse_period("30d").unwrap(), Duration::from_secs(30 * day));
+        assert_eq!(parse_period("P30D").unwrap(), Duration::from_secs(30 * day));
+        assert_eq!(parse_period("P2W").unwrap(), parse_period("2w").unwrap());
+        assert_eq!(parse_period("P1Y").unwrap(), parse_period("1y").unwrap());
+        assert_eq!(parse_period("PT12H").unwrap(), Duration::from_secs(12 * 3600));
+        assert!(parse_period("P").is_err());
+        assert!(parse_period("P1DT").is_err());
+        assert!(parse_period("forever").is_err());
+
+        let k: KeyRotator = "2w".parse().unwrap();
+        assert_eq!(k.rotator, Rotator::Manual);
+        assert_eq!(k.to_string(), "manual:14days");
+        let k: KeyRotator = "vault-transit:P30D".parse().unwrap();
+        assert_eq!(k.to_string(), "vault-transit:30days");
+        assert_eq!("aws-kms".parse::<KeyRotator>().unwrap().period, None);
+        assert!("cron".parse::<KeyRotator>().is_err());
+        assert!("cron:30d".parse::<KeyRotator>().is_err());
+    }
+
+    #[test]
+    fn retention_limits() {
+        let mut dh: DataHandling = serde_yaml::from_str(
+            "
+informationClassification: { highestProcessed: confidentialPatientData }
+stores:
+- backend: S3
+  retentionPeriod: P8Y
+  fields: [{ name: Notes }, { name: Scans, retentionPeriod: 10y }]
+- backend: MySQL
+  informationClassification: public
+  fields: [{ name: Articles }]",
+        )
+        .unwrap();
+        dh.implicits();
+        assert!(dh.verify().is_ok());
+        let limits =
+            btreemap! { super::InformationClassification::ConfidentialPatientData => "P8Y".to_string() };
+        let err = dh.verify_retention(&limits).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Scans in S3 is retained for 10years, but confidentialPatientData data can be retained for at most 8years"
+        );
+        dh.stores[0].fields[1].retentionPeriod = None;
+        let err = dh.verify_retention(&limits).unwrap_err();
+        assert!(err.to_string().starts_with("Scans in S3 needs a retentionPeriod"));
+        dh.stores[0].fields.pop();
+        assert!(dh.verify_retention(&limits).is_ok());
+
+        // stores without fields are held to their own period
+        dh.stores[0].fields.clear();
+        dh.stores[0].retentionPeriod = Some("10y".into());
+        let err = dh.verify_retention(&limits).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "S3 is retained for 10years, but confidentialPatientData data can be retained for at most 8years"
+        );
+        dh.stores[0].retentionPeriod = None;
+        let err = dh.verify_retention(&limits).unwrap_err();
+        assert!(err.to_string().starts_with("S3 needs a retentionPeriod"));
+        dh.stores[0].retentionPeriod = Some("P8Y".into());
+        assert!(dh.verify_retention(&limits).is_ok());
+
+        dh.stores[1].keyRotator = Some("cron".into());
+        assert!(dh.verify().is_err());
+    }
+}
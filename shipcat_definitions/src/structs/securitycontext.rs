@@ -1,4 +1,4 @@
-/// Security context for ownership of volumes
+/// Security context for ownership of volumes and container privileges
 ///
 /// Verbatim from [kubernetes SecurityContext](https://kubernetes.io/docs/tasks/configure-pod-container/security-context/#configure-volume-permission-and-ownership-change-policy-for-pods)
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
@@ -9,7 +9,47 @@ pub struct SecurityContext {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     runAsGroup: Option<u32>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
+    runAsNonRoot: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     fsGroup: Option<u32>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     fsGroupChangePolicy: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    allowPrivilegeEscalation: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    privileged: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    capabilities: Option<Capabilities>,
+}
+
+impl SecurityContext {
+    /// The fields that are valid in a pod securityContext
+    pub fn pod(&self) -> Self {
+        SecurityContext {
+            allowPrivilegeEscalation: None,
+            privileged: None,
+            capabilities: None,
+            ..self.clone()
+        }
+    }
+
+    /// The fields that only exist in a container securityContext
+    pub fn container(&self) -> Self {
+        SecurityContext {
+            allowPrivilegeEscalation: self.allowPrivilegeEscalation,
+            privileged: self.privileged,
+            capabilities: self.capabilities.clone(),
+            ..SecurityContext::default()
+        }
+    }
+}
+
+/// Linux capabilities to add or drop from a container
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct Capabilities {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    add: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    drop: Vec<String>,
 }
@@ -0,0 +1,17 @@
+/// Strimzi objects generated for a service
+///
+/// Complete `KafkaTopic` and `KafkaUser` objects, rendered as is by the helm chart.
+/// This is derived by shipcat and is not something that can be set in a manifest.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct StrimziResources {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kafkaTopics: Vec<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kafkaUsers: Vec<serde_json::Value>,
+}
+
+impl StrimziResources {
+    pub fn is_empty(&self) -> bool {
+        self.kafkaTopics.is_empty() && self.kafkaUsers.is_empty()
+    }
+}
@@ -83,6 +83,9 @@ pub struct Volume {
     /// Items from the Downward API
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub downwardAPI: Option<DownwardApiWrapper>,
+    /// A file or directory from the node's filesystem (not allowed by pod security baseline)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostPath: Option<BTreeMap<String, String>>,
 }
 
 impl Volume {
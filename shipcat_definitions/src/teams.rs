@@ -32,6 +32,9 @@ pub struct Squad {
     pub github: GithubTeams,
     /// Slack channels for the squad
     pub slack: SlackSet,
+    /// StatusCake contact group id alerted when the squad's services go down
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statuscake: Option<String>,
 }
 
 /// Information about a Tribe of squads
@@ -45,6 +48,9 @@ pub struct Tribe {
     pub github: Option<GithubTeams>,
     /// Slack channels for the tribe
     pub slack: Option<SlackSet>,
+    /// StatusCake contact group id alerted when the tribe's services go down
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statuscake: Option<String>,
 }
 
 /// Team data combined into a single structure
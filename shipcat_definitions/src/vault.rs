@@ -68,6 +68,26 @@ struct ListSecrets {
     data: BTreeMap<String, Vec<String>>,
 }
 
+/// ACL policy retrieved from Vault
+#[derive(Debug, Deserialize)]
+struct AclPolicy {
+    data: AclPolicyData,
+}
+#[derive(Debug, Deserialize)]
+struct AclPolicyData {
+    policy: String,
+}
+
+/// Policies mapped to a github team in Vault's github auth backend
+#[derive(Debug, Deserialize)]
+struct TeamMapping {
+    data: TeamMappingData,
+}
+#[derive(Debug, Deserialize)]
+struct TeamMappingData {
+    value: String,
+}
+
 /// Vault client with cached data
 pub struct Vault {
     /// Our HTTP client.  This can be configured to mock out the network.
@@ -196,6 +216,134 @@ impl Vault {
         Ok(res)
     }
 
+    // Authenticated HTTP call returning the body, or None when the path does not exist
+    async fn request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<Option<String>> {
+        let url = self.addr.join(&format!("v1/{}", path))?;
+        debug!("{} {}", method, url);
+
+        let mkerr = || ErrorKind::Url(url.clone());
+        let mut req = self
+            .client
+            .request(method, url.clone())
+            .header("X-Vault-Token", self.token.clone());
+        if let Some(b) = body {
+            req = req.body(serde_json::to_string(&b)?);
+        }
+        let res = req.send().await.chain_err(mkerr)?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !res.status().is_success() {
+            let status = res.status().to_owned();
+            let err: Error = ErrorKind::UnexpectedHttpStatus(status).into();
+            return Err(err).chain_err(mkerr);
+        }
+        Ok(Some(res.text().await?))
+    }
+
+    // Keys under an arbitrary path (vault 404s on empty lists)
+    async fn list_keys(&self, path: &str) -> Result<Vec<String>> {
+        let body = self
+            .request(reqwest::Method::GET, &format!("{}?list=true", path), None)
+            .await?;
+        match body {
+            None => Ok(vec![]),
+            Some(b) => {
+                let mut lsec: ListSecrets = serde_json::from_str(&b)?;
+                Ok(lsec.data.remove("keys").unwrap_or_default())
+            }
+        }
+    }
+
+    /// List sub folders of a secret folder
+    pub async fn list_folders(&self, path: &str) -> Result<Vec<String>> {
+        let keys = self.list_keys(&format!("secret/{}", path)).await?;
+        Ok(keys
+            .into_iter()
+            .filter(|e| e.ends_with('/'))
+            .map(|e| e.trim_end_matches('/').to_string())
+            .collect())
+    }
+
+    /// Names of all ACL policies
+    pub async fn list_policies(&self) -> Result<Vec<String>> {
+        self.list_keys("sys/policies/acl").await
+    }
+
+    /// Read the rules of an ACL policy
+    pub async fn read_policy(&self, name: &str) -> Result<Option<String>> {
+        let body = self
+            .request(reqwest::Method::GET, &format!("sys/policies/acl/{}", name), None)
+            .await?;
+        match body {
+            None => Ok(None),
+            Some(b) => Ok(Some(serde_json::from_str::<AclPolicy>(&b)?.data.policy)),
+        }
+    }
+
+    /// Create or update an ACL policy
+    pub async fn write_policy(&self, name: &str, rules: &str) -> Result<()> {
+        let body = serde_json::json!({ "policy": rules });
+        let pth = format!("sys/policies/acl/{}", name);
+        self.request(reqwest::Method::PUT, &pth, Some(body)).await?;
+        Ok(())
+    }
+
+    /// Delete an ACL policy
+    pub async fn delete_policy(&self, name: &str) -> Result<()> {
+        let pth = format!("sys/policies/acl/{}", name);
+        self.request(reqwest::Method::DELETE, &pth, None).await?;
+        Ok(())
+    }
+
+    /// Github team to comma separated policies mappings of the github auth backend
+    pub async fn list_github_teams(&self) -> Result<BTreeMap<String, String>> {
+        let mut res = BTreeMap::new();
+        for team in self.list_keys("auth/github/map/teams").await? {
+            let pth = format!("auth/github/map/teams/{}", team);
+            if let Some(b) = self.request(reqwest::Method::GET, &pth, None).await? {
+                let mapping: TeamMapping = serde_json::from_str(&b)?;
+                res.insert(team, mapping.data.value);
+            }
+        }
+        Ok(res)
+    }
+
+    /// Map a github team to a comma separated list of policies
+    pub async fn map_github_team(&self, team: &str, policies: &str) -> Result<()> {
+        let body = serde_json::json!({ "value": policies });
+        let pth = format!("auth/github/map/teams/{}", team);
+        self.request(reqwest::Method::POST, &pth, Some(body)).await?;
+        Ok(())
+    }
+
+    /// Remove the policy mapping of a github team
+    pub async fn unmap_github_team(&self, team: &str) -> Result<()> {
+        let pth = format!("auth/github/map/teams/{}", team);
+        self.request(reqwest::Method::DELETE, &pth, None).await?;
+        Ok(())
+    }
+
+    /// Write a secret to Vault via an authenticated HTTP POST
+    ///
+    /// Stores the value under the single `value` key that `read` expects.
+    pub async fn write(&self, key: &str, value: &str) -> Result<()> {
+        let pth = format!("secret/{}", key);
+        if self.mode == Mode::Mocked {
+            bail!("Cannot write {} to a mocked vault", pth);
+        }
+        let body = serde_json::json!({ "value": value });
+        self.request(reqwest::Method::POST, &pth, Some(body))
+            .await
+            .chain_err(|| ErrorKind::SecretNotAccessible(pth.clone()))?;
+        Ok(())
+    }
+
     /// Read secret from a Vault via an authenticated HTTP GET (or memory cache)
     pub async fn read(&self, key: &str) -> Result<String> {
         let pth = format!("secret/{}", key);
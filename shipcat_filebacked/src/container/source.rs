@@ -2,7 +2,7 @@ use merge::Merge;
 use regex::Regex;
 
 use shipcat_definitions::{
-    structs::{Container, Probe, VolumeMount},
+    structs::{Container, Probe, SecurityContext, VolumeMount},
     Result,
 };
 
@@ -49,6 +49,8 @@ pub struct ContainerSource {
     pub ports: Option<Vec<PortSource>>,
 
     pub volume_mounts: Option<Vec<VolumeMount>>,
+
+    pub security_context: Option<SecurityContext>,
 }
 
 pub struct ContainerBuildParams {
@@ -86,6 +88,8 @@ impl Build<Container, ContainerBuildParams> for ContainerSource {
             ports: self.ports.unwrap_or_default().build(&())?,
 
             volume_mounts: self.volume_mounts.unwrap_or_default(),
+
+            security_context: self.security_context,
         })
     }
 }
@@ -4,6 +4,7 @@ use merge::Merge;
 use std::collections::BTreeMap;
 
 use shipcat_definitions::{
+    podsecurity::PodSecurityExemption,
     structs::{
         autoscaling::AutoScaling,
         metadata::{default_format_string, Contact, Context, Language, SlackChannel},
@@ -12,7 +13,7 @@ use shipcat_definitions::{
         volume::Volume,
         ConfigMap, Dependency, DestinationRule, EventStream, Gate, HealthCheck, HostAlias, Kafka,
         KafkaResources, LifeCycle, Metadata, NotificationMode, PersistentVolume, Probe, PrometheusAlert,
-        Rbac, RollingUpdate, SecurityContext, VaultOpts, VolumeMount,
+        Rbac, RollingUpdate, SecurityContext, TrafficPolicy, VaultOpts, VolumeMount,
     },
     BaseManifest, Config, Manifest, PrimaryWorkload, Region, Result,
 };
@@ -56,6 +57,7 @@ pub struct MetadataSource {
     pub language: Option<Language>,
     #[serde(default = "default_format_string")]
     pub gitTagTemplate: String,
+    pub defaultBranch: Option<String>,
     pub contacts: Vec<Contact>,
     pub maintainers: Vec<String>,
     pub support: Option<SlackChannel>,
@@ -102,6 +104,7 @@ pub struct ManifestOverrides {
     pub version: Option<ImageTagSource>,
     pub command: Option<Vec<String>>,
     pub security_context: Option<SecurityContext>,
+    pub pod_security_exemptions: Option<Vec<PodSecurityExemption>>,
     pub data_handling: Option<DataHandling>,
     pub resources: Option<ResourceRequirementsSource>,
     pub secret_files: BTreeMap<String, String>,
@@ -113,6 +116,7 @@ pub struct ManifestOverrides {
     pub health: Option<HealthCheck>,
     pub dependencies: Option<Vec<Dependency>>,
     pub destination_rules: Option<Vec<DestinationRule>>,
+    pub traffic_policy: Option<TrafficPolicy>,
     pub workers: Option<Vec<WorkerSource>>,
     pub sidecars: Option<Vec<SidecarSource>>,
     pub readiness_probe: Option<Probe>,
@@ -134,6 +138,7 @@ pub struct ManifestOverrides {
     pub kafka: Option<Kafka>,
     pub source_ranges: Option<Vec<String>>,
     pub rbac: Option<Vec<Rbac>>,
+    pub service_account: Option<String>,
     pub sentry: Option<SentrySource>,
     pub event_streams: Option<Vec<EventStream>>,
     pub kafka_resources: Option<KafkaResources>,
@@ -203,6 +208,7 @@ impl ManifestSource {
             version: simple.version,
             command: overrides.command.unwrap_or_default(),
             securityContext: overrides.security_context,
+            podSecurityExemptions: overrides.pod_security_exemptions.unwrap_or_default(),
             dataHandling: data_handling,
             resources: overrides.resources.build(&())?,
             replicaCount: defaults.replica_count,
@@ -216,6 +222,7 @@ impl ManifestSource {
             health: overrides.health,
             dependencies: overrides.dependencies.unwrap_or_default(),
             destinationRules: overrides.destination_rules,
+            trafficPolicy: overrides.traffic_policy,
             workers: overrides
                 .workers
                 .unwrap_or_default()
@@ -250,6 +257,7 @@ impl ManifestSource {
             kafka: kafka,
             sourceRanges: overrides.source_ranges.unwrap_or_default(),
             rbac: overrides.rbac.unwrap_or_default(),
+            serviceAccount: overrides.service_account,
             newrelic: overrides.newrelic.build(&team_notifications)?,
             sentry: overrides
                 .sentry
@@ -262,6 +270,11 @@ impl ManifestSource {
             environment: region.environment.to_string(),
             namespace: region.namespace.clone(),
             uid: Default::default(),
+            networkPolicy: Default::default(),
+            istio: Default::default(),
+            strimzi: Default::default(),
+            prometheusRule: Default::default(),
+            imageDigest: Default::default(),
             secrets: Default::default(),
             state: Default::default(),
             workload: overrides.workload.unwrap_or_default(),
@@ -367,6 +380,7 @@ impl ManifestSource {
             tribe: md.tribe,
             language: md.language,
             gitTagTemplate: md.gitTagTemplate,
+            defaultBranch: md.defaultBranch,
             contacts: md.contacts,
             maintainers: md.maintainers,
             support: md.support,